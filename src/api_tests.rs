@@ -0,0 +1,110 @@
+//! Exercises the public API of the instrumented connections, compiled both
+//! with and without the `passthrough` feature to keep the two in step.
+
+#[cfg(feature = "sqlite")]
+#[test]
+fn test_sqlite_api() {
+    use diesel::connection::{Connection, SimpleConnection};
+    use diesel::result::QueryResult;
+
+    use crate::sqlite::{BusyRetryPolicy, InstrumentedSqliteConnection};
+    use crate::TracingConfig;
+
+    let mut conn = InstrumentedSqliteConnection::establish_named(":memory:", "primary").unwrap();
+    conn.set_connection_name("replica");
+    assert_eq!(conn.connection_name(), Some("replica"));
+    conn.set_tracing_config(TracingConfig::default());
+    conn.set_busy_retry(BusyRetryPolicy::default());
+    assert_eq!(conn.busy_retry(), BusyRetryPolicy::default());
+    assert_eq!(conn.tracing_config(), TracingConfig::default());
+
+    conn.batch_execute("CREATE TABLE users (id INTEGER)")
+        .unwrap();
+    conn.transaction(|conn| conn.batch_execute("INSERT INTO users VALUES (1)"))
+        .unwrap();
+    conn.immediate_transaction(|conn| conn.batch_execute("INSERT INTO users VALUES (2)"))
+        .unwrap();
+    conn.exclusive_transaction(|conn| conn.batch_execute("INSERT INTO users VALUES (3)"))
+        .unwrap();
+    conn.register_collation("reverse", |a: &str, b: &str| b.cmp(a))
+        .unwrap();
+    let _: &diesel::sqlite::SqliteConnection = conn.inner();
+    let mut conn = InstrumentedSqliteConnection::from(conn.into_inner());
+    conn.inner_mut().batch_execute("SELECT 1").unwrap();
+    let buffer = conn.serialize_database_to_buffer();
+    conn.deserialize_readonly_database_from_buffer(buffer.as_slice())
+        .unwrap();
+    conn.transaction(|_| QueryResult::<()>::Err(diesel::result::Error::RollbackTransaction))
+        .unwrap_err();
+}
+
+#[cfg(feature = "postgres")]
+#[test]
+fn test_pg_api() {
+    use diesel::connection::SimpleConnection;
+
+    use crate::pg::InstrumentedPgConnection;
+
+    let url = std::env::var("POSTGRESQL_URL").expect("no POSTGRESQL_URL env var specified");
+    let mut conn = InstrumentedPgConnection::establish_without_info(&url).unwrap();
+    conn.refresh_info().unwrap();
+    let mut conn = InstrumentedPgConnection::from(conn.into_inner());
+    conn.inner_mut().batch_execute("SELECT 1").unwrap();
+    conn.preload_type_metadata(&["int4"]).unwrap();
+
+    let mut conn = InstrumentedPgConnection::establish_named(&url, "primary").unwrap();
+    assert_eq!(conn.connection_name(), Some("primary"));
+    conn.build_transaction()
+        .serializable()
+        .read_only()
+        .deferrable()
+        .run(|conn| conn.batch_execute("SELECT 1"))
+        .unwrap();
+}
+
+#[cfg(feature = "mysql")]
+#[test]
+#[ignore = "requires a mysql server at MYSQL_URL"]
+fn test_mysql_api() {
+    use diesel::connection::SimpleConnection;
+
+    use crate::mysql::InstrumentedMysqlConnection;
+
+    let url = std::env::var("MYSQL_URL").expect("no MYSQL_URL env var specified");
+    let mut conn = InstrumentedMysqlConnection::establish_named(&url, "primary").unwrap();
+    assert_eq!(conn.connection_name(), Some("primary"));
+    conn.batch_execute("SELECT 1").unwrap();
+    conn.as_mut().batch_execute("SELECT 1").unwrap();
+
+    let mut inner = diesel::mysql::MysqlConnection::from(conn);
+    inner.batch_execute("SELECT 1").unwrap();
+    let mut conn = InstrumentedMysqlConnection::from(inner);
+    conn.batch_execute("SELECT 1").unwrap();
+}
+
+#[test]
+fn test_prelude() {
+    use crate::prelude::*;
+
+    assert_eq!(
+        summarize_statement("SELECT * FROM users").table.as_deref(),
+        Some("users")
+    );
+    let _ = TracingConfig::default();
+
+    #[cfg(all(feature = "r2d2", feature = "sqlite"))]
+    {
+        let pool: SqlitePool = diesel::r2d2::Pool::builder()
+            .max_size(1)
+            .build(InstrumentedConnectionManager::new(":memory:"))
+            .unwrap();
+        pool.get().unwrap().batch_execute("SELECT 1").unwrap();
+    }
+
+    #[cfg(all(feature = "alias-diesel-names", feature = "sqlite"))]
+    {
+        let mut conn: InstrumentedSqliteConnection =
+            SqliteConnection::establish(":memory:").unwrap();
+        conn.batch_execute("SELECT 1").unwrap();
+    }
+}
@@ -0,0 +1,434 @@
+use diesel::connection::Instrumentation;
+use diesel::mysql::Mysql;
+use diesel::query_builder::{AsQuery, QueryFragment, QueryId};
+use diesel::result::{ConnectionError, ConnectionResult, QueryResult};
+use diesel_async::{
+    AnsiTransactionManager, AsyncConnection, AsyncMysqlConnection, RunQueryDsl,
+    SimpleAsyncConnection,
+};
+use futures_util::future::{BoxFuture, FutureExt};
+use tracing::{debug, field, info_span, Instrument, Span};
+
+use crate::async_stream::InstrumentedStream;
+use crate::batch::{record_batch, record_batch_error};
+use crate::config::TracingConfig;
+use crate::connection_id::ConnectionId;
+use crate::error::{error_event, record_error, record_status};
+use crate::instrumentation::SharedInstrumentationStack;
+use crate::semconv::deprecated;
+#[cfg(all(feature = "statement-fields", not(feature = "passthrough")))]
+use crate::statement::truncate_statement;
+use crate::statement::{record_query_summary, record_statement_summary};
+
+pub use crate::mysql_info::MysqlConnectionInfo;
+
+pub struct InstrumentedAsyncMysqlConnection {
+    inner: AsyncMysqlConnection,
+    info: MysqlConnectionInfo,
+    connection_id: ConnectionId,
+    connection_name: Option<String>,
+    config: TracingConfig,
+    instrumentation_stack: Option<SharedInstrumentationStack>,
+}
+
+impl InstrumentedAsyncMysqlConnection {
+    /// Replace the configuration controlling which spans are created.
+    pub fn set_tracing_config(&mut self, config: TracingConfig) {
+        self.config = config;
+    }
+
+    /// Replace every instrumentation installed on the connection with
+    /// `instrumentation`. `set_instrumentation` instead pushes onto the
+    /// connection's
+    /// [`InstrumentationStack`](crate::instrumentation::InstrumentationStack),
+    /// so that each instrumentation set receives its events.
+    pub fn replace_instrumentation(&mut self, instrumentation: impl Instrumentation) {
+        let stack = SharedInstrumentationStack::new(instrumentation);
+        self.inner.set_instrumentation(stack.clone());
+        self.instrumentation_stack = Some(stack);
+    }
+
+    /// The information about the connection queried from the server, as
+    /// recorded on its spans.
+    #[must_use]
+    pub fn connection_info(&self) -> &MysqlConnectionInfo {
+        &self.info
+    }
+
+    /// The configuration controlling which spans are created.
+    #[must_use]
+    pub fn tracing_config(&self) -> TracingConfig {
+        self.config
+    }
+
+    /// Establish a connection labelled with a name, such as `"replica"`, which
+    /// is recorded as `db.connection.name` on its spans.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the connection could not be established.
+    pub async fn establish_named(
+        database_url: &str,
+        name: impl Into<String>,
+    ) -> ConnectionResult<Self> {
+        let mut conn = Self::establish(database_url).await?;
+        conn.set_connection_name(name);
+        Ok(conn)
+    }
+
+    /// Label the connection with a name, which is recorded as
+    /// `db.connection.name` on its spans.
+    pub fn set_connection_name(&mut self, name: impl Into<String>) {
+        self.connection_name = Some(name.into());
+    }
+
+    /// The name the connection is labelled with, if any.
+    #[must_use]
+    pub fn connection_name(&self) -> Option<&str> {
+        self.connection_name.as_deref()
+    }
+
+    fn load_span(&self) -> Span {
+        if cfg!(feature = "passthrough") {
+            return Span::none();
+        }
+
+        info_span!(
+            target: "diesel_tracing::async_mysql::query",
+            "load",
+            db.namespace = %self.info.database,
+            db.name = deprecated(field::display(&self.info.database)),
+            db.system = "mysql",
+            db.version = %self.info.version,
+            otel.kind = "client",
+            db.connection_id = self.connection_id.value(),
+            db.connection.id = self.connection_id.sequence(),
+            db.connection.name = self.connection_name.as_deref(),
+            server.address = %self.info.host,
+            net.peer.name = deprecated(field::display(&self.info.host)),
+            server.port = self.info.port,
+            net.peer.port = deprecated(self.info.port),
+            db.client.address = self.info.client_address.as_deref(),
+            db.client.port = self.info.client_port,
+            db.mysql.thread_id = self.info.thread_id,
+            db.operation = field::Empty,
+            db.sql.table = field::Empty,
+            db.access_mode = field::Empty,
+            db.statement_fingerprint = field::Empty,
+            db.statement.hash = field::Empty,
+            db.bind_count = field::Empty,
+            code.function = field::Empty,
+            code.filepath = field::Empty,
+            code.lineno = field::Empty,
+            db.error.kind = field::Empty,
+            db.error.table = field::Empty,
+            otel.name = field::Empty,
+            db.error.constraint = field::Empty,
+            otel.status_code = field::Empty,
+            otel.status_message = field::Empty,
+            db.statement = field::Empty,
+        )
+    }
+
+    fn execute_span(&self) -> Span {
+        if cfg!(feature = "passthrough") {
+            return Span::none();
+        }
+
+        info_span!(
+            target: "diesel_tracing::async_mysql::query",
+            "execute_returning_count",
+            db.namespace = %self.info.database,
+            db.name = deprecated(field::display(&self.info.database)),
+            db.system = "mysql",
+            db.version = %self.info.version,
+            otel.kind = "client",
+            db.connection_id = self.connection_id.value(),
+            db.connection.id = self.connection_id.sequence(),
+            db.connection.name = self.connection_name.as_deref(),
+            server.address = %self.info.host,
+            net.peer.name = deprecated(field::display(&self.info.host)),
+            server.port = self.info.port,
+            net.peer.port = deprecated(self.info.port),
+            db.client.address = self.info.client_address.as_deref(),
+            db.client.port = self.info.client_port,
+            db.mysql.thread_id = self.info.thread_id,
+            db.operation = field::Empty,
+            db.sql.table = field::Empty,
+            db.access_mode = field::Empty,
+            db.statement_fingerprint = field::Empty,
+            db.statement.hash = field::Empty,
+            db.bind_count = field::Empty,
+            code.function = field::Empty,
+            code.filepath = field::Empty,
+            code.lineno = field::Empty,
+            db.error.kind = field::Empty,
+            db.error.table = field::Empty,
+            otel.name = field::Empty,
+            db.error.constraint = field::Empty,
+            otel.status_code = field::Empty,
+            otel.status_message = field::Empty,
+            db.statement = field::Empty,
+            db.rows_affected = field::Empty,
+        )
+    }
+}
+
+/// Record the summary, and optionally the statement, of a query on its span.
+#[cfg_attr(
+    any(not(feature = "statement-fields"), feature = "passthrough"),
+    allow(unused_variables)
+)]
+fn record_query<T>(span: &Span, source: &T, config: TracingConfig)
+where
+    T: QueryFragment<Mysql>,
+{
+    span.in_scope(|| record_query_summary::<Mysql, _>(source, config.statement_span_names));
+    #[cfg(all(feature = "statement-fields", not(feature = "passthrough")))]
+    span.record(
+        "db.statement",
+        truncate_statement(
+            diesel::debug_query::<Mysql, _>(source).to_string(),
+            config.max_statement_length(),
+        ),
+    );
+}
+
+impl InstrumentedAsyncMysqlConnection {
+    #[cfg_attr(
+        not(feature = "passthrough"),
+        tracing::instrument(
+            name = "batch_execute",
+            target = "diesel_tracing::async_mysql::query",
+            fields(
+                db.namespace=%self.info.database,
+                db.name=deprecated(field::display(&self.info.database)),
+                db.system="mysql",
+                db.version=%self.info.version,
+                otel.kind="client",
+                db.connection_id=self.connection_id.value(),
+                db.connection.id=self.connection_id.sequence(),
+                db.connection.name=self.connection_name.as_deref(),
+                server.address=%self.info.host,
+                net.peer.name=deprecated(field::display(&self.info.host)),
+                server.port=%self.info.port,
+                net.peer.port=deprecated(field::display(&self.info.port)),
+                db.client.address=self.info.client_address.as_deref(),
+                db.client.port=self.info.client_port,
+                db.mysql.thread_id=self.info.thread_id,
+                db.operation=field::Empty,
+                db.sql.table=field::Empty,
+                db.access_mode=field::Empty,
+                db.statement_fingerprint=field::Empty,
+                db.statement.hash=field::Empty,
+                db.error.kind=field::Empty,
+                db.error.table=field::Empty,
+                otel.name=field::Empty,
+                db.error.constraint=field::Empty,
+                otel.status_code=field::Empty,
+                otel.status_message=field::Empty,
+                db.batch.size=field::Empty,
+                db.batch.error_index=field::Empty,
+                db.statement=field::Empty,
+            ),
+            skip(self, query),
+        )
+    )]
+    async fn traced_batch_execute(&mut self, query: &str) -> QueryResult<()> {
+        record_statement_summary(query, self.config.statement_span_names);
+        #[cfg(all(feature = "statement-fields", not(feature = "passthrough")))]
+        Span::current().record(
+            "db.statement",
+            truncate_statement(query.to_owned(), self.config.max_statement_length()),
+        );
+        record_batch(query);
+        let levels = self.config.error_levels;
+        self.inner.batch_execute(query).await.inspect_err(|error| {
+            record_error(&Span::current(), error);
+            error_event!("diesel_tracing::async_mysql::query", levels, error);
+            record_batch_error(query, error);
+        })
+    }
+}
+
+#[async_trait::async_trait]
+impl SimpleAsyncConnection for InstrumentedAsyncMysqlConnection {
+    async fn batch_execute(&mut self, query: &str) -> QueryResult<()> {
+        if !self.config.trace_batch {
+            return self.inner.batch_execute(query).await;
+        }
+        self.traced_batch_execute(query).await
+    }
+}
+
+#[async_trait::async_trait]
+impl AsyncConnection for InstrumentedAsyncMysqlConnection {
+    type ExecuteFuture<'conn, 'query> = BoxFuture<'conn, QueryResult<usize>>;
+    type LoadFuture<'conn, 'query> = BoxFuture<'conn, QueryResult<Self::Stream<'conn, 'query>>>;
+    type Stream<'conn, 'query> =
+        InstrumentedStream<<AsyncMysqlConnection as AsyncConnection>::Stream<'conn, 'query>>;
+    type Row<'conn, 'query> = <AsyncMysqlConnection as AsyncConnection>::Row<'conn, 'query>;
+    type Backend = Mysql;
+    type TransactionManager = AnsiTransactionManager;
+
+    #[cfg_attr(
+        not(feature = "passthrough"),
+        tracing::instrument(
+            target = "diesel_tracing::async_mysql::connection",
+            fields(
+                db.namespace=field::Empty,
+                db.name=field::Empty,
+                db.system="mysql",
+                db.version=field::Empty,
+                otel.kind="client",
+                db.connection_id=field::Empty,
+                db.connection.id=field::Empty,
+                server.address=field::Empty,
+                net.peer.name=field::Empty,
+                server.port=field::Empty,
+                net.peer.port=field::Empty,
+                db.client.address=field::Empty,
+                db.client.port=field::Empty,
+                db.mysql.thread_id=field::Empty,
+                otel.status_code=field::Empty,
+                otel.status_message=field::Empty,
+            ),
+            skip(database_url),
+            err,
+        )
+    )]
+    async fn establish(database_url: &str) -> ConnectionResult<Self> {
+        debug!(target: "diesel_tracing::async_mysql::connection", "establishing mysql connection");
+        let mut conn = AsyncMysqlConnection::establish(database_url)
+            .await
+            .inspect_err(|error| record_status(&Span::current(), error))?;
+
+        let info = if cfg!(feature = "passthrough") {
+            MysqlConnectionInfo::default()
+        } else {
+            debug!(
+                target: "diesel_tracing::async_mysql::connection",
+                "querying mysql connection information",
+            );
+            MysqlConnectionInfo::query()
+                .get_result(&mut conn)
+                .await
+                .map_err(ConnectionError::CouldntSetupConfiguration)?
+        };
+        info.record(&Span::current());
+
+        let connection_id = ConnectionId::new();
+        connection_id.record(&Span::current());
+
+        Ok(InstrumentedAsyncMysqlConnection {
+            inner: conn,
+            info,
+            connection_id,
+            connection_name: None,
+            config: TracingConfig::default(),
+            instrumentation_stack: None,
+        })
+    }
+
+    fn load<'conn, 'query, T>(&'conn mut self, source: T) -> Self::LoadFuture<'conn, 'query>
+    where
+        T: AsQuery + 'query,
+        T::Query: QueryFragment<Self::Backend> + QueryId + 'query,
+    {
+        let query = source.as_query();
+        let span = if self.config.trace_load {
+            self.load_span()
+        } else {
+            Span::none()
+        };
+        record_query(&span, &query, self.config);
+
+        let config = self.config;
+        let load = AsyncConnection::load(&mut self.inner, query);
+        let stream_span = span.clone();
+        async move {
+            match load.await {
+                Ok(stream) => Ok(InstrumentedStream::new(stream, stream_span)),
+                Err(e) => {
+                    record_error(&Span::current(), &e);
+                    error_event!(
+                        "diesel_tracing::async_mysql::query",
+                        config.error_levels,
+                        &e
+                    );
+                    Err(e)
+                }
+            }
+        }
+        .instrument(span)
+        .boxed()
+    }
+
+    fn execute_returning_count<'conn, 'query, T>(
+        &'conn mut self,
+        source: T,
+    ) -> Self::ExecuteFuture<'conn, 'query>
+    where
+        T: QueryFragment<Self::Backend> + QueryId + 'query,
+    {
+        let span = if self.config.trace_execute {
+            self.execute_span()
+        } else {
+            Span::none()
+        };
+        record_query(&span, &source, self.config);
+
+        let config = self.config;
+        let execute = self.inner.execute_returning_count(source);
+        async move {
+            match execute.await {
+                Ok(count) => {
+                    Span::current().record("db.rows_affected", count);
+                    Ok(count)
+                }
+                Err(e) => {
+                    record_error(&Span::current(), &e);
+                    error_event!(
+                        "diesel_tracing::async_mysql::query",
+                        config.error_levels,
+                        &e
+                    );
+                    Err(e)
+                }
+            }
+        }
+        .instrument(span)
+        .boxed()
+    }
+
+    fn transaction_state(&mut self) -> &mut AnsiTransactionManager {
+        self.inner.transaction_state()
+    }
+
+    fn instrumentation(&mut self) -> &mut dyn Instrumentation {
+        self.inner.instrumentation()
+    }
+
+    fn set_instrumentation(&mut self, instrumentation: impl Instrumentation) {
+        if let Some(stack) =
+            SharedInstrumentationStack::push(&mut self.instrumentation_stack, instrumentation)
+        {
+            self.inner.set_instrumentation(stack);
+        }
+    }
+}
+
+#[cfg(all(test, not(feature = "passthrough")))]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    #[ignore = "requires a mysql server at MYSQL_URL"]
+    async fn test_get_info_on_establish() {
+        InstrumentedAsyncMysqlConnection::establish(
+            &std::env::var("MYSQL_URL").expect("no MYSQL_URL env var specified"),
+        )
+        .await
+        .expect("failed to establish connection or collect info");
+    }
+}
@@ -0,0 +1,551 @@
+use diesel::connection::Instrumentation;
+use diesel::pg::Pg;
+use diesel::query_builder::{AsQuery, QueryFragment, QueryId};
+use diesel::result::{ConnectionResult, QueryResult};
+use diesel::select;
+use diesel_async::{
+    AnsiTransactionManager, AsyncConnection, AsyncPgConnection, RunQueryDsl, SimpleAsyncConnection,
+};
+use futures_util::future::{BoxFuture, FutureExt};
+use tracing::{debug, field, info_span, warn, Instrument, Span};
+
+use crate::async_stream::InstrumentedStream;
+use crate::batch::{record_batch, record_batch_error};
+use crate::config::TracingConfig;
+use crate::connection_id::ConnectionId;
+use crate::error::{error_event, record_error, record_status};
+use crate::instrumentation::SharedInstrumentationStack;
+use crate::pg_info::{
+    current_database, current_schema, current_schemas, current_setting, inet_client_addr,
+    inet_client_port, inet_server_addr, inet_server_port, pg_backend_pid, version,
+};
+use crate::semconv::deprecated;
+#[cfg(all(feature = "statement-fields", not(feature = "passthrough")))]
+use crate::statement::truncate_statement;
+use crate::statement::{record_query_summary, record_statement_summary};
+
+pub use crate::pg_info::PgConnectionInfo;
+
+impl PgConnectionInfo {
+    /// Query the connection information, falling back to empty information
+    /// if it is unavailable.
+    async fn query_async(conn: &mut AsyncPgConnection) -> PgConnectionInfo {
+        if cfg!(feature = "passthrough") {
+            return PgConnectionInfo::default();
+        }
+        debug!(
+            target: "diesel_tracing::async_pg::connection",
+            "querying postgresql connection information",
+        );
+        let mut info = select((
+            current_database(),
+            inet_server_addr(),
+            inet_server_port(),
+            inet_client_addr(),
+            inet_client_port(),
+            version(),
+            current_setting("application_name", true),
+            current_setting("default_transaction_isolation", true),
+            current_setting("default_transaction_read_only", true),
+            current_schema(),
+            current_schemas(false),
+            pg_backend_pid(),
+        ))
+        .get_result(conn)
+        .await
+        .unwrap_or_else(|error| {
+            warn!(
+                target: "diesel_tracing::async_pg::connection",
+                %error,
+                "failed to query postgresql connection information",
+            );
+            PgConnectionInfo::default()
+        });
+        info.resolve_hostname();
+
+        info
+    }
+}
+
+pub struct InstrumentedAsyncPgConnection {
+    inner: AsyncPgConnection,
+    info: PgConnectionInfo,
+    connection_id: ConnectionId,
+    connection_name: Option<String>,
+    config: TracingConfig,
+    instrumentation_stack: Option<SharedInstrumentationStack>,
+}
+
+impl InstrumentedAsyncPgConnection {
+    /// Replace the configuration controlling which spans are created.
+    pub fn set_tracing_config(&mut self, config: TracingConfig) {
+        self.config = config;
+    }
+
+    /// Replace every instrumentation installed on the connection with
+    /// `instrumentation`. `set_instrumentation` instead pushes onto the
+    /// connection's
+    /// [`InstrumentationStack`](crate::instrumentation::InstrumentationStack),
+    /// so that each instrumentation set receives its events.
+    pub fn replace_instrumentation(&mut self, instrumentation: impl Instrumentation) {
+        let stack = SharedInstrumentationStack::new(instrumentation);
+        self.inner.set_instrumentation(stack.clone());
+        self.instrumentation_stack = Some(stack);
+    }
+
+    /// The information about the connection queried from the server, as
+    /// recorded on its spans.
+    #[must_use]
+    pub fn connection_info(&self) -> &PgConnectionInfo {
+        &self.info
+    }
+
+    /// The configuration controlling which spans are created.
+    #[must_use]
+    pub fn tracing_config(&self) -> TracingConfig {
+        self.config
+    }
+
+    /// Establish a connection labelled with a name, such as `"replica"`, which
+    /// is recorded as `db.connection.name` on its spans.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the connection could not be established.
+    pub async fn establish_named(
+        database_url: &str,
+        name: impl Into<String>,
+    ) -> ConnectionResult<Self> {
+        let mut conn = Self::establish(database_url).await?;
+        conn.set_connection_name(name);
+        Ok(conn)
+    }
+
+    /// Label the connection with a name, which is recorded as
+    /// `db.connection.name` on its spans.
+    pub fn set_connection_name(&mut self, name: impl Into<String>) {
+        self.connection_name = Some(name.into());
+    }
+
+    /// The name the connection is labelled with, if any.
+    #[must_use]
+    pub fn connection_name(&self) -> Option<&str> {
+        self.connection_name.as_deref()
+    }
+
+    fn load_span(&self) -> Span {
+        if cfg!(feature = "passthrough") {
+            return Span::none();
+        }
+
+        info_span!(
+            target: "diesel_tracing::async_pg::query",
+            "load",
+            db.namespace = self.info.current_database.as_deref(),
+            db.name = deprecated(self.info.current_database.as_deref()),
+            db.system = "postgresql",
+            db.version = self.info.version.as_deref(),
+            otel.kind = "client",
+            db.connection_id = self.connection_id.value(),
+            db.connection.id = self.connection_id.sequence(),
+            db.connection.name = self.connection_name.as_deref(),
+            network.peer.address = self.info.inet_server_addr.map(field::display),
+            net.peer.ip = deprecated(self.info.inet_server_addr.map(field::display)),
+            server.address = self.info.server_hostname.as_deref(),
+            net.peer.name = deprecated(self.info.server_hostname.as_deref()),
+            server.port = self.info.inet_server_port,
+            net.peer.port = deprecated(self.info.inet_server_port),
+            db.client.address = self.info.client_address.as_deref(),
+            db.client.port = self.info.client_port,
+            db.postgresql.application_name = self.info.application_name.as_deref(),
+            db.postgresql.pid = self.info.backend_pid,
+            db.schema = self.info.current_schema.as_deref(),
+            db.postgresql.schema = self.info.schema.as_deref(),
+            db.operation = field::Empty,
+            db.sql.table = field::Empty,
+            db.access_mode = field::Empty,
+            db.statement_fingerprint = field::Empty,
+            db.statement.hash = field::Empty,
+            db.bind_count = field::Empty,
+            code.function = field::Empty,
+            code.filepath = field::Empty,
+            code.lineno = field::Empty,
+            db.error.kind = field::Empty,
+            db.error.table = field::Empty,
+            otel.name = field::Empty,
+            db.error.constraint = field::Empty,
+            otel.status_code = field::Empty,
+            otel.status_message = field::Empty,
+            db.statement = field::Empty,
+        )
+    }
+
+    fn execute_span(&self) -> Span {
+        if cfg!(feature = "passthrough") {
+            return Span::none();
+        }
+
+        info_span!(
+            target: "diesel_tracing::async_pg::query",
+            "execute_returning_count",
+            db.namespace = self.info.current_database.as_deref(),
+            db.name = deprecated(self.info.current_database.as_deref()),
+            db.system = "postgresql",
+            db.version = self.info.version.as_deref(),
+            otel.kind = "client",
+            db.connection_id = self.connection_id.value(),
+            db.connection.id = self.connection_id.sequence(),
+            db.connection.name = self.connection_name.as_deref(),
+            network.peer.address = self.info.inet_server_addr.map(field::display),
+            net.peer.ip = deprecated(self.info.inet_server_addr.map(field::display)),
+            server.address = self.info.server_hostname.as_deref(),
+            net.peer.name = deprecated(self.info.server_hostname.as_deref()),
+            server.port = self.info.inet_server_port,
+            net.peer.port = deprecated(self.info.inet_server_port),
+            db.client.address = self.info.client_address.as_deref(),
+            db.client.port = self.info.client_port,
+            db.postgresql.application_name = self.info.application_name.as_deref(),
+            db.postgresql.pid = self.info.backend_pid,
+            db.schema = self.info.current_schema.as_deref(),
+            db.postgresql.schema = self.info.schema.as_deref(),
+            db.operation = field::Empty,
+            db.sql.table = field::Empty,
+            db.access_mode = field::Empty,
+            db.statement_fingerprint = field::Empty,
+            db.statement.hash = field::Empty,
+            db.bind_count = field::Empty,
+            code.function = field::Empty,
+            code.filepath = field::Empty,
+            code.lineno = field::Empty,
+            db.error.kind = field::Empty,
+            db.error.table = field::Empty,
+            otel.name = field::Empty,
+            db.error.constraint = field::Empty,
+            otel.status_code = field::Empty,
+            otel.status_message = field::Empty,
+            db.statement = field::Empty,
+            db.rows_affected = field::Empty,
+        )
+    }
+}
+
+/// Record the summary, and optionally the statement, of a query on its span.
+#[cfg_attr(
+    any(not(feature = "statement-fields"), feature = "passthrough"),
+    allow(unused_variables)
+)]
+fn record_query<T>(span: &Span, source: &T, config: TracingConfig)
+where
+    T: QueryFragment<Pg>,
+{
+    span.in_scope(|| record_query_summary::<Pg, _>(source, config.statement_span_names));
+    #[cfg(all(feature = "statement-fields", not(feature = "passthrough")))]
+    span.record(
+        "db.statement",
+        truncate_statement(
+            diesel::debug_query::<Pg, _>(source).to_string(),
+            config.max_statement_length(),
+        ),
+    );
+}
+
+impl InstrumentedAsyncPgConnection {
+    #[cfg_attr(
+        not(feature = "passthrough"),
+        tracing::instrument(
+            name = "batch_execute",
+            target = "diesel_tracing::async_pg::query",
+            fields(
+                db.namespace=self.info.current_database.as_deref(),
+                db.name=deprecated(self.info.current_database.as_deref()),
+                db.system="postgresql",
+                db.version=self.info.version.as_deref(),
+                otel.kind="client",
+                db.connection_id=self.connection_id.value(),
+                db.connection.id=self.connection_id.sequence(),
+                db.connection.name=self.connection_name.as_deref(),
+                network.peer.address=self.info.inet_server_addr.map(field::display),
+                net.peer.ip=deprecated(self.info.inet_server_addr.map(field::display)),
+                server.address=self.info.server_hostname.as_deref(),
+                net.peer.name=deprecated(self.info.server_hostname.as_deref()),
+                server.port=self.info.inet_server_port,
+                net.peer.port=deprecated(self.info.inet_server_port),
+                db.client.address=self.info.client_address.as_deref(),
+                db.client.port=self.info.client_port,
+                db.postgresql.application_name=self.info.application_name.as_deref(),
+                db.postgresql.pid=self.info.backend_pid,
+                db.schema=self.info.current_schema.as_deref(),
+                db.postgresql.schema=self.info.schema.as_deref(),
+                db.operation=field::Empty,
+                db.sql.table=field::Empty,
+                db.access_mode=field::Empty,
+                db.statement_fingerprint=field::Empty,
+                db.statement.hash=field::Empty,
+                db.error.kind=field::Empty,
+                db.error.table=field::Empty,
+                otel.name=field::Empty,
+                db.error.constraint=field::Empty,
+                otel.status_code=field::Empty,
+                otel.status_message=field::Empty,
+                db.batch.size=field::Empty,
+                db.batch.error_index=field::Empty,
+                db.statement=field::Empty,
+            ),
+            skip(self, query),
+        )
+    )]
+    async fn traced_batch_execute(&mut self, query: &str) -> QueryResult<()> {
+        debug!(target: "diesel_tracing::async_pg::query", "executing batch query");
+        record_statement_summary(query, self.config.statement_span_names);
+        #[cfg(all(feature = "statement-fields", not(feature = "passthrough")))]
+        Span::current().record(
+            "db.statement",
+            truncate_statement(query.to_owned(), self.config.max_statement_length()),
+        );
+        record_batch(query);
+        let levels = self.config.error_levels;
+        self.inner.batch_execute(query).await.inspect_err(|error| {
+            record_error(&Span::current(), error);
+            error_event!("diesel_tracing::async_pg::query", levels, error);
+            record_batch_error(query, error);
+        })
+    }
+}
+
+#[async_trait::async_trait]
+impl SimpleAsyncConnection for InstrumentedAsyncPgConnection {
+    async fn batch_execute(&mut self, query: &str) -> QueryResult<()> {
+        if !self.config.trace_batch {
+            return self.inner.batch_execute(query).await;
+        }
+        self.traced_batch_execute(query).await
+    }
+}
+
+#[async_trait::async_trait]
+impl AsyncConnection for InstrumentedAsyncPgConnection {
+    type ExecuteFuture<'conn, 'query> = BoxFuture<'query, QueryResult<usize>>;
+    type LoadFuture<'conn, 'query> = BoxFuture<'query, QueryResult<Self::Stream<'conn, 'query>>>;
+    type Stream<'conn, 'query> =
+        InstrumentedStream<<AsyncPgConnection as AsyncConnection>::Stream<'conn, 'query>>;
+    type Row<'conn, 'query> = <AsyncPgConnection as AsyncConnection>::Row<'conn, 'query>;
+    type Backend = Pg;
+    type TransactionManager = AnsiTransactionManager;
+
+    #[cfg_attr(
+        not(feature = "passthrough"),
+        tracing::instrument(
+            target = "diesel_tracing::async_pg::connection",
+            fields(
+                db.namespace=field::Empty,
+                db.name=field::Empty,
+                db.system="postgresql",
+                db.version=field::Empty,
+                otel.kind="client",
+                db.connection_id=field::Empty,
+                db.connection.id=field::Empty,
+                network.peer.address=field::Empty,
+                net.peer.ip=field::Empty,
+                server.address=field::Empty,
+                net.peer.name=field::Empty,
+                server.port=field::Empty,
+                net.peer.port=field::Empty,
+                db.client.address=field::Empty,
+                db.client.port=field::Empty,
+                db.postgresql.application_name=field::Empty,
+                db.postgresql.pid=field::Empty,
+                db.schema=field::Empty,
+                db.postgresql.schema=field::Empty,
+                otel.status_code=field::Empty,
+                otel.status_message=field::Empty,
+            ),
+            skip(database_url),
+            err,
+        )
+    )]
+    async fn establish(database_url: &str) -> ConnectionResult<Self> {
+        debug!(
+            target: "diesel_tracing::async_pg::connection",
+            "establishing postgresql connection",
+        );
+        let mut conn = AsyncPgConnection::establish(database_url)
+            .await
+            .inspect_err(|error| record_status(&Span::current(), error))?;
+
+        let mut info = PgConnectionInfo::query_async(&mut conn).await;
+        info.apply_url(database_url);
+        info.record(&Span::current());
+
+        let connection_id = ConnectionId::new();
+        connection_id.record(&Span::current());
+
+        Ok(InstrumentedAsyncPgConnection {
+            inner: conn,
+            info,
+            connection_id,
+            connection_name: None,
+            config: TracingConfig::default(),
+            instrumentation_stack: None,
+        })
+    }
+
+    fn load<'conn, 'query, T>(&'conn mut self, source: T) -> Self::LoadFuture<'conn, 'query>
+    where
+        T: AsQuery + 'query,
+        T::Query: QueryFragment<Self::Backend> + QueryId + 'query,
+    {
+        let query = source.as_query();
+        let span = if self.config.trace_load {
+            self.load_span()
+        } else {
+            Span::none()
+        };
+        record_query(&span, &query, self.config);
+
+        let config = self.config;
+        let load = AsyncConnection::load(&mut self.inner, query);
+        let stream_span = span.clone();
+        async move {
+            match load.await {
+                Ok(stream) => Ok(InstrumentedStream::new(stream, stream_span)),
+                Err(e) => {
+                    record_error(&Span::current(), &e);
+                    error_event!("diesel_tracing::async_pg::query", config.error_levels, &e);
+                    Err(e)
+                }
+            }
+        }
+        .instrument(span)
+        .boxed()
+    }
+
+    fn execute_returning_count<'conn, 'query, T>(
+        &'conn mut self,
+        source: T,
+    ) -> Self::ExecuteFuture<'conn, 'query>
+    where
+        T: QueryFragment<Self::Backend> + QueryId + 'query,
+    {
+        let span = if self.config.trace_execute {
+            self.execute_span()
+        } else {
+            Span::none()
+        };
+        record_query(&span, &source, self.config);
+
+        let config = self.config;
+        let execute = self.inner.execute_returning_count(source);
+        async move {
+            match execute.await {
+                Ok(count) => {
+                    Span::current().record("db.rows_affected", count);
+                    Ok(count)
+                }
+                Err(e) => {
+                    record_error(&Span::current(), &e);
+                    error_event!("diesel_tracing::async_pg::query", config.error_levels, &e);
+                    Err(e)
+                }
+            }
+        }
+        .instrument(span)
+        .boxed()
+    }
+
+    fn transaction_state(&mut self) -> &mut AnsiTransactionManager {
+        self.inner.transaction_state()
+    }
+
+    fn instrumentation(&mut self) -> &mut dyn Instrumentation {
+        self.inner.instrumentation()
+    }
+
+    fn set_instrumentation(&mut self, instrumentation: impl Instrumentation) {
+        if let Some(stack) =
+            SharedInstrumentationStack::push(&mut self.instrumentation_stack, instrumentation)
+        {
+            self.inner.set_instrumentation(stack);
+        }
+    }
+}
+
+#[cfg(all(test, not(feature = "passthrough")))]
+mod tests {
+    use super::*;
+    use crate::test_support::EventRecorder;
+    use diesel::dsl::sql;
+    use diesel::sql_query;
+    use diesel::sql_types::Integer;
+    use diesel_async::TransactionManager;
+
+    #[tokio::test]
+    async fn test_async_queries() {
+        let recorder = EventRecorder::default();
+        let _guard = tracing::subscriber::set_default(recorder.clone());
+
+        let mut conn = InstrumentedAsyncPgConnection::establish(
+            &std::env::var("POSTGRESQL_URL").expect("no POSTGRESQL_URL env var specified"),
+        )
+        .await
+        .expect("failed to establish connection or collect info");
+        assert!(conn.info.current_database.is_some());
+
+        conn.batch_execute(
+            "CREATE TEMPORARY TABLE async_rows (id INTEGER);
+            INSERT INTO async_rows VALUES (1), (2), (3);",
+        )
+        .await
+        .unwrap();
+        #[cfg(feature = "statement-fields")]
+        assert!(
+            recorder.span("batch_execute").unwrap().fields["db.statement"]
+                .starts_with("CREATE TEMPORARY TABLE async_rows")
+        );
+
+        let ids: Vec<i32> = sql::<Integer>("SELECT id FROM async_rows ORDER BY id")
+            .load(&mut conn)
+            .await
+            .unwrap();
+        assert_eq!(ids, [1, 2, 3]);
+        let span = recorder.span("load").unwrap();
+        assert_eq!(span.fields["db.operation"], "SELECT");
+        assert_eq!(span.fields["db.sql.table"], "async_rows");
+
+        let deleted = sql_query("DELETE FROM async_rows WHERE id > 1")
+            .execute(&mut conn)
+            .await
+            .unwrap();
+        assert_eq!(deleted, 2);
+        let span = recorder.span("execute_returning_count").unwrap();
+        assert_eq!(span.fields["db.rows_affected"], "2");
+    }
+
+    #[tokio::test]
+    async fn test_async_transaction_rollback() {
+        let mut conn = InstrumentedAsyncPgConnection::establish(
+            &std::env::var("POSTGRESQL_URL").expect("no POSTGRESQL_URL env var specified"),
+        )
+        .await
+        .expect("failed to establish connection or collect info");
+        conn.batch_execute("CREATE TEMPORARY TABLE async_tx (id INTEGER)")
+            .await
+            .unwrap();
+
+        AnsiTransactionManager::begin_transaction(&mut conn)
+            .await
+            .unwrap();
+        sql_query("INSERT INTO async_tx VALUES (1)")
+            .execute(&mut conn)
+            .await
+            .unwrap();
+        AnsiTransactionManager::rollback_transaction(&mut conn)
+            .await
+            .unwrap();
+
+        let ids: Vec<i32> = sql::<Integer>("SELECT id FROM async_tx")
+            .load(&mut conn)
+            .await
+            .unwrap();
+        assert!(ids.is_empty());
+    }
+}
@@ -0,0 +1,37 @@
+use std::pin::Pin;
+use std::task::{Context, Poll};
+
+use futures_util::Stream;
+use tracing::Span;
+
+/// Row stream returned by the async instrumented connections.
+///
+/// The query span is entered each time the stream is polled, so the span
+/// covers the time spent fetching rows as well as executing the query.
+pub struct InstrumentedStream<S> {
+    inner: S,
+    span: Span,
+}
+
+impl<S> InstrumentedStream<S> {
+    pub(crate) fn new(inner: S, span: Span) -> Self {
+        InstrumentedStream { inner, span }
+    }
+}
+
+impl<S> Stream for InstrumentedStream<S>
+where
+    S: Stream + Unpin,
+{
+    type Item = S::Item;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let this = self.get_mut();
+        let _entered = this.span.enter();
+        Pin::new(&mut this.inner).poll_next(cx)
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.inner.size_hint()
+    }
+}
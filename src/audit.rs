@@ -0,0 +1,169 @@
+//! Machine readable audit records of the statements which write, for
+//! compliance logs which should say who changed which table and when, but
+//! not what the data was.
+//!
+//! An [`AuditSink`] set on an instrumented connection with
+//! `set_audit_sink` receives an [`AuditEntry`] for every statement run
+//! through `execute_returning_count`, `batch_execute` or `load` which the
+//! statement summarizer classifies as a write, whether or not the statement
+//! was sampled for tracing. Writes run through `load`, such as an insert
+//! with `get_result` or a `RETURNING` clause, are audited once the query
+//! returns its rows, without the number of rows affected. [`JsonLinesSink`]
+//! writes each entry as a line of JSON.
+//!
+//! ```
+//! # #[cfg(feature = "sqlite")]
+//! # fn main() {
+//! use std::sync::Arc;
+//!
+//! use diesel::connection::{Connection, SimpleConnection};
+//! use diesel_tracing::audit::JsonLinesSink;
+//! use diesel_tracing::sqlite::InstrumentedSqliteConnection;
+//!
+//! let mut conn = InstrumentedSqliteConnection::establish(":memory:").unwrap();
+//! conn.set_audit_sink(Arc::new(JsonLinesSink::new(std::io::stderr())));
+//! conn.batch_execute("CREATE TABLE users (id INTEGER)").unwrap();
+//! # }
+//! # #[cfg(not(feature = "sqlite"))]
+//! # fn main() {}
+//! ```
+use std::io::Write;
+use std::sync::{Arc, Mutex, PoisonError};
+use std::time::{Instant, SystemTime, UNIX_EPOCH};
+
+use diesel::result::Error;
+use serde::Serialize;
+use tracing::warn;
+
+use crate::error::error_kind;
+use crate::statement::{summarize_statement, AccessMode, StatementSummary};
+
+/// The audit record of one statement which writes.
+///
+/// Fields which are also recorded on spans are serialized under the same
+/// names, such as `db.system` and `db.sql.table`.
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct AuditEntry {
+    /// When the statement started, in milliseconds since the Unix epoch.
+    pub timestamp_ms: u64,
+    /// The database system, such as `postgresql`.
+    #[serde(rename = "db.system")]
+    pub db_system: &'static str,
+    /// The leading keyword of the statement, such as `INSERT`.
+    #[serde(rename = "db.operation")]
+    pub operation: Option<String>,
+    /// The primary table the statement operates on.
+    #[serde(rename = "db.sql.table")]
+    pub table: Option<String>,
+    /// The number of rows the statement changed, where the connection
+    /// reports it, which it does not for statements run through `load`.
+    #[serde(rename = "db.rows_affected")]
+    pub rows_affected: Option<usize>,
+    /// Whether the statement succeeded.
+    pub success: bool,
+    /// The kind of error the statement failed with, such as
+    /// `unique_violation`.
+    #[serde(rename = "db.error.kind")]
+    pub error_kind: Option<&'static str>,
+    /// How long the statement took, in milliseconds.
+    #[serde(rename = "db.duration_ms")]
+    pub duration_ms: f64,
+    /// The name of the connection, as recorded by `db.connection.name`.
+    #[serde(rename = "db.connection.name")]
+    pub connection_name: Option<String>,
+}
+
+/// Receives the audit entries of an instrumented connection.
+pub trait AuditSink: Send + Sync {
+    /// Record the entry of a statement which finished.
+    fn record(&self, entry: AuditEntry);
+}
+
+/// An [`AuditSink`] writing each entry to a writer as a line of JSON.
+///
+/// Entries which fail to be written are logged as warnings and dropped, so
+/// that auditing never fails a statement.
+#[derive(Debug)]
+pub struct JsonLinesSink<W> {
+    writer: Mutex<W>,
+}
+
+impl<W: Write> JsonLinesSink<W> {
+    /// Write entries to the given writer.
+    pub fn new(writer: W) -> Self {
+        JsonLinesSink {
+            writer: Mutex::new(writer),
+        }
+    }
+
+    /// Unwrap the writer.
+    pub fn into_inner(self) -> W {
+        self.writer
+            .into_inner()
+            .unwrap_or_else(PoisonError::into_inner)
+    }
+}
+
+impl<W: Write + Send> AuditSink for JsonLinesSink<W> {
+    fn record(&self, entry: AuditEntry) {
+        let mut writer = self.writer.lock().unwrap_or_else(PoisonError::into_inner);
+        let written = serde_json::to_writer(&mut *writer, &entry)
+            .map_err(std::io::Error::from)
+            .and_then(|()| writer.write_all(b"\n"));
+        if let Err(error) = written {
+            warn!(%error, "failed to write audit entry");
+        }
+    }
+}
+
+/// An audit entry for a statement which is running.
+pub(crate) struct PendingAudit {
+    sink: Arc<dyn AuditSink>,
+    db_system: &'static str,
+    connection_name: Option<String>,
+    summary: StatementSummary,
+    timestamp: SystemTime,
+    started: Instant,
+}
+
+impl PendingAudit {
+    /// Start auditing a statement, unless it does not write.
+    pub(crate) fn start(
+        sink: &Arc<dyn AuditSink>,
+        db_system: &'static str,
+        connection_name: Option<&str>,
+        sql: &str,
+    ) -> Option<Self> {
+        let summary = summarize_statement(sql);
+        (summary.access_mode == AccessMode::Write).then(|| PendingAudit {
+            sink: Arc::clone(sink),
+            db_system,
+            connection_name: connection_name.map(str::to_owned),
+            summary,
+            timestamp: SystemTime::now(),
+            started: Instant::now(),
+        })
+    }
+
+    /// Send the entry of the finished statement to the sink, with the number
+    /// of rows it changed if it succeeded and that is known.
+    pub(crate) fn finish(self, outcome: Result<Option<usize>, &Error>) {
+        let timestamp_ms = self
+            .timestamp
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |since| {
+                u64::try_from(since.as_millis()).unwrap_or(u64::MAX)
+            });
+        self.sink.record(AuditEntry {
+            timestamp_ms,
+            db_system: self.db_system,
+            operation: self.summary.operation,
+            table: self.summary.table,
+            rows_affected: outcome.ok().flatten(),
+            success: outcome.is_ok(),
+            error_kind: outcome.err().map(error_kind),
+            duration_ms: self.started.elapsed().as_secs_f64() * 1000.0,
+            connection_name: self.connection_name,
+        });
+    }
+}
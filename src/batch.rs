@@ -0,0 +1,166 @@
+use std::ops::Range;
+
+use diesel::result::Error;
+use tracing::Span;
+
+use crate::fingerprint::{dollar_tag, is_word_char, quoted_end};
+
+/// Statements longer than this are truncated in the per-statement events.
+#[cfg(feature = "statement-fields")]
+const PREVIEW_LEN: usize = 200;
+
+/// Split a batch of SQL statements on top-level semicolons, returning the
+/// byte range of each non-empty statement with surrounding whitespace
+/// trimmed.
+///
+/// Semicolons inside quoted strings and identifiers, dollar-quoted strings
+/// and comments do not end a statement.
+pub(crate) fn split_statements(sql: &str) -> Vec<Range<usize>> {
+    let mut statements = Vec::new();
+    let mut start = 0;
+    let mut chars = sql.char_indices().peekable();
+
+    while let Some((i, c)) = chars.next() {
+        let end = match c {
+            ';' => {
+                push_trimmed(&mut statements, sql, start..i);
+                start = i + 1;
+                continue;
+            }
+            '\'' | '"' | '`' => quoted_end(sql, i, c, false),
+            '-' if sql[i + 1..].starts_with('-') => {
+                sql[i..].find('\n').map_or(sql.len(), |n| i + n)
+            }
+            '/' if sql[i + 1..].starts_with('*') => {
+                sql[i + 2..].find("*/").map_or(sql.len(), |n| i + 2 + n + 2)
+            }
+            '$' if !sql[..i].ends_with(is_word_char) => match dollar_tag(&sql[i + 1..]) {
+                Some(tag_len) => {
+                    let tag = &sql[i..i + tag_len + 2];
+                    let body = i + tag.len();
+                    sql[body..]
+                        .find(tag)
+                        .map_or(sql.len(), |n| body + n + tag.len())
+                }
+                None => continue,
+            },
+            _ => continue,
+        };
+        while chars.next_if(|&(n, _)| n < end).is_some() {}
+    }
+    push_trimmed(&mut statements, sql, start..sql.len());
+
+    statements
+}
+
+fn push_trimmed(statements: &mut Vec<Range<usize>>, sql: &str, range: Range<usize>) {
+    let statement = &sql[range.clone()];
+    let trimmed = statement.trim_start();
+    let start = range.start + statement.len() - trimmed.len();
+    let end = start + trimmed.trim_end().len();
+    if start < end {
+        statements.push(start..end);
+    }
+}
+
+/// Record the number of statements in a batch on the current span and, with
+/// the `statement-fields` feature, emit a debug event previewing each one.
+pub(crate) fn record_batch(sql: &str) {
+    let span = Span::current();
+    if cfg!(feature = "passthrough") || span.is_disabled() {
+        return;
+    }
+
+    let statements = split_statements(sql);
+    span.record("db.batch.size", statements.len());
+    #[cfg(feature = "statement-fields")]
+    for (index, range) in statements.into_iter().enumerate() {
+        let statement = &sql[range];
+        let preview = match statement.char_indices().nth(PREVIEW_LEN) {
+            Some((end, _)) => &statement[..end],
+            None => statement,
+        };
+        tracing::debug!(
+            db.batch.index = index,
+            db.statement = preview,
+            "batch statement"
+        );
+    }
+}
+
+/// Record on the current span the index of the statement in a batch which
+/// failed, where the backend reports the position of the error.
+pub(crate) fn record_batch_error(sql: &str, error: &Error) {
+    let Error::DatabaseError(_, info) = error else {
+        return;
+    };
+    if cfg!(feature = "passthrough") {
+        return;
+    }
+    // the position is a 1-based character index into the batch
+    let Some(position) = info
+        .statement_position()
+        .and_then(|position| usize::try_from(position).ok())
+        .and_then(|position| position.checked_sub(1))
+        .and_then(|position| sql.char_indices().nth(position))
+        .map(|(offset, _)| offset)
+    else {
+        return;
+    };
+    if let Some(index) = split_statements(sql)
+        .iter()
+        .position(|range| position < range.end)
+    {
+        Span::current().record("db.batch.error_index", index);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn split(sql: &str) -> Vec<&str> {
+        split_statements(sql)
+            .into_iter()
+            .map(|range| &sql[range])
+            .collect()
+    }
+
+    #[test]
+    fn test_split_statements() {
+        let cases: &[(&str, &[&str])] = &[
+            ("SELECT 1; SELECT 2", &["SELECT 1", "SELECT 2"]),
+            ("SELECT 1;\n\nSELECT 2;\n", &["SELECT 1", "SELECT 2"]),
+            ("SELECT 1;;  ;", &["SELECT 1"]),
+            (
+                "INSERT INTO t VALUES ('a;b', 'it''s;'); SELECT \";\"",
+                &["INSERT INTO t VALUES ('a;b', 'it''s;')", "SELECT \";\""],
+            ),
+            (
+                "CREATE FUNCTION f() RETURNS int AS $$ SELECT 1; $$ LANGUAGE sql; \
+                 DO $body$ BEGIN PERFORM 1; END $body$",
+                &[
+                    "CREATE FUNCTION f() RETURNS int AS $$ SELECT 1; $$ LANGUAGE sql",
+                    "DO $body$ BEGIN PERFORM 1; END $body$",
+                ],
+            ),
+            (
+                "SELECT 1 -- one; two\n; /* three; */ SELECT `a;b`",
+                &["SELECT 1 -- one; two", "/* three; */ SELECT `a;b`"],
+            ),
+            (
+                "SELECT price$1; SELECT $1",
+                &["SELECT price$1", "SELECT $1"],
+            ),
+            (
+                "SELECT 'unterminated; SELECT 2",
+                &["SELECT 'unterminated; SELECT 2"],
+            ),
+            ("", &[]),
+        ];
+
+        for (sql, expected) in cases {
+            assert_eq!(&split(sql), expected, "split of {sql}");
+        }
+    }
+}
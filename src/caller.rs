@@ -0,0 +1,118 @@
+use std::sync::RwLock;
+
+use tracing::Span;
+
+/// Frames of functions under these paths are never reported as the caller.
+const SKIPPED_PREFIXES: &[&str] = &[
+    "diesel::",
+    "diesel_async::",
+    "diesel_tracing::",
+    "alloc::",
+    "core::",
+    "std::",
+    "backtrace::",
+    "tracing::",
+    "tracing_core::",
+];
+
+static EXTRA_SKIPPED_PREFIXES: RwLock<Vec<String>> = RwLock::new(Vec::new());
+
+/// Set further function path prefixes, such as `my_app::db::` for an
+/// application's own data access layer, whose frames are skipped when
+/// finding the caller recorded on query spans with the `caller-location`
+/// feature.
+///
+/// Frames in diesel, this crate and the standard library are always skipped.
+/// This replaces any prefixes set previously.
+pub fn set_caller_skip_prefixes<I, S>(prefixes: I)
+where
+    I: IntoIterator<Item = S>,
+    S: Into<String>,
+{
+    let prefixes = prefixes.into_iter().map(Into::into).collect();
+    *EXTRA_SKIPPED_PREFIXES
+        .write()
+        .unwrap_or_else(std::sync::PoisonError::into_inner) = prefixes;
+}
+
+fn is_skipped(function: &str, extra: &[String]) -> bool {
+    let skipped = |path: &str| {
+        let path = path
+            .trim_start_matches(['<', '&'])
+            .trim_start_matches("mut ");
+        SKIPPED_PREFIXES
+            .iter()
+            .copied()
+            .chain(extra.iter().map(String::as_str))
+            .any(|prefix| path.starts_with(prefix))
+    };
+
+    // trait methods are named like `<my_app::Repo as my_app::Dal>::find`, and
+    // are skipped if either the type or the trait is
+    match function.split_once(" as ") {
+        Some((self_type, trait_path)) => skipped(self_type) || skipped(trait_path),
+        None => skipped(function),
+    }
+}
+
+/// Record the first frame of the current backtrace outside the skipped
+/// prefixes on the span as `code.function`, `code.filepath` and
+/// `code.lineno`.
+pub(crate) fn record_caller(span: &Span) {
+    if span.is_disabled() {
+        return;
+    }
+
+    let extra = EXTRA_SKIPPED_PREFIXES
+        .read()
+        .unwrap_or_else(std::sync::PoisonError::into_inner);
+    let mut caller = None;
+    backtrace::trace(|frame| {
+        backtrace::resolve_frame(frame, |symbol| {
+            if caller.is_some() {
+                return;
+            }
+            let (Some(name), Some(filepath), Some(lineno)) =
+                (symbol.name(), symbol.filename(), symbol.lineno())
+            else {
+                return;
+            };
+            // the alternate format leaves off the symbol hash
+            let function = format!("{name:#}");
+            if !is_skipped(&function, &extra) {
+                caller = Some((function, filepath.display().to_string(), lineno));
+            }
+        });
+        caller.is_none()
+    });
+
+    if let Some((function, filepath, lineno)) = caller {
+        span.record("code.function", function.as_str());
+        span.record("code.filepath", filepath.as_str());
+        span.record("code.lineno", lineno);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_is_skipped() {
+        let extra = vec!["my_app::db::".to_string()];
+        for function in [
+            "diesel::query_dsl::RunQueryDsl::execute",
+            "<diesel_tracing::sqlite::InstrumentedSqliteConnection as diesel::connection::Connection>::execute_returning_count",
+            "<T as diesel::query_dsl::load_dsl::ExecuteDsl<Conn,DB>>::execute",
+            "<&mut F as core::ops::function::FnOnce<A>>::call_once",
+            "core::ops::function::FnOnce::call_once",
+            "my_app::db::find_user",
+            "<my_app::db::Repo as my_app::Dal>::find",
+        ] {
+            assert!(is_skipped(function, &extra), "{function} not skipped");
+        }
+        for function in ["my_app::handlers::get_user", "my_app::main::{{closure}}"] {
+            assert!(!is_skipped(function, &extra), "{function} skipped");
+        }
+    }
+}
@@ -0,0 +1,149 @@
+use diesel::backend::Backend;
+use diesel::connection::Connection;
+use diesel::query_builder::{InsertStatement, QueryFragment};
+use diesel::query_dsl::methods::ExecuteDsl;
+use diesel::query_dsl::LoadQuery;
+use diesel::result::{Error, QueryResult};
+use diesel::{Insertable, Table};
+use tracing::Span;
+
+use crate::statement::{count_bind_params, render_sql};
+
+/// A connection which can check the number of parameters bound by the next
+/// statement it runs against the limit of its backend, rejecting the
+/// statement before it is run.
+pub(crate) trait BindLimit {
+    /// Check the binds of the next statement run, or stop checking them.
+    fn check_binds(&mut self, check: bool);
+}
+
+/// Insert values in chunks of at most `chunk_size` rows, each run in the
+/// span returned by `chunk_span` for its index and size, recording the number
+/// of chunks and rows inserted on the current span, or on failure the number
+/// of rows inserted by the chunks before it.
+///
+/// The binds of the first chunk, which is the largest, are checked against
+/// the limit of the backend, so chunks binding too many parameters are
+/// rejected before any are inserted.
+pub(crate) fn insert_in_chunks<C, T, V>(
+    conn: &mut C,
+    table: T,
+    values: Vec<V>,
+    chunk_size: usize,
+    chunk_span: impl Fn(usize, usize) -> Span,
+) -> QueryResult<usize>
+where
+    C: Connection + BindLimit,
+    T: Table + Copy,
+    Vec<V>: Insertable<T>,
+    InsertStatement<T, <Vec<V> as Insertable<T>>::Values>: ExecuteDsl<C>,
+{
+    if chunk_size == 0 {
+        return Err(Error::QueryBuilderError(
+            "chunk size must be at least one".into(),
+        ));
+    }
+    let span = Span::current();
+    let chunks = values.len().div_ceil(chunk_size);
+
+    let mut values = values.into_iter();
+    let mut inserted = 0;
+    for index in 0..chunks {
+        let chunk: Vec<V> = values.by_ref().take(chunk_size).collect();
+        let size = chunk.len();
+        let statement = diesel::insert_into(table).values(chunk);
+        let chunk_span = if cfg!(feature = "passthrough") {
+            Span::none()
+        } else {
+            chunk_span(index, size)
+        };
+        conn.check_binds(index == 0);
+        let result = chunk_span.in_scope(|| ExecuteDsl::execute(statement, conn));
+        conn.check_binds(false);
+        match result {
+            Ok(count) => inserted += count,
+            Err(error) => {
+                span.record("db.batch.rows_committed", inserted);
+                return Err(error);
+            }
+        }
+        if index == 0 {
+            span.record("db.batch.chunks", chunks);
+        }
+    }
+    span.record("db.rows_affected", inserted);
+
+    Ok(inserted)
+}
+
+/// Reject a statement which binds more parameters than `max_binds`, counted
+/// from the placeholders of its rendered SQL.
+pub(crate) fn check_binds<DB, T>(source: &T, max_binds: usize) -> QueryResult<()>
+where
+    DB: Backend + Default,
+    DB::QueryBuilder: Default,
+    T: QueryFragment<DB> + ?Sized,
+{
+    let binds = render_sql::<DB, _>(source).map_or(0, |sql| count_bind_params(&sql));
+    if binds > max_binds {
+        return Err(Error::QueryBuilderError(
+            format!("chunks may bind {binds} parameters, more than the limit of {max_binds}")
+                .into(),
+        ));
+    }
+    Ok(())
+}
+
+/// Load the rows of a query through its cursor in chunks of at most
+/// `chunk_size`, passing each to `f` in the span returned by `chunk_span` for
+/// its index and size, and return the
+/// number of rows loaded. The number of chunks and rows are recorded on the
+/// current span, and an error from `f` stops the load.
+pub(crate) fn load_chunked<'conn, 'query, C, B, Q, U, F>(
+    conn: &'conn mut C,
+    query: Q,
+    chunk_size: usize,
+    chunk_span: impl Fn(usize, usize) -> Span,
+    mut f: F,
+) -> QueryResult<usize>
+where
+    C: Connection,
+    Q: LoadQuery<'query, C, U, B> + 'conn,
+    U: 'conn,
+    F: FnMut(Vec<U>) -> QueryResult<()>,
+{
+    if chunk_size == 0 {
+        return Err(Error::QueryBuilderError(
+            "chunk size must be at least one".into(),
+        ));
+    }
+    let span = Span::current();
+    let mut rows = query.load_iter::<U, B>(conn)?;
+    let mut chunks = 0;
+    let mut loaded = 0;
+    loop {
+        let chunk = rows
+            .by_ref()
+            .take(chunk_size)
+            .collect::<QueryResult<Vec<U>>>()?;
+        if chunk.is_empty() {
+            break;
+        }
+        let size = chunk.len();
+        let chunk_span = if cfg!(feature = "passthrough") {
+            Span::none()
+        } else {
+            chunk_span(chunks, size)
+        };
+        chunk_span.in_scope(|| f(chunk))?;
+        chunks += 1;
+        loaded += size;
+        span.record("db.batch.chunks", chunks);
+        span.record("db.batch.total_rows", loaded);
+        if size < chunk_size {
+            break;
+        }
+    }
+
+    Ok(loaded)
+}
@@ -0,0 +1,301 @@
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, PoisonError, RwLock};
+use std::time::Duration;
+
+use diesel::result::{DatabaseErrorKind, Error};
+use tracing::Level;
+
+/// The default maximum length of a recorded `db.statement`, in characters.
+pub const DEFAULT_MAX_STATEMENT_LENGTH: usize = 2048;
+
+static MAX_STATEMENT_LENGTH: AtomicUsize = AtomicUsize::new(DEFAULT_MAX_STATEMENT_LENGTH);
+
+/// Set the maximum length, in characters, of the `db.statement` field
+/// recorded with the `statement-fields` feature, for every connection which
+/// does not set its own [`TracingConfig::max_statement_length`].
+///
+/// Longer statements are truncated and marked with the length of the full
+/// statement. The default is [`DEFAULT_MAX_STATEMENT_LENGTH`].
+pub fn set_max_statement_length(max_statement_length: usize) {
+    MAX_STATEMENT_LENGTH.store(max_statement_length, Ordering::Relaxed);
+}
+
+/// Format the extra fields of a connection as the `db.extra` span field.
+#[cfg(any(feature = "mysql", feature = "postgres", feature = "sqlite"))]
+pub(crate) fn format_extra_fields(fields: Vec<(String, String)>) -> Option<String> {
+    if fields.is_empty() {
+        return None;
+    }
+
+    let formatted = fields
+        .into_iter()
+        .map(|(key, value)| format!("{key}={value}"))
+        .collect::<Vec<_>>()
+        .join(",");
+    Some(formatted)
+}
+
+/// Names the spans of an instrumented connection after the application
+/// operation they belong to, such as `checkout.reserve_stock`.
+///
+/// `tracing` fixes the name of a span where it is declared, so the spans keep
+/// their method names and a different name is recorded as `otel.name`, which
+/// `tracing-opentelemetry` exports as the span name.
+pub trait SpanNamer: Send + Sync + 'static {
+    /// The name of the span created for the given connection method, such as
+    /// `load` or `execute_returning_count`.
+    fn name_span(&self, db_method: &'static str) -> &'static str;
+}
+
+/// A [`SpanNamer`] which keeps the method name of every span, recording no
+/// `otel.name`.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct DefaultSpanNamer;
+
+impl SpanNamer for DefaultSpanNamer {
+    fn name_span(&self, db_method: &'static str) -> &'static str {
+        db_method
+    }
+}
+
+/// A [`SpanNamer`] which names the spans of queries and transactions after
+/// their kind rather than the method which created them, so that they can be
+/// told apart in trace search.
+///
+/// | method | name |
+/// |---|---|
+/// | `load`, `load_chunked` | `db.query` |
+/// | `execute_returning_count` | `db.execute` |
+/// | `batch_execute` | `db.batch_execute` |
+/// | `transaction`, `immediate_transaction`, `exclusive_transaction`, `pg_transaction` | `db.transaction` |
+/// | `savepoint` | `db.savepoint` |
+/// | `establish`, `establish_with_init`, `establish_with_config`, `establish_without_info` | `db.connect` |
+///
+/// The spans of other methods keep their method names. The `establish` spans
+/// are created before a connection exists to take a namer, so they are only
+/// renamed by the namer set with [`set_default_span_namer`].
+#[derive(Clone, Copy, Debug, Default)]
+pub struct OperationSpanNamer;
+
+impl SpanNamer for OperationSpanNamer {
+    fn name_span(&self, db_method: &'static str) -> &'static str {
+        match db_method {
+            "load" | "load_chunked" => "db.query",
+            "execute_returning_count" => "db.execute",
+            "batch_execute" => "db.batch_execute",
+            "transaction"
+            | "immediate_transaction"
+            | "exclusive_transaction"
+            | "pg_transaction" => "db.transaction",
+            "savepoint" => "db.savepoint",
+            "establish"
+            | "establish_with_init"
+            | "establish_with_config"
+            | "establish_without_info" => "db.connect",
+            _ => db_method,
+        }
+    }
+}
+
+static DEFAULT_SPAN_NAMER: RwLock<Option<Arc<dyn SpanNamer>>> = RwLock::new(None);
+
+/// Set the [`SpanNamer`] of every connection established afterwards which
+/// does not set its own with `with_span_namer`.
+///
+/// This namer also names the spans of the `establish` methods, which are
+/// created before a connection exists to take a namer of its own. This
+/// replaces any namer set previously.
+pub fn set_default_span_namer<N: SpanNamer>(namer: N) {
+    *DEFAULT_SPAN_NAMER
+        .write()
+        .unwrap_or_else(PoisonError::into_inner) = Some(Arc::new(namer));
+}
+
+/// The namer set with [`set_default_span_namer`], sharing it between
+/// connections.
+#[cfg(any(feature = "mysql", feature = "postgres", feature = "sqlite"))]
+struct SharedSpanNamer(Arc<dyn SpanNamer>);
+
+#[cfg(any(feature = "mysql", feature = "postgres", feature = "sqlite"))]
+impl SpanNamer for SharedSpanNamer {
+    fn name_span(&self, db_method: &'static str) -> &'static str {
+        self.0.name_span(db_method)
+    }
+}
+
+/// The namer of a newly established connection, which is the one set with
+/// [`set_default_span_namer`] or else [`DefaultSpanNamer`].
+#[cfg(any(feature = "mysql", feature = "postgres", feature = "sqlite"))]
+pub(crate) fn default_span_namer() -> Box<dyn SpanNamer> {
+    match DEFAULT_SPAN_NAMER
+        .read()
+        .unwrap_or_else(PoisonError::into_inner)
+        .clone()
+    {
+        Some(namer) => Box::new(SharedSpanNamer(namer)),
+        None => Box::new(DefaultSpanNamer),
+    }
+}
+
+/// The `otel.name` of the span created for an `establish` method, if the
+/// namer set with [`set_default_span_namer`] renames it.
+#[cfg(any(feature = "mysql", feature = "postgres", feature = "sqlite"))]
+#[cfg_attr(feature = "passthrough", allow(dead_code))]
+pub(crate) fn establish_span_name(db_method: &'static str) -> Option<&'static str> {
+    span_name(default_span_namer().as_ref(), db_method)
+}
+
+/// The `otel.name` of the span created for a connection method, if the namer
+/// renames it.
+#[cfg(any(feature = "mysql", feature = "postgres", feature = "sqlite"))]
+#[cfg_attr(feature = "passthrough", allow(dead_code))]
+pub(crate) fn span_name(namer: &dyn SpanNamer, db_method: &'static str) -> Option<&'static str> {
+    let name = namer.name_span(db_method);
+    (name != db_method).then_some(name)
+}
+
+/// Whether the span created for a connection method is named after its
+/// statement. A [`SpanNamer`] which renames the span takes precedence.
+#[cfg(any(feature = "mysql", feature = "postgres", feature = "sqlite"))]
+#[cfg_attr(feature = "passthrough", allow(dead_code))]
+pub(crate) fn name_by_statement(
+    config: TracingConfig,
+    namer: &dyn SpanNamer,
+    db_method: &'static str,
+) -> bool {
+    config.statement_span_names && span_name(namer, db_method).is_none()
+}
+
+/// The levels at which the error events of failed queries are emitted.
+///
+/// Some errors are an expected outcome of the query rather than a fault,
+/// such as a lookup finding no row or an upsert racing another writer, and
+/// logging them at `ERROR` drowns out real failures. The default emits
+/// [`Error::NotFound`] at `DEBUG`, unique violations at `WARN` and every
+/// other error at `ERROR`.
+///
+/// ```
+/// use diesel_tracing::{ErrorLevelPolicy, TracingConfig};
+///
+/// let config = TracingConfig {
+///     error_levels: ErrorLevelPolicy {
+///         not_found: tracing::Level::INFO,
+///         ..Default::default()
+///     },
+///     ..Default::default()
+/// };
+///
+/// assert_eq!(config.error_levels.unique_violation, tracing::Level::WARN);
+/// ```
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ErrorLevelPolicy {
+    /// The level of [`Error::NotFound`].
+    pub not_found: Level,
+    /// The level of a [`DatabaseErrorKind::UniqueViolation`].
+    pub unique_violation: Level,
+    /// The level of every other error.
+    pub other: Level,
+}
+
+impl Default for ErrorLevelPolicy {
+    fn default() -> Self {
+        ErrorLevelPolicy {
+            not_found: Level::DEBUG,
+            unique_violation: Level::WARN,
+            other: Level::ERROR,
+        }
+    }
+}
+
+impl ErrorLevelPolicy {
+    /// A policy emitting every error at `ERROR`.
+    #[must_use]
+    pub fn all_errors() -> Self {
+        ErrorLevelPolicy {
+            not_found: Level::ERROR,
+            unique_violation: Level::ERROR,
+            other: Level::ERROR,
+        }
+    }
+
+    /// The level at which the error event of `error` is emitted.
+    #[must_use]
+    pub fn level(&self, error: &Error) -> Level {
+        match error {
+            Error::NotFound => self.not_found,
+            Error::DatabaseError(DatabaseErrorKind::UniqueViolation, _) => self.unique_violation,
+            _ => self.other,
+        }
+    }
+}
+
+/// Runtime switches for the spans created by an instrumented connection.
+///
+/// Creating a span for every query has a measurable cost for workloads which
+/// issue a very large number of small queries. Disabling a kind of span here
+/// skips its construction entirely and calls straight through to the inner
+/// connection. The default traces everything.
+///
+/// ```
+/// let config = diesel_tracing::TracingConfig {
+///     trace_load: false,
+///     ..Default::default()
+/// };
+///
+/// assert!(config.trace_execute);
+/// ```
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[allow(clippy::struct_excessive_bools)]
+pub struct TracingConfig {
+    /// Create spans for `load`.
+    pub trace_load: bool,
+    /// Create spans for `execute_returning_count`.
+    pub trace_execute: bool,
+    /// Create spans for `batch_execute`, including the statements diesel
+    /// runs through it to begin and end transactions.
+    pub trace_batch: bool,
+    /// Create spans for `transaction`, for sqlite's `immediate_transaction`
+    /// and `exclusive_transaction`, and for the `transaction_state` and
+    /// `instrumentation` calls diesel makes while running them.
+    pub trace_transactions: bool,
+    /// The maximum length of a recorded `db.statement`, in characters,
+    /// overriding the global limit set by [`set_max_statement_length`].
+    pub max_statement_length: Option<usize>,
+    /// The duration after which a query is considered slow, and its plan
+    /// logged if explaining slow queries is enabled on the connection.
+    pub slow_query_threshold: Option<Duration>,
+    /// The levels at which the error events of failed queries are emitted.
+    pub error_levels: ErrorLevelPolicy,
+    /// Record the operation and table of each query as `otel.name`, such as
+    /// `SELECT users`, so that exported query spans are named after what
+    /// they do rather than after the connection method.
+    pub statement_span_names: bool,
+}
+
+impl Default for TracingConfig {
+    fn default() -> Self {
+        TracingConfig {
+            trace_load: true,
+            trace_execute: true,
+            trace_batch: true,
+            trace_transactions: true,
+            max_statement_length: None,
+            slow_query_threshold: None,
+            error_levels: ErrorLevelPolicy::default(),
+            statement_span_names: false,
+        }
+    }
+}
+
+impl TracingConfig {
+    /// The maximum length of a recorded `db.statement` for this
+    /// configuration.
+    #[cfg_attr(
+        any(not(feature = "statement-fields"), feature = "passthrough"),
+        allow(dead_code)
+    )]
+    pub(crate) fn max_statement_length(self) -> usize {
+        self.max_statement_length
+            .unwrap_or_else(|| MAX_STATEMENT_LENGTH.load(Ordering::Relaxed))
+    }
+}
@@ -0,0 +1,112 @@
+use std::sync::atomic::{AtomicU64, Ordering};
+
+static NEXT_SEQUENCE: AtomicU64 = AtomicU64::new(1);
+
+/// Identifier of a physical connection. A sequence number, unique within the
+/// process, is always recorded on spans as `db.connection.id`, and a random
+/// UUID is recorded as `db.connection_id` when the `connection-id` feature is
+/// enabled.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) struct ConnectionId {
+    sequence: u64,
+    #[cfg(feature = "connection-id")]
+    uuid: uuid::Uuid,
+}
+
+impl ConnectionId {
+    pub(crate) fn new() -> Self {
+        ConnectionId {
+            sequence: NEXT_SEQUENCE.fetch_add(1, Ordering::Relaxed),
+            #[cfg(feature = "connection-id")]
+            uuid: uuid::Uuid::new_v4(),
+        }
+    }
+
+    pub(crate) fn sequence(self) -> u64 {
+        self.sequence
+    }
+
+    // matches the signature without the feature, where the field is left empty
+    #[cfg(feature = "connection-id")]
+    #[allow(clippy::unnecessary_wraps)]
+    pub(crate) fn value(self) -> Option<tracing::field::DisplayValue<uuid::Uuid>> {
+        Some(tracing::field::display(self.uuid))
+    }
+
+    /// Without the `connection-id` feature the field is left empty.
+    #[cfg(not(feature = "connection-id"))]
+    #[allow(clippy::unused_self)]
+    pub(crate) fn value(self) -> Option<&'static str> {
+        None
+    }
+
+    /// Record the identifiers on the span of a newly established connection.
+    pub(crate) fn record(self, span: &tracing::Span) {
+        span.record("db.connection.id", self.sequence);
+        if let Some(value) = self.value() {
+            span.record("db.connection_id", value);
+        }
+    }
+}
+
+/// The span covering the lifetime of a connection, which logs that the
+/// connection was closed when it is dropped, along with how long it was open
+/// and the number of queries run on it.
+#[cfg(any(feature = "mysql", feature = "postgres", feature = "sqlite"))]
+#[derive(Debug)]
+pub(crate) struct ConnectionSpan {
+    span: tracing::Span,
+    opened: std::time::Instant,
+    queries: u64,
+}
+
+#[cfg(any(feature = "mysql", feature = "postgres", feature = "sqlite"))]
+impl ConnectionSpan {
+    pub(crate) fn new(span: tracing::Span) -> Self {
+        ConnectionSpan {
+            span,
+            opened: std::time::Instant::now(),
+            queries: 0,
+        }
+    }
+
+    /// Count a `load`, `execute_returning_count` or `batch_execute` run on
+    /// the connection.
+    pub(crate) fn count_query(&mut self) {
+        self.queries += 1;
+    }
+}
+
+#[cfg(any(feature = "mysql", feature = "postgres", feature = "sqlite"))]
+impl std::ops::Deref for ConnectionSpan {
+    type Target = tracing::Span;
+
+    fn deref(&self) -> &tracing::Span {
+        &self.span
+    }
+}
+
+// allows the span to be passed to `follows_from` in `tracing::instrument`
+#[cfg(any(feature = "mysql", feature = "postgres", feature = "sqlite"))]
+impl From<&ConnectionSpan> for Option<tracing::Id> {
+    fn from(span: &ConnectionSpan) -> Self {
+        span.span.id()
+    }
+}
+
+#[cfg(any(feature = "mysql", feature = "postgres", feature = "sqlite"))]
+impl Drop for ConnectionSpan {
+    fn drop(&mut self) {
+        if cfg!(feature = "passthrough") {
+            return;
+        }
+        let duration_ms = u64::try_from(self.opened.elapsed().as_millis()).unwrap_or(u64::MAX);
+        self.span.in_scope(|| {
+            tracing::debug!(
+                db.connection.queries = self.queries,
+                db.connection.duration_ms = duration_ms,
+                "connection closed"
+            );
+        });
+    }
+}
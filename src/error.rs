@@ -0,0 +1,89 @@
+use diesel::result::{DatabaseErrorKind, Error};
+use tracing::{field, Span};
+
+/// A short, low cardinality name for the kind of a diesel error.
+pub(crate) fn error_kind(error: &Error) -> &'static str {
+    match error {
+        Error::DatabaseError(kind, _) => match kind {
+            DatabaseErrorKind::UniqueViolation => "unique_violation",
+            DatabaseErrorKind::ForeignKeyViolation => "foreign_key_violation",
+            DatabaseErrorKind::UnableToSendCommand => "unable_to_send_command",
+            DatabaseErrorKind::SerializationFailure => "serialization_failure",
+            DatabaseErrorKind::ReadOnlyTransaction => "read_only_transaction",
+            DatabaseErrorKind::NotNullViolation => "not_null_violation",
+            DatabaseErrorKind::CheckViolation => "check_violation",
+            DatabaseErrorKind::ClosedConnection => "closed_connection",
+            _ => "database_error",
+        },
+        Error::NotFound => "not_found",
+        Error::InvalidCString(_) => "invalid_c_string",
+        Error::QueryBuilderError(_) => "query_builder_error",
+        Error::DeserializationError(_) => "deserialization_error",
+        Error::SerializationError(_) => "serialization_error",
+        Error::RollbackErrorOnCommit { .. } => "rollback_error_on_commit",
+        Error::RollbackTransaction => "rollback_transaction",
+        Error::AlreadyInTransaction => "already_in_transaction",
+        Error::NotInTransaction => "not_in_transaction",
+        Error::BrokenTransactionManager => "broken_transaction_manager",
+        _ => "unknown",
+    }
+}
+
+/// Mark a span as failed with `otel.status_code` and `otel.status_message`,
+/// which `tracing-opentelemetry` exports as the status of the span.
+pub(crate) fn record_status(span: &Span, error: &dyn std::fmt::Display) {
+    if cfg!(feature = "passthrough") {
+        return;
+    }
+    span.record("otel.status_code", "ERROR");
+    span.record("otel.status_message", field::display(error));
+}
+
+/// Record the kind of an error on a span, along with the table and
+/// constraint names where the backend provides them, and mark the span as
+/// failed.
+pub(crate) fn record_error(span: &Span, error: &Error) {
+    if cfg!(feature = "passthrough") {
+        return;
+    }
+    record_status(span, error);
+    span.record("db.error.kind", error_kind(error));
+    if let Error::DatabaseError(_, info) = error {
+        if let Some(table) = info.table_name() {
+            span.record("db.error.table", table);
+        }
+        if let Some(constraint) = info.constraint_name() {
+            span.record("db.error.constraint", constraint);
+        }
+    }
+}
+
+/// Emit the error event of a failed query at the level `levels` assigns to
+/// the error, in place of the `ERROR` event of `#[instrument(err)]`, followed
+/// by any further fields and message given.
+///
+/// The level and target of an event are fixed where it is declared, so each
+/// level has its own event and the target must be a literal.
+macro_rules! error_event {
+    ($target:literal, $levels:expr, $error:expr $(, $($fields:tt)+)?) => {
+        if !cfg!(feature = "passthrough") {
+            let error = $error;
+            match $levels.level(error) {
+                tracing::Level::ERROR => {
+                    tracing::error!(target: $target, error = %error $(, $($fields)+)?)
+                }
+                tracing::Level::WARN => {
+                    tracing::warn!(target: $target, error = %error $(, $($fields)+)?)
+                }
+                tracing::Level::INFO => {
+                    tracing::info!(target: $target, error = %error $(, $($fields)+)?)
+                }
+                tracing::Level::DEBUG => {
+                    tracing::debug!(target: $target, error = %error $(, $($fields)+)?)
+                }
+                _ => tracing::trace!(target: $target, error = %error $(, $($fields)+)?),
+            }
+        }
+    };
+}
+pub(crate) use error_event;
@@ -0,0 +1,99 @@
+use std::time::Duration;
+
+use diesel::backend::Backend;
+use diesel::query_builder::{AstPass, Query, QueryFragment, QueryId};
+use diesel::result::QueryResult;
+use diesel::sql_types::Untyped;
+use diesel::RunQueryDsl;
+use tracing::{debug, warn};
+
+use crate::statement::{summarize_statement, truncate_statement};
+
+/// Whether `EXPLAIN` may be run on a statement. Other statements, such as
+/// DDL, could have side effects or are rejected by `EXPLAIN`.
+pub(crate) fn is_explainable(sql: &str) -> bool {
+    matches!(
+        summarize_statement(sql).operation.as_deref(),
+        Some("SELECT" | "INSERT" | "UPDATE" | "DELETE")
+    )
+}
+
+/// Whether a query which took `elapsed` should be explained.
+pub(crate) fn is_slow(threshold: Option<Duration>, elapsed: Duration) -> bool {
+    threshold.is_some_and(|threshold| elapsed > threshold)
+}
+
+/// A statement prefixed with an `EXPLAIN` command, run with the statement's
+/// binds so the plan is for the same query.
+pub(crate) struct Explain<'a, T> {
+    command: &'static str,
+    statement: &'a T,
+}
+
+impl<'a, T> Explain<'a, T> {
+    pub(crate) fn new(command: &'static str, statement: &'a T) -> Self {
+        Explain { command, statement }
+    }
+}
+
+impl<DB, T> QueryFragment<DB> for Explain<'_, T>
+where
+    DB: Backend,
+    T: QueryFragment<DB>,
+{
+    fn walk_ast<'b>(&'b self, mut out: AstPass<'_, 'b, DB>) -> QueryResult<()> {
+        out.push_sql(self.command);
+        out.push_sql(" ");
+        self.statement.walk_ast(out.reborrow())
+    }
+}
+
+impl<T> QueryId for Explain<'_, T> {
+    type QueryId = ();
+
+    const HAS_STATIC_QUERY_ID: bool = false;
+}
+
+impl<T> Query for Explain<'_, T> {
+    type SqlType = Untyped;
+}
+
+impl<T, Conn> RunQueryDsl<Conn> for Explain<'_, T> {}
+
+/// Log the plan of a slow query as a warning, truncated like a statement, or
+/// that explaining it failed.
+pub(crate) fn record_plan(plan: QueryResult<String>, elapsed: Duration, max_length: usize) {
+    let duration_ms = u64::try_from(elapsed.as_millis()).unwrap_or(u64::MAX);
+    match plan {
+        Ok(plan) => warn!(
+            db.query_plan = truncate_statement(plan, max_length),
+            db.duration_ms = duration_ms,
+            "slow query plan"
+        ),
+        Err(error) => debug!(%error, db.duration_ms = duration_ms, "failed to explain slow query"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_is_explainable() {
+        assert!(is_explainable("SELECT * FROM users"));
+        assert!(is_explainable("update users set name = ?"));
+        assert!(is_explainable("INSERT INTO users VALUES (1)"));
+        assert!(is_explainable("DELETE FROM users"));
+        assert!(!is_explainable("CREATE TABLE users (id INTEGER)"));
+        assert!(!is_explainable("VACUUM"));
+        assert!(!is_explainable(""));
+    }
+
+    #[test]
+    fn test_is_slow() {
+        let threshold = Some(Duration::from_millis(10));
+        assert!(is_slow(threshold, Duration::from_millis(11)));
+        assert!(!is_slow(threshold, Duration::from_millis(10)));
+        assert!(!is_slow(None, Duration::from_secs(1)));
+    }
+}
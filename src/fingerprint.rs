@@ -0,0 +1,321 @@
+/// Normalize a SQL statement into a fingerprint which identifies its shape
+/// without any of the values it contains.
+///
+/// String, numeric, UUID and dollar-quoted literals and numbered bind
+/// parameters are replaced with `?`, comments are removed and whitespace is
+/// collapsed. Lists of two or more placeholders, such as the values of an
+/// `IN` clause, are collapsed to `?,+` so that statements differing only in
+/// the number of values share a fingerprint. Identifiers, including quoted
+/// identifiers, are kept as they are.
+///
+/// ```
+/// let fingerprint = diesel_tracing::fingerprint_sql(
+///     "SELECT * FROM users WHERE name = 'alice' AND id IN (1, 2, 3)",
+/// );
+///
+/// assert_eq!(fingerprint, "SELECT * FROM users WHERE name = ? AND id IN (?,+)");
+/// ```
+#[must_use]
+pub fn fingerprint_sql(sql: &str) -> String {
+    let mut fingerprint = String::with_capacity(sql.len());
+    let mut chars = sql.char_indices().peekable();
+    let mut space = false;
+
+    while let Some((start, c)) = chars.next() {
+        match c {
+            c if c.is_whitespace() => {
+                space = true;
+                continue;
+            }
+            '-' if sql[start + 1..].starts_with('-') => {
+                let end = sql[start..].find('\n').map_or(sql.len(), |i| start + i);
+                skip_to(&mut chars, end);
+                space = true;
+                continue;
+            }
+            '/' if sql[start + 1..].starts_with('*') => {
+                let end = sql[start + 2..]
+                    .find("*/")
+                    .map_or(sql.len(), |i| start + 2 + i + 2);
+                skip_to(&mut chars, end);
+                space = true;
+                continue;
+            }
+            _ => {}
+        }
+
+        if space && !fingerprint.is_empty() {
+            fingerprint.push(' ');
+        }
+        space = false;
+
+        match c {
+            '\'' => {
+                skip_to(&mut chars, quoted_end(sql, start, '\'', true));
+                fingerprint.push('?');
+            }
+            '"' | '`' => {
+                let end = quoted_end(sql, start, c, false);
+                skip_to(&mut chars, end);
+                fingerprint.push_str(&sql[start..end]);
+            }
+            '$' => {
+                let rest = &sql[start + 1..];
+                let digits = rest.bytes().take_while(u8::is_ascii_digit).count();
+                if digits > 0 {
+                    skip_to(&mut chars, start + 1 + digits);
+                    fingerprint.push('?');
+                } else if let Some(tag_len) = dollar_tag(rest) {
+                    let tag = &sql[start..start + tag_len + 2];
+                    let body = start + tag.len();
+                    let end = sql[body..]
+                        .find(tag)
+                        .map_or(sql.len(), |i| body + i + tag.len());
+                    skip_to(&mut chars, end);
+                    fingerprint.push('?');
+                } else {
+                    fingerprint.push('$');
+                }
+            }
+            '.' if !ends_with_word(&fingerprint)
+                && sql[start + 1..].starts_with(|c: char| c.is_ascii_digit()) =>
+            {
+                skip_to(&mut chars, number_end(sql, start + 1));
+                fingerprint.push('?');
+            }
+            c if c.is_ascii_hexdigit() && is_uuid(&sql[start..]) => {
+                skip_to(&mut chars, start + UUID_LEN);
+                fingerprint.push('?');
+            }
+            c if c.is_ascii_digit() => {
+                skip_to(&mut chars, number_end(sql, start));
+                fingerprint.push('?');
+            }
+            c if is_word_char(c) => {
+                let end = word_end(sql, start);
+                skip_to(&mut chars, end);
+                fingerprint.push_str(&sql[start..end]);
+            }
+            c => fingerprint.push(c),
+        }
+    }
+
+    collapse_lists(&fingerprint)
+}
+
+/// Hash a SQL statement into a stable 64-bit fingerprint, recorded as
+/// `db.statement.hash`.
+///
+/// This is the 64-bit FNV-1a hash of [`fingerprint_sql`], so statements which
+/// differ only in their literal values, bind placeholders, comments or
+/// whitespace hash identically. The hash does not depend on the process or
+/// platform, so it can be computed offline from a catalog of queries.
+///
+/// ```
+/// use diesel_tracing::statement_fingerprint;
+///
+/// assert_eq!(
+///     statement_fingerprint("SELECT * FROM users WHERE id = 1"),
+///     statement_fingerprint("SELECT *\n  FROM users\n  WHERE id = $1"),
+/// );
+/// ```
+#[must_use]
+pub fn statement_fingerprint(sql: &str) -> u64 {
+    const FNV_OFFSET_BASIS: u64 = 0xcbf2_9ce4_8422_2325;
+    const FNV_PRIME: u64 = 0x0100_0000_01b3;
+
+    fingerprint_sql(sql)
+        .bytes()
+        .fold(FNV_OFFSET_BASIS, |hash, byte| {
+            (hash ^ u64::from(byte)).wrapping_mul(FNV_PRIME)
+        })
+}
+
+const UUID_LEN: usize = 36;
+
+pub(crate) fn is_word_char(c: char) -> bool {
+    c.is_alphanumeric() || c == '_' || c == '$'
+}
+
+fn ends_with_word(fingerprint: &str) -> bool {
+    fingerprint
+        .chars()
+        .next_back()
+        .is_some_and(|c| is_word_char(c) || matches!(c, '"' | '`' | '?'))
+}
+
+/// Advance past every character starting before `end`.
+fn skip_to(chars: &mut std::iter::Peekable<std::str::CharIndices<'_>>, end: usize) {
+    while chars.next_if(|&(i, _)| i < end).is_some() {}
+}
+
+fn word_end(sql: &str, start: usize) -> usize {
+    sql[start..]
+        .find(|c: char| !is_word_char(c))
+        .map_or(sql.len(), |i| start + i)
+}
+
+/// The end of a numeric literal, including any fraction and signed exponent.
+fn number_end(sql: &str, start: usize) -> usize {
+    let mut end = word_end(sql, start);
+    if sql[end..].starts_with('.') {
+        end = word_end(sql, end + 1);
+    }
+    if sql[start..end].ends_with(['e', 'E']) && sql[end..].starts_with(['+', '-']) {
+        end = word_end(sql, end + 1);
+    }
+    end
+}
+
+/// The end of a quoted string or identifier opened at `start`, treating a
+/// doubled `close` and, if `backslash` is set, a backslash as escapes. An
+/// unterminated quote runs to the end of the statement.
+pub(crate) fn quoted_end(sql: &str, start: usize, close: char, backslash: bool) -> usize {
+    let mut chars = sql[start + 1..].char_indices().peekable();
+    while let Some((i, c)) = chars.next() {
+        if backslash && c == '\\' {
+            chars.next();
+        } else if c == close && chars.next_if(|&(_, next)| next == close).is_none() {
+            return start + 1 + i + c.len_utf8();
+        }
+    }
+    sql.len()
+}
+
+/// The length of the tag of a dollar-quoted string such as `$tag$...$tag$`,
+/// given the text following the opening `$`.
+pub(crate) fn dollar_tag(rest: &str) -> Option<usize> {
+    let len = rest
+        .find(|c: char| !(c.is_alphanumeric() || c == '_'))
+        .unwrap_or(rest.len());
+    rest[len..].starts_with('$').then_some(len)
+}
+
+fn is_uuid(sql: &str) -> bool {
+    let bytes = sql.as_bytes();
+    bytes.len() >= UUID_LEN
+        && bytes[..UUID_LEN].iter().enumerate().all(|(i, b)| match i {
+            8 | 13 | 18 | 23 => *b == b'-',
+            _ => b.is_ascii_hexdigit(),
+        })
+        && !sql[UUID_LEN..].starts_with(is_word_char)
+}
+
+/// Replace runs of two or more comma-separated placeholders with `?,+`.
+fn collapse_lists(fingerprint: &str) -> String {
+    let mut collapsed = String::with_capacity(fingerprint.len());
+    let mut rest = fingerprint;
+    while let Some(i) = rest.find('?') {
+        collapsed.push_str(&rest[..i]);
+        let mut end = i + 1;
+        while let Some(next) = rest[end..]
+            .trim_start()
+            .strip_prefix(',')
+            .and_then(|next| next.trim_start().strip_prefix('?'))
+        {
+            end = rest.len() - next.len();
+        }
+        collapsed.push_str(if end == i + 1 { "?" } else { "?,+" });
+        rest = &rest[end..];
+    }
+    collapsed.push_str(rest);
+    collapsed
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_fingerprint_sql() {
+        let cases = [
+            (
+                "SELECT * FROM users WHERE id = 42",
+                "SELECT * FROM users WHERE id = ?",
+            ),
+            (
+                "SELECT * FROM users WHERE name = 'O''Brien' AND score > 1.5e-3",
+                "SELECT * FROM users WHERE name = ? AND score > ?",
+            ),
+            (
+                "SELECT * FROM t WHERE note = 'it\\'s secret'",
+                "SELECT * FROM t WHERE note = ?",
+            ),
+            (
+                "SELECT * FROM t WHERE id = 123e4567-e89b-12d3-a456-426614174000",
+                "SELECT * FROM t WHERE id = ?",
+            ),
+            (
+                "SELECT * FROM t WHERE id IN ($1, $2, $3) AND x = $4",
+                "SELECT * FROM t WHERE id IN (?,+) AND x = ?",
+            ),
+            (
+                "INSERT INTO t (a, b) VALUES (?, ?), (?, ?)",
+                "INSERT INTO t (a, b) VALUES (?,+), (?,+)",
+            ),
+            (
+                r#"SELECT "col1", `t2`.`x3` FROM "t 1" -- id 7
+                /* 8 */ WHERE y = .5"#,
+                r#"SELECT "col1", `t2`.`x3` FROM "t 1" WHERE y = ?"#,
+            ),
+            ("SELECT $body$ secret $ body $body$, $$x$$", "SELECT ?,+"),
+            ("SELECT price$1 FROM t1", "SELECT price$1 FROM t1"),
+            ("  SELECT\n\t1  ", "SELECT ?"),
+            ("SELECT 'unterminated", "SELECT ?"),
+            ("", ""),
+        ];
+
+        for (sql, expected) in cases {
+            assert_eq!(fingerprint_sql(sql), expected, "fingerprint of {sql}");
+        }
+    }
+
+    #[test]
+    fn test_statement_fingerprint() {
+        let hash = statement_fingerprint("SELECT * FROM users WHERE name = 'alice' AND id = 1");
+        for sql in [
+            "SELECT * FROM users WHERE name = 'bob' AND id = 42",
+            "SELECT *\n\tFROM users   WHERE name = $1 AND id = $2",
+            "SELECT * FROM users WHERE name = ? AND id = ? -- by name",
+        ] {
+            assert_eq!(statement_fingerprint(sql), hash, "hash of {sql}");
+        }
+        assert_ne!(
+            statement_fingerprint("SELECT * FROM posts WHERE name = 'alice' AND id = 1"),
+            hash
+        );
+        // pinned so that the hash stays stable across releases
+        assert_eq!(statement_fingerprint(""), 0xcbf2_9ce4_8422_2325);
+        assert_eq!(
+            statement_fingerprint("SELECT 1"),
+            statement_fingerprint("SELECT ?")
+        );
+    }
+
+    #[test]
+    fn test_fingerprint_sql_does_not_panic() {
+        const ALPHABET: &[char] = &[
+            'a', 'e', 'E', 'f', '0', '1', '9', '-', '+', '.', ',', '?', '$', '\'', '"', '`', '\\',
+            '/', '*', '\n', ' ', '(', ')', 'é', '€', '𝄞',
+        ];
+
+        let mut state = 0x2545_f491_4f6c_dd1d_u64;
+        for _ in 0..20_000 {
+            let mut sql = String::new();
+            let len = state % 48;
+            for _ in 0..len {
+                state ^= state << 13;
+                state ^= state >> 7;
+                state ^= state << 17;
+                #[allow(clippy::cast_possible_truncation)]
+                sql.push(ALPHABET[(state % ALPHABET.len() as u64) as usize]);
+            }
+            state ^= state << 13;
+            state ^= state >> 7;
+            state ^= state << 17;
+
+            let fingerprint = fingerprint_sql(&sql);
+            assert_eq!(fingerprint_sql(&fingerprint), fingerprint, "{sql:?}");
+        }
+    }
+}
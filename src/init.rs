@@ -0,0 +1,44 @@
+use diesel::connection::SimpleConnection;
+use diesel::result::ConnectionError;
+use tracing::{debug, error};
+
+use crate::statement::summarize_statement;
+
+/// Run the session settings of a new connection in order, emitting an event
+/// for each with its index and operation, and failing with an error naming
+/// the first statement which fails.
+pub(crate) fn run_init_statements<C, S>(
+    conn: &mut C,
+    statements: &[S],
+) -> Result<(), ConnectionError>
+where
+    C: SimpleConnection,
+    S: AsRef<str>,
+{
+    for (index, statement) in statements.iter().enumerate() {
+        let statement = statement.as_ref();
+        let operation = summarize_statement(statement).operation;
+        if let Err(source) = conn.batch_execute(statement) {
+            if !cfg!(feature = "passthrough") {
+                error!(
+                    db.init.index = index,
+                    db.operation = operation.as_deref(),
+                    error = %source,
+                    "connection init statement failed",
+                );
+            }
+            return Err(ConnectionError::BadConnection(format!(
+                "connection init statement {index} ({statement}) failed: {source}"
+            )));
+        }
+        if !cfg!(feature = "passthrough") {
+            debug!(
+                db.init.index = index,
+                db.operation = operation.as_deref(),
+                "ran connection init statement",
+            );
+        }
+    }
+
+    Ok(())
+}
@@ -0,0 +1,942 @@
+/*!
+Reusable [`Instrumentation`] building blocks.
+
+Diesel connections hold a single instrumentation. The types in this module
+allow several instrumentations to be installed on one connection, for example
+`MetricsInstrumentation` from the `metrics` feature together with an
+application's own logging.
+*/
+use std::collections::HashMap;
+use std::num::NonZeroU32;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, MutexGuard, PoisonError};
+use std::time::{Duration, Instant};
+
+use diesel::backend::Backend;
+use diesel::connection::{
+    get_default_instrumentation, Connection, Instrumentation, InstrumentationEvent,
+};
+use diesel::query_builder::QueryFragment;
+use diesel::result::{DatabaseErrorKind, Error};
+use tracing::{debug, field, info_span, trace, warn, Span};
+
+use crate::fingerprint::{fingerprint_sql, statement_fingerprint};
+
+/// Forwards every event to two instrumentations, `first` then `second`.
+///
+/// Compositions can be nested to combine more than two instrumentations.
+///
+/// `first` is given a copy of each event, rebuilt from its fields. Events of
+/// a kind added by a newer diesel version than this crate knows of cannot be
+/// copied, so they are only given to `second`, and a debug event is logged.
+///
+/// ```
+/// use diesel::connection::InstrumentationEvent;
+/// use diesel_tracing::instrumentation::ComposedInstrumentation;
+///
+/// let instrumentation = ComposedInstrumentation::new(
+///     |event: InstrumentationEvent<'_>| println!("first: {event:?}"),
+///     |event: InstrumentationEvent<'_>| println!("second: {event:?}"),
+/// );
+/// ```
+#[derive(Debug, Default)]
+pub struct ComposedInstrumentation<A, B> {
+    first: A,
+    second: B,
+}
+
+impl<A, B> ComposedInstrumentation<A, B> {
+    pub fn new(first: A, second: B) -> Self {
+        ComposedInstrumentation { first, second }
+    }
+
+    /// Split the composition back into its parts.
+    pub fn into_inner(self) -> (A, B) {
+        (self.first, self.second)
+    }
+}
+
+impl<A, B> Instrumentation for ComposedInstrumentation<A, B>
+where
+    A: Instrumentation,
+    B: Instrumentation,
+{
+    fn on_connection_event(&mut self, event: InstrumentationEvent<'_>) {
+        if let Some(copy) = copy_event(&event) {
+            self.first.on_connection_event(copy);
+        }
+        self.second.on_connection_event(event);
+    }
+}
+
+/// Forwards every event to an ordered list of instrumentations, in the order
+/// they were pushed.
+///
+/// Calling `set_instrumentation` on an instrumented connection pushes onto
+/// its stack rather than replacing the instrumentation already installed,
+/// and `replace_instrumentation` starts a new stack.
+///
+/// Every instrumentation but the last is given a copy of each event, rebuilt
+/// from its fields. Events of a kind added by a newer diesel version than
+/// this crate knows of cannot be copied, so only the last instrumentation
+/// receives them, and a debug event is logged.
+///
+/// ```
+/// use diesel::connection::InstrumentationEvent;
+/// use diesel_tracing::instrumentation::{InstrumentationStack, TransactionSpanInstrumentation};
+///
+/// let mut stack = InstrumentationStack::new();
+/// stack.push(TransactionSpanInstrumentation::new());
+/// stack.push(|event: InstrumentationEvent<'_>| println!("{event:?}"));
+/// assert_eq!(stack.len(), 2);
+/// ```
+#[derive(Default)]
+pub struct InstrumentationStack {
+    layers: Vec<Box<dyn Instrumentation>>,
+}
+
+impl InstrumentationStack {
+    #[must_use]
+    pub fn new() -> Self {
+        InstrumentationStack::default()
+    }
+
+    /// Add an instrumentation, which receives events after those already on
+    /// the stack.
+    pub fn push(&mut self, instrumentation: impl Instrumentation) {
+        self.layers.push(Box::new(instrumentation));
+    }
+
+    /// The number of instrumentations on the stack.
+    #[must_use]
+    pub fn len(&self) -> usize {
+        self.layers.len()
+    }
+
+    #[must_use]
+    pub fn is_empty(&self) -> bool {
+        self.layers.is_empty()
+    }
+}
+
+impl std::fmt::Debug for InstrumentationStack {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("InstrumentationStack")
+            .field("layers", &self.layers.len())
+            .finish()
+    }
+}
+
+impl Instrumentation for InstrumentationStack {
+    fn on_connection_event(&mut self, event: InstrumentationEvent<'_>) {
+        if let Some((last, rest)) = self.layers.split_last_mut() {
+            for layer in rest {
+                let Some(copy) = copy_event(&event) else {
+                    break;
+                };
+                layer.on_connection_event(copy);
+            }
+            last.on_connection_event(event);
+        }
+    }
+}
+
+/// The [`InstrumentationStack`] of an instrumented connection, shared with
+/// the inner connection it is installed on so that more can be pushed.
+#[cfg_attr(
+    not(any(
+        feature = "mysql",
+        feature = "postgres",
+        feature = "sqlite",
+        feature = "async-mysql",
+        feature = "async-postgres"
+    )),
+    allow(dead_code)
+)]
+#[derive(Clone, Debug, Default)]
+pub(crate) struct SharedInstrumentationStack {
+    stack: Arc<Mutex<InstrumentationStack>>,
+    // the number of `CacheQuery` events seen, each a statement prepared and
+    // added to the inner connection's cache
+    statements_cached: Arc<AtomicU64>,
+    // the transaction being watched for exceeding its warning thresholds
+    transaction_watch: Arc<Mutex<Option<TransactionWatch>>>,
+}
+
+#[cfg_attr(
+    not(any(
+        feature = "mysql",
+        feature = "postgres",
+        feature = "sqlite",
+        feature = "async-mysql",
+        feature = "async-postgres"
+    )),
+    allow(dead_code)
+)]
+impl SharedInstrumentationStack {
+    /// A stack holding only `instrumentation`.
+    pub(crate) fn new(instrumentation: impl Instrumentation) -> Self {
+        let stack = SharedInstrumentationStack::default();
+        stack.lock().push(instrumentation);
+        stack
+    }
+
+    /// Push onto the stack in `slot`. If there is none yet, one is created
+    /// holding a new instance of the default instrumentation set with
+    /// `set_default_instrumentation`, and returned to be installed on the
+    /// inner connection.
+    pub(crate) fn push(
+        slot: &mut Option<Self>,
+        instrumentation: impl Instrumentation,
+    ) -> Option<Self> {
+        if let Some(stack) = slot {
+            stack.lock().push(instrumentation);
+            return None;
+        }
+        let stack = SharedInstrumentationStack::with_default();
+        stack.lock().push(instrumentation);
+        *slot = Some(stack.clone());
+        Some(stack)
+    }
+
+    /// Install a stack holding a new instance of the default instrumentation
+    /// on a newly established connection, so that `db.statement.cached` can
+    /// be recorded before any instrumentation is set. Nothing is installed
+    /// with the `passthrough` feature.
+    #[cfg_attr(
+        not(any(feature = "mysql", feature = "postgres", feature = "sqlite")),
+        allow(dead_code)
+    )]
+    pub(crate) fn install<C: Connection>(conn: &mut C) -> Option<Self> {
+        if cfg!(feature = "passthrough") {
+            return None;
+        }
+        let stack = SharedInstrumentationStack::with_default();
+        conn.set_instrumentation(stack.clone());
+        Some(stack)
+    }
+
+    /// Start watching the transaction which just began for exceeding
+    /// `thresholds`, unless they are unset or an enclosing transaction is
+    /// already watched. Returns whether it is watched.
+    #[cfg_attr(
+        not(any(feature = "mysql", feature = "postgres", feature = "sqlite")),
+        allow(dead_code)
+    )]
+    pub(crate) fn watch_transaction(&self, thresholds: TransactionWarnThresholds) -> bool {
+        if thresholds.is_disabled() {
+            return false;
+        }
+        let mut watch = self.lock_transaction_watch();
+        if watch.is_some() {
+            return false;
+        }
+        *watch = Some(TransactionWatch {
+            thresholds,
+            started: Instant::now(),
+            queries: 0,
+            warned: false,
+        });
+        true
+    }
+
+    /// Stop watching the transaction, returning how long it was open and the
+    /// number of queries run in it.
+    #[cfg_attr(
+        not(any(feature = "mysql", feature = "postgres", feature = "sqlite")),
+        allow(dead_code)
+    )]
+    pub(crate) fn finish_transaction(&self) -> Option<(Duration, usize)> {
+        self.lock_transaction_watch()
+            .take()
+            .map(|watch| (watch.started.elapsed(), watch.queries))
+    }
+
+    fn lock_transaction_watch(&self) -> MutexGuard<'_, Option<TransactionWatch>> {
+        self.transaction_watch
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+    }
+
+    fn with_default() -> Self {
+        let stack = SharedInstrumentationStack::default();
+        if let Some(default) = get_default_instrumentation() {
+            stack.lock().push(default);
+        }
+        stack
+    }
+
+    fn lock(&self) -> MutexGuard<'_, InstrumentationStack> {
+        self.stack.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+}
+
+impl Instrumentation for SharedInstrumentationStack {
+    fn on_connection_event(&mut self, event: InstrumentationEvent<'_>) {
+        match event {
+            InstrumentationEvent::CacheQuery { .. } => {
+                self.statements_cached.fetch_add(1, Ordering::Relaxed);
+            }
+            InstrumentationEvent::StartQuery { .. } => {
+                if let Some(watch) = self.lock_transaction_watch().as_mut() {
+                    watch.queries += 1;
+                    watch.check();
+                }
+            }
+            InstrumentationEvent::FinishQuery { .. } => {
+                if let Some(watch) = self.lock_transaction_watch().as_mut() {
+                    watch.check();
+                }
+            }
+            _ => {}
+        }
+        self.lock().on_connection_event(event);
+    }
+}
+
+/// How long a transaction may stay open, and how many queries it may run,
+/// before a warning is logged while it is still open.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub(crate) struct TransactionWarnThresholds {
+    pub(crate) after: Option<Duration>,
+    pub(crate) query_count: Option<usize>,
+}
+
+impl TransactionWarnThresholds {
+    fn is_disabled(self) -> bool {
+        self.after.is_none() && self.query_count.is_none()
+    }
+}
+
+#[derive(Debug)]
+struct TransactionWatch {
+    thresholds: TransactionWarnThresholds,
+    started: Instant,
+    queries: usize,
+    warned: bool,
+}
+
+impl TransactionWatch {
+    /// Warn, once, if the transaction has exceeded either threshold.
+    fn check(&mut self) {
+        if self.warned {
+            return;
+        }
+        let elapsed = self.started.elapsed();
+        let too_long = self.thresholds.after.is_some_and(|after| elapsed > after);
+        let too_many = self
+            .thresholds
+            .query_count
+            .is_some_and(|query_count| self.queries > query_count);
+        if !too_long && !too_many {
+            return;
+        }
+        self.warned = true;
+        warn!(
+            db.transaction.duration_ms = u64::try_from(elapsed.as_millis()).unwrap_or(u64::MAX),
+            db.transaction.queries = self.queries,
+            "transaction still open past its warning threshold"
+        );
+    }
+}
+
+/// Records `db.statement.cached` on the span of a query, whether the inner
+/// connection found its prepared statement in its cache.
+///
+/// Diesel emits a `CacheQuery` event when it prepares a statement and adds it
+/// to the cache, so a statement which may be cached was cached already if no
+/// such event fired while it ran. Statements which are never cached, such as
+/// those of `sql_query`, record nothing.
+#[cfg_attr(
+    not(any(feature = "mysql", feature = "postgres", feature = "sqlite")),
+    allow(dead_code)
+)]
+pub(crate) struct StatementCacheProbe {
+    statements_cached: Arc<AtomicU64>,
+    before: u64,
+}
+
+#[cfg_attr(
+    not(any(feature = "mysql", feature = "postgres", feature = "sqlite")),
+    allow(dead_code)
+)]
+impl StatementCacheProbe {
+    /// Start watching the cache for `source`, about to run on a connection
+    /// with `stack` installed. The statement is only walked if the current
+    /// span is recorded.
+    pub(crate) fn start<DB, T>(
+        stack: Option<&SharedInstrumentationStack>,
+        source: &T,
+        backend: &DB,
+    ) -> Option<Self>
+    where
+        DB: Backend,
+        T: QueryFragment<DB> + ?Sized,
+    {
+        let stack = stack?;
+        if Span::current().is_disabled()
+            || !source.is_safe_to_cache_prepared(backend).unwrap_or(false)
+        {
+            return None;
+        }
+        Some(StatementCacheProbe {
+            statements_cached: Arc::clone(&stack.statements_cached),
+            before: stack.statements_cached.load(Ordering::Relaxed),
+        })
+    }
+
+    pub(crate) fn record(self, span: &Span) {
+        let cached = self.statements_cached.load(Ordering::Relaxed) == self.before;
+        span.record("db.statement.cached", cached);
+    }
+}
+
+/// Opens a span covering each transaction, from its `BeginTransaction` event
+/// until the matching `CommitTransaction` or `RollbackTransaction`.
+///
+/// The span is entered while the transaction is open, so the spans and events
+/// of the queries run inside it are nested beneath it. Outermost transactions
+/// are named `db.transaction` and nested savepoints `db.savepoint`, and both
+/// record `db.transaction_depth`. When the transaction finishes its span
+/// records `db.transaction.outcome` as `commit` or `rollback` and
+/// `db.transaction.duration_ms`, and a warning is logged if it was open for
+/// longer than the threshold set with [`Self::with_warn_threshold`].
+///
+/// Combine this with other instrumentations using
+/// [`ComposedInstrumentation`].
+///
+/// ```
+/// use diesel::connection::Connection;
+/// use diesel::sqlite::SqliteConnection;
+/// use diesel_tracing::instrumentation::TransactionSpanInstrumentation;
+///
+/// let mut conn = SqliteConnection::establish(":memory:").unwrap();
+/// conn.set_instrumentation(TransactionSpanInstrumentation::new());
+/// ```
+#[derive(Debug, Default)]
+pub struct TransactionSpanInstrumentation {
+    transaction_spans: HashMap<NonZeroU32, (Span, Instant)>,
+    warn_threshold: Option<Duration>,
+}
+
+impl TransactionSpanInstrumentation {
+    #[must_use]
+    pub fn new() -> Self {
+        TransactionSpanInstrumentation::default()
+    }
+
+    /// Log a warning for each transaction or savepoint which stays open for
+    /// longer than `threshold`.
+    #[must_use]
+    pub fn with_warn_threshold(mut self, threshold: Duration) -> Self {
+        self.warn_threshold = Some(threshold);
+        self
+    }
+
+    fn begin(&mut self, depth: NonZeroU32) {
+        // a failed BEGIN is not followed by a commit or rollback, so close
+        // anything left open at or above this depth first
+        self.close_from(depth);
+
+        let span = if depth.get() == 1 {
+            info_span!(
+                "db.transaction",
+                db.transaction_depth = depth.get(),
+                db.transaction.outcome = field::Empty,
+                db.transaction.duration_ms = field::Empty,
+            )
+        } else {
+            info_span!(
+                "db.savepoint",
+                db.transaction_depth = depth.get(),
+                db.transaction.outcome = field::Empty,
+                db.transaction.duration_ms = field::Empty,
+            )
+        };
+        span.with_subscriber(|(id, dispatch)| dispatch.enter(id));
+        self.transaction_spans.insert(depth, (span, Instant::now()));
+    }
+
+    /// Record how the transaction at `depth` finished, then close it along
+    /// with anything still open inside it.
+    fn finish(&mut self, depth: NonZeroU32, outcome: &'static str) {
+        if let Some((span, started)) = self.transaction_spans.get(&depth) {
+            let elapsed = started.elapsed();
+            let duration_ms = u64::try_from(elapsed.as_millis()).unwrap_or(u64::MAX);
+            span.record("db.transaction.outcome", outcome);
+            span.record("db.transaction.duration_ms", duration_ms);
+            if self
+                .warn_threshold
+                .is_some_and(|threshold| elapsed > threshold)
+            {
+                warn!(
+                    parent: span,
+                    db.transaction_depth = depth.get(),
+                    db.transaction.duration_ms = duration_ms,
+                    "long running transaction"
+                );
+            }
+        }
+        self.close_from(depth);
+    }
+
+    /// Exit and close the spans at `depth` and deeper, innermost first.
+    fn close_from(&mut self, depth: NonZeroU32) {
+        let mut open: Vec<_> = self
+            .transaction_spans
+            .keys()
+            .copied()
+            .filter(|open| *open >= depth)
+            .collect();
+        open.sort_unstable_by(|a, b| b.cmp(a));
+        for depth in open {
+            if let Some((span, _)) = self.transaction_spans.remove(&depth) {
+                span.with_subscriber(|(id, dispatch)| dispatch.exit(id));
+            }
+        }
+    }
+}
+
+impl Instrumentation for TransactionSpanInstrumentation {
+    fn on_connection_event(&mut self, event: InstrumentationEvent<'_>) {
+        match event {
+            InstrumentationEvent::BeginTransaction { depth, .. } => self.begin(depth),
+            InstrumentationEvent::CommitTransaction { depth, .. } => self.finish(depth, "commit"),
+            InstrumentationEvent::RollbackTransaction { depth, .. } => {
+                self.finish(depth, "rollback");
+            }
+            // no commit or rollback follows once the connection is lost
+            InstrumentationEvent::FinishQuery {
+                error: Some(Error::DatabaseError(DatabaseErrorKind::ClosedConnection, _)),
+                ..
+            } => self.close_from(NonZeroU32::MIN),
+            _ => {}
+        }
+    }
+}
+
+/// A snapshot of the counters of a [`QueryCacheStatsInstrumentation`].
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct QueryCacheStats {
+    /// The number of statements added to the prepared statement cache.
+    pub statements_cached: u64,
+    /// The number of queries started, whether or not their statement was
+    /// cached.
+    pub queries_started: u64,
+    /// The number of queries which finished with an error.
+    pub queries_failed: u64,
+}
+
+impl QueryCacheStats {
+    /// The fraction of started queries which prepared and cached a new
+    /// statement, rather than reusing a cached one or not being cacheable.
+    #[must_use]
+    #[allow(clippy::cast_precision_loss)]
+    pub fn miss_ratio(&self) -> f64 {
+        if self.queries_started == 0 {
+            return 0.0;
+        }
+        self.statements_cached as f64 / self.queries_started as f64
+    }
+}
+
+/// The number of started queries between the events logged by a
+/// [`QueryCacheStatsInstrumentation`], unless changed with
+/// [`QueryCacheStatsInstrumentation::with_report_interval`].
+pub const DEFAULT_REPORT_INTERVAL: u64 = 1000;
+
+#[derive(Debug, Default)]
+struct QueryCacheCounters {
+    statements_cached: AtomicU64,
+    queries_started: AtomicU64,
+    queries_failed: AtomicU64,
+}
+
+/// Counts the statements added to a connection's prepared statement cache
+/// against the queries it starts, to help size the cache.
+///
+/// Clones share their counters, so a clone kept by the application reports
+/// the statistics of the one installed on the connection. A debug event
+/// recording the counters and their `miss_ratio` is logged every
+/// [`DEFAULT_REPORT_INTERVAL`] started queries.
+///
+/// Each statement added to the cache logs a trace event recording its
+/// `db.statement_fingerprint` and `db.statement.hash`, so that dynamic SQL
+/// which is cached over and over in slightly different forms stands out. A
+/// warning is logged once the number of cached statements exceeds the limit
+/// set with [`QueryCacheStatsInstrumentation::with_soft_limit`], as either the
+/// cache is too small or the queries are not parameterized. diesel reports
+/// neither lookups nor evictions, so the count is of the statements ever
+/// added to the cache.
+///
+/// ```
+/// use diesel::connection::{Connection, SimpleConnection};
+/// use diesel::sqlite::SqliteConnection;
+/// use diesel_tracing::instrumentation::QueryCacheStatsInstrumentation;
+///
+/// let stats = QueryCacheStatsInstrumentation::new();
+/// let mut conn = SqliteConnection::establish(":memory:")?;
+/// conn.set_instrumentation(stats.clone());
+/// conn.batch_execute("SELECT 1")?;
+/// assert_eq!(stats.stats().queries_started, 1);
+/// # Ok::<(), Box<dyn std::error::Error>>(())
+/// ```
+#[derive(Clone, Debug)]
+pub struct QueryCacheStatsInstrumentation {
+    counters: Arc<QueryCacheCounters>,
+    report_interval: u64,
+    soft_limit: Option<u64>,
+}
+
+impl Default for QueryCacheStatsInstrumentation {
+    fn default() -> Self {
+        QueryCacheStatsInstrumentation {
+            counters: Arc::default(),
+            report_interval: DEFAULT_REPORT_INTERVAL,
+            soft_limit: None,
+        }
+    }
+}
+
+impl QueryCacheStatsInstrumentation {
+    #[must_use]
+    pub fn new() -> Self {
+        QueryCacheStatsInstrumentation::default()
+    }
+
+    /// Log the counters every `report_interval` started queries, `0` disables
+    /// the events.
+    #[must_use]
+    pub fn with_report_interval(mut self, report_interval: u64) -> Self {
+        self.report_interval = report_interval;
+        self
+    }
+
+    /// Log a warning when the number of cached statements first exceeds
+    /// `soft_limit`.
+    #[must_use]
+    pub fn with_soft_limit(mut self, soft_limit: u64) -> Self {
+        self.soft_limit = Some(soft_limit);
+        self
+    }
+
+    /// The current values of the counters.
+    #[must_use]
+    pub fn stats(&self) -> QueryCacheStats {
+        QueryCacheStats {
+            statements_cached: self.counters.statements_cached.load(Ordering::Relaxed),
+            queries_started: self.counters.queries_started.load(Ordering::Relaxed),
+            queries_failed: self.counters.queries_failed.load(Ordering::Relaxed),
+        }
+    }
+
+    fn report(&self) {
+        let stats = self.stats();
+        debug!(
+            db.statement_cache.statements_cached = stats.statements_cached,
+            db.statement_cache.queries_started = stats.queries_started,
+            db.statement_cache.queries_failed = stats.queries_failed,
+            db.statement_cache.miss_ratio = stats.miss_ratio(),
+            "prepared statement cache statistics"
+        );
+    }
+}
+
+impl Instrumentation for QueryCacheStatsInstrumentation {
+    fn on_connection_event(&mut self, event: InstrumentationEvent<'_>) {
+        match event {
+            InstrumentationEvent::StartQuery { .. } => {
+                let started = self
+                    .counters
+                    .queries_started
+                    .fetch_add(1, Ordering::Relaxed)
+                    + 1;
+                if self.report_interval > 0 && started.is_multiple_of(self.report_interval) {
+                    self.report();
+                }
+            }
+            InstrumentationEvent::CacheQuery { sql, .. } => {
+                let cached = self
+                    .counters
+                    .statements_cached
+                    .fetch_add(1, Ordering::Relaxed)
+                    + 1;
+                trace!(
+                    db.statement_fingerprint = fingerprint_sql(sql),
+                    db.statement.hash = statement_fingerprint(sql),
+                    db.statement_cache.statements_cached = cached,
+                    "statement cached"
+                );
+                if self.soft_limit.is_some_and(|limit| cached == limit + 1) {
+                    warn!(
+                        db.statement_cache.statements_cached = cached,
+                        db.statement_cache.soft_limit = self.soft_limit,
+                        "prepared statement cache exceeds its soft limit, the cache may be too \
+                         small or queries may not be parameterized"
+                    );
+                }
+            }
+            InstrumentationEvent::FinishQuery { error: Some(_), .. } => {
+                self.counters.queries_failed.fetch_add(1, Ordering::Relaxed);
+            }
+            _ => {}
+        }
+    }
+}
+
+/// Rebuild an event so it can be delivered more than once.
+///
+/// `InstrumentationEvent` is neither `Clone` nor exhaustive, so variants added
+/// by future diesel versions cannot be copied, which is logged as a debug
+/// event, and `None` is returned.
+pub(crate) fn copy_event<'a>(event: &InstrumentationEvent<'a>) -> Option<InstrumentationEvent<'a>> {
+    let copy = match *event {
+        InstrumentationEvent::StartEstablishConnection { url, .. } => {
+            InstrumentationEvent::start_establish_connection(url)
+        }
+        InstrumentationEvent::FinishEstablishConnection { url, error, .. } => {
+            InstrumentationEvent::finish_establish_connection(url, error)
+        }
+        InstrumentationEvent::StartQuery { query, .. } => InstrumentationEvent::start_query(query),
+        InstrumentationEvent::CacheQuery { sql, .. } => InstrumentationEvent::cache_query(sql),
+        InstrumentationEvent::FinishQuery { query, error, .. } => {
+            InstrumentationEvent::finish_query(query, error)
+        }
+        InstrumentationEvent::BeginTransaction { depth, .. } => {
+            InstrumentationEvent::begin_transaction(depth)
+        }
+        InstrumentationEvent::CommitTransaction { depth, .. } => {
+            InstrumentationEvent::commit_transaction(depth)
+        }
+        InstrumentationEvent::RollbackTransaction { depth, .. } => {
+            InstrumentationEvent::rollback_transaction(depth)
+        }
+        _ => {
+            debug!(
+                ?event,
+                "instrumentation event cannot be copied, skipping all but the last instrumentation"
+            );
+            return None;
+        }
+    };
+
+    Some(copy)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::EventRecorder;
+    use diesel::connection::{Connection, SimpleConnection};
+    use diesel::sql_types::Integer;
+    use diesel::sqlite::SqliteConnection;
+    use diesel::{IntoSql, RunQueryDsl};
+    use std::sync::{Arc, Mutex};
+
+    #[test]
+    fn test_composed_instrumentation_forwards_to_both() {
+        let seen = Arc::new(Mutex::new(Vec::new()));
+        let recorder = |name: &'static str| {
+            let seen = Arc::clone(&seen);
+            move |event: InstrumentationEvent<'_>| {
+                if let InstrumentationEvent::StartQuery { .. } = event {
+                    seen.lock().unwrap().push(name);
+                }
+            }
+        };
+
+        let mut conn = SqliteConnection::establish(":memory:").unwrap();
+        conn.set_instrumentation(ComposedInstrumentation::new(
+            recorder("first"),
+            recorder("second"),
+        ));
+        conn.batch_execute("SELECT 1").unwrap();
+
+        assert_eq!(*seen.lock().unwrap(), vec!["first", "second"]);
+    }
+
+    #[test]
+    fn test_instrumentation_stack_forwards_in_order() {
+        let seen = Arc::new(Mutex::new(Vec::new()));
+        let recorder = |name: &'static str| {
+            let seen = Arc::clone(&seen);
+            move |event: InstrumentationEvent<'_>| {
+                if let InstrumentationEvent::StartQuery { .. } = event {
+                    seen.lock().unwrap().push(name);
+                }
+            }
+        };
+
+        let mut stack = InstrumentationStack::new();
+        for name in ["first", "second", "third"] {
+            stack.push(recorder(name));
+        }
+        let mut conn = SqliteConnection::establish(":memory:").unwrap();
+        conn.set_instrumentation(stack);
+        conn.batch_execute("SELECT 1").unwrap();
+
+        assert_eq!(*seen.lock().unwrap(), vec!["first", "second", "third"]);
+    }
+
+    #[test]
+    fn test_transaction_spans_cover_transactions() {
+        let recorder = EventRecorder::default();
+        tracing::subscriber::with_default(recorder.clone(), || {
+            let mut conn = SqliteConnection::establish(":memory:").unwrap();
+            conn.set_instrumentation(TransactionSpanInstrumentation::new());
+            conn.transaction(|conn| {
+                let outer = tracing::Span::current().metadata().unwrap().name();
+                let inner = conn.transaction(|_| {
+                    Ok::<_, diesel::result::Error>(
+                        tracing::Span::current().metadata().unwrap().name(),
+                    )
+                })?;
+                Ok::<_, diesel::result::Error>((outer, inner))
+            })
+            .map(|names| assert_eq!(names, ("db.transaction", "db.savepoint")))
+            .unwrap();
+            assert!(tracing::Span::current().is_none());
+        });
+
+        let transaction = recorder.span("db.transaction").unwrap();
+        let savepoint = recorder.span("db.savepoint").unwrap();
+        assert_eq!(transaction.fields["db.transaction_depth"], "1");
+        assert_eq!(savepoint.fields["db.transaction_depth"], "2");
+        assert_eq!(transaction.fields["db.transaction.outcome"], "commit");
+        assert_eq!(savepoint.fields["db.transaction.outcome"], "commit");
+    }
+
+    #[test]
+    fn test_transaction_duration_recorded() {
+        let recorder = EventRecorder::default();
+        tracing::subscriber::with_default(recorder.clone(), || {
+            let mut conn = SqliteConnection::establish(":memory:").unwrap();
+            conn.register_sql_function::<(Integer,), Integer, _, _, _>(
+                "sleep_ms",
+                false,
+                |(ms,): (i32,)| {
+                    std::thread::sleep(Duration::from_millis(ms.unsigned_abs().into()));
+                    ms
+                },
+            )
+            .unwrap();
+            conn.set_instrumentation(
+                TransactionSpanInstrumentation::new()
+                    .with_warn_threshold(Duration::from_millis(10)),
+            );
+            conn.transaction(|conn| {
+                conn.batch_execute("SELECT sleep_ms(20)")?;
+                Err::<(), _>(diesel::result::Error::RollbackTransaction)
+            })
+            .unwrap_err();
+        });
+
+        let transaction = recorder.span("db.transaction").unwrap();
+        assert_eq!(transaction.fields["db.transaction.outcome"], "rollback");
+        let duration_ms: u64 = transaction.fields["db.transaction.duration_ms"]
+            .parse()
+            .unwrap();
+        assert!(duration_ms >= 20);
+        assert!(recorder
+            .events()
+            .iter()
+            .any(|event| event["message"] == "long running transaction"));
+    }
+
+    #[test]
+    fn test_query_cache_stats() {
+        let recorder = EventRecorder::default();
+        let stats = QueryCacheStatsInstrumentation::new().with_report_interval(5);
+        tracing::subscriber::with_default(recorder.clone(), || {
+            let mut conn = SqliteConnection::establish(":memory:").unwrap();
+            conn.set_instrumentation(stats.clone());
+            for n in 0..5 {
+                let result: i32 = diesel::select(n.into_sql::<Integer>())
+                    .get_result(&mut conn)
+                    .unwrap();
+                assert_eq!(result, n);
+            }
+        });
+
+        assert_eq!(
+            stats.stats(),
+            QueryCacheStats {
+                statements_cached: 1,
+                queries_started: 5,
+                queries_failed: 0,
+            }
+        );
+        let report = recorder
+            .events()
+            .into_iter()
+            .find(|event| {
+                event
+                    .get("message")
+                    .is_some_and(|message| message == "prepared statement cache statistics")
+            })
+            .unwrap();
+        assert_eq!(report["db.statement_cache.miss_ratio"], "0.2");
+    }
+
+    #[test]
+    fn test_query_cache_soft_limit() {
+        use diesel::{ExpressionMethods, QueryDsl};
+
+        diesel::table! {
+            items (id) {
+                id -> Integer,
+            }
+        }
+
+        let recorder = EventRecorder::default();
+        let stats = QueryCacheStatsInstrumentation::new().with_soft_limit(20);
+        tracing::subscriber::with_default(recorder.clone(), || {
+            let mut conn = SqliteConnection::establish(":memory:").unwrap();
+            conn.batch_execute("CREATE TABLE items (id INTEGER PRIMARY KEY)")
+                .unwrap();
+            conn.set_instrumentation(stats.clone());
+            // boxed queries are cached by their sql, so each number of
+            // filters caches a new statement
+            for n in 1..=50 {
+                let query = (0..n)
+                    .fold(items::table.select(items::id).into_boxed(), |query, id| {
+                        query.or_filter(items::id.eq(id))
+                    });
+                query.load::<i32>(&mut conn).unwrap();
+            }
+        });
+
+        assert_eq!(stats.stats().statements_cached, 50);
+        let events = recorder.events();
+        let with_message = |message: &str| {
+            events
+                .iter()
+                .filter(|event| event.get("message").is_some_and(|m| m == message))
+                .collect::<Vec<_>>()
+        };
+        let warnings = with_message(
+            "prepared statement cache exceeds its soft limit, the cache may be too small or \
+             queries may not be parameterized",
+        );
+        assert_eq!(warnings.len(), 1);
+        assert_eq!(warnings[0]["level"], "WARN");
+        assert_eq!(warnings[0]["db.statement_cache.statements_cached"], "21");
+        let cached = with_message("statement cached");
+        assert_eq!(cached.len(), 50);
+        assert_eq!(
+            cached[1]["db.statement_fingerprint"],
+            "SELECT `items`.`id` FROM `items` WHERE ((`items`.`id` = ?) OR (`items`.`id` = ?))"
+        );
+    }
+
+    #[test]
+    fn test_mismatched_transaction_depths() {
+        let mut instrumentation = TransactionSpanInstrumentation::new();
+        let depth = |depth| NonZeroU32::new(depth).unwrap();
+        instrumentation.on_connection_event(InstrumentationEvent::begin_transaction(depth(1)));
+        instrumentation.on_connection_event(InstrumentationEvent::rollback_transaction(depth(3)));
+        instrumentation.on_connection_event(InstrumentationEvent::commit_transaction(depth(1)));
+        instrumentation.on_connection_event(InstrumentationEvent::commit_transaction(depth(1)));
+        assert!(instrumentation.transaction_spans.is_empty());
+    }
+}
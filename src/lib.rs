@@ -15,6 +15,10 @@ database driver to support. Just as in diesel configure this in your
 diesel-tracing = { version = "<version>", features = ["<postgres|mysql|sqlite>"] }
 ```
 
+The `passthrough` feature compiles out all spans, events and field recording
+while keeping the same API, so the instrumented connections can stay in place
+in builds where tracing is unwanted.
+
 # Establishing a connection
 
 `diesel-tracing` has several instrumented connection structs that wrap the underlying
@@ -25,6 +29,7 @@ the `diesel` crate. For example, with the `postgres` feature flag:
 ```
 #[cfg(feature = "postgres")]
 {
+    use diesel::Connection;
     use diesel_tracing::pg::InstrumentedPgConnection;
 
     let conn = InstrumentedPgConnection::establish("postgresql://example");
@@ -34,6 +39,25 @@ the `diesel` crate. For example, with the `postgres` feature flag:
 This connection can then be used with diesel dsl methods such as
 `diesel::prelude::RunQueryDsl::execute` or `diesel::prelude::RunQueryDsl::get_results`.
 
+An already established diesel connection can be wrapped with `From`, and the
+diesel connection is available again through `inner`, `inner_mut` and
+`into_inner` for backend specific APIs which the wrappers do not expose.
+Anything run directly on the inner connection is not traced.
+
+`diesel_tracing::prelude` exports the instrumented connections of the enabled
+backends together with `Connection`, `SimpleConnection`, `RunQueryDsl`, the
+configuration types and, with the `r2d2` feature, the pool manager and pool
+aliases such as `PgPool`. With the `alias-diesel-names` feature it also
+exports the connections as `PgConnection`, `MysqlConnection` and
+`SqliteConnection`, so that switching to them only changes where those names
+are imported from.
+
+The information queried from the server when a postgresql or mysql connection
+is established, such as the database name and version, is available through
+`connection_info`, and `is_healthy` runs a traced `SELECT 1` to check the
+connection. The connections implement `Debug`, which never prints the URL
+they were established with.
+
 # Code reuse
 
 In some applications it may be desirable to be able to use both instrumented and
@@ -56,11 +80,246 @@ encapsulated by the `diesel::Connection` trait, so in those places it is
 likely that you will just need to replace your connection type with the
 Instrumented version.
 
+The instrumented connections can also be stored as type erased
+`Box<dyn BoxableConnection<_>>`, through which migrations and queries are still
+traced. `downcast_from` recovers the instrumented connection from such a box
+to call its backend specific methods, such as `build_transaction` or
+`immediate_transaction`.
+
 ## Connection Pooling
 
 `diesel-tracing` supports the `r2d2` connection pool, through the `r2d2`
 feature flag. See `diesel::r2d2` for details of usage.
 
+The pool itself can be traced by installing
+`pool::R2d2TracingEventHandler` as its event and error handler, which emits
+an event for each connection acquired, released, checked out and checked in,
+and for checkout timeouts and connection errors. Building the pool from a
+`pool::InstrumentedConnectionManager` instead of diesel's `ConnectionManager`
+also traces opening and validating its connections in `connect` and
+`is_valid` spans.
+
+The async `mobc` connection pool is supported through the `mobc` feature flag,
+with a `MobcPgManager`, `MobcMysqlManager` and `MobcSqliteManager` for the
+instrumented connections. Connections are opened in a `connect` span and
+checked with `SELECT 1` in a `check` span recording `db.operation="ping"`.
+
+```no_run
+# #[cfg(all(feature = "mobc", feature = "async-postgres"))]
+# async fn example() -> Result<(), Box<dyn std::error::Error>> {
+use diesel_tracing::MobcPgManager;
+
+let pool = mobc::Pool::builder()
+    .max_open(16)
+    .build(MobcPgManager::new("postgres://localhost/app"));
+let conn = pool.get().await?;
+# Ok(())
+# }
+```
+
+## Runtime configuration
+
+Each instrumented connection holds a [`TracingConfig`] which can be changed
+with `set_tracing_config`. Disabling the spans for `load`,
+`execute_returning_count`, `batch_execute` or transactions skips creating them
+entirely, for workloads where the cost of a span per query is noticeable.
+Disabling transactions also skips the spans of the `transaction_state` and
+`instrumentation` calls diesel makes while running them, and the statements
+which begin and end them are `batch_execute` spans. Everything is traced by
+default.
+
+`set_query_sample_rate` on the synchronous connections instead creates spans
+for only a fraction of queries, such as `0.01` for one in a hundred, spread
+evenly across them. Queries which are sampled out still log an error if they
+fail, and a transaction is sampled together with every query in it.
+
+`InstrumentedSqliteConnection::set_busy_retry` opts in to retrying statements
+which fail because another connection has locked the database, as described by
+a `sqlite::BusyRetryPolicy`. Each retry logs a warning, and the span of the
+statement records the number of retries as `db.sqlite.retries`. Loads are
+not retried, as sqlite only reports the lock once their rows are iterated, and
+neither are batches of more than one statement, whose statements before the
+locked one have already run.
+
+Pragmas can be set and inspected with the traced `set_pragma`, `journal_mode`
+and `wal_checkpoint` helpers of `InstrumentedSqliteConnection`, whose spans
+record `db.operation` as `PRAGMA` and the pragma as `db.sqlite.pragma`.
+
+`serialize_database_to_buffer` and `deserialize_readonly_database_from_buffer`
+snapshot and restore a sqlite database in spans recording its size as
+`db.sqlite.buffer_size`, and `backup_to_file` writes a snapshot to a file,
+replacing it atomically.
+
+With the `statement-fields` feature, `set_explain_on_slow(true)` on
+`InstrumentedPgConnection` or `InstrumentedSqliteConnection` logs a warning
+with the plan of each `execute_returning_count` statement taking longer than
+the `slow_query_threshold` of its `TracingConfig`, recorded as
+`db.query_plan`. The plan is queried with `EXPLAIN (FORMAT JSON)` on postgres
+and `EXPLAIN QUERY PLAN` on sqlite, which plan the statement again without
+running it. This costs the time to plan it again, and an extra round trip on
+postgres, so it is off by default. Only `SELECT`, `INSERT`, `UPDATE` and
+`DELETE` statements are explained, and `load` is not, as its rows borrow the
+connection.
+
+## Retrying transient errors
+
+The `retry` feature flag provides `retry::RetryingInstrumentedConnection`,
+which wraps a synchronous connection and retries statements failing with
+transient errors, such as serialization failures. Statements failing because
+the connection was closed, such as during a failover of a cloud database, may
+already have been run by the server, so they are only retried after
+`with_retry_on_disconnect`. Closed connections are replaced before the next
+statement, and the wait between attempts is set by a
+`retry::ExponentialBackoff`. Retries are made inside a `db.retry` span
+recording `db.retry_attempt`, with a warning logged for each retry and an
+error if every attempt fails.
+
+Without retrying statements, the `reconnect` feature flag adds
+`InstrumentedPgConnection::with_auto_reconnect`, which re-establishes a
+postgresql connection after a statement fails because the connection was
+closed. Each attempt logs a warning recording `db.reconnect_attempt`, and an
+error is logged if every attempt fails. The new connection gets its own
+`db.connection` span, and runs the init statements of `establish_with_init`
+and the read only setting of the connection it replaces again.
+
+## Migrations
+
+The `migrations` feature flag provides
+`migrations::TracedMigrationHarness`, which wraps the `MigrationHarness` of any
+of the instrumented connections from
+[`diesel_migrations`](https://docs.rs/diesel_migrations). It runs each
+migration in a `db.migration` span recording its name, version, outcome and
+duration, and logs the number of migrations applied by
+`run_pending_migrations`. The instrumented connections also trace creating
+diesel's migrations table in a `create_migrations_table` span, and their
+`ensure_migrations_table` creates it only if it is missing, returning and
+recording as `db.migrations_table.created` whether it was created.
+
+## Async connections
+
+The `async-postgres` and `async-mysql` feature flags provide
+`async_pg::InstrumentedAsyncPgConnection` and
+`async_mysql::InstrumentedAsyncMysqlConnection`, which wrap the connections
+from [`diesel-async`](https://docs.rs/diesel-async) and implement its
+`AsyncConnection` trait. Their spans record the same fields as the
+synchronous connections and are attached to the returned futures and row
+streams, so they cover the time spent awaiting the database. The
+`diesel-async` feature flag is an alias for `async-postgres`.
+
+## Transaction spans
+
+[`instrumentation::TransactionSpanInstrumentation`] is a diesel
+`Instrumentation` which opens a span for the whole of each transaction, so
+the queries run inside it are nested beneath it. It works with any diesel
+connection, including ones which are not wrapped by this crate. Each span
+records the transaction's `db.transaction.outcome` and
+`db.transaction.duration_ms`, and
+`TransactionSpanInstrumentation::with_warn_threshold` logs a warning for
+transactions which stay open too long.
+
+On the synchronous connections, `set_transaction_warn_after` and
+`set_transaction_warn_query_count` log a warning as soon as a transaction has
+been open for too long or has run too many queries. The warning is logged at
+the next query, while the transaction is still open, rather than when it
+finishes. The `transaction` span then records `db.transaction.duration_ms` and
+`db.transaction.queries`. Both thresholds are disabled by default.
+
+Transactions started with `InstrumentedPgConnection::build_transaction` are
+traced in a `pg_transaction` span, which records the chosen options as
+`db.transaction.isolation_level`, `db.transaction.read_only` and
+`db.transaction.deferrable`. Options which are not chosen, and the
+`transaction` spans of `Connection::transaction`, record the session defaults
+read when the connection is established, which
+`InstrumentedPgConnection::set_default_isolation_level` changes.
+`InstrumentedPgConnection::serializable_transaction_with_retries` runs a
+serializable transaction, retrying it after serialization failures and
+deadlocks with a warning for each retry, and records the number of retries as
+`db.transaction.retries`. `pg::sqlstate` and `pg::is_serialization_failure`
+classify these errors for other retry loops.
+
+`instrumentation::QueryCacheStatsInstrumentation` counts the statements
+added to a connection's prepared statement cache against the queries it
+starts, and periodically logs the counts, to help tune the size of the cache.
+Each cached statement is logged with its fingerprint, and `with_soft_limit`
+adds a warning once more statements have been cached than expected.
+
+`set_instrumentation` on the instrumented connections pushes onto an
+`instrumentation::InstrumentationStack` rather than replacing the
+instrumentation already installed, so each instrumentation set receives every
+event of the connection, in the order they were set. Events of a kind added by
+a newer diesel version than this crate knows of cannot be copied, so only the
+last instrumentation set receives them. The first call keeps a
+new instance of the default instrumentation set with diesel's
+`set_default_instrumentation`. `replace_instrumentation` installs a single
+instrumentation in place of all of them.
+
+## Trace context comments
+
+The `opentelemetry` feature flag adds `enable_sql_comments` to
+`InstrumentedPgConnection` and `InstrumentedMysqlConnection`. Once enabled,
+statements run through `batch_execute` and `execute_returning_count` are
+followed by a [sqlcommenter](https://google.github.io/sqlcommenter/) style
+comment such as `/*traceparent='00-<trace_id>-<span_id>-01'*/`, taken from the
+OpenTelemetry context which `tracing-opentelemetry` gives the statement's span.
+This lets server side logs, such as the slow query log or
+`pg_stat_activity`, be matched with traces. Statements whose span has no
+OpenTelemetry context are left unchanged. Commented statements are not cached
+as prepared statements, so comments are off by default.
+
+## Audit logs
+
+The `audit` feature flag adds `set_audit_sink` to the synchronous connections.
+The sink is given an `audit::AuditEntry` for every statement run through
+`batch_execute`, `execute_returning_count` or `load` which writes, whether or
+not the statement was sampled for tracing, so writes returning rows such as
+`INSERT ... RETURNING` are audited too. Entries hold the operation, table, rows
+affected, outcome, duration and connection name of the statement, but never
+its text or binds. `audit::JsonLinesSink` serializes each entry as a line of
+JSON to any writer, such as a file kept for compliance.
+
+## Metrics
+
+Enabling the `metrics` feature provides
+`metrics::MetricsInstrumentation`, a diesel `Instrumentation` which emits
+query counts, error counts, durations and transaction counts through the
+[`metrics`](https://docs.rs/metrics) crate. Its `db_system` label should be
+set with `with_db_system` when it is installed on an established connection,
+which it otherwise records as `unknown`. It can be installed together with
+other instrumentations using
+[`instrumentation::ComposedInstrumentation`].
+
+The `metrics` feature also makes the instrumented connections themselves
+record a `db.client.operations` counter, labelled with `db.system` and an
+`outcome` of `ok` or `error`, and a `db.client.operation.duration` histogram
+in seconds. These cover `load`, `execute_returning_count` and `batch_execute`,
+including the statements used to begin, commit and roll back transactions.
+Without the feature no metrics are recorded.
+
+`time_tracking::scope` totals the time spent in the database, such as for each
+request handled by a web server. While the guard it returns is alive, every
+`load`, `execute_returning_count` and `batch_execute` of a synchronous
+connection on the same thread counts towards `total` and `query_count`,
+including those of nested scopes, and the totals are recorded as
+`db.total_duration_ms` and `db.query_count` on its `db_time_scope` span when
+it is dropped.
+
+## Testing
+
+The `test-support` feature flag provides `test_support::EventRecorder`, a
+`tracing` subscriber which records the spans and events of the instrumented
+connections, for asserting in tests on the queries an application runs. For
+example `recorder.query_spans().len()` counts the queries run while it was
+installed, and `test_support::assert_query_span` checks that a statement
+containing some text was run when the `statement-fields` feature is enabled.
+
+The `mock` feature flag provides `mock::InstrumentedMockConnection`, a scripted
+postgresql connection for unit testing code which takes a connection without a
+database. Each statement must match the next expectation queued with
+`expect_query` or `expect_execute`, which supplies the rows, row count or
+error to return, and is traced in a span with the same query fields and
+`diesel_tracing::pg::query` target as `InstrumentedPgConnection`. Only the
+postgresql backend is supported.
+
 # Notes
 
 ## Fields
@@ -71,36 +330,274 @@ This was chosen for compatibility with the `tracing-opentelemetry` crate, but
 if it makes sense for other standards to be available this could be set by
 feature flag later.
 
+Fields follow version 1.20 and later of the conventions, so the database name
+is recorded as `db.namespace`, the server's host and port as `server.address`
+and `server.port`, and its IP address as `network.peer.address`. The
+`compat-otel-pre-1.20` feature also records them under their deprecated
+names, `db.name`, `net.peer.name`, `net.peer.port` and `net.peer.ip`, for
+backends which have not migrated yet.
+
+Every query span also records `db.operation` and `db.sql.table`, the leading
+keyword of the statement and the primary table it targets. These are low
+cardinality and do not contain any bound values, so they are always recorded.
+`db.access_mode` records whether the statement is a `read`, a `write`, which
+includes data modifying `WITH` clauses, `SELECT ... FOR UPDATE` and schema
+changes, or `unknown`, to help audit writes routed to read replicas.
+The same extraction is available as [`summarize_statement`].
+`load` and `execute_returning_count` spans record the number of bind
+parameters, but not their values, as `db.bind_count`.
+`execute_returning_count` spans additionally record the number of affected rows
+as `db.rows_affected`. On mysql, `execute_returning_count` spans of `INSERT`
+statements also record the generated id as `db.mysql.last_insert_id`, and
+`InstrumentedMysqlConnection::last_insert_id` and `affected_rows` query
+`LAST_INSERT_ID()` and `ROW_COUNT()` in their own spans. On the synchronous
+connections, `load` and `execute_returning_count` spans of statements which
+diesel caches as prepared statements record `db.statement.cached`, false when
+the statement was prepared for that query and true when it was reused from the
+cache. Connections wrapped with `From` record it once an instrumentation is
+set. Transaction spans
+record `db.transaction.depth`, which is 1 for the outermost transaction and
+greater for nested savepoints, and `db.transaction.rolled_back`.
+The `savepoint` method of the synchronous connections runs a closure in a
+named savepoint, traced in a `savepoint` span which records the name as
+`db.transaction.savepoint` along with `db.transaction.rolled_back`.
+
+When a query fails, its span records `db.error.kind`, such as
+`unique_violation` or `not_found`, along with `db.error.table` and
+`db.error.constraint` where the backend reports them. Failed queries, failed
+connection attempts and rolled back transactions also record
+`otel.status_code` as `ERROR`, with the error as `otel.status_message` where
+there is one, which `tracing-opentelemetry` exports as the span status.
+
+The postgres connections record the server address as `network.peer.address`.
+Enabling the `resolve-hostname` feature also resolves it to a hostname with a
+reverse DNS lookup when the connection is established, recorded as
+`server.address`. If
+the lookup fails the address is used instead. The lookup blocks, including for
+the async connections.
+
+For connection URLs listing several hosts, such as
+`postgresql://host1,host2/db?target_session_attrs=read-write`, the host which
+resolves to the server address is recorded as `server.address`, or the whole
+list if none match. The synchronous postgres connection also records
+`db.postgresql.target_session_attrs` when the URL sets it.
+
+The address and port of the client, as seen by the server, are recorded as
+`db.client.address` and `db.client.port`. Postgres reports them with
+`inet_client_addr()` and `inet_client_port()`, and mysql through the
+connection's process list entry. Connections over a unix socket record
+`localhost` and no port. The sqlite connection records the path it was
+established with as `db.client.address`.
+
+The synchronous postgres and mysql connections record whether TLS was
+negotiated as `db.connection.tls` on every span. Postgres reads it from
+`pg_stat_ssl`, along with `db.connection.tls_version` and
+`db.connection.tls_cipher` on the `establish` span, and mysql from the
+`Ssl_cipher` status, recording `db.connection.tls_cipher`. If the state cannot
+be read, such as when the view is not readable, it is left unrecorded and the
+connection is still established.
+
+`set_read_only` on the synchronous connections makes the session read only,
+with `default_transaction_read_only` on postgres, `transaction_read_only` on
+mysql and `PRAGMA query_only` on sqlite. The spans of a read only connection
+record `db.connection.read_only`, and a statement which the summary classifies
+as a write logs a "write attempted on a read only connection" warning before
+it is sent, so that the call site is traced alongside the database's error.
+With `set_strict_read_only` the statement fails without being sent.
+
+The postgres connections record the session's `application_name` as
+`db.postgresql.application_name` and the schemas in its search path as
+`db.postgresql.schema`, read when the connection is established. Changing the
+application name with `InstrumentedPgConnection::set_application_name` keeps
+the recorded value up to date. The current schema, the first schema of the
+search path which exists, is also recorded as `db.schema`, and
+`InstrumentedPgConnection::refresh_schema` reads both again after the
+`search_path` of a long-lived connection has changed.
+
+The process ID of the server backend serving the session, as shown in
+`pg_stat_activity` and the postgresql server logs, is recorded as
+`db.postgresql.pid` so that traces can be matched with the server's logs.
+The mysql connections likewise record the ID of the connection's server
+thread, as returned by `CONNECTION_ID()` and listed by `SHOW PROCESSLIST`, as
+`db.mysql.thread_id`.
+
+`InstrumentedPgConnection::listen`, `unlisten` and `notify` run postgres
+`LISTEN`, `UNLISTEN` and `NOTIFY` statements as traced queries, and emit an
+event with `db.operation` and the channel as `db.postgresql.channel`. The
+payload of a notification is only recorded with the `statement-fields`
+feature. diesel does not expose received notifications, so they cannot be
+read through the connection.
+
+Enabling the `connection-id` feature generates a random UUID for each
+connection when it is established and records it as `db.connection_id` on every
+span, so the queries of a single pooled connection can be grouped together.
+
+Every span also records `db.connection.id`, a sequence number assigned when
+the connection is established which is unique within the process. Connections
+can be labelled with `establish_named` or `set_connection_name`, such as to
+tell a primary and a replica apart, and the label is recorded as
+`db.connection.name`. Static fields such as a tenant or shard identifier can
+be attached to every span of a synchronous connection with
+`with_extra_fields`, and are recorded as `key=value` pairs in `db.extra`.
+Passing no fields clears them, leaving `db.extra` empty on the connection span.
+
+`establish_with_init` on the synchronous connections runs session settings,
+such as `PRAGMA foreign_keys = ON`, through `batch_execute` as soon as the
+connection is established, with an event for each inside the establish span.
+The first statement which fails fails the connection with an error naming it.
+`InstrumentedConnectionManager::with_init_statements` runs the same settings on
+every connection an r2d2 pool opens.
+
+The spans of a synchronous connection are named after the connection method
+which created them, such as `load`. `with_span_namer` takes a `SpanNamer`
+which names them after the application operation instead. `tracing` fixes span
+names where they are declared, so the name is recorded as `otel.name`, which
+`tracing-opentelemetry` exports as the span name. `OperationSpanNamer` names
+query and transaction spans after their kind, such as `db.query`,
+`db.execute`, `db.transaction` and `db.connect`, so that they can be told
+apart in trace search. `set_default_span_namer` sets the namer of every
+connection established afterwards, and is the only namer of the `establish`
+spans, which are created before there is a connection to take one. Setting
+`TracingConfig::statement_span_names` names the query spans after their
+operation and table instead, such as `SELECT users`, unless the namer renames
+them.
+
+The synchronous connections also open a `db.connection` span when they are
+established, which is closed with a "connection closed" event when the
+connection is dropped. It records the connection fields, and the spans of the
+connection's operations follow from it. They are not its children, so they
+stay nested beneath the caller's spans.
+The "connection closed" event records how long the connection was open as
+`db.connection.duration_ms` and the number of `load`, `execute_returning_count`
+and `batch_execute` calls made on it as `db.connection.queries`, to help
+correlate connection churn with latency.
+
+Enabling the `caller-location` feature records the application code which
+issued each query as `code.function`, `code.filepath` and `code.lineno`,
+taken from the first frame of a backtrace outside diesel and this crate.
+Capturing a backtrace for every query is expensive, so the feature is not
+enabled by default. Frames of an application's own data access layer can be
+skipped too with `set_caller_skip_prefixes`.
+
 Database statements may optionally be recorded by enabling the
 `statement-fields` feature. This uses [`diesel::debug_query`](https://docs.rs/diesel/latest/diesel/fn.debug_query.html)
 to convert the query into a string. As this may expose sensitive information,
 the feature is not enabled by default.
 
+Statements are only rendered for spans which the subscriber enables, so
+filtering out the spans of a connection also avoids the cost of rendering
+its queries.
+
+Recorded statements longer than [`DEFAULT_MAX_STATEMENT_LENGTH`] characters
+are truncated and marked with their full length, so that large bulk inserts do
+not exceed the span size limits of collectors. The limit can be changed for
+every connection with [`set_max_statement_length`], or for one connection
+through [`TracingConfig::max_statement_length`].
+
+`batch_execute` spans record the number of statements in the batch as
+`db.batch.size` and, where the backend reports the position of an error, the
+index of the failing statement as `db.batch.error_index`. With the
+`statement-fields` feature they also record the whole batch as `db.statement`,
+and a debug event is emitted for each statement in the batch, with its index
+and the first 200 characters of its text.
+
+`insert_in_chunks` on the synchronous connections inserts a `Vec` of rows in
+chunks which stay within the backend's limit of bind parameters. Its span
+records `db.batch.chunks` and `db.batch.total_rows`, each chunk is inserted in
+an `insert_chunk` span with its `db.batch.index` and `db.batch.size`, and when
+a chunk fails the number of rows inserted before it is recorded as
+`db.batch.rows_committed`.
+
+`load_chunked` is its counterpart for large result sets, reading the rows of a
+query from its cursor and passing them to a callback in chunks rather than
+loading them all at once. The postgres connection reads them row by row, so
+only one chunk is held in memory. Its span records `db.batch.chunks` and
+`db.batch.total_rows`, and each call of the callback runs in a `load_chunk`
+span with the chunk's `db.batch.index` and `db.batch.size`.
+
+The `statement-fingerprint` feature instead records `db.statement_fingerprint`,
+the statement with its literal values replaced by `?` as produced by
+[`fingerprint_sql`]. This groups queries of the same shape together without
+exposing the values they contain.
+
+Without any feature flags, spans which record a statement summary also record
+`db.statement.hash`, a 64-bit hash of the fingerprint computed by
+[`statement_fingerprint`]. Statements differing only in their values, bind
+placeholders or whitespace share a hash, which is stable across processes and
+releases, so spans can be grouped by query shape without recording the
+statement at all, and the hash of a known query can be computed offline.
+
 It would be quite useful to be able to parse connection strings to be able
 to provide more information, but this may be difficult if it requires use of
 diesel feature flags by default to access the underlying C bindings.
 
+## Targets
+
+Spans and events of the connections have a target nested under their module,
+so they can be filtered with directives such as
+`RUST_LOG=diesel_tracing::pg=debug,diesel_tracing::sqlite=off`. Each backend
+module, `pg`, `mysql`, `sqlite`, `async_pg` and `async_mysql`, splits its
+targets further:
+
+- `diesel_tracing::<backend>::connection` for the connection span,
+  establishing and pinging connections, and other connection management.
+- `diesel_tracing::<backend>::query` for `load`, `execute_returning_count`
+  and `batch_execute` spans, along with the other operations running
+  statements, such as `COPY` and pragmas.
+- `diesel_tracing::<backend>::transaction` for transaction and savepoint spans.
+
+For example, `RUST_LOG=diesel_tracing=debug,diesel_tracing::pg::transaction=off`
+silences postgres transaction spans only. The spans and events of other
+modules, such as `diesel_tracing::instrumentation` and
+`diesel_tracing::retry`, use the module path as their target.
+
 ## Levels
 
-All logged traces are currently set to DEBUG level, potentially this could be
-changed to a different default or set to be configured by feature flags. At
-them moment this crate is quite new and it's unclear what a sensible default
-would be.
+Spans are at INFO, the default level of the `instrument` macro: the spans of
+the methods of the connections, such as `load`, `execute_returning_count`,
+`batch_execute`, `transaction` and `establish`, along with the
+`insert_chunk`, `load_chunk`, `db.transaction`, `db.savepoint` and
+`db.migration` spans. The exception is the `db.connection` span of each
+connection, which is at DEBUG.
+
+Events describing routine work, such as querying connection information,
+running init statements or reconnecting, are at DEBUG, and those about the
+postgres type metadata cache at TRACE. The errors of queries are logged at the
+levels of an [`ErrorLevelPolicy`], see below. Events which need attention are
+at WARN: retrying a locked sqlite database, a serialization failure or a
+transient error, each reconnect attempt, the plan of a slow query, a write on
+a read only connection, a transaction open past its threshold, a prepared
+statement cache past its soft limit, and failing to query connection
+information or to write an audit record. Giving up is logged at ERROR: a
+failed init statement, running out of reconnect or retry attempts, a
+panicking sqlite function and a failed migration.
 
 ## Errors
 
 Errors in Result objects returned by methods on the connection should be
 automatically logged through the `err` directive in the `instrument` macro.
 
+The errors of queries are instead logged at a level chosen by the
+[`ErrorLevelPolicy`] in the `error_levels` of the connection's `TracingConfig`, so
+that expected outcomes don't drown out real failures. By default
+`NotFound` is logged at DEBUG, unique violations at WARN and every other
+error at ERROR.
+
 ## Sensitive Information
 
 As statements may contain sensitive information they are currently not recorded
 explicitly, unless you opt in by enabling the `statement-fields` feature.
-Finding a way to filter statements intelligently to solve this problem is a
-TODO.
+The `statement-fingerprint` feature records a normalized form of each
+statement with its literal values removed.
 
 Similarly connection strings are not recorded in spans as they may contain
-passwords
+passwords. If you log them yourself, [`url::mask_connection_url`] replaces
+the password with `[REDACTED]`, in URLs and libpq key/value connection
+strings alike. `InstrumentedPgConnection::establish_with_config`
+establishes a connection from a `pg::PgConnectConfig` instead of a URL,
+escaping each setting into a libpq connection string and holding the password
+as a `url::Secret`, which prints as `[REDACTED]`. Its span records the host,
+port, user and database name of the configuration.
 
 ## TODO
 
@@ -110,9 +607,147 @@ passwords
 */
 #![warn(clippy::all, clippy::pedantic)]
 
+#[cfg(any(
+    feature = "mysql",
+    feature = "postgres",
+    feature = "sqlite",
+    feature = "async"
+))]
+mod batch;
+#[cfg(feature = "caller-location")]
+#[cfg_attr(
+    not(any(
+        feature = "mysql",
+        feature = "postgres",
+        feature = "sqlite",
+        feature = "async"
+    )),
+    allow(dead_code)
+)]
+mod caller;
+#[cfg(any(feature = "mysql", feature = "postgres", feature = "sqlite"))]
+mod chunks;
+mod config;
+#[cfg_attr(
+    not(any(
+        feature = "mysql",
+        feature = "postgres",
+        feature = "sqlite",
+        feature = "async"
+    )),
+    allow(dead_code)
+)]
+mod connection_id;
+#[cfg(any(
+    feature = "mysql",
+    feature = "postgres",
+    feature = "sqlite",
+    feature = "async",
+    feature = "mock"
+))]
+mod error;
+#[cfg(all(
+    feature = "statement-fields",
+    not(feature = "passthrough"),
+    any(feature = "postgres", feature = "sqlite")
+))]
+mod explain;
+mod fingerprint;
+#[cfg(any(
+    feature = "mysql",
+    feature = "postgres",
+    feature = "sqlite",
+    feature = "r2d2"
+))]
+mod init;
+#[cfg_attr(
+    not(any(feature = "mysql", feature = "postgres", feature = "sqlite")),
+    allow(dead_code)
+)]
+mod operation;
+#[cfg(any(feature = "mysql", feature = "postgres", feature = "sqlite"))]
+mod read_only;
+#[cfg(any(feature = "mysql", feature = "postgres", feature = "sqlite"))]
+mod sampling;
+#[cfg(any(
+    feature = "mysql",
+    feature = "postgres",
+    feature = "async-mysql",
+    feature = "async-postgres"
+))]
+mod semconv;
+#[cfg(all(
+    feature = "opentelemetry",
+    any(feature = "mysql", feature = "postgres")
+))]
+mod sql_comment;
+#[cfg_attr(
+    not(any(feature = "mysql", feature = "postgres", feature = "sqlite")),
+    allow(dead_code, unused_imports)
+)]
+mod statement;
+#[cfg(any(feature = "mysql", feature = "postgres", feature = "sqlite"))]
+mod transaction;
+
+#[cfg(feature = "caller-location")]
+pub use caller::set_caller_skip_prefixes;
+pub use config::{
+    set_default_span_namer, set_max_statement_length, DefaultSpanNamer, ErrorLevelPolicy,
+    OperationSpanNamer, SpanNamer, TracingConfig, DEFAULT_MAX_STATEMENT_LENGTH,
+};
+pub use fingerprint::{fingerprint_sql, statement_fingerprint};
+pub use statement::{summarize_statement, AccessMode, StatementSummary};
+
+#[cfg(all(feature = "mobc", feature = "async-mysql"))]
+pub use pool::MobcMysqlManager;
+#[cfg(all(feature = "mobc", feature = "async-postgres"))]
+pub use pool::MobcPgManager;
+#[cfg(all(feature = "mobc", feature = "sqlite"))]
+pub use pool::MobcSqliteManager;
+
+#[cfg(feature = "async-mysql")]
+pub mod async_mysql;
+#[cfg(feature = "async-postgres")]
+pub mod async_pg;
+#[cfg(feature = "async")]
+mod async_stream;
+#[cfg(all(
+    feature = "audit",
+    any(feature = "mysql", feature = "postgres", feature = "sqlite")
+))]
+pub mod audit;
+#[cfg(feature = "async")]
+pub use async_stream::InstrumentedStream;
+
+pub mod instrumentation;
+#[cfg(feature = "metrics")]
+pub mod metrics;
+
+#[cfg(feature = "migrations")]
+pub mod migrations;
+#[cfg(feature = "mock")]
+pub mod mock;
 #[cfg(feature = "mysql")]
 pub mod mysql;
+#[cfg(any(feature = "mysql", feature = "async-mysql"))]
+mod mysql_info;
 #[cfg(feature = "postgres")]
 pub mod pg;
+#[cfg(feature = "postgres")]
+mod pg_config;
+#[cfg(any(feature = "postgres", feature = "async-postgres"))]
+mod pg_info;
+#[cfg(any(feature = "r2d2", feature = "mobc"))]
+pub mod pool;
+pub mod prelude;
+#[cfg(feature = "retry")]
+pub mod retry;
 #[cfg(feature = "sqlite")]
 pub mod sqlite;
+#[cfg(any(test, feature = "test-support"))]
+pub mod test_support;
+pub mod time_tracking;
+pub mod url;
+
+#[cfg(test)]
+mod api_tests;
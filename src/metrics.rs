@@ -0,0 +1,243 @@
+/*!
+Query and transaction metrics through the [`metrics`] crate.
+
+[`MetricsInstrumentation`] is a [`diesel::connection::Instrumentation`] which
+emits the following to the globally installed `metrics` recorder:
+
+- `diesel_queries_total{db_system, db_operation}`: counter of finished queries
+- `diesel_query_errors_total{db_system, db_error_type}`: counter of failed
+  queries
+- `diesel_query_duration_seconds{db_system}`: histogram of query durations
+- `diesel_transactions_total{db_system, db_transaction_event}`: counter of
+  transaction `begin`, `commit` and `rollback` events
+
+As a connection holds a single instrumentation, use
+[`ComposedInstrumentation`](crate::instrumentation::ComposedInstrumentation) to
+install it alongside another.
+*/
+use std::time::Instant;
+
+use diesel::connection::{Instrumentation, InstrumentationEvent};
+use diesel::result::Error;
+use metrics::{counter, histogram};
+
+use crate::statement::summarize_statement;
+
+/// Emits `metrics` counters and histograms for connection events.
+///
+/// ```
+/// use diesel::connection::{Connection, SimpleConnection};
+/// use diesel::sqlite::SqliteConnection;
+/// use diesel_tracing::metrics::MetricsInstrumentation;
+///
+/// let mut conn = SqliteConnection::establish(":memory:")?;
+/// conn.set_instrumentation(MetricsInstrumentation::new().with_db_system("sqlite"));
+/// conn.batch_execute("SELECT 1")?;
+/// # Ok::<(), Box<dyn std::error::Error>>(())
+/// ```
+#[derive(Debug, Default)]
+pub struct MetricsInstrumentation {
+    db_system: Option<&'static str>,
+    query_started: Option<Instant>,
+}
+
+impl MetricsInstrumentation {
+    /// Create an instrumentation which infers `db_system` from the URL of the
+    /// established connection, labelling it `unknown` if the URL is not
+    /// recognised.
+    ///
+    /// The URL is only seen if the instrumentation is installed before the
+    /// connection is established, such as with
+    /// `diesel::connection::set_default_instrumentation`. Installed with
+    /// `set_instrumentation` on an established connection, which is how it
+    /// is installed on the instrumented connections, every series is
+    /// labelled `db_system="unknown"` unless set with
+    /// [`with_db_system`](Self::with_db_system).
+    #[must_use]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Set the `db_system` label explicitly, e.g. when the instrumentation is
+    /// installed on an already established connection.
+    #[must_use]
+    pub fn with_db_system(mut self, db_system: &'static str) -> Self {
+        self.db_system = Some(db_system);
+        self
+    }
+
+    fn db_system(&self) -> &'static str {
+        self.db_system.unwrap_or("unknown")
+    }
+
+    fn record_transaction(&self, event: &'static str) {
+        counter!(
+            "diesel_transactions_total",
+            "db_system" => self.db_system(),
+            "db_transaction_event" => event,
+        )
+        .increment(1);
+    }
+}
+
+impl Instrumentation for MetricsInstrumentation {
+    fn on_connection_event(&mut self, event: InstrumentationEvent<'_>) {
+        match event {
+            InstrumentationEvent::StartEstablishConnection { url, .. }
+                if self.db_system.is_none() =>
+            {
+                self.db_system = Some(db_system_from_url(url));
+            }
+            InstrumentationEvent::StartQuery { .. } => {
+                self.query_started = Some(Instant::now());
+            }
+            InstrumentationEvent::FinishQuery { query, error, .. } => {
+                let db_system = self.db_system();
+                let operation = summarize_statement(&query.to_string())
+                    .operation
+                    .unwrap_or_else(|| "UNKNOWN".to_string());
+
+                counter!(
+                    "diesel_queries_total",
+                    "db_system" => db_system,
+                    "db_operation" => operation,
+                )
+                .increment(1);
+
+                if let Some(error) = error {
+                    counter!(
+                        "diesel_query_errors_total",
+                        "db_system" => db_system,
+                        "db_error_type" => error_type(error),
+                    )
+                    .increment(1);
+                }
+
+                if let Some(started) = self.query_started.take() {
+                    histogram!("diesel_query_duration_seconds", "db_system" => db_system)
+                        .record(started.elapsed().as_secs_f64());
+                }
+            }
+            InstrumentationEvent::BeginTransaction { .. } => self.record_transaction("begin"),
+            InstrumentationEvent::CommitTransaction { .. } => self.record_transaction("commit"),
+            InstrumentationEvent::RollbackTransaction { .. } => {
+                self.record_transaction("rollback");
+            }
+            _ => {}
+        }
+    }
+}
+
+/// The database system of a connection URL, or `unknown` for anything other
+/// than a postgres or mysql URL, a sqlite URI or `:memory:`, or a path to a
+/// sqlite database file.
+fn db_system_from_url(url: &str) -> &'static str {
+    let path = url.split('?').next().unwrap_or_default();
+    if url.starts_with("postgres://") || url.starts_with("postgresql://") {
+        "postgresql"
+    } else if url.starts_with("mysql://") {
+        "mysql"
+    } else if url.starts_with("sqlite:")
+        || url.starts_with("file:")
+        || path == ":memory:"
+        || [".db", ".sqlite", ".sqlite3"]
+            .iter()
+            .any(|extension| path.ends_with(extension))
+    {
+        "sqlite"
+    } else {
+        "unknown"
+    }
+}
+
+fn error_type(error: &Error) -> &'static str {
+    match error {
+        Error::InvalidCString(_) => "invalid_c_string",
+        Error::DatabaseError(_, _) => "database_error",
+        Error::NotFound => "not_found",
+        Error::QueryBuilderError(_) => "query_builder_error",
+        Error::DeserializationError(_) => "deserialization_error",
+        Error::SerializationError(_) => "serialization_error",
+        Error::RollbackErrorOnCommit { .. } => "rollback_error_on_commit",
+        Error::RollbackTransaction => "rollback_transaction",
+        Error::AlreadyInTransaction => "already_in_transaction",
+        Error::NotInTransaction => "not_in_transaction",
+        Error::BrokenTransactionManager => "broken_transaction_manager",
+        _ => "unknown",
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use diesel::connection::{Connection, SimpleConnection};
+    use diesel::sqlite::SqliteConnection;
+    use metrics_util::debugging::{DebugValue, DebuggingRecorder};
+    use metrics_util::MetricKind;
+
+    #[test]
+    fn test_metrics_instrumentation() {
+        let recorder = DebuggingRecorder::new();
+        let snapshotter = recorder.snapshotter();
+
+        metrics::with_local_recorder(&recorder, || {
+            let mut conn = SqliteConnection::establish(":memory:").unwrap();
+            conn.set_instrumentation(MetricsInstrumentation::new().with_db_system("sqlite"));
+            conn.transaction(|conn| conn.batch_execute("SELECT 1"))
+                .unwrap();
+            conn.batch_execute("SELECT * FROM missing").unwrap_err();
+        });
+
+        let metrics = snapshotter.snapshot().into_vec();
+        let find = |kind: MetricKind, name: &str, label: (&str, &str)| {
+            metrics.iter().find_map(|(key, _, _, value)| {
+                let key_matches = key.kind() == kind
+                    && key.key().name() == name
+                    && key
+                        .key()
+                        .labels()
+                        .any(|l| l.key() == label.0 && l.value() == label.1);
+                key_matches.then_some(value)
+            })
+        };
+
+        assert_eq!(
+            find(
+                MetricKind::Counter,
+                "diesel_queries_total",
+                ("db_operation", "SELECT")
+            ),
+            Some(&DebugValue::Counter(2))
+        );
+        assert_eq!(
+            find(
+                MetricKind::Counter,
+                "diesel_query_errors_total",
+                ("db_error_type", "database_error")
+            ),
+            Some(&DebugValue::Counter(1))
+        );
+        assert_eq!(
+            find(
+                MetricKind::Counter,
+                "diesel_transactions_total",
+                ("db_transaction_event", "commit")
+            ),
+            Some(&DebugValue::Counter(1))
+        );
+        assert!(matches!(
+            find(MetricKind::Histogram, "diesel_query_duration_seconds", ("db_system", "sqlite")),
+            Some(DebugValue::Histogram(values)) if !values.is_empty()
+        ));
+    }
+
+    #[test]
+    fn test_db_system_from_url() {
+        assert_eq!(db_system_from_url("postgres://localhost/db"), "postgresql");
+        assert_eq!(db_system_from_url("mysql://localhost/db"), "mysql");
+        assert_eq!(db_system_from_url(":memory:"), "sqlite");
+        assert_eq!(db_system_from_url("file:test.db?mode=ro"), "sqlite");
+        assert_eq!(db_system_from_url("/var/lib/app.sqlite3"), "sqlite");
+        assert_eq!(db_system_from_url("host=localhost dbname=app"), "unknown");
+    }
+}
@@ -0,0 +1,285 @@
+/*!
+Tracing for migrations applied with [`diesel_migrations`].
+*/
+use std::time::Instant;
+
+use diesel::backend::Backend;
+use diesel::migration::{Migration, MigrationSource, MigrationVersion, Result};
+use diesel_migrations::MigrationHarness;
+use tracing::{error, field, info, info_span};
+
+/// The version of the placeholder migration diesel runs to set up its
+/// migrations table, which is not traced.
+const SETUP_VERSION: &str = "00000000000000";
+
+/// Wraps a [`MigrationHarness`], such as any of the instrumented connections,
+/// opening a span for each migration it runs or reverts.
+///
+/// The `db.migration` spans record `db.migration.name`,
+/// `db.migration.version` and `db.migration.direction`, `up` or `down`, and
+/// when the migration finishes `db.migration.outcome`, `success` or
+/// `failure`, and `db.migration.duration_ms`. The statements of a migration
+/// are nested beneath its span. `run_pending_migrations` also logs the number
+/// of migrations applied. Creating diesel's migrations table on an
+/// instrumented connection is traced by a `create_migrations_table` span.
+///
+/// ```no_run
+/// # #[cfg(feature = "sqlite")]
+/// # fn example() -> diesel::migration::Result<()> {
+/// use diesel::connection::Connection;
+/// use diesel_migrations::{EmbeddedMigrations, MigrationHarness};
+/// use diesel_tracing::migrations::TracedMigrationHarness;
+/// use diesel_tracing::sqlite::InstrumentedSqliteConnection;
+///
+/// # const MIGRATIONS: EmbeddedMigrations = EmbeddedMigrations::new(&[]);
+/// let mut conn = InstrumentedSqliteConnection::establish("app.db")?;
+/// TracedMigrationHarness::new(&mut conn).run_pending_migrations(MIGRATIONS)?;
+/// # Ok(())
+/// # }
+/// ```
+pub struct TracedMigrationHarness<'a, C> {
+    harness: &'a mut C,
+}
+
+impl<'a, C> TracedMigrationHarness<'a, C> {
+    pub fn new<DB>(harness: &'a mut C) -> Self
+    where
+        C: MigrationHarness<DB>,
+        DB: Backend,
+    {
+        TracedMigrationHarness { harness }
+    }
+}
+
+impl<C, DB> MigrationHarness<DB> for TracedMigrationHarness<'_, C>
+where
+    C: MigrationHarness<DB>,
+    DB: Backend,
+{
+    fn run_pending_migrations<S: MigrationSource<DB>>(
+        &mut self,
+        source: S,
+    ) -> Result<Vec<MigrationVersion<'_>>> {
+        let pending = self.pending_migrations(source)?;
+        let applied = self.run_migrations(&pending)?;
+        if !cfg!(feature = "passthrough") {
+            info!(
+                db.migration.count = applied.len(),
+                "applied pending migrations"
+            );
+        }
+
+        Ok(applied)
+    }
+
+    fn run_migration(
+        &mut self,
+        migration: &dyn Migration<DB>,
+    ) -> Result<MigrationVersion<'static>> {
+        traced(migration, "up", |migration| {
+            self.harness.run_migration(migration)
+        })
+    }
+
+    fn revert_migration(
+        &mut self,
+        migration: &dyn Migration<DB>,
+    ) -> Result<MigrationVersion<'static>> {
+        traced(migration, "down", |migration| {
+            self.harness.revert_migration(migration)
+        })
+    }
+
+    fn applied_migrations(&mut self) -> Result<Vec<MigrationVersion<'static>>> {
+        self.harness.applied_migrations()
+    }
+}
+
+/// Run or revert a migration inside its span.
+fn traced<DB: Backend>(
+    migration: &dyn Migration<DB>,
+    direction: &'static str,
+    apply: impl FnOnce(&dyn Migration<DB>) -> Result<MigrationVersion<'static>>,
+) -> Result<MigrationVersion<'static>> {
+    if cfg!(feature = "passthrough")
+        || migration.name().version() == MigrationVersion::from(SETUP_VERSION)
+    {
+        return apply(migration);
+    }
+
+    let span = info_span!(
+        "db.migration",
+        db.migration.name = %migration.name(),
+        db.migration.version = %migration.name().version(),
+        db.migration.direction = direction,
+        db.migration.outcome = field::Empty,
+        db.migration.duration_ms = field::Empty,
+    );
+    let started = Instant::now();
+    let result = span.in_scope(|| apply(migration));
+    let duration_ms = u64::try_from(started.elapsed().as_millis()).unwrap_or(u64::MAX);
+    span.record("db.migration.duration_ms", duration_ms);
+    match &result {
+        Ok(_) => {
+            span.record("db.migration.outcome", "success");
+        }
+        Err(error) => {
+            span.record("db.migration.outcome", "failure");
+            error!(parent: &span, %error, "migration failed");
+        }
+    }
+
+    result
+}
+
+#[cfg(all(test, feature = "sqlite", not(feature = "passthrough")))]
+mod tests {
+    use super::*;
+    use crate::sqlite::InstrumentedSqliteConnection;
+    use crate::test_support::EventRecorder;
+    use diesel::connection::{BoxableConnection, Connection, SimpleConnection};
+    use diesel::migration::{MigrationMetadata, MigrationName};
+    use diesel::sqlite::Sqlite;
+    use std::fmt::{self, Display};
+
+    struct TestMigration {
+        name: TestMigrationName,
+        up: &'static str,
+        down: &'static str,
+    }
+
+    /// Named as diesel names migration directories, `<version>_<name>`.
+    struct TestMigrationName(&'static str);
+
+    struct TestMigrationMetadata;
+
+    struct TestMigrations;
+
+    impl Display for TestMigrationName {
+        fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+            f.write_str(self.0)
+        }
+    }
+
+    impl MigrationName for TestMigrationName {
+        fn version(&self) -> MigrationVersion<'_> {
+            self.0.split('_').next().unwrap_or_default().into()
+        }
+    }
+
+    impl MigrationMetadata for TestMigrationMetadata {}
+
+    impl Migration<Sqlite> for TestMigration {
+        fn run(&self, conn: &mut dyn BoxableConnection<Sqlite>) -> Result<()> {
+            Ok(conn.batch_execute(self.up)?)
+        }
+
+        fn revert(&self, conn: &mut dyn BoxableConnection<Sqlite>) -> Result<()> {
+            Ok(conn.batch_execute(self.down)?)
+        }
+
+        fn metadata(&self) -> &dyn MigrationMetadata {
+            &TestMigrationMetadata
+        }
+
+        fn name(&self) -> &dyn MigrationName {
+            &self.name
+        }
+    }
+
+    impl MigrationSource<Sqlite> for TestMigrations {
+        fn migrations(&self) -> Result<Vec<Box<dyn Migration<Sqlite>>>> {
+            Ok(vec![
+                Box::new(TestMigration {
+                    name: TestMigrationName("20240101000000_create_users"),
+                    up: "CREATE TABLE users (id INTEGER PRIMARY KEY)",
+                    down: "DROP TABLE users",
+                }),
+                Box::new(TestMigration {
+                    name: TestMigrationName("20240102000000_add_user_names"),
+                    up: "ALTER TABLE users ADD COLUMN name TEXT",
+                    down: "ALTER TABLE users DROP COLUMN name",
+                }),
+            ])
+        }
+    }
+
+    #[test]
+    fn test_migration_spans() {
+        let recorder = EventRecorder::default();
+        tracing::subscriber::with_default(recorder.clone(), || {
+            let mut conn = InstrumentedSqliteConnection::establish(":memory:").unwrap();
+            let mut harness = TracedMigrationHarness::new(&mut conn);
+            let applied = harness.run_pending_migrations(TestMigrations).unwrap();
+            assert_eq!(applied.len(), 2);
+        });
+
+        let spans = recorder.spans("db.migration");
+        let names: Vec<_> = spans
+            .iter()
+            .map(|span| span.fields["db.migration.name"].as_str())
+            .collect();
+        assert_eq!(
+            names,
+            [
+                "20240101000000_create_users",
+                "20240102000000_add_user_names"
+            ]
+        );
+        assert_eq!(spans[0].fields["db.migration.version"], "20240101000000");
+        assert_eq!(spans[0].fields["db.migration.direction"], "up");
+        assert_eq!(spans[0].fields["db.migration.outcome"], "success");
+        assert!(spans[0].fields.contains_key("db.migration.duration_ms"));
+
+        let setup = recorder.span("create_migrations_table").unwrap();
+        assert_eq!(setup.fields["db.operation"], "create_migrations_table");
+        assert_eq!(setup.target, "diesel_tracing::sqlite::query");
+
+        let summary = recorder
+            .events()
+            .into_iter()
+            .find(|event| {
+                event
+                    .get("message")
+                    .is_some_and(|message| message == "applied pending migrations")
+            })
+            .unwrap();
+        assert_eq!(summary["db.migration.count"], "2");
+    }
+
+    #[test]
+    fn test_boxed_connection() {
+        let recorder = EventRecorder::default();
+        tracing::subscriber::with_default(recorder.clone(), || {
+            let conn = InstrumentedSqliteConnection::establish(":memory:").unwrap();
+            let mut boxed: Box<dyn BoxableConnection<Sqlite>> = Box::new(conn);
+            for migration in TestMigrations.migrations().unwrap() {
+                migration.run(boxed.as_mut()).unwrap();
+            }
+            boxed
+                .batch_execute("INSERT INTO users (id, name) VALUES (1, 'ferris')")
+                .unwrap();
+
+            let conn = InstrumentedSqliteConnection::downcast_from(boxed.as_mut()).unwrap();
+            conn.immediate_transaction(|conn| {
+                conn.batch_execute("UPDATE users SET name = 'corro' WHERE id = 1")
+            })
+            .unwrap();
+
+            let mut other: Box<dyn BoxableConnection<Sqlite>> =
+                Box::new(diesel::SqliteConnection::establish(":memory:").unwrap());
+            assert!(InstrumentedSqliteConnection::downcast_from(other.as_mut()).is_none());
+        });
+
+        let statements: Vec<_> = recorder
+            .spans("batch_execute")
+            .into_iter()
+            .map(|span| span.fields["db.operation"].clone())
+            .collect();
+        assert_eq!(
+            statements,
+            ["CREATE", "ALTER", "INSERT", "BEGIN", "UPDATE", "COMMIT"]
+        );
+        assert!(recorder.span("immediate_transaction").is_some());
+    }
+}
@@ -0,0 +1,787 @@
+/*!
+A scripted postgresql connection for unit testing code which takes a diesel
+connection, without a database.
+
+[`InstrumentedMockConnection`] implements `Connection`, `SimpleConnection` and
+`LoadConnection` for the [`Pg`] backend. Each statement it runs must match the
+next expectation queued on it, which supplies the rows or row count to return,
+or the error to fail with. Queries are traced in `load`,
+`execute_returning_count` and `batch_execute` spans recording the same query
+fields as [`InstrumentedPgConnection`](crate::pg::InstrumentedPgConnection),
+such as `db.operation`, `db.sql.table` and `db.statement`, so assertions on the
+spans of the real connection carry over. The spans and events have the same
+`diesel_tracing::pg::query` target, so the filters of the real connection
+apply too. There is no server, so the fields describing it are not recorded.
+
+Only the postgresql backend is supported, so code generic over the backend
+can only be tested against postgres queries.
+
+A statement which does not match the next expectation panics with the
+statement and the expectations remaining, and so does dropping the connection
+with expectations left unmet. The transaction statements issued by diesel's
+transaction manager need no expectations.
+
+```
+use diesel::connection::LoadConnection;
+use diesel::pg::Pg;
+use diesel::prelude::*;
+use diesel::sql_types::Text;
+use diesel_tracing::mock::{contains, InstrumentedMockConnection, MockRow};
+
+#[derive(QueryableByName)]
+struct User {
+    #[diesel(sql_type = Text)]
+    name: String,
+}
+
+fn user_names(conn: &mut impl LoadConnection<Backend = Pg>) -> QueryResult<Vec<String>> {
+    let users = diesel::sql_query("SELECT name FROM users").load::<User>(conn)?;
+    Ok(users.into_iter().map(|user| user.name).collect())
+}
+
+let mut conn = InstrumentedMockConnection::new();
+conn.expect_query(
+    contains("FROM users"),
+    Ok(vec![MockRow::new().column("name", "ferris")]),
+);
+assert_eq!(user_names(&mut conn).unwrap(), ["ferris"]);
+```
+*/
+use std::collections::VecDeque;
+use std::fmt;
+use std::num::NonZeroU32;
+use std::ops::Range;
+
+use diesel::connection::{
+    AnsiTransactionManager, Connection, ConnectionSealed, DefaultLoadingMode, Instrumentation,
+    InstrumentationEvent, LoadConnection, SimpleConnection, TransactionManager,
+};
+use diesel::expression::QueryMetadata;
+use diesel::pg::{Pg, PgValue};
+use diesel::query_builder::{Query, QueryFragment, QueryId};
+use diesel::result::{ConnectionResult, QueryResult};
+use diesel::row::{Field, PartialRow, Row, RowIndex, RowSealed};
+#[cfg(not(feature = "passthrough"))]
+use tracing::field;
+use tracing::Span;
+
+use crate::config::TracingConfig;
+use crate::connection_id::ConnectionId;
+use crate::error::{error_event, record_error};
+#[cfg(all(feature = "statement-fields", not(feature = "passthrough")))]
+use crate::statement::truncate_statement;
+use crate::statement::{record_query_summary, record_statement_summary};
+use crate::statement::{render_sql, summarize_statement};
+
+/// Decides whether a statement, rendered without its bind values, matches an
+/// expectation.
+pub struct StatementMatcher {
+    description: String,
+    matches: Box<dyn Fn(&str) -> bool + Send>,
+}
+
+impl StatementMatcher {
+    /// A matcher described as `description` in panic messages, matching the
+    /// statements for which `matches` returns true.
+    pub fn new(
+        description: impl Into<String>,
+        matches: impl Fn(&str) -> bool + Send + 'static,
+    ) -> Self {
+        StatementMatcher {
+            description: description.into(),
+            matches: Box::new(matches),
+        }
+    }
+}
+
+impl fmt::Debug for StatementMatcher {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.description)
+    }
+}
+
+/// Match the statements containing `text`.
+#[must_use]
+pub fn contains(text: impl Into<String>) -> StatementMatcher {
+    let text = text.into();
+    StatementMatcher::new(format!("a statement containing `{text}`"), move |sql| {
+        sql.contains(&text)
+    })
+}
+
+/// Match any statement.
+#[must_use]
+pub fn any_statement() -> StatementMatcher {
+    StatementMatcher::new("any statement", |_| true)
+}
+
+/// A value which can be returned in a [`MockRow`], encoded in the binary
+/// format of postgresql that diesel's `FromSql` implementations read.
+pub trait MockValue {
+    /// The oid of the postgresql type of the value.
+    fn oid() -> NonZeroU32;
+
+    /// The encoded value, or `None` for `NULL`.
+    fn encode(self) -> Option<Vec<u8>>;
+}
+
+macro_rules! mock_value {
+    ($($ty:ty => $oid:literal, |$value:ident| $encode:expr;)*) => {
+        $(
+            impl MockValue for $ty {
+                fn oid() -> NonZeroU32 {
+                    NonZeroU32::new($oid).expect("oids are not zero")
+                }
+
+                fn encode(self) -> Option<Vec<u8>> {
+                    let $value = self;
+                    Some($encode)
+                }
+            }
+        )*
+    };
+}
+
+mock_value! {
+    bool => 16, |value| vec![u8::from(value)];
+    i16 => 21, |value| value.to_be_bytes().to_vec();
+    i32 => 23, |value| value.to_be_bytes().to_vec();
+    i64 => 20, |value| value.to_be_bytes().to_vec();
+    f32 => 700, |value| value.to_be_bytes().to_vec();
+    f64 => 701, |value| value.to_be_bytes().to_vec();
+    &str => 25, |value| value.as_bytes().to_vec();
+    String => 25, |value| value.into_bytes();
+    Vec<u8> => 17, |value| value;
+}
+
+impl<T: MockValue> MockValue for Option<T> {
+    fn oid() -> NonZeroU32 {
+        T::oid()
+    }
+
+    fn encode(self) -> Option<Vec<u8>> {
+        self.and_then(MockValue::encode)
+    }
+}
+
+#[derive(Clone, Debug)]
+struct MockColumn {
+    name: String,
+    oid: NonZeroU32,
+    value: Option<Vec<u8>>,
+}
+
+/// A row returned by a query of an [`InstrumentedMockConnection`], with
+/// named columns so that it can be loaded into `Queryable` and
+/// `QueryableByName` types alike.
+#[derive(Clone, Debug, Default)]
+pub struct MockRow {
+    columns: Vec<MockColumn>,
+}
+
+impl MockRow {
+    /// An empty row.
+    #[must_use]
+    pub fn new() -> Self {
+        MockRow::default()
+    }
+
+    /// Add a column named `name` holding `value`.
+    #[must_use]
+    pub fn column<T: MockValue>(mut self, name: impl Into<String>, value: T) -> Self {
+        self.columns.push(MockColumn {
+            name: name.into(),
+            oid: T::oid(),
+            value: value.encode(),
+        });
+        self
+    }
+}
+
+impl RowSealed for MockRow {}
+
+impl<'a> Row<'a, Pg> for MockRow {
+    type Field<'f>
+        = MockField<'f>
+    where
+        'a: 'f,
+        Self: 'f;
+    type InnerPartialRow = Self;
+
+    fn field_count(&self) -> usize {
+        self.columns.len()
+    }
+
+    fn get<'b, I>(&'b self, idx: I) -> Option<Self::Field<'b>>
+    where
+        'a: 'b,
+        Self: RowIndex<I>,
+    {
+        let idx = self.idx(idx)?;
+        self.columns.get(idx).map(|column| MockField { column })
+    }
+
+    fn partial_row(&self, range: Range<usize>) -> PartialRow<'_, Self::InnerPartialRow> {
+        PartialRow::new(self, range)
+    }
+}
+
+impl RowIndex<usize> for MockRow {
+    fn idx(&self, idx: usize) -> Option<usize> {
+        (idx < self.columns.len()).then_some(idx)
+    }
+}
+
+impl<'a> RowIndex<&'a str> for MockRow {
+    fn idx(&self, name: &'a str) -> Option<usize> {
+        self.columns.iter().position(|column| column.name == name)
+    }
+}
+
+/// A column of a [`MockRow`].
+#[derive(Debug)]
+pub struct MockField<'a> {
+    column: &'a MockColumn,
+}
+
+impl<'a> Field<'a, Pg> for MockField<'a> {
+    fn field_name(&self) -> Option<&str> {
+        Some(&self.column.name)
+    }
+
+    fn value(&self) -> Option<PgValue<'_>> {
+        let value = self.column.value.as_deref()?;
+        Some(PgValue::new(value, &self.column.oid))
+    }
+}
+
+/// The rows of a query of an [`InstrumentedMockConnection`].
+#[derive(Debug)]
+pub struct MockCursor(std::vec::IntoIter<MockRow>);
+
+impl Iterator for MockCursor {
+    type Item = QueryResult<MockRow>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.0.next().map(Ok)
+    }
+}
+
+#[derive(Debug)]
+enum MockResult {
+    Rows(Vec<MockRow>),
+    Count(usize),
+}
+
+#[derive(Debug)]
+struct Expectation {
+    matcher: StatementMatcher,
+    result: QueryResult<MockResult>,
+}
+
+impl fmt::Display for Expectation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let kind = match self.result {
+            Ok(MockResult::Rows(_)) => "query",
+            Ok(MockResult::Count(_)) => "execute",
+            Err(_) => "failing statement",
+        };
+        write!(f, "{kind} matching {:?}", self.matcher)
+    }
+}
+
+/// A statement run by `batch_execute`, passed to instrumentations.
+struct BatchQuery<'a>(&'a str);
+
+impl fmt::Debug for BatchQuery<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.0)
+    }
+}
+
+impl fmt::Display for BatchQuery<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.0)
+    }
+}
+
+impl diesel::connection::DebugQuery for BatchQuery<'_> {}
+
+/// A scripted postgresql connection, see the [module documentation](self).
+pub struct InstrumentedMockConnection {
+    expectations: VecDeque<Expectation>,
+    transaction_manager: AnsiTransactionManager,
+    instrumentation: Option<Box<dyn Instrumentation>>,
+    #[cfg_attr(feature = "passthrough", allow(dead_code))]
+    connection_id: ConnectionId,
+    config: TracingConfig,
+}
+
+impl fmt::Debug for InstrumentedMockConnection {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("InstrumentedMockConnection")
+            .field("expectations", &self.expectations)
+            .field("connection_id", &self.connection_id)
+            .field("config", &self.config)
+            .finish_non_exhaustive()
+    }
+}
+
+impl Default for InstrumentedMockConnection {
+    fn default() -> Self {
+        InstrumentedMockConnection::new()
+    }
+}
+
+impl InstrumentedMockConnection {
+    /// A connection with no expectations.
+    #[must_use]
+    pub fn new() -> Self {
+        InstrumentedMockConnection {
+            expectations: VecDeque::new(),
+            transaction_manager: AnsiTransactionManager::default(),
+            instrumentation: None,
+            connection_id: ConnectionId::new(),
+            config: TracingConfig::default(),
+        }
+    }
+
+    /// Expect a query matching `matcher` to be loaded next, returning
+    /// `result`.
+    pub fn expect_query(
+        &mut self,
+        matcher: StatementMatcher,
+        result: QueryResult<Vec<MockRow>>,
+    ) -> &mut Self {
+        self.expectations.push_back(Expectation {
+            matcher,
+            result: result.map(MockResult::Rows),
+        });
+        self
+    }
+
+    /// Expect a statement matching `matcher` to be executed next, with
+    /// `execute_returning_count` or `batch_execute`, returning `result`.
+    pub fn expect_execute(
+        &mut self,
+        matcher: StatementMatcher,
+        result: QueryResult<usize>,
+    ) -> &mut Self {
+        self.expectations.push_back(Expectation {
+            matcher,
+            result: result.map(MockResult::Count),
+        });
+        self
+    }
+
+    /// The number of expectations not yet met.
+    #[must_use]
+    pub fn remaining_expectations(&self) -> usize {
+        self.expectations.len()
+    }
+
+    /// Replace the configuration of the spans of the connection.
+    pub fn set_tracing_config(&mut self, config: TracingConfig) {
+        self.config = config;
+    }
+
+    /// Take the next expectation, panicking unless it matches `sql` and
+    /// expects a result of the kind run.
+    fn next_result(&mut self, sql: &str, loads: bool) -> QueryResult<MockResult> {
+        let matches = self.expectations.front().is_some_and(|expectation| {
+            let kind_matches = match expectation.result {
+                Ok(MockResult::Rows(_)) => loads,
+                Ok(MockResult::Count(_)) => !loads,
+                Err(_) => true,
+            };
+            kind_matches && (expectation.matcher.matches)(sql)
+        });
+        if !matches {
+            let remaining = if self.expectations.is_empty() {
+                "none".to_owned()
+            } else {
+                self.expectations
+                    .iter()
+                    .map(ToString::to_string)
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            };
+            let kind = if loads { "query" } else { "execute" };
+            panic!("unexpected {kind} `{sql}`, remaining expectations: {remaining}");
+        }
+        self.expectations
+            .pop_front()
+            .map(|expectation| expectation.result)
+            .expect("the expectation was matched")
+    }
+
+    #[cfg_attr(
+        not(feature = "passthrough"),
+        tracing::instrument(
+            name = "load",
+            fields(
+                db.system="postgresql",
+                otel.kind="client",
+                db.connection_id=self.connection_id.value(),
+                db.connection.id=self.connection_id.sequence(),
+                db.operation=field::Empty,
+                db.sql.table=field::Empty,
+                db.access_mode=field::Empty,
+                db.statement_fingerprint=field::Empty,
+                db.statement.hash=field::Empty,
+                db.bind_count=field::Empty,
+                code.function=field::Empty,
+                code.filepath=field::Empty,
+                code.lineno=field::Empty,
+                db.error.kind=field::Empty,
+                db.error.table=field::Empty,
+                db.error.constraint=field::Empty,
+                otel.status_code=field::Empty,
+                otel.status_message=field::Empty,
+                otel.name=field::Empty,
+                db.statement=field::Empty,
+            ),
+            target = "diesel_tracing::pg::query",
+            skip(self, source),
+        )
+    )]
+    fn traced_load<T>(&mut self, source: &T) -> QueryResult<Vec<MockRow>>
+    where
+        T: QueryFragment<Pg>,
+    {
+        record_query_summary::<Pg, _>(source, self.config.statement_span_names);
+        self.record_statement(source);
+        let query = diesel::debug_query::<Pg, _>(source);
+        self.instrumentation
+            .on_connection_event(InstrumentationEvent::start_query(&query));
+        let sql = render_sql::<Pg, _>(source).unwrap_or_default();
+        let result = match self.next_result(&sql, true) {
+            Ok(MockResult::Rows(rows)) => Ok(rows),
+            Ok(MockResult::Count(_)) => unreachable!("loads only match queries"),
+            Err(error) => Err(error),
+        };
+        self.instrumentation
+            .on_connection_event(InstrumentationEvent::finish_query(
+                &query,
+                result.as_ref().err(),
+            ));
+        result.inspect_err(|error| {
+            record_error(&Span::current(), error);
+            error_event!("diesel_tracing::pg::query", self.config.error_levels, error);
+        })
+    }
+
+    #[cfg_attr(
+        not(feature = "passthrough"),
+        tracing::instrument(
+            name = "execute_returning_count",
+            fields(
+                db.system="postgresql",
+                otel.kind="client",
+                db.connection_id=self.connection_id.value(),
+                db.connection.id=self.connection_id.sequence(),
+                db.operation=field::Empty,
+                db.sql.table=field::Empty,
+                db.access_mode=field::Empty,
+                db.statement_fingerprint=field::Empty,
+                db.statement.hash=field::Empty,
+                db.bind_count=field::Empty,
+                code.function=field::Empty,
+                code.filepath=field::Empty,
+                code.lineno=field::Empty,
+                db.error.kind=field::Empty,
+                db.error.table=field::Empty,
+                db.error.constraint=field::Empty,
+                otel.status_code=field::Empty,
+                otel.status_message=field::Empty,
+                otel.name=field::Empty,
+                db.statement=field::Empty,
+                db.rows_affected=field::Empty,
+            ),
+            target = "diesel_tracing::pg::query",
+            skip(self, source),
+        )
+    )]
+    fn traced_execute_returning_count<T>(&mut self, source: &T) -> QueryResult<usize>
+    where
+        T: QueryFragment<Pg>,
+    {
+        record_query_summary::<Pg, _>(source, self.config.statement_span_names);
+        self.record_statement(source);
+        let query = diesel::debug_query::<Pg, _>(source);
+        self.instrumentation
+            .on_connection_event(InstrumentationEvent::start_query(&query));
+        let sql = render_sql::<Pg, _>(source).unwrap_or_default();
+        let result = match self.next_result(&sql, false) {
+            Ok(MockResult::Count(count)) => Ok(count),
+            Ok(MockResult::Rows(_)) => unreachable!("executes only match counts"),
+            Err(error) => Err(error),
+        };
+        self.instrumentation
+            .on_connection_event(InstrumentationEvent::finish_query(
+                &query,
+                result.as_ref().err(),
+            ));
+        let count = result.inspect_err(|error| {
+            record_error(&Span::current(), error);
+            error_event!("diesel_tracing::pg::query", self.config.error_levels, error);
+        })?;
+        Span::current().record("db.rows_affected", count);
+
+        Ok(count)
+    }
+
+    #[cfg_attr(
+        not(feature = "passthrough"),
+        tracing::instrument(
+            name = "batch_execute",
+            fields(
+                db.system="postgresql",
+                otel.kind="client",
+                db.connection_id=self.connection_id.value(),
+                db.connection.id=self.connection_id.sequence(),
+                db.operation=field::Empty,
+                db.sql.table=field::Empty,
+                db.access_mode=field::Empty,
+                db.statement_fingerprint=field::Empty,
+                db.statement.hash=field::Empty,
+                db.error.kind=field::Empty,
+                db.error.table=field::Empty,
+                db.error.constraint=field::Empty,
+                otel.status_code=field::Empty,
+                otel.status_message=field::Empty,
+                otel.name=field::Empty,
+                db.statement=field::Empty,
+            ),
+            target = "diesel_tracing::pg::query",
+            skip(self, query),
+        )
+    )]
+    fn traced_batch_execute(&mut self, query: &str) -> QueryResult<()> {
+        record_statement_summary(query, self.config.statement_span_names);
+        #[cfg(all(feature = "statement-fields", not(feature = "passthrough")))]
+        Span::current().record(
+            "db.statement",
+            truncate_statement(query.to_owned(), self.config.max_statement_length()),
+        );
+        let debug_query = BatchQuery(query);
+        self.instrumentation
+            .on_connection_event(InstrumentationEvent::start_query(&debug_query));
+        let result = if is_transaction_statement(query) {
+            Ok(())
+        } else {
+            self.next_result(query, false).map(|_| ())
+        };
+        self.instrumentation
+            .on_connection_event(InstrumentationEvent::finish_query(
+                &debug_query,
+                result.as_ref().err(),
+            ));
+        result.inspect_err(|error| {
+            record_error(&Span::current(), error);
+            error_event!("diesel_tracing::pg::query", self.config.error_levels, error);
+        })
+    }
+
+    #[cfg_attr(
+        any(not(feature = "statement-fields"), feature = "passthrough"),
+        allow(unused_variables, clippy::unused_self)
+    )]
+    fn record_statement<T>(&self, source: &T)
+    where
+        T: QueryFragment<Pg>,
+    {
+        #[cfg(all(feature = "statement-fields", not(feature = "passthrough")))]
+        Span::current().record(
+            "db.statement",
+            truncate_statement(
+                diesel::debug_query::<Pg, _>(source).to_string(),
+                self.config.max_statement_length(),
+            ),
+        );
+    }
+}
+
+/// Whether a statement is one of those issued by diesel's transaction
+/// manager, which are accepted without an expectation.
+fn is_transaction_statement(sql: &str) -> bool {
+    summarize_statement(sql).operation.is_some_and(|operation| {
+        matches!(
+            operation.as_str(),
+            "BEGIN" | "COMMIT" | "ROLLBACK" | "SAVEPOINT" | "RELEASE"
+        )
+    })
+}
+
+impl Drop for InstrumentedMockConnection {
+    fn drop(&mut self) {
+        if !self.expectations.is_empty() && !std::thread::panicking() {
+            let remaining = self
+                .expectations
+                .iter()
+                .map(ToString::to_string)
+                .collect::<Vec<_>>()
+                .join(", ");
+            panic!("mock connection dropped with unmet expectations: {remaining}");
+        }
+    }
+}
+
+impl SimpleConnection for InstrumentedMockConnection {
+    fn batch_execute(&mut self, query: &str) -> QueryResult<()> {
+        self.traced_batch_execute(query)
+    }
+}
+
+impl ConnectionSealed for InstrumentedMockConnection {}
+
+impl Connection for InstrumentedMockConnection {
+    type Backend = Pg;
+    type TransactionManager = AnsiTransactionManager;
+
+    /// A connection with no expectations, ignoring the URL.
+    fn establish(_database_url: &str) -> ConnectionResult<Self> {
+        Ok(InstrumentedMockConnection::new())
+    }
+
+    fn execute_returning_count<T>(&mut self, source: &T) -> QueryResult<usize>
+    where
+        T: QueryFragment<Pg> + QueryId,
+    {
+        self.traced_execute_returning_count(source)
+    }
+
+    fn transaction_state(
+        &mut self,
+    ) -> &mut <Self::TransactionManager as TransactionManager<Self>>::TransactionStateData {
+        &mut self.transaction_manager
+    }
+
+    fn instrumentation(&mut self) -> &mut dyn Instrumentation {
+        &mut self.instrumentation
+    }
+
+    fn set_instrumentation(&mut self, instrumentation: impl Instrumentation) {
+        self.instrumentation = Some(Box::new(instrumentation));
+    }
+}
+
+impl LoadConnection<DefaultLoadingMode> for InstrumentedMockConnection {
+    type Cursor<'conn, 'query> = MockCursor;
+    type Row<'conn, 'query> = MockRow;
+
+    fn load<'conn, 'query, T>(
+        &'conn mut self,
+        source: T,
+    ) -> QueryResult<Self::Cursor<'conn, 'query>>
+    where
+        T: Query + QueryFragment<Pg> + QueryId + 'query,
+        Pg: QueryMetadata<T::SqlType>,
+    {
+        self.traced_load(&source)
+            .map(|rows| MockCursor(rows.into_iter()))
+    }
+}
+
+#[cfg(all(test, not(feature = "passthrough")))]
+mod tests {
+    use diesel::connection::{Connection, LoadConnection};
+    use diesel::pg::Pg;
+    use diesel::result::{DatabaseErrorKind, Error, QueryResult};
+    use diesel::sql_types::{Integer, Nullable, Text};
+    use diesel::{sql_query, QueryableByName, RunQueryDsl};
+
+    use super::*;
+    use crate::test_support::EventRecorder;
+
+    #[derive(Debug, PartialEq, QueryableByName)]
+    struct User {
+        #[diesel(sql_type = Integer)]
+        id: i32,
+        #[diesel(sql_type = Nullable<Text>)]
+        name: Option<String>,
+    }
+
+    fn load_users(conn: &mut impl LoadConnection<Backend = Pg>) -> QueryResult<Vec<User>> {
+        sql_query("SELECT id, name FROM users").load(conn)
+    }
+
+    #[test]
+    fn test_load_succeeds() {
+        let recorder = EventRecorder::default();
+        let users = tracing::subscriber::with_default(recorder.clone(), || {
+            let mut conn = InstrumentedMockConnection::new();
+            conn.expect_query(
+                contains("FROM users"),
+                Ok(vec![
+                    MockRow::new().column("id", 1).column("name", "ferris"),
+                    MockRow::new().column("id", 2).column("name", None::<&str>),
+                ]),
+            );
+            load_users(&mut conn).unwrap()
+        });
+
+        assert_eq!(
+            users,
+            [
+                User {
+                    id: 1,
+                    name: Some("ferris".to_owned())
+                },
+                User { id: 2, name: None },
+            ]
+        );
+        let span = recorder.span("load").unwrap();
+        assert_eq!(span.target, "diesel_tracing::pg::query");
+        assert_eq!(span.fields["db.system"], "postgresql");
+        assert_eq!(span.fields["db.operation"], "SELECT");
+        assert_eq!(span.fields["db.sql.table"], "users");
+    }
+
+    #[test]
+    fn test_load_fails() {
+        let recorder = EventRecorder::default();
+        let error = tracing::subscriber::with_default(recorder.clone(), || {
+            let mut conn = InstrumentedMockConnection::new();
+            conn.expect_query(
+                contains("FROM users"),
+                Err(Error::DatabaseError(
+                    DatabaseErrorKind::Unknown,
+                    Box::new("relation \"users\" does not exist".to_owned()),
+                )),
+            );
+            load_users(&mut conn).unwrap_err()
+        });
+
+        assert!(matches!(error, Error::DatabaseError(..)));
+        let span = recorder.span("load").unwrap();
+        assert_eq!(span.fields["otel.status_code"], "ERROR");
+        assert!(recorder
+            .events_in_span(&span.id)
+            .iter()
+            .any(|event| event.contains_key("error")
+                && event["target"] == "diesel_tracing::pg::query"));
+    }
+
+    #[test]
+    fn test_transaction_needs_no_expectations() {
+        let mut conn = InstrumentedMockConnection::new();
+        conn.expect_execute(contains("UPDATE users"), Ok(1));
+        let count = conn
+            .transaction(|conn| sql_query("UPDATE users SET name = 'ferris'").execute(conn))
+            .unwrap();
+        assert_eq!(count, 1);
+        assert_eq!(conn.remaining_expectations(), 0);
+    }
+
+    #[test]
+    #[should_panic(
+        expected = "unexpected query `SELECT id, name FROM users`, remaining expectations: \
+                    execute matching a statement containing `DELETE`"
+    )]
+    fn test_unexpected_statement_panics() {
+        let mut conn = InstrumentedMockConnection::new();
+        conn.expect_execute(contains("DELETE"), Ok(1));
+        let _ = load_users(&mut conn);
+    }
+}
@@ -1,31 +1,281 @@
+#[cfg(feature = "audit")]
+use std::sync::Arc;
+
 use diesel::associations::HasTable;
 use diesel::connection::{
-    AnsiTransactionManager, Connection, ConnectionSealed, DefaultLoadingMode, Instrumentation,
-    LoadConnection, MultiConnectionHelper, SimpleConnection, TransactionManager,
+    AnsiTransactionManager, BoxableConnection, Connection, ConnectionSealed, DefaultLoadingMode,
+    Instrumentation, LoadConnection, MultiConnectionHelper, SimpleConnection, TransactionManager,
 };
-use diesel::dsl::{Find, Update};
+use diesel::dsl::{sql, Find, Update};
 use diesel::expression::{is_aggregate, MixedAggregates, QueryMetadata, ValidGrouping};
 use diesel::migration::{MigrationConnection, CREATE_MIGRATIONS_TABLE};
 use diesel::mysql::{Mysql, MysqlConnection};
-use diesel::query_builder::{AsChangeset, IntoUpdateTarget, Query, QueryFragment, QueryId};
+use diesel::query_builder::{
+    AsChangeset, InsertStatement, IntoUpdateTarget, Query, QueryFragment, QueryId,
+};
 use diesel::query_dsl::methods::{ExecuteDsl, FindDsl};
 use diesel::query_dsl::{LoadQuery, UpdateAndFetchResults};
-use diesel::r2d2::R2D2Connection;
-use diesel::result::{ConnectionResult, QueryResult};
+use diesel::result::{ConnectionError, ConnectionResult, QueryResult};
+use diesel::sql_types::{BigInt, Unsigned};
 use diesel::RunQueryDsl;
-use diesel::{sql_query, Identifiable, Table};
-use tracing::{debug, instrument};
+use diesel::{select, sql_query, Identifiable, Insertable, Table};
+use tracing::{debug, debug_span, field, info_span, warn, Span};
+
+#[cfg(feature = "audit")]
+use crate::audit::{AuditSink, PendingAudit};
+use crate::batch::{record_batch, record_batch_error};
+use crate::chunks;
+use crate::config::{
+    default_span_namer, format_extra_fields, name_by_statement, SpanNamer, TracingConfig,
+};
+#[cfg(not(feature = "passthrough"))]
+use crate::config::{establish_span_name, span_name};
+use crate::connection_id::{ConnectionId, ConnectionSpan};
+use crate::error::{error_event, record_error, record_status};
+use crate::init::run_init_statements;
+use crate::instrumentation::{
+    SharedInstrumentationStack, StatementCacheProbe, TransactionWarnThresholds,
+};
+use crate::mysql_info::MYSQL_TLS_QUERY;
+use crate::operation::measure;
+use crate::read_only::{check_read_only, ReadOnly};
+use crate::sampling::{sampled_out_error_event, QuerySampler};
+use crate::semconv::deprecated;
+#[cfg(feature = "opentelemetry")]
+use crate::sql_comment::{current_traceparent, Commented};
+use crate::statement::render_sql;
+#[cfg(all(feature = "statement-fields", not(feature = "passthrough")))]
+use crate::statement::truncate_statement;
+use crate::statement::{record_query_summary, record_statement_summary};
+use crate::transaction::{
+    record_transaction_depth, record_transaction_outcome, run_savepoint, watch_transaction,
+};
+
+pub use crate::mysql_info::MysqlConnectionInfo;
+
+#[cfg(feature = "r2d2")]
+use diesel::r2d2::R2D2Connection;
+
+/// Query the id generated by the most recent `INSERT` of the session.
+fn query_last_insert_id(conn: &mut MysqlConnection) -> QueryResult<u64> {
+    select(sql::<Unsigned<BigInt>>("LAST_INSERT_ID()")).get_result(conn)
+}
+
+// the most bind parameters a mysql prepared statement can have
+const MAX_BINDS: usize = 65535;
+
+// whether diesel's migrations table exists, for ensure_migrations_table
+const MIGRATIONS_TABLE_EXISTS_QUERY: &str = "SELECT COUNT(*) FROM information_schema.tables \
+     WHERE table_schema = DATABASE() AND table_name = '__diesel_schema_migrations'";
+
+/// Create the span covering the lifetime of a connection, which the spans of
+/// its operations follow from.
+fn connection_span(info: &MysqlConnectionInfo, connection_id: ConnectionId) -> ConnectionSpan {
+    if cfg!(feature = "passthrough") {
+        return ConnectionSpan::new(Span::none());
+    }
+
+    let span = debug_span!(
+        target: "diesel_tracing::mysql::connection",
+        parent: None,
+        "db.connection",
+        db.namespace=%info.database,
+        db.name=deprecated(field::display(&info.database)),
+        db.system="mysql",
+        db.version=%info.version,
+        db.connection_id=connection_id.value(),
+        db.connection.id=connection_id.sequence(),
+        db.connection.name=field::Empty,
+        db.extra=field::Empty,
+        server.address=%info.host,
+        net.peer.name=deprecated(field::display(&info.host)),
+        server.port=%info.port,
+        net.peer.port=deprecated(field::display(&info.port)),
+        db.client.address=info.client_address.as_deref(),
+        db.client.port=info.client_port,
+        db.mysql.thread_id=info.thread_id,
+        db.connection.tls=info.tls,
+    );
+    Span::current().follows_from(&span);
+
+    ConnectionSpan::new(span)
+}
+
+/// Instrument a method of the connection `connection` with the spans given,
+/// each under an optional `#[cfg]` predicate, adding the fields describing the
+/// mysql connection and following from its connection span.
+///
+/// A macro cannot expand to an attribute, so this wraps the whole method.
+macro_rules! instrument_method {
+    (
+        connection = $conn:tt,
+        $(
+            $(#[cfg($cfg:meta)])?
+            instrument(
+                $(name = $name:literal,)?
+                fields($($field:tt)*),
+                $($arg:tt)*
+            )
+        ),+;
+        $item:item
+    ) => {
+        $(
+            #[cfg_attr(
+                all(not(feature = "passthrough") $(, $cfg)?),
+                tracing::instrument(
+                    $(name = $name,)?
+                    fields(
+                        db.namespace=%$conn.info.database,
+                        db.name=deprecated(field::display(&$conn.info.database)),
+                        db.system="mysql",
+                        db.version=%$conn.info.version,
+                        otel.kind="client",
+                        db.connection_id=$conn.connection_id.value(),
+                        db.connection.id=$conn.connection_id.sequence(),
+                        db.connection.name=$conn.connection_name.as_deref(),
+                        db.extra=$conn.extra_fields.as_deref(),
+                        db.connection.read_only=$conn.read_only.field(),
+                        server.address=%$conn.info.host,
+                        net.peer.name=deprecated(field::display(&$conn.info.host)),
+                        server.port=%$conn.info.port,
+                        net.peer.port=deprecated(field::display(&$conn.info.port)),
+                        db.client.address=$conn.info.client_address.as_deref(),
+                        db.client.port=$conn.info.client_port,
+                        db.mysql.thread_id=$conn.info.thread_id,
+                        db.connection.tls=$conn.info.tls,
+                        $($field)*
+                    ),
+                    follows_from = [&$conn.connection_span],
+                    $($arg)*
+                )
+            )]
+        )+
+        $item
+    };
+}
 
 pub struct InstrumentedMysqlConnection {
     inner: MysqlConnection,
+    #[cfg_attr(feature = "passthrough", allow(dead_code))]
+    info: MysqlConnectionInfo,
+    #[cfg_attr(feature = "passthrough", allow(dead_code))]
+    connection_id: ConnectionId,
+    connection_name: Option<String>,
+    extra_fields: Option<String>,
+    span_namer: Box<dyn SpanNamer>,
+    connection_span: ConnectionSpan,
+    config: TracingConfig,
+    instrumentation_stack: Option<SharedInstrumentationStack>,
+    sampler: QuerySampler,
+    read_only: ReadOnly,
+    transaction_warn: TransactionWarnThresholds,
+    // set by insert_in_chunks for the binds of its first statement to be
+    // checked against MAX_BINDS before it is run
+    check_binds: bool,
+    #[cfg_attr(not(feature = "opentelemetry"), allow(dead_code))]
+    sql_comments: bool,
+    #[cfg(feature = "audit")]
+    audit_sink: Option<Arc<dyn AuditSink>>,
+}
+
+/// Prints the connection information, never the URL it was established with.
+impl std::fmt::Debug for InstrumentedMysqlConnection {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("InstrumentedMysqlConnection")
+            .field("info", &self.info)
+            .field("connection_id", &self.connection_id)
+            .field("connection_name", &self.connection_name)
+            .field("config", &self.config)
+            .finish_non_exhaustive()
+    }
+}
+
+/// Wrap an established connection. Like `establish`, this queries the server
+/// for the information recorded on spans, which is left empty if the query
+/// fails.
+impl From<MysqlConnection> for InstrumentedMysqlConnection {
+    fn from(mut inner: MysqlConnection) -> Self {
+        let info = if cfg!(feature = "passthrough") {
+            MysqlConnectionInfo::default()
+        } else {
+            let mut info = MysqlConnectionInfo::query()
+                .get_result(&mut inner)
+                .unwrap_or_else(|error| {
+                    warn!(
+                        target: "diesel_tracing::mysql::connection",
+                        %error,
+                        "failed to query mysql connection information",
+                    );
+                    MysqlConnectionInfo::default()
+                });
+            info.apply_tls(sql_query(MYSQL_TLS_QUERY).get_result(&mut inner));
+            info
+        };
+        let connection_id = ConnectionId::new();
+        let connection_span = connection_span(&info, connection_id);
+
+        InstrumentedMysqlConnection {
+            inner,
+            info,
+            connection_id,
+            connection_name: None,
+            extra_fields: None,
+            span_namer: default_span_namer(),
+            connection_span,
+            config: TracingConfig::default(),
+            instrumentation_stack: None,
+            sampler: QuerySampler::default(),
+            read_only: ReadOnly::default(),
+            transaction_warn: TransactionWarnThresholds::default(),
+            check_binds: false,
+            sql_comments: false,
+            #[cfg(feature = "audit")]
+            audit_sink: None,
+        }
+    }
+}
+
+/// Unwrap the diesel connection, see
+/// [`InstrumentedMysqlConnection::into_inner`].
+impl From<InstrumentedMysqlConnection> for MysqlConnection {
+    fn from(conn: InstrumentedMysqlConnection) -> Self {
+        conn.into_inner()
+    }
+}
+
+impl AsRef<MysqlConnection> for InstrumentedMysqlConnection {
+    fn as_ref(&self) -> &MysqlConnection {
+        &self.inner
+    }
+}
+
+/// Queries run through the returned connection are not traced.
+impl AsMut<MysqlConnection> for InstrumentedMysqlConnection {
+    fn as_mut(&mut self) -> &mut MysqlConnection {
+        &mut self.inner
+    }
 }
 
 #[cfg(feature = "r2d2")]
 impl R2D2Connection for InstrumentedMysqlConnection {
-    fn ping(&mut self) -> QueryResult<()> {
-        self.inner.batch_execute("SELECT 1")?;
+    instrument_method! {
+        connection = self,
+        instrument(
+            fields(
+                otel.name=span_name(self.span_namer.as_ref(), "ping"),
+                db.operation="ping",
+            ),
+            target = "diesel_tracing::mysql::connection",
+            skip(self),
+            err,
+        );
+        fn ping(&mut self) -> QueryResult<()> {
+            self.inner.ping()
+        }
+    }
 
-        Ok(())
+    fn is_broken(&mut self) -> bool {
+        self.inner.is_broken()
     }
 }
 
@@ -44,11 +294,23 @@ impl MultiConnectionHelper for InstrumentedMysqlConnection {
 }
 
 impl SimpleConnection for InstrumentedMysqlConnection {
-    #[instrument(fields(db.system="mysql", otel.kind="client"), skip(self, query), err)]
     fn batch_execute(&mut self, query: &str) -> QueryResult<()> {
-        self.inner.batch_execute(query)?;
+        check_read_only!("diesel_tracing::mysql::query", self.read_only, || Some(
+            query.to_owned()
+        ))?;
+        #[cfg(feature = "audit")]
+        if let Some(audit) = self.start_audit(|| Some(query.to_owned())) {
+            let result = self.sampled_batch_execute(query);
+            audit.finish(result.as_ref().map(|&()| None));
+            return result;
+        }
+        self.sampled_batch_execute(query)
+    }
+}
 
-        Ok(())
+impl chunks::BindLimit for InstrumentedMysqlConnection {
+    fn check_binds(&mut self, check: bool) {
+        self.check_binds = check;
     }
 }
 
@@ -58,92 +320,1084 @@ impl Connection for InstrumentedMysqlConnection {
     type Backend = Mysql;
     type TransactionManager = AnsiTransactionManager;
 
-    #[instrument(fields(db.system="mysql", otel.kind="client"), skip(database_url), err)]
+    #[cfg_attr(
+        not(feature = "passthrough"),
+        tracing::instrument(
+            target = "diesel_tracing::mysql::connection",
+            fields(
+                db.namespace=field::Empty,
+                db.name=field::Empty,
+                db.system="mysql",
+                db.version=field::Empty,
+                otel.kind="client",
+                otel.name=establish_span_name("establish"),
+                db.connection_id=field::Empty,
+                db.connection.id=field::Empty,
+                server.address=field::Empty,
+                net.peer.name=field::Empty,
+                server.port=field::Empty,
+                net.peer.port=field::Empty,
+                db.client.address=field::Empty,
+                db.client.port=field::Empty,
+                db.mysql.thread_id=field::Empty,
+                db.connection.tls=field::Empty,
+                db.connection.tls_cipher=field::Empty,
+                otel.status_code=field::Empty,
+                otel.status_message=field::Empty,
+            ),
+            skip(database_url),
+            err,
+        )
+    )]
     fn establish(database_url: &str) -> ConnectionResult<InstrumentedMysqlConnection> {
+        debug!(target: "diesel_tracing::mysql::connection", "establishing mysql connection");
+        let mut conn = MysqlConnection::establish(database_url)
+            .inspect_err(|error| record_status(&tracing::Span::current(), error))?;
+
+        let info = if cfg!(feature = "passthrough") {
+            MysqlConnectionInfo::default()
+        } else {
+            debug!(
+                target: "diesel_tracing::mysql::connection",
+                "querying mysql connection information",
+            );
+            let mut info: MysqlConnectionInfo = MysqlConnectionInfo::query()
+                .get_result(&mut conn)
+                .map_err(ConnectionError::CouldntSetupConfiguration)?;
+            info.apply_tls(sql_query(MYSQL_TLS_QUERY).get_result(&mut conn));
+            info
+        };
+        info.record(&tracing::Span::current());
+
+        let instrumentation_stack = SharedInstrumentationStack::install(&mut conn);
+        let connection_id = ConnectionId::new();
+        connection_id.record(&tracing::Span::current());
+        let connection_span = connection_span(&info, connection_id);
+
         Ok(InstrumentedMysqlConnection {
-            inner: MysqlConnection::establish(database_url)?,
+            inner: conn,
+            info,
+            connection_id,
+            connection_name: None,
+            extra_fields: None,
+            span_namer: default_span_namer(),
+            connection_span,
+            config: TracingConfig::default(),
+            instrumentation_stack,
+            sampler: QuerySampler::default(),
+            read_only: ReadOnly::default(),
+            transaction_warn: TransactionWarnThresholds::default(),
+            check_binds: false,
+            sql_comments: false,
+            #[cfg(feature = "audit")]
+            audit_sink: None,
         })
     }
 
-    #[instrument(fields(db.system="mysql", otel.kind="client"), skip(self, f))]
     fn transaction<T, E, F>(&mut self, f: F) -> Result<T, E>
     where
         F: FnOnce(&mut Self) -> Result<T, E>,
         E: From<diesel::result::Error>,
     {
-        Self::TransactionManager::transaction(self, f)
+        if !self.config.trace_transactions {
+            return Self::TransactionManager::transaction(self, f);
+        }
+        let (sampled, outer) = self.sampler.begin_transaction();
+        let result = if sampled {
+            self.traced_transaction(f)
+        } else {
+            Self::TransactionManager::transaction(self, f)
+        };
+        self.sampler.end_transaction(outer);
+        result
     }
 
-    #[instrument(fields(db.system="mysql", otel.kind="client"), skip(self, source), err)]
     fn execute_returning_count<T>(&mut self, source: &T) -> QueryResult<usize>
     where
         T: QueryFragment<Mysql> + QueryId,
     {
-        self.inner.execute_returning_count(source)
+        if std::mem::take(&mut self.check_binds) {
+            chunks::check_binds::<Mysql, _>(source, MAX_BINDS)?;
+        }
+        check_read_only!("diesel_tracing::mysql::query", self.read_only, || {
+            render_sql::<Mysql, _>(source)
+        })?;
+        #[cfg(feature = "audit")]
+        if let Some(audit) = self.start_audit(|| render_sql::<Mysql, _>(source)) {
+            let result = self.sampled_execute_returning_count(source);
+            audit.finish(result.as_ref().map(|count| Some(*count)));
+            return result;
+        }
+        self.sampled_execute_returning_count(source)
     }
 
-    #[instrument(fields(db.system="mysql", otel.kind="client"), skip(self))]
     fn transaction_state(&mut self) -> &mut Self::TransactionManager {
-        self.inner.transaction_state()
+        if !self.config.trace_transactions {
+            return self.inner.transaction_state();
+        }
+        self.traced_transaction_state()
     }
 
-    #[instrument(fields(db.system="mysql", otel.kind="client"), skip(self))]
     fn instrumentation(&mut self) -> &mut dyn Instrumentation {
-        self.inner.instrumentation()
+        if !self.config.trace_transactions {
+            return self.inner.instrumentation();
+        }
+        self.traced_instrumentation()
     }
 
-    #[instrument(fields(db.system="mysql", otel.kind="client"), skip(self, instrumentation))]
-    fn set_instrumentation(&mut self, instrumentation: impl Instrumentation) {
-        self.inner.set_instrumentation(instrumentation)
+    instrument_method! {
+        connection = self,
+        instrument(
+            fields(
+                otel.name=span_name(self.span_namer.as_ref(), "set_instrumentation"),
+            ),
+            target = "diesel_tracing::mysql::connection",
+            skip(self, instrumentation),
+        );
+        fn set_instrumentation(&mut self, instrumentation: impl Instrumentation) {
+            if let Some(stack) =
+                SharedInstrumentationStack::push(&mut self.instrumentation_stack, instrumentation)
+            {
+                self.inner.set_instrumentation(stack);
+            }
+        }
     }
 }
 
 impl LoadConnection<DefaultLoadingMode> for InstrumentedMysqlConnection {
-    type Cursor<'conn, 'query> = <MysqlConnection as LoadConnection<DefaultLoadingMode>>::Cursor<'conn, 'query>
-        where
-            Self: 'conn;
-    type Row<'conn, 'query> = <MysqlConnection as LoadConnection<DefaultLoadingMode>>::Row<'conn, 'query>
-        where
-            Self: 'conn;
+    type Cursor<'conn, 'query>
+        = <MysqlConnection as LoadConnection<DefaultLoadingMode>>::Cursor<'conn, 'query>
+    where
+        Self: 'conn;
+    type Row<'conn, 'query>
+        = <MysqlConnection as LoadConnection<DefaultLoadingMode>>::Row<'conn, 'query>
+    where
+        Self: 'conn;
+
+    fn load<'conn, 'query, T>(
+        &'conn mut self,
+        source: T,
+    ) -> QueryResult<Self::Cursor<'conn, 'query>>
+    where
+        T: Query + QueryFragment<Self::Backend> + QueryId + 'query,
+        Self::Backend: QueryMetadata<T::SqlType>,
+    {
+        check_read_only!("diesel_tracing::mysql::query", self.read_only, || {
+            render_sql::<Mysql, _>(&source)
+        })?;
+        #[cfg(feature = "audit")]
+        let audit = self.start_audit(|| render_sql::<Mysql, _>(&source));
+        self.connection_span.count_query();
+        let traced = self.config.trace_load;
+        let levels = self.config.error_levels;
+        let result = if !traced || !self.sampler.sample() {
+            measure("mysql", move || self.inner.load(source)).inspect_err(|error| {
+                if traced {
+                    sampled_out_error_event!(
+                        "diesel_tracing::mysql::query",
+                        "mysql",
+                        levels,
+                        error
+                    );
+                }
+            })
+        } else {
+            self.traced_load(source)
+        };
+        #[cfg(feature = "audit")]
+        if let Some(audit) = audit {
+            audit.finish(result.as_ref().map(|_| None));
+        }
+
+        result
+    }
+}
+
+impl InstrumentedMysqlConnection {
+    /// Replace the configuration controlling which spans are created.
+    pub fn set_tracing_config(&mut self, config: TracingConfig) {
+        self.config = config;
+    }
+
+    /// Make the connection read only, or writable again, by setting
+    /// `transaction_read_only` for the session.
+    ///
+    /// While the connection is read only its spans record
+    /// `db.connection.read_only`, and a statement which writes is logged as a
+    /// warning before it is sent, so that the call site is traced even though
+    /// the database rejects it. See [`Self::set_strict_read_only`] to reject
+    /// it without sending it.
+    ///
+    /// # Errors
+    ///
+    /// Returns the error of the statement changing the setting, leaving the
+    /// connection as it was.
+    pub fn set_read_only(&mut self, read_only: bool) -> QueryResult<()> {
+        self.batch_execute(if read_only {
+            "SET SESSION transaction_read_only = 1"
+        } else {
+            "SET SESSION transaction_read_only = 0"
+        })?;
+        self.read_only.enabled = read_only;
+        Ok(())
+    }
+
+    /// Whether the connection was made read only with [`Self::set_read_only`].
+    #[must_use]
+    pub fn is_read_only(&self) -> bool {
+        self.read_only.enabled
+    }
+
+    /// Fail statements which write on a read only connection with an error,
+    /// rather than sending them to be rejected by the database.
+    pub fn set_strict_read_only(&mut self, strict: bool) {
+        self.read_only.strict = strict;
+    }
+
+    /// Log a warning while a transaction is still open once it has been open
+    /// for longer than `after`, so that long running transactions can be
+    /// caught before they finish. Its `transaction` span records how long it
+    /// took as `db.transaction.duration_ms` and the number of queries it ran
+    /// as `db.transaction.queries`. Disabled by default.
+    pub fn set_transaction_warn_after(&mut self, after: std::time::Duration) {
+        self.transaction_warn.after = Some(after);
+    }
 
+    /// Log a warning while a transaction is still open once it has run more
+    /// than `query_count` queries, like
+    /// [`set_transaction_warn_after`](Self::set_transaction_warn_after).
+    pub fn set_transaction_warn_query_count(&mut self, query_count: usize) {
+        self.transaction_warn.query_count = Some(query_count);
+    }
+
+    /// Replace every instrumentation installed on the connection with
+    /// `instrumentation`. `set_instrumentation` instead pushes onto the
+    /// connection's
+    /// [`InstrumentationStack`](crate::instrumentation::InstrumentationStack),
+    /// so that each instrumentation set receives its events.
+    pub fn replace_instrumentation(&mut self, instrumentation: impl Instrumentation) {
+        let stack = SharedInstrumentationStack::new(instrumentation);
+        self.inner.set_instrumentation(stack.clone());
+        self.instrumentation_stack = Some(stack);
+    }
+
+    /// The configuration controlling which spans are created.
+    #[must_use]
+    pub fn tracing_config(&self) -> TracingConfig {
+        self.config
+    }
+
+    /// Create spans for only the given fraction of queries, between 0 and 1,
+    /// to reduce the cost of tracing busy connections. The default of 1
+    /// traces every query.
+    ///
+    /// Queries which are sampled out still log an event if they fail, at the
+    /// level given to the error by the `error_levels` of the connection's
+    /// [`TracingConfig`]. The queries of a transaction are sampled together
+    /// with the transaction.
+    pub fn set_query_sample_rate(&mut self, rate: f32) {
+        self.sampler.set_rate(rate);
+    }
+
+    /// The fraction of queries which create spans.
+    #[must_use]
+    pub fn query_sample_rate(&self) -> f32 {
+        self.sampler.rate()
+    }
+
+    /// Append a sqlcommenter style `/*traceparent='...'*/` comment, naming the
+    /// OpenTelemetry trace and span of each statement, to the statements run
+    /// by `batch_execute` and `execute_returning_count`, so that the server's
+    /// logs and process list can be correlated with traces. Statements
+    /// are left as they are when the span has no OpenTelemetry context.
+    ///
+    /// Commented statements are not cached as prepared statements, as the
+    /// comment differs for each one, so this is off by default.
+    #[cfg(feature = "opentelemetry")]
+    pub fn enable_sql_comments(&mut self, enabled: bool) {
+        self.sql_comments = enabled;
+    }
+
+    /// The comment to append to a statement, if enabled.
+    #[cfg(feature = "opentelemetry")]
+    fn sql_comment(&self) -> Option<String> {
+        self.sql_comments.then(current_traceparent).flatten()
+    }
+
+    /// Establish a connection labelled with a name, such as `"replica"`, which
+    /// is recorded as `db.connection.name` on its spans.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the connection could not be established.
+    pub fn establish_named(database_url: &str, name: impl Into<String>) -> ConnectionResult<Self> {
+        let mut conn = Self::establish(database_url)?;
+        conn.set_connection_name(name);
+        Ok(conn)
+    }
+
+    /// Establish a connection and run session settings on it, such as
+    /// `SET statement_timeout = '5s'`, before it is used.
+    ///
+    /// Each statement is run in order through the traced `batch_execute`,
+    /// inside an `establish_with_init` span, and a debug event with its index
+    /// as `db.init.index` is emitted for each.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the connection could not be established, or one
+    /// naming the first init statement which failed.
     #[cfg_attr(
-        feature = "statement-fields",
-        instrument(
+        not(feature = "passthrough"),
+        tracing::instrument(
+            target = "diesel_tracing::mysql::connection",
             fields(
                 db.system="mysql",
                 otel.kind="client",
-                db.statement=%diesel::debug_query(&source),
+                otel.name=establish_span_name("establish_with_init"),
+                db.init.count=init_statements.len(),
             ),
-            skip(self, source),
+            skip(database_url, init_statements),
             err,
-        ),
+        )
     )]
-    #[cfg_attr(
-        not(feature = "statement-fields"),
+    pub fn establish_with_init(
+        database_url: &str,
+        init_statements: &[&str],
+    ) -> ConnectionResult<Self> {
+        let mut conn = Self::establish(database_url)?;
+        run_init_statements(&mut conn, init_statements)?;
+        Ok(conn)
+    }
+
+    /// Label the connection with a name, which is recorded as
+    /// `db.connection.name` on its spans.
+    pub fn set_connection_name(&mut self, name: impl Into<String>) {
+        let name = name.into();
+        self.connection_span
+            .record("db.connection.name", name.as_str());
+        self.connection_name = Some(name);
+    }
+
+    /// The name the connection is labelled with, if any.
+    #[must_use]
+    pub fn connection_name(&self) -> Option<&str> {
+        self.connection_name.as_deref()
+    }
+
+    /// Send an [`AuditEntry`](crate::audit::AuditEntry) to the sink for
+    /// every statement which writes, whether or not it is sampled for
+    /// tracing.
+    #[cfg(feature = "audit")]
+    pub fn set_audit_sink(&mut self, sink: Arc<dyn AuditSink>) {
+        self.audit_sink = Some(sink);
+    }
+
+    /// Start auditing a statement, if the connection has an audit sink and
+    /// the statement writes.
+    #[cfg(feature = "audit")]
+    fn start_audit(&self, sql: impl FnOnce() -> Option<String>) -> Option<PendingAudit> {
+        let sink = self.audit_sink.as_ref()?;
+        PendingAudit::start(sink, "mysql", self.connection_name.as_deref(), &sql()?)
+    }
+
+    instrument_method! {
+        connection = self,
         instrument(
+            name = "transaction_state",
             fields(
-                db.system="mysql",
-                otel.kind="client",
+                otel.name=span_name(self.span_namer.as_ref(), "transaction_state"),
             ),
-            skip(self, source),
+            target = "diesel_tracing::mysql::connection",
+            skip(self),
+        );
+        fn traced_transaction_state(&mut self) -> &mut AnsiTransactionManager {
+            self.inner.transaction_state()
+        }
+    }
+
+    instrument_method! {
+        connection = self,
+        instrument(
+            name = "instrumentation",
+            fields(
+                otel.name=span_name(self.span_namer.as_ref(), "instrumentation"),
+            ),
+            target = "diesel_tracing::mysql::connection",
+            skip(self),
+        );
+        fn traced_instrumentation(&mut self) -> &mut dyn Instrumentation {
+            self.inner.instrumentation()
+        }
+    }
+
+    fn sampled_batch_execute(&mut self, query: &str) -> QueryResult<()> {
+        self.connection_span.count_query();
+        let traced = self.config.trace_batch;
+        let levels = self.config.error_levels;
+        if !traced || !self.sampler.sample() {
+            return measure("mysql", || self.inner.batch_execute(query)).inspect_err(|error| {
+                if traced {
+                    sampled_out_error_event!(
+                        "diesel_tracing::mysql::query",
+                        "mysql",
+                        levels,
+                        error
+                    );
+                }
+            });
+        }
+        self.traced_batch_execute(query)
+    }
+
+    fn sampled_execute_returning_count<T>(&mut self, source: &T) -> QueryResult<usize>
+    where
+        T: QueryFragment<Mysql> + QueryId,
+    {
+        self.connection_span.count_query();
+        let traced = self.config.trace_execute;
+        let levels = self.config.error_levels;
+        if !traced || !self.sampler.sample() {
+            return measure("mysql", || self.inner.execute_returning_count(source)).inspect_err(
+                |error| {
+                    if traced {
+                        sampled_out_error_event!(
+                            "diesel_tracing::mysql::query",
+                            "mysql",
+                            levels,
+                            error
+                        );
+                    }
+                },
+            );
+        }
+        self.traced_execute_returning_count(source)
+    }
+
+    /// Attach static fields, such as a tenant or shard identifier, to every
+    /// span of the connection, replacing any attached before.
+    ///
+    /// Span field names are fixed when the span is declared, so the fields
+    /// are recorded together as `db.extra`, formatted as comma separated
+    /// `key=value` pairs, when each span is created. Passing no fields
+    /// removes them, recording `db.extra` as empty on the connection span,
+    /// since a field recorded on a span cannot be unset.
+    pub fn with_extra_fields(&mut self, fields: Vec<(String, String)>) {
+        self.extra_fields = format_extra_fields(fields);
+        self.connection_span
+            .record("db.extra", self.extra_fields.as_deref().unwrap_or_default());
+    }
+
+    /// Name the spans of the connection with a [`SpanNamer`], which records
+    /// the names it gives as `otel.name`.
+    #[must_use]
+    pub fn with_span_namer<N: SpanNamer>(mut self, namer: N) -> Self {
+        self.span_namer = Box::new(namer);
+        self
+    }
+
+    /// The information about the connection queried from the server, as
+    /// recorded on its spans.
+    #[must_use]
+    pub fn connection_info(&self) -> &MysqlConnectionInfo {
+        &self.info
+    }
+
+    /// Run `SELECT 1` on the connection, traced as a `batch_execute`, and
+    /// report whether it succeeded.
+    pub fn is_healthy(&mut self) -> bool {
+        self.batch_execute("SELECT 1").is_ok()
+    }
+
+    /// The wrapped diesel connection.
+    #[must_use]
+    pub fn inner(&self) -> &MysqlConnection {
+        &self.inner
+    }
+
+    /// The wrapped diesel connection, for calling APIs this wrapper does not
+    /// expose. Queries run on it directly are not traced.
+    pub fn inner_mut(&mut self) -> &mut MysqlConnection {
+        &mut self.inner
+    }
+
+    /// Recover the instrumented connection from a type erased
+    /// `dyn BoxableConnection<Mysql>`, or `None` if it holds another type of
+    /// connection.
+    pub fn downcast_from(conn: &mut dyn BoxableConnection<Mysql>) -> Option<&mut Self> {
+        conn.as_any_mut().downcast_mut()
+    }
+
+    /// Unwrap the diesel connection, ending the connection's span.
+    #[must_use]
+    pub fn into_inner(self) -> MysqlConnection {
+        self.inner
+    }
+
+    instrument_method! {
+        connection = self,
+        instrument(
+            fields(
+                otel.name=span_name(self.span_namer.as_ref(), "last_insert_id"),
+                db.operation="SELECT",
+                db.mysql.last_insert_id=field::Empty,
+            ),
+            target = "diesel_tracing::mysql::query",
+            skip(self),
             err,
+        );
+        /// The id generated for an `AUTO_INCREMENT` column by the most recent
+        /// `INSERT` of the session, as returned by `LAST_INSERT_ID()` and
+        /// recorded as `db.mysql.last_insert_id`.
+        ///
+        /// # Errors
+        ///
+        /// Returns an error if the query fails.
+        pub fn last_insert_id(&mut self) -> QueryResult<u64> {
+            let id = query_last_insert_id(&mut self.inner)?;
+            #[cfg(not(feature = "passthrough"))]
+            Span::current().record("db.mysql.last_insert_id", id);
+
+            Ok(id)
+        }
+    }
+
+    instrument_method! {
+        connection = self,
+        instrument(
+            fields(
+                otel.name=span_name(self.span_namer.as_ref(), "affected_rows"),
+                db.operation="SELECT",
+                db.rows_affected=field::Empty,
+            ),
+            target = "diesel_tracing::mysql::query",
+            skip(self),
+            err,
+        );
+        /// The number of rows changed by the previous statement of the session,
+        /// as returned by `ROW_COUNT()` and recorded as `db.rows_affected`. This
+        /// is -1 if the previous statement returned rows, so it must be called
+        /// straight after the statement it reports on.
+        ///
+        /// # Errors
+        ///
+        /// Returns an error if the query fails.
+        pub fn affected_rows(&mut self) -> QueryResult<i64> {
+            let rows = select(sql::<BigInt>("ROW_COUNT()")).get_result(&mut self.inner)?;
+            #[cfg(not(feature = "passthrough"))]
+            Span::current().record("db.rows_affected", rows);
+
+            Ok(rows)
+        }
+    }
+
+    /// Record the id generated by an `INSERT` on the current span as
+    /// `db.mysql.last_insert_id`.
+    fn record_last_insert_id(&mut self) {
+        if cfg!(feature = "passthrough") {
+            return;
+        }
+        match query_last_insert_id(&mut self.inner) {
+            Ok(id) => {
+                Span::current().record("db.mysql.last_insert_id", id);
+            }
+            Err(error) => debug!(
+                target: "diesel_tracing::mysql::query",
+                %error,
+                "failed to query the last insert id",
+            ),
+        }
+    }
+
+    instrument_method! {
+        connection = self,
+        #[cfg(feature = "statement-fields")]
+        instrument(
+            name = "batch_execute",
+            fields(
+                otel.name=span_name(self.span_namer.as_ref(), "batch_execute"),
+                db.operation=field::Empty,
+                db.sql.table=field::Empty,
+                db.access_mode=field::Empty,
+                db.statement_fingerprint=field::Empty,
+                db.statement.hash=field::Empty,
+                db.error.kind=field::Empty,
+                db.error.table=field::Empty,
+                db.error.constraint=field::Empty,
+                otel.status_code=field::Empty,
+                otel.status_message=field::Empty,
+                db.batch.size=field::Empty,
+                db.batch.error_index=field::Empty,
+                db.statement=truncate_statement(
+                    query.to_owned(),
+                    self.config.max_statement_length(),
+                ),
+            ),
+            target = "diesel_tracing::mysql::query",
+            skip(self, query),
         ),
-    )]
-    fn load<'conn, 'query, T>(
-        &'conn mut self,
-        source: T,
-    ) -> QueryResult<Self::Cursor<'conn, 'query>>
+        #[cfg(not(feature = "statement-fields"))]
+        instrument(
+            name = "batch_execute",
+            fields(
+                otel.name=span_name(self.span_namer.as_ref(), "batch_execute"),
+                db.operation=field::Empty,
+                db.sql.table=field::Empty,
+                db.access_mode=field::Empty,
+                db.statement_fingerprint=field::Empty,
+                db.statement.hash=field::Empty,
+                db.error.kind=field::Empty,
+                db.error.table=field::Empty,
+                db.error.constraint=field::Empty,
+                otel.status_code=field::Empty,
+                otel.status_message=field::Empty,
+                db.batch.size=field::Empty,
+                db.batch.error_index=field::Empty,
+            ),
+            target = "diesel_tracing::mysql::query",
+            skip(self, query),
+        );
+        fn traced_batch_execute(&mut self, query: &str) -> QueryResult<()> {
+            record_statement_summary(
+                query,
+                name_by_statement(self.config, self.span_namer.as_ref(), "batch_execute"),
+            );
+            record_batch(query);
+            #[cfg(feature = "opentelemetry")]
+            let commented = self
+                .sql_comment()
+                .map(|comment| format!("{query} {comment}"));
+            #[cfg(feature = "opentelemetry")]
+            let sql = commented.as_deref().unwrap_or(query);
+            #[cfg(not(feature = "opentelemetry"))]
+            let sql = query;
+            measure("mysql", || self.inner.batch_execute(sql)).inspect_err(|error| {
+                record_error(&tracing::Span::current(), error);
+                error_event!(
+                    "diesel_tracing::mysql::query",
+                    self.config.error_levels,
+                    error
+                );
+                record_batch_error(query, error);
+            })?;
+
+            Ok(())
+        }
+    }
+
+    /// Run the closure inside a savepoint with the given name, releasing it
+    /// if the closure returns `Ok` and rolling back to it otherwise, without
+    /// ending the enclosing transaction. The `savepoint` span records the
+    /// name as `db.transaction.savepoint`, making nested transactions easier
+    /// to tell apart than diesel's numbered savepoints.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the name is not a plain identifier, any error from
+    /// creating, releasing or rolling back to the savepoint, or the error
+    /// from the closure.
+    pub fn savepoint<T, E, F>(&mut self, name: &str, f: F) -> Result<T, E>
     where
-        T: Query + QueryFragment<Self::Backend> + QueryId + 'query,
-        Self::Backend: QueryMetadata<T::SqlType>,
+        F: FnOnce(&mut Self) -> Result<T, E>,
+        E: From<diesel::result::Error>,
     {
-        self.inner.load(source)
+        if !self.config.trace_transactions || !self.sampler.sample() {
+            return run_savepoint(self, name, f);
+        }
+        self.traced_savepoint(name, f)
+    }
+
+    instrument_method! {
+        connection = self,
+        instrument(
+            name = "insert_in_chunks",
+            fields(
+                otel.name=span_name(self.span_namer.as_ref(), "insert_in_chunks"),
+                db.operation="INSERT",
+                db.batch.chunks=field::Empty,
+                db.batch.total_rows=values.len(),
+                db.batch.rows_committed=field::Empty,
+                db.rows_affected=field::Empty,
+            ),
+            target = "diesel_tracing::mysql::query",
+            skip(self, table, values),
+            err,
+        );
+        /// Insert rows in chunks of at most `chunk_size`, keeping each statement
+        /// within the mysql limit of 65535 bind parameters, and return the number
+        /// of rows inserted.
+        ///
+        /// The insert is traced by an `insert_in_chunks` span recording
+        /// `db.batch.chunks` and `db.batch.total_rows`, with an `insert_chunk`
+        /// span for each chunk recording its `db.batch.index` and `db.batch.size`.
+        /// Chunks are not inserted in a transaction, so when one fails the rows of
+        /// the chunks before it remain, and their number is recorded as
+        /// `db.batch.rows_committed`.
+        ///
+        /// # Errors
+        ///
+        /// Returns an error, before inserting anything, if `chunk_size` is zero or
+        /// a chunk would exceed the bind parameter limit, or else the error of the
+        /// first chunk which fails.
+        pub fn insert_in_chunks<T, V>(
+            &mut self,
+            table: T,
+            values: Vec<V>,
+            chunk_size: usize,
+        ) -> QueryResult<usize>
+        where
+            T: Table + Copy,
+            Vec<V>: Insertable<T>,
+            InsertStatement<T, <Vec<V> as Insertable<T>>::Values>: ExecuteDsl<Self>,
+        {
+            chunks::insert_in_chunks(self, table, values, chunk_size, |index, size| {
+                info_span!(
+                    target: "diesel_tracing::mysql::query",
+                    "insert_chunk",
+                    db.batch.index = index,
+                    db.batch.size = size,
+                )
+            })
+        }
+    }
+
+    instrument_method! {
+        connection = self,
+        instrument(
+            name = "load_chunked",
+            fields(
+                otel.name=span_name(self.span_namer.as_ref(), "load_chunked"),
+                db.batch.chunks=field::Empty,
+                db.batch.total_rows=field::Empty,
+            ),
+            target = "diesel_tracing::mysql::query",
+            skip(self, query, f),
+            err,
+        );
+        /// Load the rows of a query in chunks of at most `chunk_size`, passing
+        /// each chunk to `f` as it is read, and return the number of rows loaded.
+        ///
+        /// The load is traced by a `load_chunked` span recording `db.batch.chunks`
+        /// and `db.batch.total_rows`, with a `load_chunk` span around each call of
+        /// `f` recording the chunk's `db.batch.index` and `db.batch.size`.
+        ///
+        /// # Errors
+        ///
+        /// Returns an error if `chunk_size` is zero, or else the first error of
+        /// the query or of `f`, which stops the load.
+        pub fn load_chunked<'conn, 'query, U, Q>(
+            &'conn mut self,
+            query: Q,
+            chunk_size: usize,
+            f: impl FnMut(Vec<U>) -> QueryResult<()>,
+        ) -> QueryResult<usize>
+        where
+            Q: LoadQuery<'query, Self, U, DefaultLoadingMode> + 'conn,
+            U: 'conn,
+        {
+            let chunk_span = |index, size| {
+                info_span!(
+                    target: "diesel_tracing::mysql::query",
+                    "load_chunk",
+                    db.batch.index = index,
+                    db.batch.size = size,
+                )
+            };
+            chunks::load_chunked::<_, DefaultLoadingMode, _, _, _>(
+                self, query, chunk_size, chunk_span, f,
+            )
+        }
+    }
+
+    instrument_method! {
+        connection = self,
+        instrument(
+            name = "savepoint",
+            fields(
+                otel.name=span_name(self.span_namer.as_ref(), "savepoint"),
+                db.transaction.savepoint=name,
+                db.transaction.rolled_back=field::Empty,
+                otel.status_code=field::Empty,
+            ),
+            target = "diesel_tracing::mysql::transaction",
+            skip(self, name, f),
+        );
+        fn traced_savepoint<T, E, F>(&mut self, name: &str, f: F) -> Result<T, E>
+        where
+            F: FnOnce(&mut Self) -> Result<T, E>,
+            E: From<diesel::result::Error>,
+        {
+            let result = run_savepoint(self, name, f);
+            record_transaction_outcome(&result);
+
+            result
+        }
+    }
+
+    instrument_method! {
+        connection = self,
+        instrument(
+            name = "transaction",
+            fields(
+                otel.name=span_name(self.span_namer.as_ref(), "transaction"),
+                db.transaction.depth=field::Empty,
+                db.transaction.rolled_back=field::Empty,
+                db.transaction.duration_ms=field::Empty,
+                db.transaction.queries=field::Empty,
+                otel.status_code=field::Empty,
+            ),
+            target = "diesel_tracing::mysql::transaction",
+            skip(self, f),
+        );
+        fn traced_transaction<T, E, F>(&mut self, f: F) -> Result<T, E>
+        where
+            F: FnOnce(&mut Self) -> Result<T, E>,
+            E: From<diesel::result::Error>,
+        {
+            record_transaction_depth(AnsiTransactionManager::transaction_manager_status_mut(self));
+            let stack = self.instrumentation_stack.clone();
+            let thresholds = self.transaction_warn;
+            let result = AnsiTransactionManager::transaction(self, |conn| {
+                watch_transaction(stack.as_ref(), thresholds, || f(conn))
+            });
+            record_transaction_outcome(&result);
+
+            result
+        }
+    }
+
+    instrument_method! {
+        connection = self,
+        #[cfg(feature = "statement-fields")]
+        instrument(
+            name = "execute_returning_count",
+            fields(
+                otel.name=span_name(self.span_namer.as_ref(), "execute_returning_count"),
+                db.operation=field::Empty,
+                db.sql.table=field::Empty,
+                db.access_mode=field::Empty,
+                db.statement_fingerprint=field::Empty,
+                db.statement.hash=field::Empty,
+                db.bind_count=field::Empty,
+                code.function=field::Empty,
+                code.filepath=field::Empty,
+                code.lineno=field::Empty,
+                db.error.kind=field::Empty,
+                db.error.table=field::Empty,
+                db.error.constraint=field::Empty,
+                otel.status_code=field::Empty,
+                otel.status_message=field::Empty,
+                db.statement.cached=field::Empty,
+                db.rows_affected=field::Empty,
+                db.mysql.last_insert_id=field::Empty,
+                db.statement=truncate_statement(
+                    diesel::debug_query::<Mysql, _>(source).to_string(),
+                    self.config.max_statement_length(),
+                ),
+            ),
+            target = "diesel_tracing::mysql::query",
+            skip(self, source),
+        ),
+        #[cfg(not(feature = "statement-fields"))]
+        instrument(
+            name = "execute_returning_count",
+            fields(
+                otel.name=span_name(self.span_namer.as_ref(), "execute_returning_count"),
+                db.operation=field::Empty,
+                db.sql.table=field::Empty,
+                db.access_mode=field::Empty,
+                db.statement_fingerprint=field::Empty,
+                db.statement.hash=field::Empty,
+                db.bind_count=field::Empty,
+                code.function=field::Empty,
+                code.filepath=field::Empty,
+                code.lineno=field::Empty,
+                db.error.kind=field::Empty,
+                db.error.table=field::Empty,
+                db.error.constraint=field::Empty,
+                otel.status_code=field::Empty,
+                otel.status_message=field::Empty,
+                db.statement.cached=field::Empty,
+                db.rows_affected=field::Empty,
+                db.mysql.last_insert_id=field::Empty,
+            ),
+            target = "diesel_tracing::mysql::query",
+            skip(self, source),
+        );
+        fn traced_execute_returning_count<T>(&mut self, source: &T) -> QueryResult<usize>
+        where
+            T: QueryFragment<Mysql> + QueryId,
+        {
+            let summary = record_query_summary::<Mysql, _>(
+                source,
+                name_by_statement(
+                    self.config,
+                    self.span_namer.as_ref(),
+                    "execute_returning_count",
+                ),
+            );
+            #[cfg(feature = "opentelemetry")]
+            let comment = self.sql_comment();
+            #[cfg(feature = "opentelemetry")]
+            let source = &Commented::new(source, comment.as_deref());
+            let cache_probe =
+                StatementCacheProbe::start(self.instrumentation_stack.as_ref(), source, &Mysql);
+            let result = measure("mysql", || self.inner.execute_returning_count(source));
+            if let Some(probe) = cache_probe {
+                probe.record(&tracing::Span::current());
+            }
+            let count = result.inspect_err(|error| {
+                record_error(&tracing::Span::current(), error);
+                error_event!(
+                    "diesel_tracing::mysql::query",
+                    self.config.error_levels,
+                    error
+                );
+            })?;
+            tracing::Span::current().record("db.rows_affected", count);
+            if count > 0
+                && summary.is_some_and(|summary| summary.operation.as_deref() == Some("INSERT"))
+            {
+                self.record_last_insert_id();
+            }
+
+            Ok(count)
+        }
+    }
+
+    instrument_method! {
+        connection = self,
+        #[cfg(feature = "statement-fields")]
+        instrument(
+            name = "load",
+            fields(
+                otel.name=span_name(self.span_namer.as_ref(), "load"),
+                db.operation=field::Empty,
+                db.sql.table=field::Empty,
+                db.access_mode=field::Empty,
+                db.statement_fingerprint=field::Empty,
+                db.statement.hash=field::Empty,
+                db.bind_count=field::Empty,
+                code.function=field::Empty,
+                code.filepath=field::Empty,
+                code.lineno=field::Empty,
+                db.error.kind=field::Empty,
+                db.error.table=field::Empty,
+                db.error.constraint=field::Empty,
+                otel.status_code=field::Empty,
+                otel.status_message=field::Empty,
+                db.statement.cached=field::Empty,
+                db.statement=truncate_statement(
+                    diesel::debug_query(&source).to_string(),
+                    self.config.max_statement_length(),
+                ),
+            ),
+            target = "diesel_tracing::mysql::query",
+            skip(self, source),
+        ),
+        #[cfg(not(feature = "statement-fields"))]
+        instrument(
+            name = "load",
+            fields(
+                otel.name=span_name(self.span_namer.as_ref(), "load"),
+                db.operation=field::Empty,
+                db.sql.table=field::Empty,
+                db.access_mode=field::Empty,
+                db.statement_fingerprint=field::Empty,
+                db.statement.hash=field::Empty,
+                db.bind_count=field::Empty,
+                code.function=field::Empty,
+                code.filepath=field::Empty,
+                code.lineno=field::Empty,
+                db.error.kind=field::Empty,
+                db.error.table=field::Empty,
+                db.error.constraint=field::Empty,
+                otel.status_code=field::Empty,
+                otel.status_message=field::Empty,
+                db.statement.cached=field::Empty,
+            ),
+            target = "diesel_tracing::mysql::query",
+            skip(self, source),
+        );
+        fn traced_load<'conn, 'query, T>(
+            &'conn mut self,
+            source: T,
+        ) -> QueryResult<<Self as LoadConnection<DefaultLoadingMode>>::Cursor<'conn, 'query>>
+        where
+            T: Query + QueryFragment<Mysql> + QueryId + 'query,
+            Mysql: QueryMetadata<T::SqlType>,
+        {
+            record_query_summary::<Mysql, _>(
+                &source,
+                name_by_statement(self.config, self.span_namer.as_ref(), "load"),
+            );
+            let cache_probe =
+                StatementCacheProbe::start(self.instrumentation_stack.as_ref(), &source, &Mysql);
+            let levels = self.config.error_levels;
+            let result = measure("mysql", move || self.inner.load(source));
+            if let Some(probe) = cache_probe {
+                probe.record(&tracing::Span::current());
+            }
+            result.inspect_err(|error| {
+                record_error(&tracing::Span::current(), error);
+                error_event!("diesel_tracing::mysql::query", levels, error);
+            })
+        }
     }
 }
 
 impl MigrationConnection for InstrumentedMysqlConnection {
-    fn setup(&mut self) -> QueryResult<usize> {
-        sql_query(CREATE_MIGRATIONS_TABLE).execute(self)
+    instrument_method! {
+        connection = self,
+        instrument(
+            name = "create_migrations_table",
+            fields(
+                otel.name=span_name(self.span_namer.as_ref(), "create_migrations_table"),
+                db.operation="create_migrations_table",
+            ),
+            target = "diesel_tracing::mysql::query",
+            skip(self),
+            err,
+        );
+        fn setup(&mut self) -> QueryResult<usize> {
+            sql_query(CREATE_MIGRATIONS_TABLE).execute(self)
+        }
+    }
+}
+
+impl InstrumentedMysqlConnection {
+    instrument_method! {
+        connection = self,
+        instrument(
+            name = "ensure_migrations_table",
+            fields(
+                otel.name=span_name(self.span_namer.as_ref(), "ensure_migrations_table"),
+                db.operation="ensure_migrations_table",
+                db.migrations_table.created=field::Empty,
+            ),
+            target = "diesel_tracing::mysql::query",
+            skip(self),
+            err,
+        );
+        /// Create diesel's migrations table unless it already exists, returning
+        /// whether it was created. The check is traced like any other query, and
+        /// a debug event says whether the table was created or already existed.
+        ///
+        /// # Errors
+        ///
+        /// Returns an error if the check or creating the table fails.
+        pub fn ensure_migrations_table(&mut self) -> QueryResult<bool> {
+            let exists = sql::<BigInt>(MIGRATIONS_TABLE_EXISTS_QUERY).get_result::<i64>(self)? > 0;
+            if exists {
+                debug!(target: "diesel_tracing::mysql::query", "migrations table already exists");
+            } else {
+                self.setup()?;
+                debug!(target: "diesel_tracing::mysql::query", "created migrations table");
+            }
+            Span::current().record("db.migrations_table.created", !exists);
+
+            Ok(!exists)
+        }
     }
 }
 
@@ -152,14 +1406,174 @@ where
     Changes: Copy + Identifiable,
     Changes: AsChangeset<Target = <Changes as HasTable>::Table> + IntoUpdateTarget,
     Changes::Table: FindDsl<Changes::Id>,
-    Update<Changes, Changes>: ExecuteDsl<MysqlConnection>,
-    Find<Changes::Table, Changes::Id>: LoadQuery<'b, MysqlConnection, Output>,
+    Update<Changes, Changes>: ExecuteDsl<Self>,
+    Find<Changes::Table, Changes::Id>: LoadQuery<'b, Self, Output>,
     <Changes::Table as Table>::AllColumns: ValidGrouping<()>,
     <<Changes::Table as Table>::AllColumns as ValidGrouping<()>>::IsAggregate:
         MixedAggregates<is_aggregate::No, Output = is_aggregate::No>,
+    Update<Changes, Changes>: QueryFragment<Mysql>,
 {
-    fn update_and_fetch(&mut self, changeset: Changes) -> QueryResult<Output> {
-        debug!("updating and fetching changeset");
-        self.inner.update_and_fetch(changeset)
+    instrument_method! {
+        connection = self,
+        #[cfg(feature = "statement-fields")]
+        instrument(
+            fields(
+                otel.name=span_name(self.span_namer.as_ref(), "update_and_fetch"),
+                db.statement=truncate_statement(
+                    diesel::debug_query::<Mysql, _>(&diesel::update(changeset).set(changeset))
+                        .to_string(),
+                    self.config.max_statement_length(),
+                ),
+            ),
+            target = "diesel_tracing::mysql::query",
+            skip(self, changeset),
+        ),
+        #[cfg(not(feature = "statement-fields"))]
+        instrument(
+            fields(
+                otel.name=span_name(self.span_namer.as_ref(), "update_and_fetch"),
+            ),
+            target = "diesel_tracing::mysql::query",
+            skip(self, changeset),
+        );
+        fn update_and_fetch(&mut self, changeset: Changes) -> QueryResult<Output> {
+            debug!(target: "diesel_tracing::mysql::query", "updating and fetching changeset");
+            let levels = self.config.error_levels;
+            diesel::update(changeset)
+                .set(changeset)
+                .execute(self)
+                .and_then(|_| FindDsl::find(Changes::table(), changeset.id()).get_result(self))
+                .inspect_err(|error| {
+                    error_event!("diesel_tracing::mysql::query", levels, error);
+                })
+        }
+    }
+}
+
+#[cfg(all(test, not(feature = "passthrough")))]
+mod tests {
+    use super::*;
+
+    #[test]
+    #[ignore = "requires a mysql server at MYSQL_URL"]
+    fn test_get_info_on_establish() {
+        InstrumentedMysqlConnection::establish(
+            &std::env::var("MYSQL_URL").expect("no MYSQL_URL env var specified"),
+        )
+        .expect("failed to establish connection or collect info");
+    }
+
+    #[cfg(feature = "r2d2")]
+    #[test]
+    #[ignore = "requires a mysql server at MYSQL_URL"]
+    fn test_ping_span_recorded() {
+        let recorder = crate::test_support::EventRecorder::default();
+        tracing::subscriber::with_default(recorder.clone(), || {
+            let mut conn = InstrumentedMysqlConnection::establish(
+                &std::env::var("MYSQL_URL").expect("no MYSQL_URL env var specified"),
+            )
+            .expect("failed to establish connection");
+            conn.ping().unwrap();
+        });
+
+        let span = recorder.span("ping").unwrap();
+        assert_eq!(span.fields["db.system"], "mysql");
+        assert_eq!(span.fields["otel.kind"], "client");
+        assert_eq!(span.fields["db.operation"], "ping");
+    }
+
+    #[test]
+    #[ignore = "requires a mysql server at MYSQL_URL"]
+    fn test_tls_recorded() {
+        let recorder = crate::test_support::EventRecorder::default();
+        tracing::subscriber::with_default(recorder.clone(), || {
+            let mut conn = InstrumentedMysqlConnection::establish(
+                &std::env::var("MYSQL_URL").expect("no MYSQL_URL env var specified"),
+            )
+            .expect("failed to establish connection");
+            conn.batch_execute("SELECT 1").unwrap();
+        });
+
+        let establish = recorder.span("establish").unwrap();
+        let tls = &establish.fields["db.connection.tls"];
+        assert!(tls == "true" || tls == "false");
+        let span = recorder.span("batch_execute").unwrap();
+        assert_eq!(&span.fields["db.connection.tls"], tls);
+    }
+
+    #[test]
+    #[ignore = "requires a mysql server at MYSQL_URL"]
+    fn test_thread_id_recorded() {
+        let recorder = crate::test_support::EventRecorder::default();
+        let thread_id = tracing::subscriber::with_default(recorder.clone(), || {
+            let mut conn = InstrumentedMysqlConnection::establish(
+                &std::env::var("MYSQL_URL").expect("no MYSQL_URL env var specified"),
+            )
+            .expect("failed to establish connection");
+            conn.batch_execute("SELECT 1").unwrap();
+            conn.connection_info().thread_id().unwrap()
+        });
+
+        for name in ["establish", "batch_execute"] {
+            let span = recorder.span(name).unwrap();
+            assert_eq!(span.fields["db.mysql.thread_id"], thread_id.to_string());
+        }
+    }
+
+    #[cfg(feature = "statement-fields")]
+    #[test]
+    #[ignore = "requires a mysql server at MYSQL_URL"]
+    fn test_execute_statement_recorded() {
+        let recorder = crate::test_support::EventRecorder::default();
+        tracing::subscriber::with_default(recorder.clone(), || {
+            let mut conn = InstrumentedMysqlConnection::establish(
+                &std::env::var("MYSQL_URL").expect("no MYSQL_URL env var specified"),
+            )
+            .expect("failed to establish connection");
+            conn.batch_execute("CREATE TEMPORARY TABLE users (id INTEGER)")
+                .unwrap();
+            sql_query("INSERT INTO users VALUES (?)")
+                .bind::<diesel::sql_types::Integer, _>(1)
+                .execute(&mut conn)
+                .unwrap();
+        });
+
+        let span = recorder.span("execute_returning_count").unwrap();
+        assert_eq!(
+            span.fields["db.statement"],
+            "INSERT INTO users VALUES (?) -- binds: [1]"
+        );
+    }
+
+    #[test]
+    #[ignore = "requires a mysql server at MYSQL_URL"]
+    fn test_last_insert_id_recorded() {
+        let recorder = crate::test_support::EventRecorder::default();
+        tracing::subscriber::with_default(recorder.clone(), || {
+            let mut conn = InstrumentedMysqlConnection::establish(
+                &std::env::var("MYSQL_URL").expect("no MYSQL_URL env var specified"),
+            )
+            .expect("failed to establish connection");
+            conn.batch_execute(
+                "CREATE TEMPORARY TABLE users (id INTEGER AUTO_INCREMENT PRIMARY KEY, name TEXT)",
+            )
+            .unwrap();
+            sql_query("INSERT INTO users (name) VALUES ('alice'), ('bob')")
+                .execute(&mut conn)
+                .unwrap();
+            assert_eq!(conn.affected_rows().unwrap(), 2);
+            assert_eq!(conn.last_insert_id().unwrap(), 1);
+        });
+
+        let span = recorder.span("execute_returning_count").unwrap();
+        assert_eq!(span.fields["db.mysql.last_insert_id"], "1");
+        assert_eq!(
+            recorder.span("affected_rows").unwrap().fields["db.rows_affected"],
+            "2"
+        );
+        assert_eq!(
+            recorder.span("last_insert_id").unwrap().fields["db.mysql.last_insert_id"],
+            "1"
+        );
     }
 }
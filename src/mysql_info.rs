@@ -0,0 +1,230 @@
+use diesel::backend::Backend;
+use diesel::deserialize::{self, FromStaticSqlRow, Queryable};
+use diesel::dsl::sql;
+use diesel::expression::SqlLiteral;
+#[cfg(feature = "mysql")]
+use diesel::result::QueryResult;
+use diesel::sql_types::{BigInt, Integer, Nullable, Text, Unsigned};
+#[cfg(feature = "mysql")]
+use diesel::QueryableByName;
+
+use crate::semconv::record_renamed;
+
+// db.namespace, db.version, server.address and server.port, then the client's
+// host and port as seen by the server for db.client.address and
+// db.client.port, then the thread ID for db.mysql.thread_id
+// DATABASE() is NULL when no default database was selected
+const MYSQL_CONNECTION_INFO_QUERY: &str =
+    "SELECT COALESCE(DATABASE(), ''), VERSION(), @@hostname, @@port, \
+     (SELECT HOST FROM information_schema.PROCESSLIST WHERE ID = CONNECTION_ID()), \
+     CONNECTION_ID()";
+
+// db.connection.tls and db.connection.tls_cipher, the cipher is empty for
+// connections without TLS
+#[cfg(feature = "mysql")]
+pub(crate) const MYSQL_TLS_QUERY: &str = "SHOW STATUS LIKE 'Ssl_cipher'";
+
+/// The information about a mysql connection recorded on its spans, queried
+/// from the server when the connection is established.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct MysqlConnectionInfo {
+    pub(crate) database: String,
+    pub(crate) version: String,
+    pub(crate) host: String,
+    pub(crate) port: u32,
+    // db.client.address, localhost when connected over a unix socket
+    pub(crate) client_address: Option<String>,
+    // db.client.port
+    pub(crate) client_port: Option<u32>,
+    // db.connection.tls, unknown if the status could not be queried
+    pub(crate) tls: Option<bool>,
+    // db.connection.tls_cipher
+    pub(crate) tls_cipher: Option<String>,
+    // db.mysql.thread_id, the ID of the connection's server thread
+    pub(crate) thread_id: Option<u64>,
+}
+
+impl MysqlConnectionInfo {
+    /// The name of the default database, recorded as `db.namespace`, empty if
+    /// none was selected.
+    #[must_use]
+    pub fn database(&self) -> &str {
+        &self.database
+    }
+
+    /// The version string of the server, recorded as `db.version`.
+    #[must_use]
+    pub fn version(&self) -> &str {
+        &self.version
+    }
+
+    /// The host name of the server, recorded as `server.address`.
+    #[must_use]
+    pub fn host(&self) -> &str {
+        &self.host
+    }
+
+    /// The port of the server, recorded as `server.port`.
+    #[must_use]
+    pub fn port(&self) -> u32 {
+        self.port
+    }
+
+    /// Whether the connection uses TLS, recorded as `db.connection.tls`.
+    #[must_use]
+    pub fn tls(&self) -> Option<bool> {
+        self.tls
+    }
+
+    /// The ID of the server thread of the connection, as returned by
+    /// `CONNECTION_ID()` and listed by `SHOW PROCESSLIST`, recorded as
+    /// `db.mysql.thread_id`.
+    #[must_use]
+    pub fn thread_id(&self) -> Option<u64> {
+        self.thread_id
+    }
+}
+
+/// A row of `SHOW STATUS`.
+#[cfg(feature = "mysql")]
+#[derive(QueryableByName)]
+pub(crate) struct MysqlStatusRow {
+    #[diesel(sql_type = Text, column_name = Value)]
+    value: String,
+}
+
+type MysqlConnectionInfoRow = (String, String, String, u32, Option<String>, u64);
+
+impl<ST, DB> Queryable<ST, DB> for MysqlConnectionInfo
+where
+    DB: Backend,
+    MysqlConnectionInfoRow: FromStaticSqlRow<ST, DB>,
+{
+    type Row = MysqlConnectionInfoRow;
+
+    fn build(
+        (database, version, host, port, client, thread_id): Self::Row,
+    ) -> deserialize::Result<Self> {
+        let (client_address, client_port) = parse_client(client.as_deref());
+        Ok(MysqlConnectionInfo {
+            database,
+            version,
+            host,
+            port,
+            client_address: Some(client_address),
+            client_port,
+            tls: None,
+            tls_cipher: None,
+            thread_id: Some(thread_id),
+        })
+    }
+}
+
+impl MysqlConnectionInfo {
+    /// The query selecting the connection information.
+    #[allow(clippy::type_complexity)]
+    pub(crate) fn query() -> SqlLiteral<(
+        Text,
+        Text,
+        Text,
+        Unsigned<Integer>,
+        Nullable<Text>,
+        Unsigned<BigInt>,
+    )> {
+        sql(MYSQL_CONNECTION_INFO_QUERY)
+    }
+
+    /// Apply the TLS state of the connection from its `Ssl_cipher` status,
+    /// leaving it unknown if the status could not be queried.
+    #[cfg(feature = "mysql")]
+    pub(crate) fn apply_tls(&mut self, row: QueryResult<MysqlStatusRow>) {
+        match row {
+            Ok(row) => {
+                self.tls = Some(!row.value.is_empty());
+                self.tls_cipher = Some(row.value).filter(|cipher| !cipher.is_empty());
+            }
+            Err(error) => {
+                tracing::debug!(%error, "failed to query mysql tls state");
+            }
+        }
+    }
+
+    pub(crate) fn record(&self, span: &tracing::Span) {
+        record_renamed(span, "db.namespace", "db.name", self.database.as_str());
+        span.record("db.version", self.version.as_str());
+        record_renamed(span, "server.address", "net.peer.name", self.host.as_str());
+        record_renamed(span, "server.port", "net.peer.port", self.port);
+        if let Some(client_address) = &self.client_address {
+            span.record("db.client.address", client_address.as_str());
+        }
+        if let Some(client_port) = self.client_port {
+            span.record("db.client.port", client_port);
+        }
+        if let Some(tls) = self.tls {
+            span.record("db.connection.tls", tls);
+        }
+        if let Some(tls_cipher) = &self.tls_cipher {
+            span.record("db.connection.tls_cipher", tls_cipher.as_str());
+        }
+        if let Some(thread_id) = self.thread_id {
+            span.record("db.mysql.thread_id", thread_id);
+        }
+    }
+}
+
+/// Split the `host:port` of a process list entry, which is only `localhost`
+/// for connections over a unix socket.
+fn parse_client(client: Option<&str>) -> (String, Option<u32>) {
+    let Some(client) = client.filter(|client| !client.is_empty()) else {
+        return ("localhost".to_string(), None);
+    };
+    match client.rsplit_once(':') {
+        Some((address, port)) if !address.is_empty() => match port.parse() {
+            Ok(port) => (address.to_string(), Some(port)),
+            Err(_) => (client.to_string(), None),
+        },
+        _ => (client.to_string(), None),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[cfg(feature = "mysql")]
+    #[test]
+    fn test_apply_tls() {
+        let mut info = MysqlConnectionInfo::default();
+        info.apply_tls(Err(diesel::result::Error::NotFound));
+        assert_eq!(info.tls, None);
+
+        info.apply_tls(Ok(MysqlStatusRow {
+            value: String::new(),
+        }));
+        assert_eq!(info.tls, Some(false));
+        assert_eq!(info.tls_cipher, None);
+
+        info.apply_tls(Ok(MysqlStatusRow {
+            value: "TLS_AES_128_GCM_SHA256".to_string(),
+        }));
+        assert_eq!(info.tls, Some(true));
+        assert_eq!(info.tls_cipher.as_deref(), Some("TLS_AES_128_GCM_SHA256"));
+    }
+
+    #[test]
+    fn test_parse_client() {
+        assert_eq!(
+            parse_client(Some("10.0.0.5:51234")),
+            ("10.0.0.5".to_string(), Some(51234))
+        );
+        assert_eq!(
+            parse_client(Some("::1:51234")),
+            ("::1".to_string(), Some(51234))
+        );
+        assert_eq!(
+            parse_client(Some("localhost")),
+            ("localhost".to_string(), None)
+        );
+        assert_eq!(parse_client(None), ("localhost".to_string(), None));
+    }
+}
@@ -0,0 +1,104 @@
+use crate::time_tracking;
+
+/// Run a client operation, recording the `db.client.operations` counter and
+/// `db.client.operation.duration` histogram for it.
+///
+/// Transaction `BEGIN`, `COMMIT` and `ROLLBACK` statements are issued through
+/// `batch_execute` by the transaction manager, so they are measured here too.
+#[cfg(feature = "metrics")]
+pub(crate) fn measure<T, E>(
+    db_system: &'static str,
+    operation: impl FnOnce() -> Result<T, E>,
+) -> Result<T, E> {
+    let started = std::time::Instant::now();
+    let result = operation();
+    let elapsed = started.elapsed();
+    time_tracking::record_query(elapsed);
+    let outcome = if result.is_ok() { "ok" } else { "error" };
+
+    metrics::counter!(
+        "db.client.operations",
+        "db.system" => db_system,
+        "outcome" => outcome,
+    )
+    .increment(1);
+    metrics::histogram!("db.client.operation.duration", "db.system" => db_system)
+        .record(elapsed.as_secs_f64());
+
+    result
+}
+
+/// Run a client operation. Without the `metrics` feature it is only timed
+/// for the active [`time_tracking`] scopes, if there are any.
+#[cfg(not(feature = "metrics"))]
+#[inline]
+pub(crate) fn measure<T, E>(
+    _db_system: &'static str,
+    operation: impl FnOnce() -> Result<T, E>,
+) -> Result<T, E> {
+    if !time_tracking::is_active() {
+        return operation();
+    }
+    let started = std::time::Instant::now();
+    let result = operation();
+    time_tracking::record_query(started.elapsed());
+
+    result
+}
+
+#[cfg(all(test, feature = "metrics", feature = "sqlite"))]
+mod tests {
+    use crate::sqlite::InstrumentedSqliteConnection;
+    use diesel::connection::{Connection, SimpleConnection};
+    use diesel::sql_query;
+    use diesel::RunQueryDsl;
+    use metrics_util::debugging::{DebugValue, DebuggingRecorder};
+    use metrics_util::MetricKind;
+
+    #[test]
+    fn test_measure_sqlite_operations() {
+        let recorder = DebuggingRecorder::new();
+        let snapshotter = recorder.snapshotter();
+
+        metrics::with_local_recorder(&recorder, || {
+            let mut conn = InstrumentedSqliteConnection::establish(":memory:").unwrap();
+            conn.batch_execute("CREATE TABLE users (id INTEGER)")
+                .unwrap();
+            sql_query("INSERT INTO users VALUES (1)")
+                .execute(&mut conn)
+                .unwrap();
+            conn.batch_execute("SELECT * FROM missing").unwrap_err();
+        });
+
+        let metrics = snapshotter.snapshot().into_vec();
+        let find = |kind: MetricKind, name: &str, outcome: Option<&str>| {
+            metrics.iter().find_map(|(key, _, _, value)| {
+                let key_matches = key.kind() == kind
+                    && key.key().name() == name
+                    && key
+                        .key()
+                        .labels()
+                        .any(|l| l.key() == "db.system" && l.value() == "sqlite")
+                    && outcome.is_none_or(|outcome| {
+                        key.key()
+                            .labels()
+                            .any(|l| l.key() == "outcome" && l.value() == outcome)
+                    });
+                key_matches.then_some(value)
+            })
+        };
+
+        assert_eq!(
+            find(MetricKind::Counter, "db.client.operations", Some("ok")),
+            Some(&DebugValue::Counter(2))
+        );
+        assert_eq!(
+            find(MetricKind::Counter, "db.client.operations", Some("error")),
+            Some(&DebugValue::Counter(1))
+        );
+        assert!(matches!(
+            find(MetricKind::Histogram, "db.client.operation.duration", None),
+            Some(DebugValue::Histogram(values)) if values.len() == 3
+        ));
+    }
+}
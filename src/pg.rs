@@ -1,51 +1,356 @@
+#[cfg(feature = "reconnect")]
+use std::sync::atomic::{AtomicBool, Ordering};
+#[cfg(any(feature = "audit", feature = "reconnect"))]
+use std::sync::Arc;
+
 use diesel::associations::HasTable;
 use diesel::connection::{
-    AnsiTransactionManager, Connection, ConnectionSealed, DefaultLoadingMode,
+    AnsiTransactionManager, BoxableConnection, Connection, ConnectionSealed, DefaultLoadingMode,
     MultiConnectionHelper, SimpleConnection,
 };
 use diesel::connection::{Instrumentation, LoadConnection, TransactionManager};
-use diesel::deserialize::Queryable;
-use diesel::dsl::Update;
+use diesel::deserialize::FromSqlRow;
+use diesel::dsl::{sql, Update};
 use diesel::expression::{is_aggregate, MixedAggregates, QueryMetadata, ValidGrouping};
+use diesel::expression::{AppearsOnTable, Expression};
+use diesel::internal::table_macro::StaticQueryFragment;
 use diesel::migration::{MigrationConnection, CREATE_MIGRATIONS_TABLE};
-use diesel::pg::{GetPgMetadataCache, Pg, PgConnection, PgRowByRowLoadingMode, TransactionBuilder};
-use diesel::query_builder::{AsChangeset, IntoUpdateTarget, Query, QueryFragment, QueryId};
+use diesel::pg::{
+    CopyFromQuery, CopyTarget, GetPgMetadataCache, Pg, PgConnection, PgMetadataCacheKey,
+    PgQueryBuilder, PgRowByRowLoadingMode, TransactionBuilder,
+};
+use diesel::query_builder::QueryBuilder;
+use diesel::query_builder::{
+    AsChangeset, InsertStatement, IntoUpdateTarget, Query, QueryFragment, QueryId,
+};
+use diesel::query_dsl::methods::ExecuteDsl;
 use diesel::query_dsl::{LoadQuery, UpdateAndFetchResults};
+use diesel::result::{ConnectionError, ConnectionResult, DatabaseErrorKind, QueryResult};
+use diesel::sql_types::{Array, BigInt, Oid, Text};
+use diesel::{select, Insertable, QueryableByName, Table};
+use diesel::{sql_query, RunQueryDsl};
+use diesel::{ExecuteCopyFromDsl, Selectable};
+use tracing::{debug, debug_span, field, info_span, trace, warn, Span};
+
+#[cfg(feature = "audit")]
+use crate::audit::{AuditSink, PendingAudit};
+use crate::batch::{record_batch, record_batch_error};
+use crate::chunks;
+use crate::config::{
+    default_span_namer, format_extra_fields, name_by_statement, SpanNamer, TracingConfig,
+};
+#[cfg(not(feature = "passthrough"))]
+use crate::config::{establish_span_name, span_name};
+use crate::connection_id::{ConnectionId, ConnectionSpan};
+use crate::error::{error_event, record_error, record_status};
+#[cfg(all(feature = "statement-fields", not(feature = "passthrough")))]
+use crate::explain::{is_explainable, is_slow, record_plan, Explain};
+use crate::init::run_init_statements;
+use crate::instrumentation::{
+    SharedInstrumentationStack, StatementCacheProbe, TransactionWarnThresholds,
+};
+use crate::operation::measure;
+use crate::pg_info::{
+    current_database, current_schema, current_schemas, current_setting, inet_client_addr,
+    inet_client_port, inet_server_addr, inet_server_port, pg_backend_pid, set_config, version,
+    PG_TLS_QUERY,
+};
+use crate::read_only::{check_read_only, ReadOnly};
+#[cfg(feature = "reconnect")]
+use crate::retry::ExponentialBackoff;
+use crate::sampling::{sampled_out_error_event, QuerySampler};
+use crate::semconv::deprecated;
+#[cfg(feature = "opentelemetry")]
+use crate::sql_comment::{current_traceparent, Commented};
+use crate::statement::render_sql;
+#[cfg(all(feature = "statement-fields", not(feature = "passthrough")))]
+use crate::statement::truncate_statement;
+use crate::statement::{record_query_summary, record_statement_summary};
+use crate::transaction::{
+    record_transaction_depth, record_transaction_outcome, run_savepoint, watch_transaction,
+};
+#[cfg(feature = "reconnect")]
+use crate::url::mask_connection_url;
+
+pub use crate::pg_config::PgConnectConfig;
+pub use crate::pg_info::PgConnectionInfo;
+
+#[cfg(feature = "r2d2")]
 use diesel::r2d2::R2D2Connection;
-use diesel::result::{ConnectionError, ConnectionResult, QueryResult};
-use diesel::{define_sql_function, sql_query, RunQueryDsl};
-use diesel::{select, Table};
-use tracing::{debug, field, instrument};
-
-// https://www.postgresql.org/docs/12/functions-info.html
-// db.name
-define_sql_function!(fn current_database() -> diesel::sql_types::Text);
-// net.peer.ip
-define_sql_function!(fn inet_server_addr() -> diesel::sql_types::Inet);
-// net.peer.port
-define_sql_function!(fn inet_server_port() -> diesel::sql_types::Integer);
-// db.version
-define_sql_function!(fn version() -> diesel::sql_types::Text);
-
-#[derive(Queryable, Clone, Debug, PartialEq)]
-struct PgConnectionInfo {
-    current_database: String,
-    inet_server_addr: ipnetwork::IpNetwork,
-    inet_server_port: i32,
-    version: String,
+
+impl PgConnectionInfo {
+    /// Query the connection information, falling back to empty information
+    /// if it is unavailable.
+    fn query(conn: &mut PgConnection) -> PgConnectionInfo {
+        if cfg!(feature = "passthrough") {
+            return PgConnectionInfo::default();
+        }
+        Self::try_query(conn).unwrap_or_else(|error| {
+            warn!(
+                target: "diesel_tracing::pg::connection",
+                %error,
+                "failed to query postgresql connection information",
+            );
+            PgConnectionInfo::default()
+        })
+    }
+
+    fn try_query(conn: &mut PgConnection) -> QueryResult<PgConnectionInfo> {
+        debug!(
+            target: "diesel_tracing::pg::connection",
+            "querying postgresql connection information",
+        );
+        let mut info: PgConnectionInfo = select((
+            current_database(),
+            inet_server_addr(),
+            inet_server_port(),
+            inet_client_addr(),
+            inet_client_port(),
+            version(),
+            current_setting("application_name", true),
+            current_setting("default_transaction_isolation", true),
+            current_setting("default_transaction_read_only", true),
+            current_schema(),
+            current_schemas(false),
+            pg_backend_pid(),
+        ))
+        .get_result(conn)?;
+        info.apply_tls(sql_query(PG_TLS_QUERY).get_result(conn));
+        info.resolve_hostname();
+
+        Ok(info)
+    }
+}
+
+// the most bind parameters a postgres statement can have
+const MAX_BINDS: usize = 65535;
+
+// whether diesel's migrations table exists, for ensure_migrations_table
+const MIGRATIONS_TABLE_EXISTS_QUERY: &str = "SELECT COUNT(*) FROM information_schema.tables \
+     WHERE table_schema = current_schema() AND table_name = '__diesel_schema_migrations'";
+
+/// Create the span covering the lifetime of a connection, which the spans of
+/// its operations follow from.
+fn connection_span(info: &PgConnectionInfo, connection_id: ConnectionId) -> ConnectionSpan {
+    if cfg!(feature = "passthrough") {
+        return ConnectionSpan::new(Span::none());
+    }
+
+    let span = debug_span!(
+        target: "diesel_tracing::pg::connection",
+        parent: None,
+        "db.connection",
+        db.namespace=info.current_database.as_deref(),
+        db.name=deprecated(info.current_database.as_deref()),
+        db.system="postgresql",
+        db.version=info.version.as_deref(),
+        db.connection_id=connection_id.value(),
+        db.connection.id=connection_id.sequence(),
+        db.connection.name=field::Empty,
+        db.extra=field::Empty,
+        network.peer.address=info.inet_server_addr.map(field::display),
+        net.peer.ip=deprecated(info.inet_server_addr.map(field::display)),
+        server.address=info.server_hostname.as_deref(),
+        net.peer.name=deprecated(info.server_hostname.as_deref()),
+        server.port=info.inet_server_port,
+        net.peer.port=deprecated(info.inet_server_port),
+        db.client.address=info.client_address.as_deref(),
+        db.client.port=info.client_port,
+        db.postgresql.target_session_attrs=info.target_session_attrs.as_deref(),
+        db.postgresql.application_name=info.application_name.as_deref(),
+        db.postgresql.pid=info.backend_pid,
+        db.schema=info.current_schema.as_deref(),
+        db.postgresql.schema=info.schema.as_deref(),
+        db.connection.tls=info.tls,
+    );
+    Span::current().follows_from(&span);
+
+    ConnectionSpan::new(span)
+}
+
+/// Instrument a method of the connection `connection` with the spans given,
+/// each under an optional `#[cfg]` predicate, adding the fields describing the
+/// postgres connection and following from its connection span.
+///
+/// A macro cannot expand to an attribute, so this wraps the whole method.
+macro_rules! instrument_method {
+    (
+        connection = $conn:tt,
+        $(
+            $(#[cfg($cfg:meta)])?
+            instrument(
+                $(name = $name:literal,)?
+                fields($($field:tt)*),
+                $($arg:tt)*
+            )
+        ),+;
+        $item:item
+    ) => {
+        $(
+            #[cfg_attr(
+                all(not(feature = "passthrough") $(, $cfg)?),
+                tracing::instrument(
+                    $(name = $name,)?
+                    fields(
+                        db.namespace=$conn.info.current_database.as_deref(),
+                        db.name=deprecated($conn.info.current_database.as_deref()),
+                        db.system="postgresql",
+                        db.version=$conn.info.version.as_deref(),
+                        otel.kind="client",
+                        db.connection_id=$conn.connection_id.value(),
+                        db.connection.id=$conn.connection_id.sequence(),
+                        db.connection.name=$conn.connection_name.as_deref(),
+                        db.extra=$conn.extra_fields.as_deref(),
+                        db.connection.read_only=$conn.read_only.field(),
+                        network.peer.address=$conn.info.inet_server_addr.map(field::display),
+                        net.peer.ip=deprecated($conn.info.inet_server_addr.map(field::display)),
+                        server.address=$conn.info.server_hostname.as_deref(),
+                        net.peer.name=deprecated($conn.info.server_hostname.as_deref()),
+                        server.port=$conn.info.inet_server_port,
+                        net.peer.port=deprecated($conn.info.inet_server_port),
+                        db.client.address=$conn.info.client_address.as_deref(),
+                        db.client.port=$conn.info.client_port,
+                        db.postgresql.target_session_attrs=$conn.info.target_session_attrs.as_deref(),
+                        db.postgresql.application_name=$conn.info.application_name.as_deref(),
+                        db.postgresql.pid=$conn.info.backend_pid,
+                        db.schema=$conn.info.current_schema.as_deref(),
+                        db.postgresql.schema=$conn.info.schema.as_deref(),
+                        db.connection.tls=$conn.info.tls,
+                        $($field)*
+                    ),
+                    follows_from = [&$conn.connection_span],
+                    $($arg)*
+                )
+            )]
+        )+
+        $item
+    };
 }
 
 pub struct InstrumentedPgConnection {
     inner: PgConnection,
     info: PgConnectionInfo,
+    #[cfg_attr(feature = "passthrough", allow(dead_code))]
+    connection_id: ConnectionId,
+    connection_name: Option<String>,
+    extra_fields: Option<String>,
+    span_namer: Box<dyn SpanNamer>,
+    connection_span: ConnectionSpan,
+    config: TracingConfig,
+    instrumentation_stack: Option<SharedInstrumentationStack>,
+    sampler: QuerySampler,
+    read_only: ReadOnly,
+    transaction_warn: TransactionWarnThresholds,
+    // set by insert_in_chunks for the binds of its first statement to be
+    // checked against MAX_BINDS before it is run
+    check_binds: bool,
+    #[cfg_attr(
+        any(not(feature = "statement-fields"), feature = "passthrough"),
+        allow(dead_code)
+    )]
+    explain_on_slow: bool,
+    #[cfg_attr(not(feature = "opentelemetry"), allow(dead_code))]
+    sql_comments: bool,
+    #[cfg(feature = "audit")]
+    audit_sink: Option<Arc<dyn AuditSink>>,
+    metadata_cache: MetadataCacheStats,
+    #[cfg(feature = "reconnect")]
+    auto_reconnect: Option<AutoReconnect>,
+    // run again on the new session after reconnecting
+    #[cfg(feature = "reconnect")]
+    init_statements: Vec<String>,
+}
+
+/// The settings of automatic reconnection, enabled with
+/// [`InstrumentedPgConnection::with_auto_reconnect`].
+#[cfg(feature = "reconnect")]
+struct AutoReconnect {
+    database_url: String,
+    masked_url: String,
+    max_attempts: u32,
+    /// Set when a load fails because the connection was closed, for the next
+    /// statement to reconnect first, as the cursor returned by a load borrows
+    /// the connection.
+    closed_by_load: Arc<AtomicBool>,
+}
+
+/// Whether `error` is from a statement run on a closed connection.
+#[cfg(feature = "reconnect")]
+fn is_closed_connection(error: &diesel::result::Error) -> bool {
+    matches!(
+        error,
+        diesel::result::Error::DatabaseError(DatabaseErrorKind::ClosedConnection, _)
+    )
+}
+
+/// Prints the connection information, never the URL it was established with.
+impl std::fmt::Debug for InstrumentedPgConnection {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("InstrumentedPgConnection")
+            .field("info", &self.info)
+            .field("connection_id", &self.connection_id)
+            .field("connection_name", &self.connection_name)
+            .field("config", &self.config)
+            .finish_non_exhaustive()
+    }
+}
+
+/// Wrap an established connection. Like `establish`, this queries the server
+/// for the information recorded on spans, which is left empty if the query
+/// fails.
+impl From<PgConnection> for InstrumentedPgConnection {
+    fn from(mut inner: PgConnection) -> Self {
+        let info = PgConnectionInfo::query(&mut inner);
+        let connection_id = ConnectionId::new();
+        let connection_span = connection_span(&info, connection_id);
+
+        InstrumentedPgConnection {
+            inner,
+            info,
+            connection_id,
+            connection_name: None,
+            extra_fields: None,
+            span_namer: default_span_namer(),
+            connection_span,
+            config: TracingConfig::default(),
+            instrumentation_stack: None,
+            sampler: QuerySampler::default(),
+            read_only: ReadOnly::default(),
+            transaction_warn: TransactionWarnThresholds::default(),
+            check_binds: false,
+            explain_on_slow: false,
+            sql_comments: false,
+            #[cfg(feature = "audit")]
+            audit_sink: None,
+            metadata_cache: MetadataCacheStats::default(),
+            #[cfg(feature = "reconnect")]
+            auto_reconnect: None,
+            #[cfg(feature = "reconnect")]
+            init_statements: Vec::new(),
+        }
+    }
 }
 
 #[cfg(feature = "r2d2")]
 impl R2D2Connection for InstrumentedPgConnection {
-    fn ping(&mut self) -> QueryResult<()> {
-        self.inner.batch_execute("SELECT 1")?;
+    instrument_method! {
+        connection = self,
+        instrument(
+            fields(
+                otel.name=span_name(self.span_namer.as_ref(), "ping"),
+                db.operation="ping",
+            ),
+            target = "diesel_tracing::pg::connection",
+            skip(self),
+            err,
+        );
+        fn ping(&mut self) -> QueryResult<()> {
+            self.inner.ping()
+        }
+    }
 
-        Ok(())
+    fn is_broken(&mut self) -> bool {
+        self.inner.is_broken()
     }
 }
 
@@ -66,23 +371,23 @@ impl MultiConnectionHelper for InstrumentedPgConnection {
 }
 
 impl SimpleConnection for InstrumentedPgConnection {
-    #[instrument(
-        fields(
-            db.name=%self.info.current_database,
-            db.system="postgresql",
-            db.version=%self.info.version,
-            otel.kind="client",
-            net.peer.ip=%self.info.inet_server_addr,
-            net.peer.port=%self.info.inet_server_port,
-        ),
-        skip(self, query),
-        err,
-    )]
     fn batch_execute(&mut self, query: &str) -> QueryResult<()> {
-        debug!("executing batch query");
-        self.inner.batch_execute(query)?;
+        check_read_only!("diesel_tracing::pg::query", self.read_only, || Some(
+            query.to_owned()
+        ))?;
+        #[cfg(feature = "audit")]
+        if let Some(audit) = self.start_audit(|| Some(query.to_owned())) {
+            let result = self.sampled_batch_execute(query);
+            audit.finish(result.as_ref().map(|&()| None));
+            return result;
+        }
+        self.sampled_batch_execute(query)
+    }
+}
 
-        Ok(())
+impl chunks::BindLimit for InstrumentedPgConnection {
+    fn check_binds(&mut self, check: bool) {
+        self.check_binds = check;
     }
 }
 
@@ -92,166 +397,128 @@ impl Connection for InstrumentedPgConnection {
     type Backend = Pg;
     type TransactionManager = AnsiTransactionManager;
 
-    #[instrument(
-        fields(
-            db.name=field::Empty,
-            db.system="postgresql",
-            db.version=field::Empty,
-            otel.kind="client",
-            net.peer.ip=field::Empty,
-            net.peer.port=field::Empty,
-        ),
-        skip(database_url),
-        err,
+    #[cfg_attr(
+        not(feature = "passthrough"),
+        tracing::instrument(
+            target = "diesel_tracing::pg::connection",
+            fields(
+                db.namespace=field::Empty,
+                db.name=field::Empty,
+                db.system="postgresql",
+                db.version=field::Empty,
+                otel.kind="client",
+                otel.name=establish_span_name("establish"),
+                db.connection_id=field::Empty,
+                db.connection.id=field::Empty,
+                network.peer.address=field::Empty,
+                net.peer.ip=field::Empty,
+                server.address=field::Empty,
+                net.peer.name=field::Empty,
+                server.port=field::Empty,
+                net.peer.port=field::Empty,
+                db.client.address=field::Empty,
+                db.client.port=field::Empty,
+                db.postgresql.target_session_attrs=field::Empty,
+                db.postgresql.application_name=field::Empty,
+                db.postgresql.pid=field::Empty,
+                db.schema=field::Empty,
+                db.postgresql.schema=field::Empty,
+                db.connection.tls=field::Empty,
+                db.connection.tls_version=field::Empty,
+                db.connection.tls_cipher=field::Empty,
+                otel.status_code=field::Empty,
+                otel.status_message=field::Empty,
+            ),
+            skip(database_url),
+            err,
+        )
     )]
     fn establish(database_url: &str) -> ConnectionResult<InstrumentedPgConnection> {
-        debug!("establishing postgresql connection");
-        let mut conn = PgConnection::establish(database_url)?;
+        Self::establish_in_current_span(database_url, true)
+    }
 
-        debug!("querying postgresql connection information");
-        let info: PgConnectionInfo = select((
-            current_database(),
-            inet_server_addr(),
-            inet_server_port(),
-            version(),
-        ))
-        .get_result(&mut conn)
-        .map_err(ConnectionError::CouldntSetupConfiguration)?;
-
-        let span = tracing::Span::current();
-        span.record("db.name", info.current_database.as_str());
-        span.record("db.version", info.version.as_str());
-        span.record("net.peer.ip", format!("{}", info.inet_server_addr).as_str());
-        span.record("net.peer.port", info.inet_server_port);
-
-        Ok(InstrumentedPgConnection { inner: conn, info })
-    }
-
-    #[instrument(
-        fields(
-            db.name=%self.info.current_database,
-            db.system="postgresql",
-            db.version=%self.info.version,
-            otel.kind="client",
-            net.peer.ip=%self.info.inet_server_addr,
-            net.peer.port=%self.info.inet_server_port,
-        ),
-        skip(self, f),
-    )]
     fn transaction<T, E, F>(&mut self, f: F) -> Result<T, E>
     where
         F: FnOnce(&mut Self) -> Result<T, E>,
         E: From<diesel::result::Error>,
     {
-        Self::TransactionManager::transaction(self, f)
+        if !self.config.trace_transactions {
+            return Self::TransactionManager::transaction(self, f);
+        }
+        let (sampled, outer) = self.sampler.begin_transaction();
+        let result = if sampled {
+            self.traced_transaction(f)
+        } else {
+            Self::TransactionManager::transaction(self, f)
+        };
+        self.sampler.end_transaction(outer);
+        result
     }
 
-    #[instrument(
-        fields(
-            db.name=%self.info.current_database,
-            db.system="postgresql",
-            db.version=%self.info.version,
-            otel.kind="client",
-            net.peer.ip=%self.info.inet_server_addr,
-            net.peer.port=%self.info.inet_server_port,
-        ),
-        skip(self, source),
-        err,
-    )]
     fn execute_returning_count<T>(&mut self, source: &T) -> QueryResult<usize>
     where
         T: QueryFragment<Pg> + QueryId,
     {
-        self.inner.execute_returning_count(source)
+        if std::mem::take(&mut self.check_binds) {
+            chunks::check_binds::<Pg, _>(source, MAX_BINDS)?;
+        }
+        check_read_only!(
+            "diesel_tracing::pg::query",
+            self.read_only,
+            || render_sql::<Pg, _>(source)
+        )?;
+        #[cfg(feature = "audit")]
+        if let Some(audit) = self.start_audit(|| render_sql::<Pg, _>(source)) {
+            let result = self.sampled_execute_returning_count(source);
+            audit.finish(result.as_ref().map(|count| Some(*count)));
+            return result;
+        }
+        self.sampled_execute_returning_count(source)
     }
 
-    #[instrument(
-        fields(
-            db.name=%self.info.current_database,
-            db.system="postgresql",
-            db.version=%self.info.version,
-            otel.kind="client",
-            net.peer.ip=%self.info.inet_server_addr,
-            net.peer.port=%self.info.inet_server_port,
-        ),
-        skip(self),
-    )]
     fn transaction_state(&mut self) -> &mut Self::TransactionManager {
-        self.inner.transaction_state()
+        if !self.config.trace_transactions {
+            return self.inner.transaction_state();
+        }
+        self.traced_transaction_state()
     }
 
-    #[instrument(
-        fields(
-            db.name=%self.info.current_database,
-            db.system="postgresql",
-            db.version=%self.info.version,
-            otel.kind="client",
-            net.peer.ip=%self.info.inet_server_addr,
-            net.peer.port=%self.info.inet_server_port,
-        ),
-        skip(self),
-    )]
     fn instrumentation(&mut self) -> &mut dyn Instrumentation {
-        self.inner.instrumentation()
+        if !self.config.trace_transactions {
+            return self.inner.instrumentation();
+        }
+        self.traced_instrumentation()
     }
 
-    #[instrument(
-        fields(
-            db.name=%self.info.current_database,
-            db.system="postgresql",
-            db.version=%self.info.version,
-            otel.kind="client",
-            net.peer.ip=%self.info.inet_server_addr,
-            net.peer.port=%self.info.inet_server_port,
-        ),
-        skip(self, instrumentation)
-    )]
-    fn set_instrumentation(&mut self, instrumentation: impl Instrumentation) {
-        self.inner.set_instrumentation(instrumentation)
+    instrument_method! {
+        connection = self,
+        instrument(
+            fields(
+                otel.name=span_name(self.span_namer.as_ref(), "set_instrumentation"),
+            ),
+            target = "diesel_tracing::pg::connection",
+            skip(self, instrumentation),
+        );
+        fn set_instrumentation(&mut self, instrumentation: impl Instrumentation) {
+            if let Some(stack) =
+                SharedInstrumentationStack::push(&mut self.instrumentation_stack, instrumentation)
+            {
+                self.inner.set_instrumentation(stack);
+            }
+        }
     }
 }
 
 impl LoadConnection<DefaultLoadingMode> for InstrumentedPgConnection {
-    type Cursor<'conn, 'query> =
-        <PgConnection as LoadConnection<DefaultLoadingMode>>::Cursor<'conn, 'query>
-            where
-                Self: 'conn;
-    type Row<'conn, 'query> =
-        <PgConnection as LoadConnection<DefaultLoadingMode>>::Row<'conn, 'query>
-            where
-                Self: 'conn;
+    type Cursor<'conn, 'query>
+        = <PgConnection as LoadConnection<DefaultLoadingMode>>::Cursor<'conn, 'query>
+    where
+        Self: 'conn;
+    type Row<'conn, 'query>
+        = <PgConnection as LoadConnection<DefaultLoadingMode>>::Row<'conn, 'query>
+    where
+        Self: 'conn;
 
-    #[cfg_attr(
-        feature = "statement-fields",
-        instrument(
-            fields(
-                db.name=%self.info.current_database,
-                db.system="postgresql",
-                db.version=%self.info.version,
-                otel.kind="client",
-                net.peer.ip=%self.info.inet_server_addr,
-                net.peer.port=%self.info.inet_server_port,
-                db.statement=%diesel::debug_query(&source),
-            ),
-            skip(self, source),
-            err,
-        )
-    )]
-    #[cfg_attr(
-        not(feature = "statement-fields"),
-        instrument(
-            fields(
-                db.name=%self.info.current_database,
-                db.system="postgresql",
-                db.version=%self.info.version,
-                otel.kind="client",
-                net.peer.ip=%self.info.inet_server_addr,
-                net.peer.port=%self.info.inet_server_port,
-            ),
-            skip(self, source),
-            err,
-        )
-    )]
     fn load<'conn, 'query, T>(
         &'conn mut self,
         source: T,
@@ -260,32 +527,60 @@ impl LoadConnection<DefaultLoadingMode> for InstrumentedPgConnection {
         T: Query + QueryFragment<Pg> + QueryId + 'query,
         Self::Backend: QueryMetadata<T::SqlType>,
     {
-        <PgConnection as LoadConnection<DefaultLoadingMode>>::load(&mut self.inner, source)
+        check_read_only!(
+            "diesel_tracing::pg::query",
+            self.read_only,
+            || render_sql::<Pg, _>(&source)
+        )?;
+        #[cfg(feature = "audit")]
+        let audit = self.start_audit(|| render_sql::<Pg, _>(&source));
+        #[cfg(feature = "reconnect")]
+        let closed_by_load = self.closed_by_load_flag();
+        self.connection_span.count_query();
+        let traced = self.config.trace_load;
+        let levels = self.config.error_levels;
+        let result = if !traced || !self.sampler.sample() {
+            measure("postgresql", move || {
+                <PgConnection as LoadConnection<DefaultLoadingMode>>::load(&mut self.inner, source)
+            })
+            .inspect_err(|error| {
+                if traced {
+                    sampled_out_error_event!(
+                        "diesel_tracing::pg::query",
+                        "postgresql",
+                        levels,
+                        error
+                    );
+                }
+            })
+        } else {
+            self.traced_load(source)
+        };
+        #[cfg(feature = "reconnect")]
+        if let (Some(closed_by_load), Err(error)) = (&closed_by_load, &result) {
+            if is_closed_connection(error) {
+                closed_by_load.store(true, Ordering::Relaxed);
+            }
+        }
+        #[cfg(feature = "audit")]
+        if let Some(audit) = audit {
+            audit.finish(result.as_ref().map(|_| None));
+        }
+
+        result
     }
 }
 
 impl LoadConnection<PgRowByRowLoadingMode> for InstrumentedPgConnection {
-    type Cursor<'conn, 'query> =
-        <PgConnection as LoadConnection<PgRowByRowLoadingMode>>::Cursor<'conn, 'query>
+    type Cursor<'conn, 'query>
+        = <PgConnection as LoadConnection<PgRowByRowLoadingMode>>::Cursor<'conn, 'query>
     where
         Self: 'conn;
-    type Row<'conn, 'query> =
-        <PgConnection as LoadConnection<PgRowByRowLoadingMode>>::Row<'conn, 'query>
+    type Row<'conn, 'query>
+        = <PgConnection as LoadConnection<PgRowByRowLoadingMode>>::Row<'conn, 'query>
     where
         Self: 'conn;
 
-    #[instrument(
-        fields(
-            db.name=%self.info.current_database,
-            db.system="postgresql",
-            db.version=%self.info.version,
-            otel.kind="client",
-            net.peer.ip=%self.info.inet_server_addr,
-            net.peer.port=%self.info.inet_server_port,
-        ),
-        skip(self, source),
-        err,
-    )]
     fn load<'conn, 'query, T>(
         &'conn mut self,
         source: T,
@@ -294,62 +589,2907 @@ impl LoadConnection<PgRowByRowLoadingMode> for InstrumentedPgConnection {
         T: Query + QueryFragment<Pg> + QueryId + 'query,
         Self::Backend: QueryMetadata<T::SqlType>,
     {
-        <PgConnection as LoadConnection<PgRowByRowLoadingMode>>::load(&mut self.inner, source)
+        check_read_only!(
+            "diesel_tracing::pg::query",
+            self.read_only,
+            || render_sql::<Pg, _>(&source)
+        )?;
+        #[cfg(feature = "audit")]
+        let audit = self.start_audit(|| render_sql::<Pg, _>(&source));
+        #[cfg(feature = "reconnect")]
+        let closed_by_load = self.closed_by_load_flag();
+        self.connection_span.count_query();
+        let traced = self.config.trace_load;
+        let levels = self.config.error_levels;
+        let result = if !traced || !self.sampler.sample() {
+            measure("postgresql", move || {
+                <PgConnection as LoadConnection<PgRowByRowLoadingMode>>::load(
+                    &mut self.inner,
+                    source,
+                )
+            })
+            .inspect_err(|error| {
+                if traced {
+                    sampled_out_error_event!(
+                        "diesel_tracing::pg::query",
+                        "postgresql",
+                        levels,
+                        error
+                    );
+                }
+            })
+        } else {
+            self.traced_load_row_by_row(source)
+        };
+        #[cfg(feature = "reconnect")]
+        if let (Some(closed_by_load), Err(error)) = (&closed_by_load, &result) {
+            if is_closed_connection(error) {
+                closed_by_load.store(true, Ordering::Relaxed);
+            }
+        }
+        #[cfg(feature = "audit")]
+        if let Some(audit) = audit {
+            audit.finish(result.as_ref().map(|_| None));
+        }
+
+        result
+    }
+}
+
+/// Record the table targeted by a `COPY` on the current span as `db.sql.table`.
+fn record_copy_table<T>()
+where
+    T: StaticQueryFragment,
+    T::Component: QueryFragment<Pg>,
+{
+    if cfg!(feature = "passthrough") {
+        return;
+    }
+
+    let mut query_builder = PgQueryBuilder::default();
+    if T::STATIC_COMPONENT.to_sql(&mut query_builder, &Pg).is_ok() {
+        let table = query_builder.finish().replace('"', "");
+        Span::current().record("db.sql.table", table.as_str());
+    }
+}
+
+/// Quote a notification channel name as an identifier, for statements such as
+/// `LISTEN` which cannot take it as a bind parameter.
+fn quote_identifier(identifier: &str) -> QueryResult<String> {
+    let mut query_builder = PgQueryBuilder::default();
+    query_builder.push_identifier(identifier)?;
+
+    Ok(query_builder.finish())
+}
+
+impl<T, A> ExecuteCopyFromDsl<InstrumentedPgConnection> for CopyFromQuery<T, A>
+where
+    CopyFromQuery<T, A>: ExecuteCopyFromDsl<PgConnection>,
+    T: StaticQueryFragment,
+    T::Component: QueryFragment<Pg>,
+{
+    type Error = <Self as ExecuteCopyFromDsl<PgConnection>>::Error;
+
+    instrument_method! {
+        connection = conn,
+        instrument(
+            name = "copy_from",
+            fields(
+                otel.name=span_name(conn.span_namer.as_ref(), "copy_from"),
+                db.operation="COPY",
+                db.copy_direction="FROM",
+                db.sql.table=field::Empty,
+                db.access_mode=field::Empty,
+                db.rows_affected=field::Empty,
+            ),
+            target = "diesel_tracing::pg::query",
+            skip(self, conn),
+            err,
+        );
+        fn execute(self, conn: &mut InstrumentedPgConnection) -> Result<usize, Self::Error> {
+            record_copy_table::<T>();
+            let count = measure("postgresql", || {
+                <Self as ExecuteCopyFromDsl<PgConnection>>::execute(self, &mut conn.inner)
+            })?;
+            tracing::Span::current().record("db.rows_affected", count);
+
+            Ok(count)
+        }
     }
 }
 
 impl MigrationConnection for InstrumentedPgConnection {
-    fn setup(&mut self) -> QueryResult<usize> {
-        sql_query(CREATE_MIGRATIONS_TABLE).execute(self)
+    instrument_method! {
+        connection = self,
+        instrument(
+            name = "create_migrations_table",
+            fields(
+                otel.name=span_name(self.span_namer.as_ref(), "create_migrations_table"),
+                db.operation="create_migrations_table",
+            ),
+            target = "diesel_tracing::pg::query",
+            skip(self),
+            err,
+        );
+        fn setup(&mut self) -> QueryResult<usize> {
+            sql_query(CREATE_MIGRATIONS_TABLE).execute(self)
+        }
+    }
+}
+
+impl InstrumentedPgConnection {
+    instrument_method! {
+        connection = self,
+        instrument(
+            name = "ensure_migrations_table",
+            fields(
+                otel.name=span_name(self.span_namer.as_ref(), "ensure_migrations_table"),
+                db.operation="ensure_migrations_table",
+                db.migrations_table.created=field::Empty,
+            ),
+            target = "diesel_tracing::pg::query",
+            skip(self),
+            err,
+        );
+        /// Create diesel's migrations table unless it already exists, returning
+        /// whether it was created. The check is traced like any other query, and
+        /// a debug event says whether the table was created or already existed.
+        ///
+        /// # Errors
+        ///
+        /// Returns an error if the check or creating the table fails.
+        pub fn ensure_migrations_table(&mut self) -> QueryResult<bool> {
+            let exists = sql::<BigInt>(MIGRATIONS_TABLE_EXISTS_QUERY).get_result::<i64>(self)? > 0;
+            if exists {
+                debug!(target: "diesel_tracing::pg::query", "migrations table already exists");
+            } else {
+                self.setup()?;
+                debug!(target: "diesel_tracing::pg::query", "created migrations table");
+            }
+            Span::current().record("db.migrations_table.created", !exists);
+
+            Ok(!exists)
+        }
     }
 }
 
 impl GetPgMetadataCache for InstrumentedPgConnection {
     fn get_metadata_cache(&mut self) -> &mut diesel::pg::PgMetadataCache {
+        if self.metadata_cache.lookups == 0 {
+            debug!(
+                target: "diesel_tracing::pg::query",
+                "first access of postgresql type metadata cache",
+            );
+        }
+        self.metadata_cache.lookups += 1;
+        trace!(target: "diesel_tracing::pg::query", "consulting postgresql type metadata cache");
         self.inner.get_metadata_cache()
     }
 }
 
 impl InstrumentedPgConnection {
-    #[instrument(
-        fields(
-            db.name=%self.info.current_database,
-            db.system="postgresql",
-            db.version=%self.info.version,
-            otel.kind="client",
-            net.peer.ip=%self.info.inet_server_addr,
-            net.peer.port=%self.info.inet_server_port,
-        ),
-        skip(self),
+    /// Establish a connection without querying the server for the
+    /// information recorded on spans, avoiding the extra round trips.
+    ///
+    /// This suits servers where the queries fail or are unwanted, such as
+    /// Redshift, pgbouncer in transaction mode and other postgres forks, at
+    /// the cost of fewer span fields. Spans from the connection only record
+    /// `db.system`, `otel.kind` and the connection's own fields, without
+    /// `db.namespace`, `db.version`, `server.*`, `db.client.*`, the schema,
+    /// application name or `db.connection.tls*`.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the connection could not be established.
+    #[cfg_attr(
+        not(feature = "passthrough"),
+        tracing::instrument(
+            target = "diesel_tracing::pg::connection",
+            fields(
+                db.system="postgresql",
+                otel.kind="client",
+                otel.name=establish_span_name("establish_without_info"),
+                db.connection_id=field::Empty,
+                db.connection.id=field::Empty,
+                otel.status_code=field::Empty,
+                otel.status_message=field::Empty,
+            ),
+            skip(database_url),
+            err,
+        )
     )]
-    pub fn build_transaction(&mut self) -> TransactionBuilder<'_, InstrumentedPgConnection> {
-        TransactionBuilder::new(self)
+    pub fn establish_without_info(
+        database_url: &str,
+    ) -> ConnectionResult<InstrumentedPgConnection> {
+        debug!(
+            target: "diesel_tracing::pg::connection",
+            "establishing postgresql connection without information",
+        );
+        let mut inner = PgConnection::establish(database_url)
+            .inspect_err(|error| record_status(&tracing::Span::current(), error))?;
+        let instrumentation_stack = SharedInstrumentationStack::install(&mut inner);
+        let connection_id = ConnectionId::new();
+        connection_id.record(&tracing::Span::current());
+        let connection_span = connection_span(&PgConnectionInfo::default(), connection_id);
+
+        Ok(InstrumentedPgConnection {
+            inner,
+            info: PgConnectionInfo::default(),
+            connection_id,
+            connection_name: None,
+            extra_fields: None,
+            span_namer: default_span_namer(),
+            connection_span,
+            config: TracingConfig::default(),
+            instrumentation_stack,
+            sampler: QuerySampler::default(),
+            read_only: ReadOnly::default(),
+            transaction_warn: TransactionWarnThresholds::default(),
+            check_binds: false,
+            explain_on_slow: false,
+            sql_comments: false,
+            #[cfg(feature = "audit")]
+            audit_sink: None,
+            metadata_cache: MetadataCacheStats::default(),
+            #[cfg(feature = "reconnect")]
+            auto_reconnect: None,
+            #[cfg(feature = "reconnect")]
+            init_statements: Vec::new(),
+        })
     }
-}
 
-impl<'b, Changes, Output> UpdateAndFetchResults<Changes, Output> for InstrumentedPgConnection
-where
-    Changes: Copy + AsChangeset<Target = <Changes as HasTable>::Table> + IntoUpdateTarget,
-    Update<Changes, Changes>: LoadQuery<'b, PgConnection, Output>,
-    <Changes::Table as Table>::AllColumns: ValidGrouping<()>,
-    <<Changes::Table as Table>::AllColumns as ValidGrouping<()>>::IsAggregate:
-        MixedAggregates<is_aggregate::No, Output = is_aggregate::No>,
-{
-    fn update_and_fetch(&mut self, changeset: Changes) -> QueryResult<Output> {
-        debug!("updating and fetching changeset");
-        self.inner.update_and_fetch(changeset)
+    instrument_method! {
+        connection = self,
+        instrument(
+            fields(
+                otel.name=span_name(self.span_namer.as_ref(), "copy_to"),
+                db.operation="COPY",
+                db.copy_direction="TO",
+                db.sql.table=field::Empty,
+                db.access_mode=field::Empty,
+                db.error.kind=field::Empty,
+                db.error.table=field::Empty,
+                db.error.constraint=field::Empty,
+                otel.status_code=field::Empty,
+                otel.status_message=field::Empty,
+                db.rows_affected=field::Empty,
+            ),
+            target = "diesel_tracing::pg::query",
+            skip(self, target),
+            err,
+        );
+        /// Copy the rows of `target` out of the database with
+        /// `diesel::copy_to(target).load()`, traced with `db.operation="COPY"`,
+        /// `db.copy_direction="TO"`, `db.sql.table` and `db.rows_affected`.
+        ///
+        /// Diesel only loads `diesel::copy_to` queries from its own connections,
+        /// so the rows are collected here while the span is open.
+        ///
+        /// # Errors
+        ///
+        /// Returns an error if the copy fails or a row cannot be deserialized.
+        pub fn copy_to<U, T>(&mut self, target: T) -> QueryResult<Vec<U>>
+        where
+            T: CopyTarget,
+            T::Table: StaticQueryFragment,
+            <T::Table as StaticQueryFragment>::Component: QueryFragment<Pg>,
+            U: FromSqlRow<<U::SelectExpression as Expression>::SqlType, Pg> + Selectable<Pg>,
+            U::SelectExpression: AppearsOnTable<T::Table> + CopyTarget<Table = T::Table>,
+        {
+            record_copy_table::<T::Table>();
+            let rows = measure("postgresql", || {
+                diesel::copy_to(target)
+                    .load::<U, _>(&mut self.inner)?
+                    .collect::<QueryResult<Vec<_>>>()
+            })
+            .inspect_err(|error| record_error(&tracing::Span::current(), error))?;
+            tracing::Span::current().record("db.rows_affected", rows.len());
+
+            Ok(rows)
+        }
     }
-}
 
-#[cfg(test)]
-mod tests {
-    use super::*;
+    /// Build a transaction with a non-default isolation level, access mode or
+    /// deferrability, which is traced when it is run.
+    pub fn build_transaction(&mut self) -> InstrumentedTransactionBuilder<'_> {
+        InstrumentedTransactionBuilder {
+            conn: self,
+            isolation_level: None,
+            read_only: None,
+            deferrable: None,
+            retries: None,
+        }
+    }
 
-    #[test]
-    fn test_get_info_on_establish() {
-        InstrumentedPgConnection::establish(
-            &std::env::var("POSTGRESQL_URL").expect("no POSTGRESQL_URL env var specified"),
+    /// Run the closure in a serializable transaction, retrying it up to
+    /// `max_retries` times if it fails with a serialization failure or a
+    /// deadlock, see [`is_serialization_failure`].
+    ///
+    /// Each retry logs a warning with the attempt number, and the
+    /// `pg_transaction` span of each attempt records the number of retries
+    /// before it as `db.transaction.retries`. The closure is run again from
+    /// the start on each attempt, so should have no side effects outside the
+    /// transaction.
+    ///
+    /// # Errors
+    ///
+    /// Returns the error from the closure or from running the transaction if
+    /// it is not a serialization failure, or the error of the last attempt
+    /// once the retries are exhausted.
+    pub fn serializable_transaction_with_retries<T, E, F>(
+        &mut self,
+        max_retries: usize,
+        mut f: F,
+    ) -> Result<T, E>
+    where
+        F: FnMut(&mut Self) -> Result<T, E>,
+        E: From<diesel::result::Error> + std::error::Error + 'static,
+    {
+        let mut retries = 0;
+        loop {
+            let mut builder = self.build_transaction().serializable();
+            builder.retries = Some(retries);
+            match builder.run(&mut f) {
+                Err(error) if retries < max_retries && find_serialization_failure(&error) => {
+                    retries += 1;
+                    if !cfg!(feature = "passthrough") {
+                        warn!(
+                            target: "diesel_tracing::pg::transaction",
+                            {
+                                db.transaction.retry_attempt = retries,
+                                %error,
+                            },
+                            "serialization failure, retrying transaction",
+                        );
+                    }
+                }
+                result => return result,
+            }
+        }
+    }
+
+    /// Replace the configuration controlling which spans are created.
+    pub fn set_tracing_config(&mut self, config: TracingConfig) {
+        self.config = config;
+    }
+
+    /// Make the connection read only, or writable again, by setting
+    /// `default_transaction_read_only` for the session.
+    ///
+    /// While the connection is read only its spans record
+    /// `db.connection.read_only`, and a statement which writes is logged as a
+    /// warning before it is sent, so that the call site is traced even though
+    /// the database rejects it. See [`Self::set_strict_read_only`] to reject
+    /// it without sending it.
+    ///
+    /// # Errors
+    ///
+    /// Returns the error of the statement changing the setting, leaving the
+    /// connection as it was.
+    pub fn set_read_only(&mut self, read_only: bool) -> QueryResult<()> {
+        self.batch_execute(if read_only {
+            "SET default_transaction_read_only = on"
+        } else {
+            "SET default_transaction_read_only = off"
+        })?;
+        self.info.default_read_only = Some(read_only);
+        self.read_only.enabled = read_only;
+        Ok(())
+    }
+
+    /// Whether the connection was made read only with [`Self::set_read_only`].
+    #[must_use]
+    pub fn is_read_only(&self) -> bool {
+        self.read_only.enabled
+    }
+
+    /// Fail statements which write on a read only connection with an error,
+    /// rather than sending them to be rejected by the database.
+    pub fn set_strict_read_only(&mut self, strict: bool) {
+        self.read_only.strict = strict;
+    }
+
+    /// Log a warning while a transaction is still open once it has been open
+    /// for longer than `after`, so that long running transactions can be
+    /// caught before they finish. Its `transaction` span records how long it
+    /// took as `db.transaction.duration_ms` and the number of queries it ran
+    /// as `db.transaction.queries`. Disabled by default.
+    pub fn set_transaction_warn_after(&mut self, after: std::time::Duration) {
+        self.transaction_warn.after = Some(after);
+    }
+
+    /// Log a warning while a transaction is still open once it has run more
+    /// than `query_count` queries, like
+    /// [`set_transaction_warn_after`](Self::set_transaction_warn_after).
+    pub fn set_transaction_warn_query_count(&mut self, query_count: usize) {
+        self.transaction_warn.query_count = Some(query_count);
+    }
+
+    /// Replace every instrumentation installed on the connection with
+    /// `instrumentation`. `set_instrumentation` instead pushes onto the
+    /// connection's
+    /// [`InstrumentationStack`](crate::instrumentation::InstrumentationStack),
+    /// so that each instrumentation set receives its events.
+    pub fn replace_instrumentation(&mut self, instrumentation: impl Instrumentation) {
+        let stack = SharedInstrumentationStack::new(instrumentation);
+        self.inner.set_instrumentation(stack.clone());
+        self.instrumentation_stack = Some(stack);
+    }
+
+    /// The configuration controlling which spans are created.
+    #[must_use]
+    pub fn tracing_config(&self) -> TracingConfig {
+        self.config
+    }
+
+    /// Create spans for only the given fraction of queries, between 0 and 1,
+    /// to reduce the cost of tracing busy connections. The default of 1
+    /// traces every query.
+    ///
+    /// Queries which are sampled out still log an event if they fail, at the
+    /// level given to the error by the `error_levels` of the connection's
+    /// [`TracingConfig`]. The queries of a transaction are sampled together
+    /// with the transaction.
+    pub fn set_query_sample_rate(&mut self, rate: f32) {
+        self.sampler.set_rate(rate);
+    }
+
+    /// The fraction of queries which create spans.
+    #[must_use]
+    pub fn query_sample_rate(&self) -> f32 {
+        self.sampler.rate()
+    }
+
+    /// Append a sqlcommenter style `/*traceparent='...'*/` comment, naming the
+    /// OpenTelemetry trace and span of each statement, to the statements run
+    /// by `batch_execute` and `execute_returning_count`, so that the server's
+    /// logs and `pg_stat_activity` can be correlated with traces. Statements
+    /// are left as they are when the span has no OpenTelemetry context.
+    ///
+    /// Commented statements are not cached as prepared statements, as the
+    /// comment differs for each one, so this is off by default.
+    #[cfg(feature = "opentelemetry")]
+    pub fn enable_sql_comments(&mut self, enabled: bool) {
+        self.sql_comments = enabled;
+    }
+
+    /// The comment to append to a statement, if enabled.
+    #[cfg(feature = "opentelemetry")]
+    fn sql_comment(&self) -> Option<String> {
+        self.sql_comments.then(current_traceparent).flatten()
+    }
+
+    /// Log the `EXPLAIN (FORMAT JSON)` plan of every
+    /// `execute_returning_count` statement which takes longer than the
+    /// configured [`TracingConfig::slow_query_threshold`], as a warning.
+    ///
+    /// The statement is planned again but not run, so explaining costs an
+    /// extra round trip and the planning time, and is off by default. Only
+    /// `SELECT`, `INSERT`, `UPDATE` and `DELETE` statements are explained,
+    /// and `load` is not, as the rows it returns borrow the connection.
+    #[cfg(feature = "statement-fields")]
+    pub fn set_explain_on_slow(&mut self, explain_on_slow: bool) {
+        self.explain_on_slow = explain_on_slow;
+    }
+
+    /// Establish a connection labelled with a name, such as `"replica"`, which
+    /// is recorded as `db.connection.name` on its spans.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the connection could not be established.
+    pub fn establish_named(database_url: &str, name: impl Into<String>) -> ConnectionResult<Self> {
+        let mut conn = Self::establish(database_url)?;
+        conn.set_connection_name(name);
+        Ok(conn)
+    }
+
+    /// Establish a connection and run session settings on it, such as
+    /// `SET statement_timeout = '5s'`, before it is used.
+    ///
+    /// Each statement is run in order through the traced `batch_execute`,
+    /// inside an `establish_with_init` span, and a debug event with its index
+    /// as `db.init.index` is emitted for each.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the connection could not be established, or one
+    /// naming the first init statement which failed.
+    #[cfg_attr(
+        not(feature = "passthrough"),
+        tracing::instrument(
+            target = "diesel_tracing::pg::connection",
+            fields(
+                db.system="postgresql",
+                otel.kind="client",
+                otel.name=establish_span_name("establish_with_init"),
+                db.init.count=init_statements.len(),
+            ),
+            skip(database_url, init_statements),
+            err,
         )
-        .expect("failed to establish connection or collect info");
+    )]
+    pub fn establish_with_init(
+        database_url: &str,
+        init_statements: &[&str],
+    ) -> ConnectionResult<Self> {
+        let mut conn = Self::establish(database_url)?;
+        run_init_statements(&mut conn, init_statements)?;
+        #[cfg(feature = "reconnect")]
+        {
+            conn.init_statements = init_statements.iter().map(ToString::to_string).collect();
+        }
+        Ok(conn)
+    }
+
+    /// Establish a connection from discrete settings rather than a URL,
+    /// escaping each value into a libpq connection string.
+    ///
+    /// The host, port, user and database name of the configuration are
+    /// recorded on an `establish_with_config` span, along with the
+    /// information of the connection recorded by `establish`, and the
+    /// password never is.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the connection could not be established.
+    #[cfg_attr(
+        not(feature = "passthrough"),
+        tracing::instrument(
+            target = "diesel_tracing::pg::connection",
+            fields(
+                db.namespace=config.dbname(),
+                db.name=deprecated(config.dbname()),
+                db.system="postgresql",
+                db.version=field::Empty,
+                otel.kind="client",
+                otel.name=establish_span_name("establish_with_config"),
+                db.user=config.user(),
+                db.connection_id=field::Empty,
+                db.connection.id=field::Empty,
+                network.peer.address=field::Empty,
+                net.peer.ip=field::Empty,
+                server.address=config.host(),
+                net.peer.name=deprecated(config.host()),
+                server.port=config.port(),
+                net.peer.port=deprecated(config.port()),
+                db.client.address=field::Empty,
+                db.client.port=field::Empty,
+                db.postgresql.target_session_attrs=field::Empty,
+                db.postgresql.application_name=field::Empty,
+                db.postgresql.pid=field::Empty,
+                db.schema=field::Empty,
+                db.postgresql.schema=field::Empty,
+                db.connection.tls=field::Empty,
+                db.connection.tls_version=field::Empty,
+                db.connection.tls_cipher=field::Empty,
+                otel.status_code=field::Empty,
+                otel.status_message=field::Empty,
+            ),
+            skip(config),
+            err,
+        )
+    )]
+    pub fn establish_with_config(config: &PgConnectConfig) -> ConnectionResult<Self> {
+        Self::establish_in_current_span(&config.connection_string(), false)
+    }
+
+    /// Establish a connection from a URL or libpq key/value connection
+    /// string, recording its information on the span of the calling
+    /// `establish` method. The hosts and `target_session_attrs` are only
+    /// read from `connection_string` if it is a `url`.
+    fn establish_in_current_span(
+        connection_string: &str,
+        url: bool,
+    ) -> ConnectionResult<InstrumentedPgConnection> {
+        debug!(target: "diesel_tracing::pg::connection", "establishing postgresql connection");
+        let mut conn = PgConnection::establish(connection_string)
+            .inspect_err(|error| record_status(&tracing::Span::current(), error))?;
+
+        let mut info = PgConnectionInfo::query(&mut conn);
+        if url {
+            info.apply_url(connection_string);
+        }
+        info.record(&tracing::Span::current());
+
+        let instrumentation_stack = SharedInstrumentationStack::install(&mut conn);
+        let connection_id = ConnectionId::new();
+        connection_id.record(&tracing::Span::current());
+        let connection_span = connection_span(&info, connection_id);
+
+        Ok(InstrumentedPgConnection {
+            inner: conn,
+            info,
+            connection_id,
+            connection_name: None,
+            extra_fields: None,
+            span_namer: default_span_namer(),
+            connection_span,
+            config: TracingConfig::default(),
+            instrumentation_stack,
+            sampler: QuerySampler::default(),
+            read_only: ReadOnly::default(),
+            transaction_warn: TransactionWarnThresholds::default(),
+            check_binds: false,
+            explain_on_slow: false,
+            sql_comments: false,
+            #[cfg(feature = "audit")]
+            audit_sink: None,
+            metadata_cache: MetadataCacheStats::default(),
+            #[cfg(feature = "reconnect")]
+            auto_reconnect: None,
+            #[cfg(feature = "reconnect")]
+            init_statements: Vec::new(),
+        })
+    }
+
+    /// Label the connection with a name, which is recorded as
+    /// `db.connection.name` on its spans.
+    pub fn set_connection_name(&mut self, name: impl Into<String>) {
+        let name = name.into();
+        self.connection_span
+            .record("db.connection.name", name.as_str());
+        self.connection_name = Some(name);
+    }
+
+    /// The name the connection is labelled with, if any.
+    #[must_use]
+    pub fn connection_name(&self) -> Option<&str> {
+        self.connection_name.as_deref()
+    }
+
+    /// Send an [`AuditEntry`](crate::audit::AuditEntry) to the sink for
+    /// every statement which writes, whether or not it is sampled for
+    /// tracing.
+    #[cfg(feature = "audit")]
+    pub fn set_audit_sink(&mut self, sink: Arc<dyn AuditSink>) {
+        self.audit_sink = Some(sink);
+    }
+
+    /// Start auditing a statement, if the connection has an audit sink and
+    /// the statement writes.
+    #[cfg(feature = "audit")]
+    fn start_audit(&self, sql: impl FnOnce() -> Option<String>) -> Option<PendingAudit> {
+        let sink = self.audit_sink.as_ref()?;
+        PendingAudit::start(sink, "postgresql", self.connection_name.as_deref(), &sql()?)
+    }
+
+    instrument_method! {
+        connection = self,
+        instrument(
+            name = "transaction_state",
+            fields(
+                otel.name=span_name(self.span_namer.as_ref(), "transaction_state"),
+            ),
+            target = "diesel_tracing::pg::connection",
+            skip(self),
+        );
+        fn traced_transaction_state(&mut self) -> &mut AnsiTransactionManager {
+            self.inner.transaction_state()
+        }
+    }
+
+    instrument_method! {
+        connection = self,
+        instrument(
+            name = "instrumentation",
+            fields(
+                otel.name=span_name(self.span_namer.as_ref(), "instrumentation"),
+            ),
+            target = "diesel_tracing::pg::connection",
+            skip(self),
+        );
+        fn traced_instrumentation(&mut self) -> &mut dyn Instrumentation {
+            self.inner.instrumentation()
+        }
+    }
+
+    fn sampled_batch_execute(&mut self, query: &str) -> QueryResult<()> {
+        let traced = self.config.trace_batch;
+        let levels = self.config.error_levels;
+        #[cfg(feature = "reconnect")]
+        self.reconnect_if_closed_by_load();
+        self.connection_span.count_query();
+        #[cfg(feature = "reconnect")]
+        let in_transaction = self.in_transaction();
+        let result = if traced && self.sampler.sample() {
+            self.traced_batch_execute(query)
+        } else {
+            measure("postgresql", || self.inner.batch_execute(query)).inspect_err(|error| {
+                if traced {
+                    sampled_out_error_event!(
+                        "diesel_tracing::pg::query",
+                        "postgresql",
+                        levels,
+                        error
+                    );
+                }
+            })
+        };
+        #[cfg(feature = "reconnect")]
+        if let Err(error) = &result {
+            self.reconnect_if_closed(in_transaction, error);
+        }
+
+        result
+    }
+
+    fn sampled_execute_returning_count<T>(&mut self, source: &T) -> QueryResult<usize>
+    where
+        T: QueryFragment<Pg> + QueryId,
+    {
+        #[cfg(feature = "reconnect")]
+        self.reconnect_if_closed_by_load();
+        self.connection_span.count_query();
+        let traced = self.config.trace_execute;
+        let levels = self.config.error_levels;
+        #[cfg(feature = "reconnect")]
+        let in_transaction = self.in_transaction();
+        let result = if traced && self.sampler.sample() {
+            self.traced_execute_returning_count(source)
+        } else {
+            measure("postgresql", || self.inner.execute_returning_count(source)).inspect_err(
+                |error| {
+                    if traced {
+                        sampled_out_error_event!(
+                            "diesel_tracing::pg::query",
+                            "postgresql",
+                            levels,
+                            error
+                        );
+                    }
+                },
+            )
+        };
+        #[cfg(feature = "reconnect")]
+        if let Err(error) = &result {
+            self.reconnect_if_closed(in_transaction, error);
+        }
+
+        result
+    }
+
+    /// Attach static fields, such as a tenant or shard identifier, to every
+    /// span of the connection, replacing any attached before.
+    ///
+    /// Span field names are fixed when the span is declared, so the fields
+    /// are recorded together as `db.extra`, formatted as comma separated
+    /// `key=value` pairs, when each span is created. Passing no fields
+    /// removes them, recording `db.extra` as empty on the connection span,
+    /// since a field recorded on a span cannot be unset.
+    pub fn with_extra_fields(&mut self, fields: Vec<(String, String)>) {
+        self.extra_fields = format_extra_fields(fields);
+        self.connection_span
+            .record("db.extra", self.extra_fields.as_deref().unwrap_or_default());
+    }
+
+    /// Name the spans of the connection with a [`SpanNamer`], which records
+    /// the names it gives as `otel.name`.
+    #[must_use]
+    pub fn with_span_namer<N: SpanNamer>(mut self, namer: N) -> Self {
+        self.span_namer = Box::new(namer);
+        self
+    }
+
+    /// Re-establish the connection from `database_url` when a statement fails
+    /// because the connection was closed, such as after a TCP disconnection,
+    /// rather than failing every later statement too.
+    ///
+    /// After a statement fails with a closed connection outside a
+    /// transaction, up to `max_attempts` connections are attempted, waiting
+    /// with an [`ExponentialBackoff`] between them. A failed `load` reconnects
+    /// when the next statement is run instead, as the cursor it returns
+    /// borrows the connection. Each attempt logs a warning recording
+    /// `db.reconnect_attempt` and `database_url` with its password masked,
+    /// and an error is logged if every attempt fails. The failed statement is
+    /// not run again, as it may not be safe to repeat. The init statements of
+    /// [`Self::establish_with_init`] and the setting of [`Self::set_read_only`]
+    /// are run again on the new connection, and if they fail it is no longer
+    /// read only and a warning is logged. Other session settings such as the
+    /// `application_name` are not restored.
+    ///
+    /// The new connection gets a new `db.connection` span and
+    /// `db.connection.id`, recording the information of its server process,
+    /// and keeps the name and extra fields of the connection it replaces.
+    ///
+    /// Requires the `reconnect` feature flag.
+    #[cfg(feature = "reconnect")]
+    #[must_use]
+    pub fn with_auto_reconnect(mut self, database_url: String, max_attempts: u32) -> Self {
+        self.auto_reconnect = Some(AutoReconnect {
+            masked_url: mask_connection_url(&database_url).into_owned(),
+            database_url,
+            max_attempts,
+            closed_by_load: Arc::default(),
+        });
+        self
+    }
+
+    /// Whether a transaction is open, or the state of the transaction
+    /// manager is unknown.
+    #[cfg(feature = "reconnect")]
+    fn in_transaction(&mut self) -> bool {
+        !matches!(
+            AnsiTransactionManager::transaction_manager_status_mut(&mut self.inner)
+                .transaction_depth(),
+            Ok(None)
+        )
+    }
+
+    /// Replace the connection after `error` if it closed the connection and
+    /// automatic reconnection is enabled. A connection which was inside a
+    /// transaction is not replaced, as the new connection would silently
+    /// lose the transaction.
+    #[cfg(feature = "reconnect")]
+    fn reconnect_if_closed(&mut self, in_transaction: bool, error: &diesel::result::Error) {
+        if !in_transaction && is_closed_connection(error) {
+            self.reconnect();
+        }
+    }
+
+    /// Replace the connection if the last load failed because the connection
+    /// was closed.
+    #[cfg(feature = "reconnect")]
+    fn reconnect_if_closed_by_load(&mut self) {
+        let closed = self
+            .auto_reconnect
+            .as_ref()
+            .is_some_and(|reconnect| reconnect.closed_by_load.swap(false, Ordering::Relaxed));
+        if closed {
+            self.reconnect();
+        }
+    }
+
+    /// The flag for a load about to run to set if it fails because the
+    /// connection was closed, if automatic reconnection is enabled and no
+    /// transaction is open.
+    #[cfg(feature = "reconnect")]
+    fn closed_by_load_flag(&mut self) -> Option<Arc<AtomicBool>> {
+        self.reconnect_if_closed_by_load();
+        if self.auto_reconnect.is_none() || self.in_transaction() {
+            return None;
+        }
+        self.auto_reconnect
+            .as_ref()
+            .map(|reconnect| Arc::clone(&reconnect.closed_by_load))
+    }
+
+    #[cfg(feature = "reconnect")]
+    fn reconnect(&mut self) {
+        let Some(reconnect) = self.auto_reconnect.take() else {
+            return;
+        };
+
+        let backoff = ExponentialBackoff::default();
+        let mut attempt = 0;
+        let result = loop {
+            attempt += 1;
+            if !cfg!(feature = "passthrough") {
+                warn!(
+                    target: "diesel_tracing::pg::connection",
+                    {
+                        db.reconnect_attempt = attempt,
+                        database_url = %reconnect.masked_url,
+                    },
+                    "postgresql connection closed, reconnecting",
+                );
+            }
+            match PgConnection::establish(&reconnect.database_url) {
+                Err(ConnectionError::BadConnection(_)) if attempt < reconnect.max_attempts => {
+                    std::thread::sleep(backoff.delay(attempt));
+                }
+                result => break result,
+            }
+        };
+        match result {
+            Ok(mut inner) => {
+                let mut info = PgConnectionInfo::query(&mut inner);
+                info.apply_url(&reconnect.database_url);
+                if let Some(stack) = &self.instrumentation_stack {
+                    inner.set_instrumentation(stack.clone());
+                }
+                self.inner = inner;
+                self.info = info;
+                self.connection_id = ConnectionId::new();
+                self.connection_span = connection_span(&self.info, self.connection_id);
+                if let Some(name) = &self.connection_name {
+                    self.connection_span
+                        .record("db.connection.name", name.as_str());
+                }
+                if let Some(extra_fields) = &self.extra_fields {
+                    self.connection_span
+                        .record("db.extra", extra_fields.as_str());
+                }
+                self.restore_session();
+                if !cfg!(feature = "passthrough") {
+                    debug!(
+                        target: "diesel_tracing::pg::connection",
+                        { db.reconnect_attempt = attempt },
+                        "reconnected postgresql connection",
+                    );
+                }
+            }
+            Err(error) if !cfg!(feature = "passthrough") => {
+                tracing::error!(
+                    target: "diesel_tracing::pg::connection",
+                    {
+                        error = %error,
+                        db.reconnect_attempt = attempt,
+                        database_url = %reconnect.masked_url,
+                    },
+                    "failed to reconnect postgresql connection",
+                );
+            }
+            Err(_) => {}
+        }
+        self.auto_reconnect = Some(reconnect);
+    }
+
+    /// Run the init statements and restore the read only setting of the
+    /// session replaced by a reconnect on the new session. If either fails
+    /// the connection is no longer read only, and a warning is logged.
+    #[cfg(feature = "reconnect")]
+    fn restore_session(&mut self) {
+        let mut result = run_init_statements(&mut self.inner, &self.init_statements)
+            .map_err(|error| error.to_string());
+        if result.is_ok() && self.read_only.enabled {
+            result = self
+                .inner
+                .batch_execute("SET default_transaction_read_only = on")
+                .map_err(|error| error.to_string());
+            self.info.default_read_only = Some(result.is_ok());
+        }
+        if let Err(error) = result {
+            self.read_only.enabled = false;
+            if !cfg!(feature = "passthrough") {
+                warn!(
+                    target: "diesel_tracing::pg::connection",
+                    { error = %error },
+                    "failed to restore the session settings of reconnected postgresql connection, \
+                     it is no longer read only",
+                );
+            }
+        }
+    }
+
+    /// Set the postgresql `application_name` of the session, which is
+    /// recorded as `db.postgresql.application_name` on the spans which
+    /// follow.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the setting could not be changed.
+    pub fn set_application_name(&mut self, name: &str) -> QueryResult<()> {
+        let application_name: Option<String> =
+            select(set_config("application_name", name, false)).get_result(self)?;
+        if let Some(application_name) = &application_name {
+            self.connection_span
+                .record("db.postgresql.application_name", application_name.as_str());
+        }
+        self.info.application_name = application_name;
+
+        Ok(())
+    }
+
+    /// The postgresql `application_name` of the session, as of when it was
+    /// established, its information was last refreshed or it was last set
+    /// with [`Self::set_application_name`].
+    #[must_use]
+    pub fn application_name(&self) -> Option<&str> {
+        self.info.application_name.as_deref()
+    }
+
+    /// Query the current schema and the schemas in the search path again,
+    /// which are recorded as `db.schema` and `db.postgresql.schema` on the
+    /// spans which follow, such as after the `search_path` has been changed.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the schemas could not be queried.
+    pub fn refresh_schema(&mut self) -> QueryResult<()> {
+        let (current_schema, schemas): (Option<String>, Option<Vec<String>>) =
+            select((current_schema(), current_schemas(false))).get_result(self)?;
+        if let Some(current_schema) = &current_schema {
+            self.connection_span
+                .record("db.schema", current_schema.as_str());
+        }
+        self.info.current_schema = current_schema;
+        self.info.schema = schemas.map(|schemas| schemas.join(","));
+        if let Some(schema) = &self.info.schema {
+            self.connection_span
+                .record("db.postgresql.schema", schema.as_str());
+        }
+
+        Ok(())
+    }
+
+    /// The current schema of the session, the first schema of the search
+    /// path which exists, as of when it was established, its information was
+    /// last refreshed or its schema was last refreshed with
+    /// [`Self::refresh_schema`].
+    #[must_use]
+    pub fn current_schema(&self) -> Option<&str> {
+        self.info.current_schema.as_deref()
+    }
+
+    /// Set the default isolation level of the transactions of the session,
+    /// which is recorded as `db.transaction.isolation_level` on the
+    /// transaction spans which follow.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the session default could not be changed.
+    pub fn set_default_isolation_level(&mut self, level: IsolationLevel) -> QueryResult<()> {
+        self.batch_execute(&format!(
+            "SET SESSION CHARACTERISTICS AS TRANSACTION ISOLATION LEVEL {}",
+            level.as_sql()
+        ))?;
+        self.info.default_isolation_level = Some(level.as_str().to_string());
+
+        Ok(())
+    }
+
+    /// Start listening for notifications on a channel, emitting an event with
+    /// the channel name once the `LISTEN` has run.
+    ///
+    /// diesel does not expose the notifications received by a connection, so
+    /// they have to be read from the connection by other means.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the `LISTEN` statement fails.
+    pub fn listen(&mut self, channel: &str) -> QueryResult<()> {
+        sql_query(format!("LISTEN {}", quote_identifier(channel)?)).execute(self)?;
+        debug!(
+            target: "diesel_tracing::pg::query",
+            {
+                db.operation = "LISTEN",
+                db.postgresql.channel = channel,
+            },
+            "listening for notifications",
+        );
+
+        Ok(())
+    }
+
+    /// Stop listening for notifications on a channel, emitting an event with
+    /// the channel name once the `UNLISTEN` has run.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the `UNLISTEN` statement fails.
+    pub fn unlisten(&mut self, channel: &str) -> QueryResult<()> {
+        sql_query(format!("UNLISTEN {}", quote_identifier(channel)?)).execute(self)?;
+        debug!(
+            target: "diesel_tracing::pg::query",
+            {
+                db.operation = "UNLISTEN",
+                db.postgresql.channel = channel,
+            },
+            "stopped listening for notifications",
+        );
+
+        Ok(())
+    }
+
+    /// Send a notification on a channel, emitting an event with the channel
+    /// name once it has been sent. The payload is only recorded on the event
+    /// with the `statement-fields` feature.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the notification could not be sent.
+    pub fn notify(&mut self, channel: &str, payload: &str) -> QueryResult<()> {
+        sql_query("SELECT pg_notify($1, $2)")
+            .bind::<Text, _>(channel)
+            .bind::<Text, _>(payload)
+            .execute(self)?;
+        #[cfg(feature = "statement-fields")]
+        debug!(
+            target: "diesel_tracing::pg::query",
+            {
+                db.operation = "NOTIFY",
+                db.postgresql.channel = channel,
+                db.postgresql.payload = payload,
+            },
+            "sent notification",
+        );
+        #[cfg(not(feature = "statement-fields"))]
+        debug!(
+            target: "diesel_tracing::pg::query",
+            {
+                db.operation = "NOTIFY",
+                db.postgresql.channel = channel,
+            },
+            "sent notification",
+        );
+
+        Ok(())
+    }
+
+    /// The information about the connection queried from the server, as
+    /// recorded on its spans.
+    #[must_use]
+    pub fn connection_info(&self) -> &PgConnectionInfo {
+        &self.info
+    }
+
+    /// Run `SELECT 1` on the connection, traced as a `batch_execute`, and
+    /// report whether it succeeded.
+    pub fn is_healthy(&mut self) -> bool {
+        self.batch_execute("SELECT 1").is_ok()
+    }
+
+    /// The wrapped diesel connection.
+    #[must_use]
+    pub fn inner(&self) -> &PgConnection {
+        &self.inner
+    }
+
+    /// The wrapped diesel connection, for calling APIs this wrapper does not
+    /// expose. Queries run on it directly are not traced.
+    pub fn inner_mut(&mut self) -> &mut PgConnection {
+        &mut self.inner
+    }
+
+    /// Recover the instrumented connection from a type erased
+    /// `dyn BoxableConnection<Pg>`, or `None` if it holds another type of
+    /// connection.
+    pub fn downcast_from(conn: &mut dyn BoxableConnection<Pg>) -> Option<&mut Self> {
+        conn.as_any_mut().downcast_mut()
+    }
+
+    /// Unwrap the diesel connection, ending the connection's span.
+    #[must_use]
+    pub fn into_inner(self) -> PgConnection {
+        self.inner
+    }
+
+    instrument_method! {
+        connection = self,
+        instrument(
+            fields(
+                otel.name=span_name(self.span_namer.as_ref(), "refresh_info"),
+            ),
+            target = "diesel_tracing::pg::connection",
+            skip(self),
+            err,
+        );
+        /// Query the server for the information recorded on spans again, such as
+        /// after a pooler may have routed the connection to a different server.
+        ///
+        /// A warning is emitted if the database name has changed. The existing
+        /// information is kept if the query fails.
+        ///
+        /// # Errors
+        ///
+        /// Returns an error if the connection information could not be queried.
+        pub fn refresh_info(&mut self) -> ConnectionResult<()> {
+            let mut info = PgConnectionInfo::try_query(&mut self.inner)
+                .map_err(ConnectionError::CouldntSetupConfiguration)?;
+            info.target_session_attrs = self.info.target_session_attrs.take();
+            info.url_hosts = std::mem::take(&mut self.info.url_hosts);
+            info.match_url_host();
+            if info.current_database != self.info.current_database {
+                warn!(
+                    target: "diesel_tracing::pg::connection",
+                    previous = self.info.current_database.as_deref(),
+                    current = info.current_database.as_deref(),
+                    "postgresql database changed, the connection may have been re-routed"
+                );
+            }
+            debug!(
+                target: "diesel_tracing::pg::connection",
+                "refreshed postgresql connection information",
+            );
+            self.info = info;
+
+            Ok(())
+        }
+    }
+
+    instrument_method! {
+        connection = self,
+        instrument(
+            fields(
+                otel.name=span_name(self.span_namer.as_ref(), "preload_type_metadata"),
+                db.operation="type_lookup",
+                db.sql.table="pg_type",
+                db.rows_affected=field::Empty,
+            ),
+            target = "diesel_tracing::pg::connection",
+            skip(self, type_names),
+            err,
+        );
+        /// Look up the OIDs of custom types by name in a single query and store
+        /// them in the metadata cache, so that the first queries binding them do
+        /// not each make their own lookup.
+        ///
+        /// Names are resolved as diesel resolves types without an explicit schema,
+        /// and names which do not resolve to a type are skipped.
+        ///
+        /// # Errors
+        ///
+        /// Returns an error if the lookup query fails.
+        pub fn preload_type_metadata(&mut self, type_names: &[&str]) -> QueryResult<()> {
+            let types = sql_query(
+                "SELECT n.name, t.oid, t.typarray \
+                 FROM unnest($1::text[]) AS n(name) \
+                 JOIN pg_type t ON t.oid = to_regtype(quote_ident(n.name))::oid",
+            )
+            .bind::<Array<Text>, _>(type_names)
+            .load::<PgTypeRow>(&mut self.inner)
+            .inspect_err(|error| record_error(&tracing::Span::current(), error))?;
+            tracing::Span::current().record("db.rows_affected", types.len());
+
+            let cache = self.inner.get_metadata_cache();
+            for PgTypeRow {
+                name,
+                oid,
+                typarray,
+            } in types
+            {
+                let key = PgMetadataCacheKey::new(None, name.into());
+                if cache.lookup_type(&key).is_none() {
+                    self.metadata_cache.entries += 1;
+                }
+                cache.store_type(key, (oid, typarray));
+            }
+
+            Ok(())
+        }
+    }
+
+    /// How the postgres type metadata cache of the connection has been used.
+    #[must_use]
+    pub fn metadata_cache_stats(&self) -> MetadataCacheStats {
+        self.metadata_cache
+    }
+
+    instrument_method! {
+        connection = self,
+        #[cfg(feature = "statement-fields")]
+        instrument(
+            name = "batch_execute",
+            fields(
+                otel.name=span_name(self.span_namer.as_ref(), "batch_execute"),
+                db.operation=field::Empty,
+                db.sql.table=field::Empty,
+                db.access_mode=field::Empty,
+                db.statement_fingerprint=field::Empty,
+                db.statement.hash=field::Empty,
+                db.error.kind=field::Empty,
+                db.error.table=field::Empty,
+                db.error.constraint=field::Empty,
+                otel.status_code=field::Empty,
+                otel.status_message=field::Empty,
+                db.batch.size=field::Empty,
+                db.batch.error_index=field::Empty,
+                db.statement=truncate_statement(
+                    query.to_owned(),
+                    self.config.max_statement_length(),
+                ),
+            ),
+            target = "diesel_tracing::pg::query",
+            skip(self, query),
+        ),
+        #[cfg(not(feature = "statement-fields"))]
+        instrument(
+            name = "batch_execute",
+            fields(
+                otel.name=span_name(self.span_namer.as_ref(), "batch_execute"),
+                db.operation=field::Empty,
+                db.sql.table=field::Empty,
+                db.access_mode=field::Empty,
+                db.statement_fingerprint=field::Empty,
+                db.statement.hash=field::Empty,
+                db.error.kind=field::Empty,
+                db.error.table=field::Empty,
+                db.error.constraint=field::Empty,
+                otel.status_code=field::Empty,
+                otel.status_message=field::Empty,
+                db.batch.size=field::Empty,
+                db.batch.error_index=field::Empty,
+            ),
+            target = "diesel_tracing::pg::query",
+            skip(self, query),
+        );
+        fn traced_batch_execute(&mut self, query: &str) -> QueryResult<()> {
+            debug!(target: "diesel_tracing::pg::query", "executing batch query");
+            record_statement_summary(
+                query,
+                name_by_statement(self.config, self.span_namer.as_ref(), "batch_execute"),
+            );
+            record_batch(query);
+            #[cfg(feature = "opentelemetry")]
+            let commented = self
+                .sql_comment()
+                .map(|comment| format!("{query} {comment}"));
+            #[cfg(feature = "opentelemetry")]
+            let sql = commented.as_deref().unwrap_or(query);
+            #[cfg(not(feature = "opentelemetry"))]
+            let sql = query;
+            measure("postgresql", || self.inner.batch_execute(sql)).inspect_err(|error| {
+                record_error(&tracing::Span::current(), error);
+                error_event!("diesel_tracing::pg::query", self.config.error_levels, error);
+                record_batch_error(query, error);
+            })?;
+
+            Ok(())
+        }
+    }
+
+    /// Run the closure inside a savepoint with the given name, releasing it
+    /// if the closure returns `Ok` and rolling back to it otherwise, without
+    /// ending the enclosing transaction. The `savepoint` span records the
+    /// name as `db.transaction.savepoint`, making nested transactions easier
+    /// to tell apart than diesel's numbered savepoints.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the name is not a plain identifier, any error from
+    /// creating, releasing or rolling back to the savepoint, or the error
+    /// from the closure.
+    pub fn savepoint<T, E, F>(&mut self, name: &str, f: F) -> Result<T, E>
+    where
+        F: FnOnce(&mut Self) -> Result<T, E>,
+        E: From<diesel::result::Error>,
+    {
+        if !self.config.trace_transactions || !self.sampler.sample() {
+            return run_savepoint(self, name, f);
+        }
+        self.traced_savepoint(name, f)
+    }
+
+    instrument_method! {
+        connection = self,
+        instrument(
+            name = "insert_in_chunks",
+            fields(
+                otel.name=span_name(self.span_namer.as_ref(), "insert_in_chunks"),
+                db.operation="INSERT",
+                db.batch.chunks=field::Empty,
+                db.batch.total_rows=values.len(),
+                db.batch.rows_committed=field::Empty,
+                db.rows_affected=field::Empty,
+            ),
+            target = "diesel_tracing::pg::query",
+            skip(self, table, values),
+            err,
+        );
+        /// Insert rows in chunks of at most `chunk_size`, keeping each statement
+        /// within the postgres limit of 65535 bind parameters, and return the number
+        /// of rows inserted.
+        ///
+        /// The insert is traced by an `insert_in_chunks` span recording
+        /// `db.batch.chunks` and `db.batch.total_rows`, with an `insert_chunk`
+        /// span for each chunk recording its `db.batch.index` and `db.batch.size`.
+        /// Chunks are not inserted in a transaction, so when one fails the rows of
+        /// the chunks before it remain, and their number is recorded as
+        /// `db.batch.rows_committed`.
+        ///
+        /// # Errors
+        ///
+        /// Returns an error, before inserting anything, if `chunk_size` is zero or
+        /// a chunk would exceed the bind parameter limit, or else the error of the
+        /// first chunk which fails.
+        pub fn insert_in_chunks<T, V>(
+            &mut self,
+            table: T,
+            values: Vec<V>,
+            chunk_size: usize,
+        ) -> QueryResult<usize>
+        where
+            T: Table + Copy,
+            Vec<V>: Insertable<T>,
+            InsertStatement<T, <Vec<V> as Insertable<T>>::Values>: ExecuteDsl<Self>,
+        {
+            chunks::insert_in_chunks(self, table, values, chunk_size, |index, size| {
+                info_span!(
+                    target: "diesel_tracing::pg::query",
+                    "insert_chunk",
+                    db.batch.index = index,
+                    db.batch.size = size,
+                )
+            })
+        }
+    }
+
+    instrument_method! {
+        connection = self,
+        instrument(
+            name = "load_chunked",
+            fields(
+                otel.name=span_name(self.span_namer.as_ref(), "load_chunked"),
+                db.batch.chunks=field::Empty,
+                db.batch.total_rows=field::Empty,
+            ),
+            target = "diesel_tracing::pg::query",
+            skip(self, query, f),
+            err,
+        );
+        /// Load the rows of a query in chunks of at most `chunk_size`, passing
+        /// each chunk to `f` as it is read, and return the number of rows loaded.
+        /// The rows are read one at a time with `PgRowByRowLoadingMode`, so at
+        /// most one chunk of them is held in memory.
+        ///
+        /// The load is traced by a `load_chunked` span recording `db.batch.chunks`
+        /// and `db.batch.total_rows`, with a `load_chunk` span around each call of
+        /// `f` recording the chunk's `db.batch.index` and `db.batch.size`.
+        ///
+        /// # Errors
+        ///
+        /// Returns an error if `chunk_size` is zero, or else the first error of
+        /// the query or of `f`, which stops the load.
+        pub fn load_chunked<'conn, 'query, U, Q>(
+            &'conn mut self,
+            query: Q,
+            chunk_size: usize,
+            f: impl FnMut(Vec<U>) -> QueryResult<()>,
+        ) -> QueryResult<usize>
+        where
+            Q: LoadQuery<'query, Self, U, PgRowByRowLoadingMode> + 'conn,
+            U: 'conn,
+        {
+            let chunk_span = |index, size| {
+                info_span!(
+                    target: "diesel_tracing::pg::query",
+                    "load_chunk",
+                    db.batch.index = index,
+                    db.batch.size = size,
+                )
+            };
+            chunks::load_chunked::<_, PgRowByRowLoadingMode, _, _, _>(
+                self, query, chunk_size, chunk_span, f,
+            )
+        }
+    }
+
+    instrument_method! {
+        connection = self,
+        instrument(
+            name = "savepoint",
+            fields(
+                otel.name=span_name(self.span_namer.as_ref(), "savepoint"),
+                db.transaction.savepoint=name,
+                db.transaction.rolled_back=field::Empty,
+                otel.status_code=field::Empty,
+            ),
+            target = "diesel_tracing::pg::transaction",
+            skip(self, name, f),
+        );
+        fn traced_savepoint<T, E, F>(&mut self, name: &str, f: F) -> Result<T, E>
+        where
+            F: FnOnce(&mut Self) -> Result<T, E>,
+            E: From<diesel::result::Error>,
+        {
+            let result = run_savepoint(self, name, f);
+            record_transaction_outcome(&result);
+
+            result
+        }
+    }
+
+    instrument_method! {
+        connection = self,
+        instrument(
+            name = "transaction",
+            fields(
+                otel.name=span_name(self.span_namer.as_ref(), "transaction"),
+                db.transaction.depth=field::Empty,
+                db.transaction.rolled_back=field::Empty,
+                db.transaction.duration_ms=field::Empty,
+                db.transaction.queries=field::Empty,
+                otel.status_code=field::Empty,
+                db.transaction.isolation_level=self.info.default_isolation_level.as_deref(),
+                db.transaction.read_only=self.info.default_read_only,
+            ),
+            target = "diesel_tracing::pg::transaction",
+            skip(self, f),
+        );
+        fn traced_transaction<T, E, F>(&mut self, f: F) -> Result<T, E>
+        where
+            F: FnOnce(&mut Self) -> Result<T, E>,
+            E: From<diesel::result::Error>,
+        {
+            record_transaction_depth(AnsiTransactionManager::transaction_manager_status_mut(self));
+            let stack = self.instrumentation_stack.clone();
+            let thresholds = self.transaction_warn;
+            let result = AnsiTransactionManager::transaction(self, |conn| {
+                watch_transaction(stack.as_ref(), thresholds, || f(conn))
+            });
+            record_transaction_outcome(&result);
+
+            result
+        }
+    }
+
+    instrument_method! {
+        connection = self,
+        #[cfg(feature = "statement-fields")]
+        instrument(
+            name = "execute_returning_count",
+            fields(
+                otel.name=span_name(self.span_namer.as_ref(), "execute_returning_count"),
+                db.operation=field::Empty,
+                db.sql.table=field::Empty,
+                db.access_mode=field::Empty,
+                db.statement_fingerprint=field::Empty,
+                db.statement.hash=field::Empty,
+                db.bind_count=field::Empty,
+                code.function=field::Empty,
+                code.filepath=field::Empty,
+                code.lineno=field::Empty,
+                db.error.kind=field::Empty,
+                db.error.table=field::Empty,
+                db.error.constraint=field::Empty,
+                otel.status_code=field::Empty,
+                otel.status_message=field::Empty,
+                db.statement.cached=field::Empty,
+                db.rows_affected=field::Empty,
+                db.statement=truncate_statement(
+                    diesel::debug_query::<Pg, _>(source).to_string(),
+                    self.config.max_statement_length(),
+                ),
+            ),
+            target = "diesel_tracing::pg::query",
+            skip(self, source),
+        ),
+        #[cfg(not(feature = "statement-fields"))]
+        instrument(
+            name = "execute_returning_count",
+            fields(
+                otel.name=span_name(self.span_namer.as_ref(), "execute_returning_count"),
+                db.operation=field::Empty,
+                db.sql.table=field::Empty,
+                db.access_mode=field::Empty,
+                db.statement_fingerprint=field::Empty,
+                db.statement.hash=field::Empty,
+                db.bind_count=field::Empty,
+                code.function=field::Empty,
+                code.filepath=field::Empty,
+                code.lineno=field::Empty,
+                db.error.kind=field::Empty,
+                db.error.table=field::Empty,
+                db.error.constraint=field::Empty,
+                otel.status_code=field::Empty,
+                otel.status_message=field::Empty,
+                db.statement.cached=field::Empty,
+                db.rows_affected=field::Empty,
+            ),
+            target = "diesel_tracing::pg::query",
+            skip(self, source),
+        );
+        fn traced_execute_returning_count<T>(&mut self, source: &T) -> QueryResult<usize>
+        where
+            T: QueryFragment<Pg> + QueryId,
+        {
+            record_query_summary::<Pg, _>(
+                source,
+                name_by_statement(
+                    self.config,
+                    self.span_namer.as_ref(),
+                    "execute_returning_count",
+                ),
+            );
+            #[cfg(feature = "opentelemetry")]
+            let comment = self.sql_comment();
+            #[cfg(feature = "opentelemetry")]
+            let source = &Commented::new(source, comment.as_deref());
+            #[cfg(all(feature = "statement-fields", not(feature = "passthrough")))]
+            let started = std::time::Instant::now();
+            let cache_probe =
+                StatementCacheProbe::start(self.instrumentation_stack.as_ref(), source, &Pg);
+            let result = measure("postgresql", || self.inner.execute_returning_count(source));
+            if let Some(probe) = cache_probe {
+                probe.record(&tracing::Span::current());
+            }
+            let count = result.inspect_err(|error| {
+                record_error(&tracing::Span::current(), error);
+                error_event!("diesel_tracing::pg::query", self.config.error_levels, error);
+            })?;
+            tracing::Span::current().record("db.rows_affected", count);
+            #[cfg(all(feature = "statement-fields", not(feature = "passthrough")))]
+            self.explain_if_slow(source, started.elapsed());
+
+            Ok(count)
+        }
+    }
+
+    /// Log the plan of a statement which took `elapsed`, if explaining slow
+    /// queries is enabled and it was slow.
+    #[cfg(all(feature = "statement-fields", not(feature = "passthrough")))]
+    fn explain_if_slow<T>(&mut self, source: &T, elapsed: std::time::Duration)
+    where
+        T: QueryFragment<Pg> + QueryId,
+    {
+        if !self.explain_on_slow
+            || !is_slow(self.config.slow_query_threshold, elapsed)
+            || !is_explainable(&diesel::debug_query::<Pg, _>(source).to_string())
+        {
+            return;
+        }
+        let plan = Explain::new("EXPLAIN (FORMAT JSON)", source)
+            .get_result::<QueryPlan>(&mut self.inner)
+            .map(|plan| plan.plan);
+        record_plan(plan, elapsed, self.config.max_statement_length());
+    }
+
+    instrument_method! {
+        connection = self,
+        #[cfg(feature = "statement-fields")]
+        instrument(
+            name = "load",
+            fields(
+                otel.name=span_name(self.span_namer.as_ref(), "load"),
+                db.operation=field::Empty,
+                db.sql.table=field::Empty,
+                db.access_mode=field::Empty,
+                db.statement_fingerprint=field::Empty,
+                db.statement.hash=field::Empty,
+                db.bind_count=field::Empty,
+                code.function=field::Empty,
+                code.filepath=field::Empty,
+                code.lineno=field::Empty,
+                db.error.kind=field::Empty,
+                db.error.table=field::Empty,
+                db.error.constraint=field::Empty,
+                otel.status_code=field::Empty,
+                otel.status_message=field::Empty,
+                db.statement.cached=field::Empty,
+                db.statement=truncate_statement(
+                    diesel::debug_query(&source).to_string(),
+                    self.config.max_statement_length(),
+                ),
+            ),
+            target = "diesel_tracing::pg::query",
+            skip(self, source),
+        ),
+        #[cfg(not(feature = "statement-fields"))]
+        instrument(
+            name = "load",
+            fields(
+                otel.name=span_name(self.span_namer.as_ref(), "load"),
+                db.operation=field::Empty,
+                db.sql.table=field::Empty,
+                db.access_mode=field::Empty,
+                db.statement_fingerprint=field::Empty,
+                db.statement.hash=field::Empty,
+                db.bind_count=field::Empty,
+                code.function=field::Empty,
+                code.filepath=field::Empty,
+                code.lineno=field::Empty,
+                db.error.kind=field::Empty,
+                db.error.table=field::Empty,
+                db.error.constraint=field::Empty,
+                otel.status_code=field::Empty,
+                otel.status_message=field::Empty,
+                db.statement.cached=field::Empty,
+            ),
+            target = "diesel_tracing::pg::query",
+            skip(self, source),
+        );
+        fn traced_load<'conn, 'query, T>(
+            &'conn mut self,
+            source: T,
+        ) -> QueryResult<<Self as LoadConnection<DefaultLoadingMode>>::Cursor<'conn, 'query>>
+        where
+            T: Query + QueryFragment<Pg> + QueryId + 'query,
+            Pg: QueryMetadata<T::SqlType>,
+        {
+            record_query_summary::<Pg, _>(
+                &source,
+                name_by_statement(self.config, self.span_namer.as_ref(), "load"),
+            );
+            let cache_probe =
+                StatementCacheProbe::start(self.instrumentation_stack.as_ref(), &source, &Pg);
+            let levels = self.config.error_levels;
+            let result = measure("postgresql", move || {
+                <PgConnection as LoadConnection<DefaultLoadingMode>>::load(&mut self.inner, source)
+            });
+            if let Some(probe) = cache_probe {
+                probe.record(&tracing::Span::current());
+            }
+            result.inspect_err(|error| {
+                record_error(&tracing::Span::current(), error);
+                error_event!("diesel_tracing::pg::query", levels, error);
+            })
+        }
+    }
+
+    instrument_method! {
+        connection = self,
+        #[cfg(feature = "statement-fields")]
+        instrument(
+            name = "load",
+            fields(
+                otel.name=span_name(self.span_namer.as_ref(), "load"),
+                db.operation=field::Empty,
+                db.sql.table=field::Empty,
+                db.access_mode=field::Empty,
+                db.statement_fingerprint=field::Empty,
+                db.statement.hash=field::Empty,
+                db.bind_count=field::Empty,
+                code.function=field::Empty,
+                code.filepath=field::Empty,
+                code.lineno=field::Empty,
+                db.error.kind=field::Empty,
+                db.error.table=field::Empty,
+                db.error.constraint=field::Empty,
+                otel.status_code=field::Empty,
+                otel.status_message=field::Empty,
+                db.statement.cached=field::Empty,
+                db.statement=truncate_statement(
+                    diesel::debug_query(&source).to_string(),
+                    self.config.max_statement_length(),
+                ),
+            ),
+            target = "diesel_tracing::pg::query",
+            skip(self, source),
+        ),
+        #[cfg(not(feature = "statement-fields"))]
+        instrument(
+            name = "load",
+            fields(
+                otel.name=span_name(self.span_namer.as_ref(), "load"),
+                db.operation=field::Empty,
+                db.sql.table=field::Empty,
+                db.access_mode=field::Empty,
+                db.statement_fingerprint=field::Empty,
+                db.statement.hash=field::Empty,
+                db.bind_count=field::Empty,
+                code.function=field::Empty,
+                code.filepath=field::Empty,
+                code.lineno=field::Empty,
+                db.error.kind=field::Empty,
+                db.error.table=field::Empty,
+                db.error.constraint=field::Empty,
+                otel.status_code=field::Empty,
+                otel.status_message=field::Empty,
+                db.statement.cached=field::Empty,
+            ),
+            target = "diesel_tracing::pg::query",
+            skip(self, source),
+        );
+        fn traced_load_row_by_row<'conn, 'query, T>(
+            &'conn mut self,
+            source: T,
+        ) -> QueryResult<<Self as LoadConnection<PgRowByRowLoadingMode>>::Cursor<'conn, 'query>>
+        where
+            T: Query + QueryFragment<Pg> + QueryId + 'query,
+            Pg: QueryMetadata<T::SqlType>,
+        {
+            record_query_summary::<Pg, _>(
+                &source,
+                name_by_statement(self.config, self.span_namer.as_ref(), "load"),
+            );
+            let cache_probe =
+                StatementCacheProbe::start(self.instrumentation_stack.as_ref(), &source, &Pg);
+            let levels = self.config.error_levels;
+            let result = measure("postgresql", move || {
+                <PgConnection as LoadConnection<PgRowByRowLoadingMode>>::load(&mut self.inner, source)
+            });
+            if let Some(probe) = cache_probe {
+                probe.record(&tracing::Span::current());
+            }
+            result.inspect_err(|error| {
+                record_error(&tracing::Span::current(), error);
+                error_event!("diesel_tracing::pg::query", levels, error);
+            })
+        }
+    }
+}
+
+#[cfg(all(feature = "statement-fields", not(feature = "passthrough")))]
+#[derive(QueryableByName)]
+struct QueryPlan {
+    #[diesel(sql_type = Text, column_name = "QUERY PLAN")]
+    plan: String,
+}
+
+#[derive(QueryableByName)]
+struct PgTypeRow {
+    #[diesel(sql_type = Text)]
+    name: String,
+    #[diesel(sql_type = Oid)]
+    oid: u32,
+    #[diesel(sql_type = Oid)]
+    typarray: u32,
+}
+
+/// Counts of the use of the postgres type metadata cache of a connection, from
+/// [`InstrumentedPgConnection::metadata_cache_stats`].
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct MetadataCacheStats {
+    /// The number of times the cache was consulted through the instrumented
+    /// connection, such as by `PgMetadataLookup` or a `MultiConnection`.
+    pub lookups: u64,
+    /// The number of types stored in the cache by `preload_type_metadata`.
+    ///
+    /// Diesel does not expose the size of the cache, so types it looks up
+    /// and stores itself are not counted.
+    pub entries: usize,
+}
+
+/// A postgres transaction isolation level, for
+/// [`InstrumentedPgConnection::set_default_isolation_level`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum IsolationLevel {
+    /// `READ COMMITTED`, the postgres default.
+    ReadCommitted,
+    /// `REPEATABLE READ`.
+    RepeatableRead,
+    /// `SERIALIZABLE`.
+    Serializable,
+}
+
+impl IsolationLevel {
+    /// The level as recorded in `db.transaction.isolation_level`.
+    fn as_str(self) -> &'static str {
+        match self {
+            IsolationLevel::ReadCommitted => "read_committed",
+            IsolationLevel::RepeatableRead => "repeatable_read",
+            IsolationLevel::Serializable => "serializable",
+        }
+    }
+
+    fn as_sql(self) -> &'static str {
+        match self {
+            IsolationLevel::ReadCommitted => "READ COMMITTED",
+            IsolationLevel::RepeatableRead => "REPEATABLE READ",
+            IsolationLevel::Serializable => "SERIALIZABLE",
+        }
+    }
+}
+
+/// The SQLSTATE code of a postgres error, where it can be recovered.
+///
+/// diesel does not expose the SQLSTATE of database errors, so it is derived
+/// from the [`DatabaseErrorKind`] where that corresponds to a single code,
+/// and deadlocks, which diesel does not classify, are recognised by the
+/// server's `deadlock detected` message. Other errors return `None`.
+#[must_use]
+pub fn sqlstate(error: &diesel::result::Error) -> Option<&'static str> {
+    let diesel::result::Error::DatabaseError(kind, info) = error else {
+        return None;
+    };
+    match kind {
+        DatabaseErrorKind::UniqueViolation => Some("23505"),
+        DatabaseErrorKind::ForeignKeyViolation => Some("23503"),
+        DatabaseErrorKind::NotNullViolation => Some("23502"),
+        DatabaseErrorKind::CheckViolation => Some("23514"),
+        DatabaseErrorKind::ReadOnlyTransaction => Some("25006"),
+        DatabaseErrorKind::SerializationFailure => Some("40001"),
+        _ if info.message().starts_with("deadlock detected") => Some("40P01"),
+        _ => None,
+    }
+}
+
+/// Whether an error is a serialization failure, SQLSTATE `40001`, or a
+/// deadlock, `40P01`, after which the transaction can usually be retried.
+#[must_use]
+pub fn is_serialization_failure(error: &diesel::result::Error) -> bool {
+    matches!(sqlstate(error), Some("40001" | "40P01"))
+}
+
+/// Whether an error, or any error in its chain of sources, is a
+/// serialization failure.
+fn find_serialization_failure(error: &(dyn std::error::Error + 'static)) -> bool {
+    let mut error = Some(error);
+    while let Some(current) = error {
+        if current
+            .downcast_ref::<diesel::result::Error>()
+            .is_some_and(is_serialization_failure)
+        {
+            return true;
+        }
+        error = current.source();
+    }
+
+    false
+}
+
+/// A wrapper around diesel's [`TransactionBuilder`] which opens a
+/// `pg_transaction` span recording the chosen options when it is run.
+///
+/// Created by [`InstrumentedPgConnection::build_transaction`]. Options which
+/// are not set are left to the server defaults and are not recorded.
+#[must_use = "transaction builders do nothing unless `run` is called"]
+pub struct InstrumentedTransactionBuilder<'a> {
+    conn: &'a mut InstrumentedPgConnection,
+    isolation_level: Option<IsolationLevel>,
+    read_only: Option<bool>,
+    deferrable: Option<bool>,
+    retries: Option<usize>,
+}
+
+impl InstrumentedTransactionBuilder<'_> {
+    /// See [`TransactionBuilder::read_only`].
+    pub fn read_only(mut self) -> Self {
+        self.read_only = Some(true);
+        self
+    }
+
+    /// See [`TransactionBuilder::read_write`].
+    pub fn read_write(mut self) -> Self {
+        self.read_only = Some(false);
+        self
+    }
+
+    /// See [`TransactionBuilder::deferrable`].
+    pub fn deferrable(mut self) -> Self {
+        self.deferrable = Some(true);
+        self
+    }
+
+    /// See [`TransactionBuilder::not_deferrable`].
+    pub fn not_deferrable(mut self) -> Self {
+        self.deferrable = Some(false);
+        self
+    }
+
+    /// See [`TransactionBuilder::read_committed`].
+    pub fn read_committed(mut self) -> Self {
+        self.isolation_level = Some(IsolationLevel::ReadCommitted);
+        self
+    }
+
+    /// See [`TransactionBuilder::repeatable_read`].
+    pub fn repeatable_read(mut self) -> Self {
+        self.isolation_level = Some(IsolationLevel::RepeatableRead);
+        self
+    }
+
+    /// See [`TransactionBuilder::serializable`].
+    pub fn serializable(mut self) -> Self {
+        self.isolation_level = Some(IsolationLevel::Serializable);
+        self
+    }
+
+    /// Run the closure in a transaction with the chosen options, committing
+    /// it if the closure returns `Ok` and rolling it back otherwise.
+    ///
+    /// # Errors
+    ///
+    /// Returns the error from the closure, or from beginning, committing or
+    /// rolling back the transaction.
+    pub fn run<T, E, F>(mut self, f: F) -> Result<T, E>
+    where
+        F: FnOnce(&mut InstrumentedPgConnection) -> Result<T, E>,
+        E: From<diesel::result::Error>,
+    {
+        if !self.conn.config.trace_transactions {
+            return self.inner_builder().run(f);
+        }
+        let (sampled, outer) = self.conn.sampler.begin_transaction();
+        let result = if sampled {
+            self.traced_run(f)
+        } else {
+            self.inner_builder().run(f)
+        };
+        self.conn.sampler.end_transaction(outer);
+        result
+    }
+
+    instrument_method! {
+        connection = (self.conn),
+        instrument(
+            name = "pg_transaction",
+            fields(
+                otel.name=span_name(self.conn.span_namer.as_ref(), "pg_transaction"),
+                db.transaction.depth=field::Empty,
+                db.transaction.rolled_back=field::Empty,
+                db.transaction.duration_ms=field::Empty,
+                db.transaction.queries=field::Empty,
+                otel.status_code=field::Empty,
+                db.transaction.isolation_level=self
+                    .isolation_level
+                    .map(IsolationLevel::as_str)
+                    .or(self.conn.info.default_isolation_level.as_deref()),
+                db.transaction.read_only=self.read_only.or(self.conn.info.default_read_only),
+                db.transaction.deferrable=self.deferrable,
+                db.transaction.retries=self.retries,
+            ),
+            target = "diesel_tracing::pg::transaction",
+            skip(self, f),
+        );
+        fn traced_run<T, E, F>(&mut self, f: F) -> Result<T, E>
+        where
+            F: FnOnce(&mut InstrumentedPgConnection) -> Result<T, E>,
+            E: From<diesel::result::Error>,
+        {
+            record_transaction_depth(AnsiTransactionManager::transaction_manager_status_mut(
+                &mut *self.conn,
+            ));
+            let stack = self.conn.instrumentation_stack.clone();
+            let thresholds = self.conn.transaction_warn;
+            let result = self
+                .inner_builder()
+                .run(|conn| watch_transaction(stack.as_ref(), thresholds, || f(conn)));
+            record_transaction_outcome(&result);
+
+            result
+        }
+    }
+
+    fn inner_builder(&mut self) -> TransactionBuilder<'_, InstrumentedPgConnection> {
+        let mut builder = TransactionBuilder::new(&mut *self.conn);
+        builder = match self.isolation_level {
+            Some(IsolationLevel::ReadCommitted) => builder.read_committed(),
+            Some(IsolationLevel::RepeatableRead) => builder.repeatable_read(),
+            Some(IsolationLevel::Serializable) => builder.serializable(),
+            None => builder,
+        };
+        builder = match self.read_only {
+            Some(true) => builder.read_only(),
+            Some(false) => builder.read_write(),
+            None => builder,
+        };
+        match self.deferrable {
+            Some(true) => builder.deferrable(),
+            Some(false) => builder.not_deferrable(),
+            None => builder,
+        }
+    }
+}
+
+impl<'b, Changes, Output> UpdateAndFetchResults<Changes, Output> for InstrumentedPgConnection
+where
+    Changes: Copy + AsChangeset<Target = <Changes as HasTable>::Table> + IntoUpdateTarget,
+    Update<Changes, Changes>: LoadQuery<'b, Self, Output>,
+    <Changes::Table as Table>::AllColumns: ValidGrouping<()>,
+    <<Changes::Table as Table>::AllColumns as ValidGrouping<()>>::IsAggregate:
+        MixedAggregates<is_aggregate::No, Output = is_aggregate::No>,
+    Update<Changes, Changes>: QueryFragment<Pg>,
+{
+    instrument_method! {
+        connection = self,
+        #[cfg(feature = "statement-fields")]
+        instrument(
+            fields(
+                otel.name=span_name(self.span_namer.as_ref(), "update_and_fetch"),
+                db.statement=truncate_statement(
+                    diesel::debug_query::<Pg, _>(&diesel::update(changeset).set(changeset))
+                        .to_string(),
+                    self.config.max_statement_length(),
+                ),
+            ),
+            target = "diesel_tracing::pg::query",
+            skip(self, changeset),
+        ),
+        #[cfg(not(feature = "statement-fields"))]
+        instrument(
+            fields(
+                otel.name=span_name(self.span_namer.as_ref(), "update_and_fetch"),
+            ),
+            target = "diesel_tracing::pg::query",
+            skip(self, changeset),
+        );
+        fn update_and_fetch(&mut self, changeset: Changes) -> QueryResult<Output> {
+            debug!(target: "diesel_tracing::pg::query", "updating and fetching changeset");
+            let levels = self.config.error_levels;
+            diesel::update(changeset)
+                .set(changeset)
+                .get_result(self)
+                .inspect_err(|error| {
+                    error_event!("diesel_tracing::pg::query", levels, error);
+                })
+        }
+    }
+}
+
+#[cfg(all(test, not(feature = "passthrough")))]
+mod tests {
+    use super::*;
+    use crate::test_support::EventRecorder;
+
+    #[test]
+    fn test_get_info_on_establish() {
+        InstrumentedPgConnection::establish(
+            &std::env::var("POSTGRESQL_URL").expect("no POSTGRESQL_URL env var specified"),
+        )
+        .expect("failed to establish connection or collect info");
+    }
+
+    #[test]
+    fn test_backend_pid_recorded() {
+        let recorder = EventRecorder::default();
+        let pid = tracing::subscriber::with_default(recorder.clone(), || {
+            let mut conn = InstrumentedPgConnection::establish(
+                &std::env::var("POSTGRESQL_URL").expect("no POSTGRESQL_URL env var specified"),
+            )
+            .expect("failed to establish connection");
+            conn.batch_execute("SELECT 1").unwrap();
+            conn.connection_info().backend_pid().unwrap()
+        });
+
+        assert!(pid > 0);
+        for name in ["establish", "batch_execute"] {
+            let span = recorder.span(name).unwrap();
+            assert_eq!(span.fields["db.postgresql.pid"], pid.to_string());
+        }
+    }
+
+    #[test]
+    fn test_disabled_tracing_config_creates_no_spans() {
+        let mut conn = InstrumentedPgConnection::establish(
+            &std::env::var("POSTGRESQL_URL").expect("no POSTGRESQL_URL env var specified"),
+        )
+        .expect("failed to establish connection");
+        conn.set_tracing_config(TracingConfig {
+            trace_load: false,
+            trace_execute: false,
+            trace_batch: false,
+            trace_transactions: false,
+            ..TracingConfig::default()
+        });
+
+        let recorder = EventRecorder::default();
+        tracing::subscriber::with_default(recorder.clone(), || {
+            conn.transaction(|conn| {
+                sql_query("SELECT 1").execute(conn)?;
+                select(sql::<diesel::sql_types::Integer>("1")).get_result::<i32>(conn)
+            })
+            .unwrap();
+        });
+
+        let names: Vec<_> = recorder
+            .all_spans()
+            .into_iter()
+            .map(|span| span.name)
+            .collect();
+        assert!(names.is_empty(), "spans recorded: {names:?}");
+    }
+
+    #[test]
+    fn test_ensure_migrations_table() {
+        let mut conn = InstrumentedPgConnection::establish(
+            &std::env::var("POSTGRESQL_URL").expect("no POSTGRESQL_URL env var specified"),
+        )
+        .expect("failed to establish connection");
+        conn.begin_test_transaction().unwrap();
+        conn.ensure_migrations_table().unwrap();
+        assert!(!conn.ensure_migrations_table().unwrap());
+    }
+
+    #[test]
+    fn test_establish_with_config_never_records_password() {
+        // the user, host, port and database of the test URL, which libpq
+        // connects to whatever password is given when it trusts the client
+        let url = std::env::var("POSTGRESQL_URL").expect("no POSTGRESQL_URL env var specified");
+        let rest = url.split_once("://").map_or(url.as_str(), |(_, rest)| rest);
+        let (authority, dbname) = rest.split_once('/').unwrap_or((rest, ""));
+        let (user, host) = authority.rsplit_once('@').unwrap_or(("", authority));
+        let user = user.split(':').next().unwrap_or_default();
+        let mut config = PgConnectConfig::new().with_password("hunter2 it's secret");
+        if let Some((host, port)) = host.rsplit_once(':') {
+            config = config.with_host(host).with_port(port.parse().unwrap());
+        } else if !host.is_empty() {
+            config = config.with_host(host);
+        }
+        if !user.is_empty() {
+            config = config.with_user(user);
+        }
+        if !dbname.is_empty() {
+            config = config.with_dbname(dbname.split('?').next().unwrap_or_default());
+        }
+
+        let recorder = EventRecorder::default();
+        tracing::subscriber::with_default(recorder.clone(), || {
+            let mut conn = InstrumentedPgConnection::establish_with_config(&config)
+                .expect("failed to establish connection");
+            conn.batch_execute("SELECT 1").unwrap();
+        });
+
+        let establish = recorder.spans("establish_with_config");
+        assert_eq!(establish.len(), 1);
+        assert!(recorder.span("establish").is_none());
+        assert!(establish[0].fields.contains_key("db.postgresql.pid"));
+        let spans = recorder.spans_matching(|_| true);
+        let events = recorder.events();
+        let values = spans
+            .iter()
+            .flat_map(|span| span.fields.values())
+            .chain(events.iter().flat_map(|event| event.values()));
+        for value in values {
+            assert!(!value.contains("hunter2"), "{value}");
+        }
+    }
+
+    #[test]
+    fn test_establish_without_info() {
+        let conn = InstrumentedPgConnection::establish_without_info(
+            &std::env::var("POSTGRESQL_URL").expect("no POSTGRESQL_URL env var specified"),
+        )
+        .expect("failed to establish connection");
+        assert_eq!(conn.info, PgConnectionInfo::default());
+    }
+
+    #[test]
+    fn test_refresh_info() {
+        let mut conn = InstrumentedPgConnection::establish(
+            &std::env::var("POSTGRESQL_URL").expect("no POSTGRESQL_URL env var specified"),
+        )
+        .expect("failed to establish connection");
+        let info = conn.info.clone();
+
+        // stale information, as if the connection had been re-routed
+        conn.info = PgConnectionInfo {
+            current_database: Some("stale".to_string()),
+            ..conn.info.clone()
+        };
+        conn.refresh_info().unwrap();
+        assert_eq!(conn.info, info);
+    }
+
+    #[test]
+    fn test_debug_omits_url() {
+        let url = std::env::var("POSTGRESQL_URL").expect("no POSTGRESQL_URL env var specified");
+        let mut conn =
+            InstrumentedPgConnection::establish(&url).expect("failed to establish connection");
+        assert!(conn.is_healthy());
+
+        let info = conn.connection_info();
+        let database = info.database().unwrap();
+        let version = info.version().unwrap();
+        let debug = format!("{conn:?}");
+        assert!(debug.contains(database));
+        assert!(debug.contains(version));
+        assert!(!debug.contains(&url));
+    }
+
+    #[test]
+    fn test_application_name_recorded() {
+        let recorder = EventRecorder::default();
+        tracing::subscriber::with_default(recorder.clone(), || {
+            let mut conn = InstrumentedPgConnection::establish(
+                &std::env::var("POSTGRESQL_URL").expect("no POSTGRESQL_URL env var specified"),
+            )
+            .expect("failed to establish connection");
+            let establish = recorder.span("establish").unwrap();
+            assert!(establish.fields.contains_key("db.postgresql.schema"));
+
+            conn.set_application_name("diesel-tracing-test").unwrap();
+            assert_eq!(conn.application_name(), Some("diesel-tracing-test"));
+            conn.batch_execute("SELECT 1").unwrap();
+            let span = recorder.span("batch_execute").unwrap();
+            assert_eq!(
+                span.fields["db.postgresql.application_name"],
+                "diesel-tracing-test"
+            );
+        });
+    }
+
+    #[test]
+    fn test_tls_recorded() {
+        let recorder = EventRecorder::default();
+        tracing::subscriber::with_default(recorder.clone(), || {
+            let mut conn = InstrumentedPgConnection::establish(
+                &std::env::var("POSTGRESQL_URL").expect("no POSTGRESQL_URL env var specified"),
+            )
+            .expect("failed to establish connection");
+            conn.batch_execute("SELECT 1").unwrap();
+        });
+
+        let establish = recorder.span("establish").unwrap();
+        let tls = &establish.fields["db.connection.tls"];
+        assert!(tls == "true" || tls == "false");
+        let span = recorder.span("batch_execute").unwrap();
+        assert_eq!(&span.fields["db.connection.tls"], tls);
+    }
+
+    #[test]
+    fn test_refresh_schema() {
+        let recorder = EventRecorder::default();
+        tracing::subscriber::with_default(recorder.clone(), || {
+            let mut conn = InstrumentedPgConnection::establish(
+                &std::env::var("POSTGRESQL_URL").expect("no POSTGRESQL_URL env var specified"),
+            )
+            .expect("failed to establish connection");
+            conn.batch_execute(
+                "CREATE SCHEMA IF NOT EXISTS tenant_acme; \
+                 SET search_path TO tenant_acme, public",
+            )
+            .unwrap();
+
+            conn.refresh_schema().unwrap();
+            assert_eq!(conn.current_schema(), Some("tenant_acme"));
+            conn.batch_execute("SELECT 1").unwrap();
+            let span = recorder.spans("batch_execute").pop().unwrap();
+            assert_eq!(span.fields["db.schema"], "tenant_acme");
+            assert_eq!(span.fields["db.postgresql.schema"], "tenant_acme,public");
+        });
+    }
+
+    #[test]
+    fn test_listen_notify_recorded() {
+        let recorder = EventRecorder::default();
+        tracing::subscriber::with_default(recorder.clone(), || {
+            let mut conn = InstrumentedPgConnection::establish(
+                &std::env::var("POSTGRESQL_URL").expect("no POSTGRESQL_URL env var specified"),
+            )
+            .expect("failed to establish connection");
+
+            conn.listen("cache \"invalidation\"").unwrap();
+            conn.notify("cache \"invalidation\"", "accounts:1").unwrap();
+            conn.unlisten("cache \"invalidation\"").unwrap();
+        });
+
+        let operations = recorder
+            .events()
+            .into_iter()
+            .filter(|event| event.contains_key("db.postgresql.channel"))
+            .map(|event| {
+                assert_eq!(event["db.postgresql.channel"], "cache \"invalidation\"");
+                assert_eq!(
+                    event.contains_key("db.postgresql.payload"),
+                    cfg!(feature = "statement-fields") && event["db.operation"] == "NOTIFY"
+                );
+                event["db.operation"].clone()
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(operations, ["LISTEN", "NOTIFY", "UNLISTEN"]);
+        assert_eq!(recorder.spans("execute_returning_count").len(), 3);
+    }
+
+    #[test]
+    fn test_rows_affected_recorded() {
+        let recorder = EventRecorder::default();
+        tracing::subscriber::with_default(recorder.clone(), || {
+            let mut conn = InstrumentedPgConnection::establish(
+                &std::env::var("POSTGRESQL_URL").expect("no POSTGRESQL_URL env var specified"),
+            )
+            .expect("failed to establish connection");
+            conn.batch_execute(
+                "CREATE TEMPORARY TABLE rows_affected (id INTEGER);
+                INSERT INTO rows_affected VALUES (1), (2), (3);",
+            )
+            .unwrap();
+
+            let deleted = sql_query("DELETE FROM rows_affected WHERE id > 1")
+                .execute(&mut conn)
+                .unwrap();
+            let span = recorder.span("execute_returning_count").unwrap();
+            assert_eq!(deleted, 2);
+            assert_eq!(span.fields["db.rows_affected"], "2");
+        });
+    }
+
+    #[test]
+    fn test_batch_error_index_recorded() {
+        let recorder = EventRecorder::default();
+        tracing::subscriber::with_default(recorder.clone(), || {
+            let mut conn = InstrumentedPgConnection::establish(
+                &std::env::var("POSTGRESQL_URL").expect("no POSTGRESQL_URL env var specified"),
+            )
+            .expect("failed to establish connection");
+
+            conn.batch_execute("SELECT 1; SELECT ';'; SELECT missing_column")
+                .unwrap_err();
+            let span = recorder.span("batch_execute").unwrap();
+            assert_eq!(span.fields["db.batch.size"], "3");
+            assert_eq!(span.fields["db.batch.error_index"], "2");
+        });
+    }
+
+    #[cfg(feature = "reconnect")]
+    #[test]
+    fn test_auto_reconnect() {
+        #[derive(QueryableByName)]
+        struct Pid {
+            #[diesel(sql_type = diesel::sql_types::Integer)]
+            pid: i32,
+        }
+        fn backend_pid(conn: &mut InstrumentedPgConnection) -> i32 {
+            sql_query("SELECT pg_backend_pid() AS pid")
+                .get_result::<Pid>(conn)
+                .unwrap()
+                .pid
+        }
+
+        let recorder = EventRecorder::default();
+        tracing::subscriber::with_default(recorder.clone(), || {
+            let url = std::env::var("POSTGRESQL_URL").expect("no POSTGRESQL_URL env var specified");
+            let mut conn = InstrumentedPgConnection::establish(&url)
+                .expect("failed to establish connection")
+                .with_auto_reconnect(url, 3);
+            let pid = backend_pid(&mut conn);
+
+            let error = conn
+                .batch_execute("SELECT pg_terminate_backend(pg_backend_pid())")
+                .unwrap_err();
+            assert!(matches!(
+                error,
+                diesel::result::Error::DatabaseError(DatabaseErrorKind::ClosedConnection, _)
+            ));
+            let reconnected_pid = backend_pid(&mut conn);
+            assert_ne!(reconnected_pid, pid);
+
+            let connection_pids = recorder
+                .spans("db.connection")
+                .into_iter()
+                .map(|span| span.fields["db.postgresql.pid"].clone())
+                .collect::<Vec<_>>();
+            assert_eq!(
+                connection_pids,
+                [pid.to_string(), reconnected_pid.to_string()]
+            );
+
+            // a load reconnects when the next statement is run
+            let error = sql_query("SELECT pg_terminate_backend(pg_backend_pid())")
+                .load::<Pid>(&mut conn)
+                .map(|_| ())
+                .unwrap_err();
+            assert!(matches!(
+                error,
+                diesel::result::Error::DatabaseError(DatabaseErrorKind::ClosedConnection, _)
+            ));
+            assert_ne!(backend_pid(&mut conn), reconnected_pid);
+        });
+
+        let reconnecting = recorder
+            .events()
+            .into_iter()
+            .filter(|event| event.contains_key("db.reconnect_attempt"))
+            .map(|event| {
+                (
+                    event["level"].clone(),
+                    event["db.reconnect_attempt"].clone(),
+                )
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(
+            reconnecting,
+            [
+                ("WARN".to_string(), "1".to_string()),
+                ("DEBUG".to_string(), "1".to_string()),
+                ("WARN".to_string(), "1".to_string()),
+                ("DEBUG".to_string(), "1".to_string()),
+            ]
+        );
+    }
+
+    #[cfg(feature = "reconnect")]
+    #[test]
+    fn test_auto_reconnect_restores_session() {
+        #[derive(QueryableByName)]
+        struct Setting {
+            #[diesel(sql_type = Text)]
+            setting: String,
+        }
+        fn setting(conn: &mut InstrumentedPgConnection, name: &str) -> String {
+            sql_query(format!("SELECT current_setting('{name}') AS setting"))
+                .get_result::<Setting>(conn)
+                .unwrap()
+                .setting
+        }
+
+        let url = std::env::var("POSTGRESQL_URL").expect("no POSTGRESQL_URL env var specified");
+        let mut conn =
+            InstrumentedPgConnection::establish_with_init(&url, &["SET statement_timeout = 4321"])
+                .expect("failed to establish connection")
+                .with_auto_reconnect(url, 3);
+        conn.set_read_only(true).unwrap();
+
+        conn.batch_execute("SELECT pg_terminate_backend(pg_backend_pid())")
+            .unwrap_err();
+        assert_eq!(setting(&mut conn, "statement_timeout"), "4321ms");
+        assert_eq!(setting(&mut conn, "default_transaction_read_only"), "on");
+        assert!(conn.is_read_only());
+    }
+
+    #[test]
+    fn test_preload_type_metadata() {
+        let recorder = EventRecorder::default();
+        tracing::subscriber::with_default(recorder.clone(), || {
+            let mut conn = InstrumentedPgConnection::establish(
+                &std::env::var("POSTGRESQL_URL").expect("no POSTGRESQL_URL env var specified"),
+            )
+            .expect("failed to establish connection");
+            conn.batch_execute(
+                "DROP TYPE IF EXISTS preload_mood; \
+                 CREATE TYPE preload_mood AS ENUM ('happy', 'sad')",
+            )
+            .unwrap();
+
+            conn.preload_type_metadata(&["preload_mood", "no_such_type"])
+                .unwrap();
+            let key = PgMetadataCacheKey::new(None, "preload_mood".into());
+            assert!(conn.get_metadata_cache().lookup_type(&key).is_some());
+            let key = PgMetadataCacheKey::new(None, "no_such_type".into());
+            assert!(conn.get_metadata_cache().lookup_type(&key).is_none());
+
+            let span = recorder.span("preload_type_metadata").unwrap();
+            assert_eq!(span.fields["db.operation"], "type_lookup");
+            assert_eq!(span.fields["db.rows_affected"], "1");
+        });
+    }
+
+    #[test]
+    fn test_metadata_cache_stats() {
+        use diesel::pg::PgMetadataLookup;
+
+        let recorder = EventRecorder::default();
+        tracing::subscriber::with_default(recorder.clone(), || {
+            let mut conn = InstrumentedPgConnection::establish(
+                &std::env::var("POSTGRESQL_URL").expect("no POSTGRESQL_URL env var specified"),
+            )
+            .expect("failed to establish connection");
+            conn.batch_execute(
+                "DROP TYPE IF EXISTS stats_mood; \
+                 CREATE TYPE stats_mood AS ENUM ('happy', 'sad')",
+            )
+            .unwrap();
+            assert_eq!(conn.metadata_cache_stats(), MetadataCacheStats::default());
+
+            conn.preload_type_metadata(&["stats_mood"]).unwrap();
+            assert!(conn.lookup_type("stats_mood", None).oid().is_ok());
+            assert!(conn.lookup_type("stats_mood", None).oid().is_ok());
+            assert_eq!(
+                conn.metadata_cache_stats(),
+                MetadataCacheStats {
+                    lookups: 2,
+                    entries: 1,
+                }
+            );
+        });
+
+        let first_accesses = recorder
+            .events()
+            .into_iter()
+            .filter(|event| {
+                event.get("message").is_some_and(|message| {
+                    message == "first access of postgresql type metadata cache"
+                })
+            })
+            .count();
+        assert_eq!(first_accesses, 1);
+    }
+
+    diesel::table! {
+        copy_rows (id) {
+            id -> Integer,
+        }
+    }
+
+    #[test]
+    fn test_copy_from_recorded() {
+        let recorder = EventRecorder::default();
+        tracing::subscriber::with_default(recorder.clone(), || {
+            let mut conn = InstrumentedPgConnection::establish(
+                &std::env::var("POSTGRESQL_URL").expect("no POSTGRESQL_URL env var specified"),
+            )
+            .expect("failed to establish connection");
+            conn.batch_execute("CREATE TEMPORARY TABLE copy_rows (id INTEGER PRIMARY KEY)")
+                .unwrap();
+
+            let copied = diesel::copy_from(copy_rows::table)
+                .from_raw_data(copy_rows::table, |copy| {
+                    writeln!(copy, "1\n2\n3").unwrap();
+                    QueryResult::Ok(())
+                })
+                .execute(&mut conn)
+                .unwrap();
+            assert_eq!(copied, 3);
+
+            let span = recorder.span("copy_from").unwrap();
+            assert_eq!(span.fields["db.operation"], "COPY");
+            assert_eq!(span.fields["db.copy_direction"], "FROM");
+            assert_eq!(span.fields["db.sql.table"], "copy_rows");
+            assert_eq!(span.fields["db.rows_affected"], "3");
+        });
+    }
+
+    #[derive(Debug, PartialEq, diesel::Queryable, diesel::Selectable)]
+    #[diesel(table_name = copy_rows)]
+    struct CopyRow {
+        id: i32,
+    }
+
+    #[test]
+    fn test_copy_to_recorded() {
+        let recorder = EventRecorder::default();
+        tracing::subscriber::with_default(recorder.clone(), || {
+            let mut conn = InstrumentedPgConnection::establish(
+                &std::env::var("POSTGRESQL_URL").expect("no POSTGRESQL_URL env var specified"),
+            )
+            .expect("failed to establish connection");
+            conn.batch_execute(
+                "CREATE TEMPORARY TABLE copy_rows (id INTEGER PRIMARY KEY);
+                INSERT INTO copy_rows VALUES (1), (2);",
+            )
+            .unwrap();
+
+            let rows = conn.copy_to::<CopyRow, _>(copy_rows::table).unwrap();
+            assert_eq!(rows, vec![CopyRow { id: 1 }, CopyRow { id: 2 }]);
+
+            let span = recorder.span("copy_to").unwrap();
+            assert_eq!(span.fields["db.operation"], "COPY");
+            assert_eq!(span.fields["db.copy_direction"], "TO");
+            assert_eq!(span.fields["db.sql.table"], "copy_rows");
+            assert_eq!(span.fields["db.rows_affected"], "2");
+        });
+    }
+
+    #[test]
+    fn test_transaction_builder_recorded() {
+        let recorder = EventRecorder::default();
+        tracing::subscriber::with_default(recorder.clone(), || {
+            let mut conn = InstrumentedPgConnection::establish(
+                &std::env::var("POSTGRESQL_URL").expect("no POSTGRESQL_URL env var specified"),
+            )
+            .expect("failed to establish connection");
+
+            conn.build_transaction()
+                .serializable()
+                .read_only()
+                .run(|conn| sql_query("SELECT 1").execute(conn))
+                .unwrap();
+
+            let span = recorder.span("pg_transaction").unwrap();
+            assert_eq!(
+                span.fields["db.transaction.isolation_level"],
+                "serializable"
+            );
+            assert_eq!(span.fields["db.transaction.read_only"], "true");
+            assert_eq!(span.fields["db.transaction.rolled_back"], "false");
+            assert!(!span.fields.contains_key("db.transaction.deferrable"));
+        });
+    }
+
+    #[test]
+    fn test_default_isolation_level_recorded() {
+        let recorder = EventRecorder::default();
+        tracing::subscriber::with_default(recorder.clone(), || {
+            let mut conn = InstrumentedPgConnection::establish(
+                &std::env::var("POSTGRESQL_URL").expect("no POSTGRESQL_URL env var specified"),
+            )
+            .expect("failed to establish connection");
+
+            conn.transaction(|conn| sql_query("SELECT 1").execute(conn))
+                .unwrap();
+            let span = recorder.span("transaction").unwrap();
+            assert_eq!(
+                span.fields["db.transaction.isolation_level"],
+                "read_committed"
+            );
+            assert_eq!(span.fields["db.transaction.read_only"], "false");
+
+            conn.set_default_isolation_level(IsolationLevel::RepeatableRead)
+                .unwrap();
+            conn.transaction(|conn| sql_query("SELECT 1").execute(conn))
+                .unwrap();
+            let span = recorder.span("transaction").unwrap();
+            assert_eq!(
+                span.fields["db.transaction.isolation_level"],
+                "repeatable_read"
+            );
+
+            conn.build_transaction()
+                .read_only()
+                .run(|conn| sql_query("SELECT 1").execute(conn))
+                .unwrap();
+            let span = recorder.span("pg_transaction").unwrap();
+            assert_eq!(
+                span.fields["db.transaction.isolation_level"],
+                "repeatable_read"
+            );
+            assert_eq!(span.fields["db.transaction.read_only"], "true");
+        });
+    }
+
+    #[cfg(feature = "r2d2")]
+    #[test]
+    fn test_r2d2_pool() {
+        use diesel::r2d2::{ConnectionManager, Pool};
+
+        let recorder = EventRecorder::default();
+        tracing::subscriber::with_default(recorder.clone(), || {
+            let pool = Pool::builder()
+                .max_size(1)
+                .test_on_check_out(true)
+                .build(ConnectionManager::<InstrumentedPgConnection>::new(
+                    std::env::var("POSTGRESQL_URL").expect("no POSTGRESQL_URL env var specified"),
+                ))
+                .unwrap();
+            let mut conn = pool.get().unwrap();
+            sql_query("SELECT 1").execute(&mut *conn).unwrap();
+        });
+
+        let ping = recorder.span("ping").unwrap();
+        assert_eq!(ping.fields["db.operation"], "ping");
+        assert!(ping.fields.contains_key("db.namespace"));
+        let span = recorder.span("execute_returning_count").unwrap();
+        assert_eq!(span.fields["db.system"], "postgresql");
+    }
+
+    #[test]
+    fn test_serialization_failure_classified() {
+        use diesel::result::Error;
+
+        let error = |kind, message: &str| Error::DatabaseError(kind, Box::new(message.to_string()));
+        let serialization = error(
+            DatabaseErrorKind::SerializationFailure,
+            "could not serialize access due to read/write dependencies among transactions",
+        );
+        assert_eq!(sqlstate(&serialization), Some("40001"));
+        assert!(is_serialization_failure(&serialization));
+        let deadlock = error(DatabaseErrorKind::Unknown, "deadlock detected");
+        assert_eq!(sqlstate(&deadlock), Some("40P01"));
+        assert!(is_serialization_failure(&deadlock));
+        let unique = error(DatabaseErrorKind::UniqueViolation, "duplicate key value");
+        assert_eq!(sqlstate(&unique), Some("23505"));
+        assert!(!is_serialization_failure(&unique));
+        assert_eq!(
+            sqlstate(&error(DatabaseErrorKind::Unknown, "syntax error")),
+            None
+        );
+        assert_eq!(sqlstate(&Error::NotFound), None);
+    }
+
+    #[test]
+    fn test_serializable_transaction_retried() {
+        let url = std::env::var("POSTGRESQL_URL").expect("no POSTGRESQL_URL env var specified");
+        let recorder = EventRecorder::default();
+        tracing::subscriber::with_default(recorder.clone(), || {
+            let mut conn = InstrumentedPgConnection::establish(&url).unwrap();
+            let mut other = InstrumentedPgConnection::establish(&url).unwrap();
+            conn.batch_execute(
+                "DROP TABLE IF EXISTS serializable_retries;
+                CREATE TABLE serializable_retries (value INTEGER);",
+            )
+            .unwrap();
+
+            let mut attempts = 0;
+            let result = conn.serializable_transaction_with_retries(2, |conn| {
+                attempts += 1;
+                sql_query("SELECT * FROM serializable_retries").execute(conn)?;
+                if attempts == 1 {
+                    // a concurrent transaction reading and writing the same
+                    // rows commits first, so this one cannot be serialized
+                    other.build_transaction().serializable().run(|other| {
+                        sql_query("SELECT * FROM serializable_retries").execute(other)?;
+                        sql_query("INSERT INTO serializable_retries VALUES (1)").execute(other)
+                    })?;
+                }
+                sql_query("INSERT INTO serializable_retries VALUES (2)").execute(conn)
+            });
+            conn.batch_execute("DROP TABLE serializable_retries")
+                .unwrap();
+
+            assert_eq!(result, Ok(1));
+            assert_eq!(attempts, 2);
+        });
+
+        let spans = recorder.spans("pg_transaction");
+        let retries: Vec<_> = spans
+            .iter()
+            .filter_map(|span| span.fields.get("db.transaction.retries"))
+            .map(String::as_str)
+            .collect();
+        assert_eq!(retries, ["0", "1"]);
+        let retry = recorder
+            .events()
+            .into_iter()
+            .find(|event| event["level"] == "WARN")
+            .unwrap();
+        assert_eq!(retry["db.transaction.retry_attempt"], "1");
+    }
+
+    #[cfg(feature = "statement-fields")]
+    #[test]
+    fn test_row_by_row_statement_recorded() {
+        #[derive(QueryableByName)]
+        struct Row {
+            #[diesel(sql_type = diesel::sql_types::Integer)]
+            n: i32,
+        }
+
+        let recorder = EventRecorder::default();
+        tracing::subscriber::with_default(recorder.clone(), || {
+            let mut conn = InstrumentedPgConnection::establish(
+                &std::env::var("POSTGRESQL_URL").expect("no POSTGRESQL_URL env var specified"),
+            )
+            .unwrap();
+            let rows: Vec<i32> = sql_query("SELECT generate_series(1, 3) AS n")
+                .load_iter::<Row, PgRowByRowLoadingMode>(&mut conn)
+                .unwrap()
+                .map(|row| row.map(|row| row.n))
+                .collect::<QueryResult<_>>()
+                .unwrap();
+            assert_eq!(rows, [1, 2, 3]);
+        });
+
+        crate::test_support::assert_query_span(&recorder, "generate_series(1, 3)");
+    }
+
+    #[cfg(feature = "statement-fields")]
+    #[test]
+    fn test_slow_query_plan_recorded() {
+        let recorder = EventRecorder::default();
+        tracing::subscriber::with_default(recorder.clone(), || {
+            let mut conn = InstrumentedPgConnection::establish(
+                &std::env::var("POSTGRESQL_URL").expect("no POSTGRESQL_URL env var specified"),
+            )
+            .unwrap();
+            conn.set_tracing_config(TracingConfig {
+                slow_query_threshold: Some(std::time::Duration::from_millis(1)),
+                ..TracingConfig::default()
+            });
+            conn.set_explain_on_slow(true);
+            sql_query("SELECT pg_sleep(0.01)")
+                .execute(&mut conn)
+                .unwrap();
+        });
+
+        let plan = recorder
+            .events()
+            .into_iter()
+            .find(|event| event.get("message").is_some_and(|m| m == "slow query plan"))
+            .unwrap();
+        assert!(plan["db.query_plan"].contains("\"Plan\""));
+    }
+
+    #[cfg(feature = "statement-fields")]
+    #[test]
+    fn test_execute_statement_recorded() {
+        let recorder = EventRecorder::default();
+        tracing::subscriber::with_default(recorder.clone(), || {
+            let mut conn = InstrumentedPgConnection::establish(
+                &std::env::var("POSTGRESQL_URL").expect("no POSTGRESQL_URL env var specified"),
+            )
+            .expect("failed to establish connection");
+            conn.batch_execute("CREATE TEMPORARY TABLE execute_users (id INTEGER)")
+                .unwrap();
+            sql_query("INSERT INTO execute_users VALUES ($1)")
+                .bind::<diesel::sql_types::Integer, _>(1)
+                .execute(&mut conn)
+                .unwrap();
+        });
+
+        let span = recorder.span("execute_returning_count").unwrap();
+        assert_eq!(
+            span.fields["db.statement"],
+            "INSERT INTO execute_users VALUES ($1) -- binds: [1]"
+        );
+    }
+
+    #[cfg(feature = "opentelemetry")]
+    #[test]
+    fn test_sql_comments_appended() {
+        use diesel::connection::InstrumentationEvent;
+        use opentelemetry::trace::TracerProvider;
+        use std::sync::{Arc, Mutex};
+        use tracing_subscriber::layer::SubscriberExt;
+
+        let provider = opentelemetry_sdk::trace::SdkTracerProvider::builder().build();
+        let subscriber = tracing_subscriber::registry()
+            .with(tracing_opentelemetry::layer().with_tracer(provider.tracer("test")));
+        let queries = Arc::new(Mutex::new(Vec::new()));
+        tracing::subscriber::with_default(subscriber, || {
+            let mut conn = InstrumentedPgConnection::establish(
+                &std::env::var("POSTGRESQL_URL").expect("no POSTGRESQL_URL env var specified"),
+            )
+            .unwrap();
+            conn.set_instrumentation({
+                let queries = queries.clone();
+                move |event: InstrumentationEvent<'_>| {
+                    if let InstrumentationEvent::StartQuery { query, .. } = event {
+                        queries.lock().unwrap().push(query.to_string());
+                    }
+                }
+            });
+            conn.batch_execute("SELECT 1").unwrap();
+            conn.enable_sql_comments(true);
+            conn.batch_execute("SELECT 2").unwrap();
+            sql_query("SELECT 3").execute(&mut conn).unwrap();
+        });
+
+        let queries = queries.lock().unwrap();
+        assert_eq!(queries[0], "SELECT 1");
+        assert!(queries[1].starts_with("SELECT 2 /*traceparent='00-"));
+        assert!(queries[1].ends_with("-01'*/"));
+        assert!(queries[2].starts_with("SELECT 3 /*traceparent='00-"));
+        assert!(queries[2].contains("-01'*/ -- binds: []"));
     }
 }
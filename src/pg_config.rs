@@ -0,0 +1,213 @@
+use std::fmt::Write;
+
+use crate::url::Secret;
+
+/// The settings of a postgresql connection, from which
+/// [`InstrumentedPgConnection::establish_with_config`](crate::pg::InstrumentedPgConnection::establish_with_config)
+/// builds a libpq connection string, escaping each value.
+///
+/// The password is held as a [`Secret`], so printing the configuration with
+/// `{:?}` never shows it.
+///
+/// ```
+/// use diesel_tracing::pg::PgConnectConfig;
+///
+/// let config = PgConnectConfig::new()
+///     .with_host("db.internal")
+///     .with_port(5432)
+///     .with_user("app")
+///     .with_password("it's a secret")
+///     .with_dbname("app");
+/// assert!(!format!("{config:?}").contains("secret"));
+/// ```
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct PgConnectConfig {
+    host: Option<String>,
+    port: Option<u16>,
+    user: Option<String>,
+    password: Option<Secret>,
+    dbname: Option<String>,
+    sslmode: Option<String>,
+    options: Option<String>,
+    application_name: Option<String>,
+}
+
+impl PgConnectConfig {
+    /// A configuration leaving every setting to libpq's defaults and
+    /// environment variables, such as `PGHOST`.
+    #[must_use]
+    pub fn new() -> Self {
+        PgConnectConfig::default()
+    }
+
+    /// The host name, address or unix socket directory of the server.
+    #[must_use]
+    pub fn with_host(mut self, host: impl Into<String>) -> Self {
+        self.host = Some(host.into());
+        self
+    }
+
+    #[must_use]
+    pub fn with_port(mut self, port: u16) -> Self {
+        self.port = Some(port);
+        self
+    }
+
+    #[must_use]
+    pub fn with_user(mut self, user: impl Into<String>) -> Self {
+        self.user = Some(user.into());
+        self
+    }
+
+    #[must_use]
+    pub fn with_password(mut self, password: impl Into<Secret>) -> Self {
+        self.password = Some(password.into());
+        self
+    }
+
+    #[must_use]
+    pub fn with_dbname(mut self, dbname: impl Into<String>) -> Self {
+        self.dbname = Some(dbname.into());
+        self
+    }
+
+    /// The libpq `sslmode`, such as `require` or `verify-full`.
+    #[must_use]
+    pub fn with_sslmode(mut self, sslmode: impl Into<String>) -> Self {
+        self.sslmode = Some(sslmode.into());
+        self
+    }
+
+    /// Command line options sent to the server, such as
+    /// `-c search_path=app`.
+    #[must_use]
+    pub fn with_options(mut self, options: impl Into<String>) -> Self {
+        self.options = Some(options.into());
+        self
+    }
+
+    #[must_use]
+    pub fn with_application_name(mut self, application_name: impl Into<String>) -> Self {
+        self.application_name = Some(application_name.into());
+        self
+    }
+
+    #[must_use]
+    pub fn host(&self) -> Option<&str> {
+        self.host.as_deref()
+    }
+
+    #[must_use]
+    pub fn port(&self) -> Option<u16> {
+        self.port
+    }
+
+    #[must_use]
+    pub fn user(&self) -> Option<&str> {
+        self.user.as_deref()
+    }
+
+    #[must_use]
+    pub fn dbname(&self) -> Option<&str> {
+        self.dbname.as_deref()
+    }
+
+    /// The libpq key value connection string of the configuration, which
+    /// contains the password.
+    pub(crate) fn connection_string(&self) -> String {
+        let port = self.port.map(|port| port.to_string());
+        let settings = [
+            ("host", self.host.as_deref()),
+            ("port", port.as_deref()),
+            ("user", self.user.as_deref()),
+            ("password", self.password.as_ref().map(Secret::expose)),
+            ("dbname", self.dbname.as_deref()),
+            ("sslmode", self.sslmode.as_deref()),
+            ("options", self.options.as_deref()),
+            ("application_name", self.application_name.as_deref()),
+        ];
+
+        let mut connection_string = String::new();
+        for (key, value) in settings {
+            let Some(value) = value else {
+                continue;
+            };
+            if !connection_string.is_empty() {
+                connection_string.push(' ');
+            }
+            let _ = write!(connection_string, "{key}='{}'", escape_value(value));
+        }
+        connection_string
+    }
+}
+
+/// Escape a value to be quoted in a libpq connection string, in which
+/// backslashes and single quotes are escaped with a backslash.
+fn escape_value(value: &str) -> String {
+    let mut escaped = String::with_capacity(value.len());
+    for c in value.chars() {
+        if matches!(c, '\\' | '\'') {
+            escaped.push('\\');
+        }
+        escaped.push(c);
+    }
+    escaped
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_password_escaped() {
+        let cases = [
+            ("hunter2", "hunter2"),
+            ("with space", "with space"),
+            ("it's", r"it\'s"),
+            (r"back\slash", r"back\\slash"),
+            (r"\'", r"\\\'"),
+            ("pässwörd🔑", "pässwörd🔑"),
+            ("", ""),
+        ];
+
+        for (password, escaped) in cases {
+            let config = PgConnectConfig::new()
+                .with_user("app")
+                .with_password(password);
+            assert_eq!(
+                config.connection_string(),
+                format!("user='app' password='{escaped}'"),
+                "escaping {password}"
+            );
+        }
+    }
+
+    #[test]
+    fn test_connection_string() {
+        let config = PgConnectConfig::new()
+            .with_host("db.internal")
+            .with_port(5433)
+            .with_dbname("app")
+            .with_sslmode("require")
+            .with_options("-c search_path=app")
+            .with_application_name("checkout");
+        assert_eq!(
+            config.connection_string(),
+            "host='db.internal' port='5433' dbname='app' sslmode='require' \
+             options='-c search_path=app' application_name='checkout'"
+        );
+        assert_eq!(PgConnectConfig::new().connection_string(), "");
+    }
+
+    #[test]
+    fn test_debug_redacts_password() {
+        for password in ["hunter2", "it's", r"back\slash", "pässwörd"] {
+            let config = PgConnectConfig::new()
+                .with_host("localhost")
+                .with_password(password);
+            let debug = format!("{config:?}");
+            assert!(!debug.contains(password), "{debug}");
+            assert!(debug.contains("[REDACTED]"), "{debug}");
+        }
+    }
+}
@@ -0,0 +1,17 @@
+//! Tracing for connection pools.
+
+#[cfg(feature = "mobc")]
+mod mobc;
+#[cfg(feature = "r2d2")]
+mod r2d2;
+
+#[cfg(all(feature = "mobc", feature = "async-mysql"))]
+pub use self::mobc::MobcMysqlManager;
+#[cfg(all(feature = "mobc", feature = "async-postgres"))]
+pub use self::mobc::MobcPgManager;
+#[cfg(all(feature = "mobc", feature = "sqlite"))]
+pub use self::mobc::MobcSqliteManager;
+#[cfg(feature = "mobc")]
+pub use self::mobc::{MobcError, MobcManager};
+#[cfg(feature = "r2d2")]
+pub use self::r2d2::{InstrumentedConnectionManager, R2d2TracingEventHandler};
@@ -0,0 +1,264 @@
+use std::error::Error;
+use std::fmt::{self, Display};
+use std::marker::PhantomData;
+
+use diesel::result::ConnectionError;
+#[cfg(any(
+    feature = "async-mysql",
+    feature = "async-postgres",
+    feature = "sqlite"
+))]
+use mobc::Manager;
+
+#[cfg(feature = "async-mysql")]
+use crate::async_mysql::InstrumentedAsyncMysqlConnection;
+#[cfg(feature = "async-postgres")]
+use crate::async_pg::InstrumentedAsyncPgConnection;
+#[cfg(feature = "sqlite")]
+use crate::sqlite::InstrumentedSqliteConnection;
+
+/// A mobc [`Manager`](mobc::Manager) opening instrumented connections to a database.
+///
+/// Opening a connection is traced in a `connect` span recording
+/// `db.pool.connect=true`, and checking a connection is still usable when it
+/// is checked out runs `SELECT 1` in a `check` span recording
+/// `db.operation="ping"`.
+///
+/// ```no_run
+/// # #[cfg(feature = "async-postgres")]
+/// # async fn example() -> Result<(), Box<dyn std::error::Error>> {
+/// use diesel_async::RunQueryDsl;
+/// use diesel_tracing::MobcPgManager;
+/// use mobc::Pool;
+///
+/// let pool = Pool::builder()
+///     .max_open(16)
+///     .build(MobcPgManager::new("postgres://localhost/app"));
+/// let mut conn = pool.get().await?;
+/// diesel::sql_query("SELECT 1").execute(&mut *conn).await?;
+/// # Ok(())
+/// # }
+/// ```
+pub struct MobcManager<C> {
+    #[cfg_attr(
+        not(any(
+            feature = "async-mysql",
+            feature = "async-postgres",
+            feature = "sqlite"
+        )),
+        allow(dead_code)
+    )]
+    database_url: String,
+    connection: PhantomData<fn() -> C>,
+}
+
+/// A mobc manager for [`InstrumentedAsyncPgConnection`].
+#[cfg(feature = "async-postgres")]
+pub type MobcPgManager = MobcManager<InstrumentedAsyncPgConnection>;
+
+/// A mobc manager for [`InstrumentedAsyncMysqlConnection`].
+#[cfg(feature = "async-mysql")]
+pub type MobcMysqlManager = MobcManager<InstrumentedAsyncMysqlConnection>;
+
+/// A mobc manager for [`InstrumentedSqliteConnection`].
+///
+/// There is no asynchronous sqlite connection, so connections are opened and
+/// checked on the task checking them out of the pool. Opening a sqlite
+/// database does no network IO, so this is rarely noticeable.
+#[cfg(feature = "sqlite")]
+pub type MobcSqliteManager = MobcManager<InstrumentedSqliteConnection>;
+
+impl<C> MobcManager<C> {
+    /// Create a manager which opens connections to `database_url`.
+    pub fn new<S: Into<String>>(database_url: S) -> Self {
+        MobcManager {
+            database_url: database_url.into(),
+            connection: PhantomData,
+        }
+    }
+}
+
+impl<C> fmt::Debug for MobcManager<C> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("MobcManager").finish_non_exhaustive()
+    }
+}
+
+/// The error of a [`MobcManager`], the error opening a connection or, if a
+/// connection failed its check, a [`ConnectionError::BadConnection`].
+#[derive(Debug)]
+pub struct MobcError(pub ConnectionError);
+
+impl Display for MobcError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.0.fmt(f)
+    }
+}
+
+impl Error for MobcError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        Some(&self.0)
+    }
+}
+
+impl From<ConnectionError> for MobcError {
+    fn from(error: ConnectionError) -> Self {
+        MobcError(error)
+    }
+}
+
+impl From<diesel::result::Error> for MobcError {
+    fn from(error: diesel::result::Error) -> Self {
+        MobcError(ConnectionError::BadConnection(error.to_string()))
+    }
+}
+
+#[cfg(feature = "async-postgres")]
+#[mobc::async_trait]
+impl Manager for MobcManager<InstrumentedAsyncPgConnection> {
+    type Connection = InstrumentedAsyncPgConnection;
+    type Error = MobcError;
+
+    #[cfg_attr(
+        not(feature = "passthrough"),
+        tracing::instrument(
+            fields(db.system="postgresql", otel.kind="client", db.pool.connect=true),
+            skip(self),
+            err,
+        )
+    )]
+    async fn connect(&self) -> Result<Self::Connection, MobcError> {
+        use diesel_async::AsyncConnection;
+
+        Ok(InstrumentedAsyncPgConnection::establish(&self.database_url).await?)
+    }
+
+    #[cfg_attr(
+        not(feature = "passthrough"),
+        tracing::instrument(
+            fields(db.system="postgresql", otel.kind="client", db.operation="ping"),
+            skip(self, conn),
+            err,
+        )
+    )]
+    async fn check(&self, mut conn: Self::Connection) -> Result<Self::Connection, MobcError> {
+        use diesel_async::SimpleAsyncConnection;
+
+        conn.batch_execute("SELECT 1").await?;
+        Ok(conn)
+    }
+}
+
+#[cfg(feature = "async-mysql")]
+#[mobc::async_trait]
+impl Manager for MobcManager<InstrumentedAsyncMysqlConnection> {
+    type Connection = InstrumentedAsyncMysqlConnection;
+    type Error = MobcError;
+
+    #[cfg_attr(
+        not(feature = "passthrough"),
+        tracing::instrument(
+            fields(db.system="mysql", otel.kind="client", db.pool.connect=true),
+            skip(self),
+            err,
+        )
+    )]
+    async fn connect(&self) -> Result<Self::Connection, MobcError> {
+        use diesel_async::AsyncConnection;
+
+        Ok(InstrumentedAsyncMysqlConnection::establish(&self.database_url).await?)
+    }
+
+    #[cfg_attr(
+        not(feature = "passthrough"),
+        tracing::instrument(
+            fields(db.system="mysql", otel.kind="client", db.operation="ping"),
+            skip(self, conn),
+            err,
+        )
+    )]
+    async fn check(&self, mut conn: Self::Connection) -> Result<Self::Connection, MobcError> {
+        use diesel_async::SimpleAsyncConnection;
+
+        conn.batch_execute("SELECT 1").await?;
+        Ok(conn)
+    }
+}
+
+#[cfg(feature = "sqlite")]
+#[mobc::async_trait]
+impl Manager for MobcManager<InstrumentedSqliteConnection> {
+    type Connection = InstrumentedSqliteConnection;
+    type Error = MobcError;
+
+    #[cfg_attr(
+        not(feature = "passthrough"),
+        tracing::instrument(
+            fields(db.system="sqlite", otel.kind="client", db.pool.connect=true),
+            skip(self),
+            err,
+        )
+    )]
+    async fn connect(&self) -> Result<Self::Connection, MobcError> {
+        use diesel::Connection;
+
+        Ok(InstrumentedSqliteConnection::establish(&self.database_url)?)
+    }
+
+    #[cfg_attr(
+        not(feature = "passthrough"),
+        tracing::instrument(
+            fields(db.system="sqlite", otel.kind="client", db.operation="ping"),
+            skip(self, conn),
+            err,
+        )
+    )]
+    async fn check(&self, mut conn: Self::Connection) -> Result<Self::Connection, MobcError> {
+        use diesel::connection::SimpleConnection;
+
+        conn.batch_execute("SELECT 1")?;
+        Ok(conn)
+    }
+}
+
+#[cfg(all(test, feature = "sqlite"))]
+mod tests {
+    use super::*;
+    use diesel::connection::SimpleConnection;
+    use mobc::Pool;
+
+    #[cfg(not(feature = "passthrough"))]
+    #[tokio::test]
+    async fn test_mobc_sqlite_manager() {
+        let recorder = crate::test_support::EventRecorder::default();
+        let _guard = tracing::subscriber::set_default(recorder.clone());
+
+        let manager = MobcSqliteManager::new(":memory:");
+        let conn = manager.connect().await.unwrap();
+        manager.check(conn).await.unwrap();
+
+        let connect = recorder.span("connect").unwrap();
+        assert_eq!(connect.fields["db.system"], "sqlite");
+        assert_eq!(connect.fields["db.pool.connect"], "true");
+        let check = recorder.span("check").unwrap();
+        assert_eq!(check.fields["db.operation"], "ping");
+    }
+
+    #[tokio::test]
+    async fn test_mobc_pool() {
+        let pool = Pool::builder()
+            .max_open(1)
+            .build(MobcSqliteManager::new(":memory:"));
+        let mut conn = pool.get().await.unwrap();
+        conn.batch_execute("CREATE TABLE users (id INTEGER)")
+            .unwrap();
+
+        let result = MobcSqliteManager::new("/nonexistent/db.sqlite")
+            .connect()
+            .await;
+        assert!(matches!(
+            result,
+            Err(MobcError(ConnectionError::BadConnection(_)))
+        ));
+    }
+}
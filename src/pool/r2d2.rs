@@ -0,0 +1,382 @@
+use std::any::TypeId;
+use std::fmt::Display;
+use std::sync::atomic::{AtomicU32, Ordering};
+use std::time::Instant;
+
+use diesel::r2d2::event::{AcquireEvent, CheckinEvent, CheckoutEvent, ReleaseEvent, TimeoutEvent};
+use diesel::r2d2::{
+    ConnectionManager, Error, HandleError, HandleEvent, ManageConnection, R2D2Connection,
+};
+use tracing::{debug, error};
+
+use crate::init::run_init_statements;
+
+/// The `db.system` of a diesel backend, if it is one this crate knows.
+#[cfg_attr(feature = "passthrough", allow(dead_code))]
+fn db_system<B: 'static>() -> Option<&'static str> {
+    let backend = TypeId::of::<B>();
+    #[cfg(feature = "postgres")]
+    if backend == TypeId::of::<diesel::pg::Pg>() {
+        return Some("postgresql");
+    }
+    #[cfg(feature = "mysql")]
+    if backend == TypeId::of::<diesel::mysql::Mysql>() {
+        return Some("mysql");
+    }
+    #[cfg(feature = "sqlite")]
+    if backend == TypeId::of::<diesel::sqlite::Sqlite>() {
+        return Some("sqlite");
+    }
+    let _ = backend;
+    None
+}
+
+/// A drop in replacement for diesel's r2d2 `ConnectionManager` which traces
+/// the connections it opens and validates.
+///
+/// Opening a connection is traced in a `connect` span recording
+/// `db.pool.connect=true`, and checking a connection is still usable in an
+/// `is_valid` span, and each emits an event with the time taken. Time spent
+/// waiting for a connection to be checked out of the pool is reported by
+/// [`R2d2TracingEventHandler`], which can be installed alongside this.
+///
+/// ```
+/// use diesel::r2d2::Pool;
+/// use diesel_tracing::pool::{InstrumentedConnectionManager, R2d2TracingEventHandler};
+/// use diesel_tracing::sqlite::InstrumentedSqliteConnection;
+///
+/// let pool = Pool::builder()
+///     .event_handler(Box::new(R2d2TracingEventHandler::new("api-db")))
+///     .build(InstrumentedConnectionManager::<InstrumentedSqliteConnection>::new(
+///         ":memory:",
+///     ))?;
+/// # Ok::<(), Box<dyn std::error::Error>>(())
+/// ```
+#[derive(Debug)]
+pub struct InstrumentedConnectionManager<C> {
+    inner: ConnectionManager<C>,
+    init_statements: Vec<String>,
+}
+
+impl<C> InstrumentedConnectionManager<C> {
+    /// Create a manager which opens connections to `database_url`.
+    pub fn new<S: Into<String>>(database_url: S) -> Self {
+        InstrumentedConnectionManager {
+            inner: ConnectionManager::new(database_url),
+            init_statements: Vec::new(),
+        }
+    }
+
+    /// Run session settings, such as `PRAGMA foreign_keys = ON`, on every
+    /// connection the manager opens, as `establish_with_init` does on the
+    /// instrumented connections.
+    #[must_use]
+    pub fn with_init_statements<I, S>(mut self, statements: I) -> Self
+    where
+        I: IntoIterator<Item = S>,
+        S: Into<String>,
+    {
+        self.init_statements = statements.into_iter().map(Into::into).collect();
+        self
+    }
+
+    /// Unwrap the diesel connection manager.
+    #[must_use]
+    pub fn into_inner(self) -> ConnectionManager<C> {
+        self.inner
+    }
+}
+
+impl<C> From<ConnectionManager<C>> for InstrumentedConnectionManager<C> {
+    fn from(inner: ConnectionManager<C>) -> Self {
+        InstrumentedConnectionManager {
+            inner,
+            init_statements: Vec::new(),
+        }
+    }
+}
+
+impl<C> ManageConnection for InstrumentedConnectionManager<C>
+where
+    C: R2D2Connection + Send + 'static,
+{
+    type Connection = C;
+    type Error = Error;
+
+    #[cfg_attr(
+        not(feature = "passthrough"),
+        tracing::instrument(
+            fields(
+                db.system=db_system::<<C as diesel::Connection>::Backend>(),
+                otel.kind="client",
+                db.pool.connect=true,
+            ),
+            skip(self),
+            err,
+        )
+    )]
+    fn connect(&self) -> Result<C, Error> {
+        let started = Instant::now();
+        let result = self.inner.connect().and_then(|mut conn| {
+            run_init_statements(&mut conn, &self.init_statements)
+                .map_err(Error::ConnectionError)?;
+            Ok(conn)
+        });
+        if !cfg!(feature = "passthrough") {
+            debug!(
+                pool.event = "connect",
+                pool.connect_duration = ?started.elapsed(),
+                "pool connection opened",
+            );
+        }
+
+        result
+    }
+
+    #[cfg_attr(
+        not(feature = "passthrough"),
+        tracing::instrument(
+            fields(
+                db.system=db_system::<<C as diesel::Connection>::Backend>(),
+                otel.kind="client",
+            ),
+            skip(self, conn),
+            err,
+        )
+    )]
+    fn is_valid(&self, conn: &mut C) -> Result<(), Error> {
+        let started = Instant::now();
+        let result = self.inner.is_valid(conn);
+        if !cfg!(feature = "passthrough") {
+            debug!(
+                pool.event = "is_valid",
+                pool.validate_duration = ?started.elapsed(),
+                "pool connection validated",
+            );
+        }
+
+        result
+    }
+
+    fn has_broken(&self, conn: &mut C) -> bool {
+        self.inner.has_broken(conn)
+    }
+}
+
+/// An r2d2 event and error handler which emits a tracing event for each
+/// connection acquired, released, checked out and checked in, and for each
+/// checkout timeout and connection error.
+///
+/// Every event carries `pool.name`, `pool.event` and the pool state as
+/// `pool.state.connections` and `pool.state.idle_connections`. r2d2 does not
+/// pass its state to event handlers, so the state is counted from the events
+/// seen by this handler, and should only be installed on a single pool.
+///
+/// ```
+/// use diesel::r2d2::{ConnectionManager, Pool};
+/// use diesel_tracing::pool::R2d2TracingEventHandler;
+/// # use diesel::sqlite::SqliteConnection as Connection;
+///
+/// let pool = Pool::builder()
+///     .event_handler(Box::new(R2d2TracingEventHandler::new("api-db")))
+///     .error_handler(Box::new(R2d2TracingEventHandler::new("api-db")))
+///     .build(ConnectionManager::<Connection>::new(":memory:"))?;
+/// # Ok::<(), Box<dyn std::error::Error>>(())
+/// ```
+#[derive(Debug)]
+pub struct R2d2TracingEventHandler {
+    pool_name: String,
+    connections: AtomicU32,
+    checked_out: AtomicU32,
+}
+
+impl R2d2TracingEventHandler {
+    /// Create a handler for the pool with the given name.
+    pub fn new(pool_name: impl Into<String>) -> Self {
+        R2d2TracingEventHandler {
+            pool_name: pool_name.into(),
+            connections: AtomicU32::new(0),
+            checked_out: AtomicU32::new(0),
+        }
+    }
+
+    fn connections(&self) -> u32 {
+        self.connections.load(Ordering::Relaxed)
+    }
+
+    fn idle_connections(&self) -> u32 {
+        self.connections()
+            .saturating_sub(self.checked_out.load(Ordering::Relaxed))
+    }
+}
+
+impl HandleEvent for R2d2TracingEventHandler {
+    fn handle_acquire(&self, event: AcquireEvent) {
+        self.connections.fetch_add(1, Ordering::Relaxed);
+        debug!(
+            pool.name = self.pool_name,
+            pool.event = "acquire",
+            pool.connection_id = event.connection_id(),
+            pool.state.connections = self.connections(),
+            pool.state.idle_connections = self.idle_connections(),
+            "pool connection acquired",
+        );
+    }
+
+    fn handle_release(&self, event: ReleaseEvent) {
+        self.connections.fetch_sub(1, Ordering::Relaxed);
+        debug!(
+            pool.name = self.pool_name,
+            pool.event = "release",
+            pool.connection_id = event.connection_id(),
+            pool.connection_age = ?event.age(),
+            pool.state.connections = self.connections(),
+            pool.state.idle_connections = self.idle_connections(),
+            "pool connection released",
+        );
+    }
+
+    fn handle_checkout(&self, event: CheckoutEvent) {
+        self.checked_out.fetch_add(1, Ordering::Relaxed);
+        debug!(
+            pool.name = self.pool_name,
+            pool.event = "checkout",
+            pool.connection_id = event.connection_id(),
+            pool.wait_duration = ?event.duration(),
+            pool.state.connections = self.connections(),
+            pool.state.idle_connections = self.idle_connections(),
+            "pool connection checked out",
+        );
+    }
+
+    fn handle_timeout(&self, event: TimeoutEvent) {
+        error!(
+            pool.name = self.pool_name,
+            pool.event = "timeout",
+            pool.timeout = ?event.timeout(),
+            pool.state.connections = self.connections(),
+            pool.state.idle_connections = self.idle_connections(),
+            "pool checkout timed out",
+        );
+    }
+
+    fn handle_checkin(&self, event: CheckinEvent) {
+        self.checked_out.fetch_sub(1, Ordering::Relaxed);
+        debug!(
+            pool.name = self.pool_name,
+            pool.event = "checkin",
+            pool.connection_id = event.connection_id(),
+            pool.checkout_duration = ?event.duration(),
+            pool.state.connections = self.connections(),
+            pool.state.idle_connections = self.idle_connections(),
+            "pool connection checked in",
+        );
+    }
+}
+
+impl<E> HandleError<E> for R2d2TracingEventHandler
+where
+    E: Display,
+{
+    fn handle_error(&self, error: E) {
+        error!(
+            pool.name = self.pool_name,
+            pool.event = "error",
+            pool.state.connections = self.connections(),
+            pool.state.idle_connections = self.idle_connections(),
+            %error,
+            "pool connection error",
+        );
+    }
+}
+
+#[cfg(all(test, feature = "sqlite"))]
+mod tests {
+    use super::*;
+    use crate::sqlite::InstrumentedSqliteConnection;
+    use crate::test_support::EventRecorder;
+    use diesel::connection::SimpleConnection;
+    use diesel::r2d2::Pool;
+
+    #[cfg(not(feature = "passthrough"))]
+    #[test]
+    fn test_instrumented_connection_manager() {
+        let recorder = EventRecorder::default();
+        let manager =
+            InstrumentedConnectionManager::<InstrumentedSqliteConnection>::new(":memory:");
+        tracing::subscriber::with_default(recorder.clone(), || {
+            // the pool opens its connections on background threads, so
+            // connect here where the events are recorded
+            manager.connect().unwrap();
+        });
+        let pool = Pool::builder()
+            .max_size(1)
+            .test_on_check_out(true)
+            .connection_timeout(std::time::Duration::from_millis(50))
+            .event_handler(Box::new(R2d2TracingEventHandler::new("test-db")))
+            .build(manager)
+            .unwrap();
+
+        tracing::subscriber::with_default(recorder.clone(), || {
+            let _conn = pool.get().unwrap();
+            assert!(pool.get().is_err());
+        });
+
+        let connect = recorder.span("connect").unwrap();
+        assert_eq!(connect.fields["db.system"], "sqlite");
+        assert_eq!(connect.fields["db.pool.connect"], "true");
+        assert!(recorder.span("is_valid").is_some());
+        let events = recorder.events();
+        for pool_event in ["connect", "is_valid", "checkout", "timeout"] {
+            assert!(
+                events
+                    .iter()
+                    .any(|event| event.get("pool.event").is_some_and(|e| e == pool_event)),
+                "no {pool_event} event"
+            );
+        }
+    }
+
+    #[test]
+    fn test_checkout_and_checkin_events() {
+        let recorder = EventRecorder::default();
+        let pool = Pool::builder()
+            .max_size(1)
+            .event_handler(Box::new(R2d2TracingEventHandler::new("test-db")))
+            .build(ConnectionManager::<InstrumentedSqliteConnection>::new(
+                ":memory:",
+            ))
+            .unwrap();
+
+        tracing::subscriber::with_default(recorder.clone(), || {
+            drop(pool.get().unwrap());
+        });
+
+        let events = recorder.events();
+        let checkout = events
+            .iter()
+            .find(|event| event["pool.event"] == "checkout")
+            .unwrap();
+        assert_eq!(checkout["pool.name"], "test-db");
+        assert_eq!(checkout["pool.state.connections"], "1");
+        assert_eq!(checkout["pool.state.idle_connections"], "0");
+        let checkin = events
+            .iter()
+            .find(|event| event["pool.event"] == "checkin")
+            .unwrap();
+        assert_eq!(checkin["pool.state.idle_connections"], "1");
+    }
+
+    #[test]
+    fn test_init_statements_run_on_connect() {
+        let manager =
+            InstrumentedConnectionManager::<InstrumentedSqliteConnection>::new(":memory:")
+                .with_init_statements(["CREATE TABLE init (id INTEGER)"]);
+        let mut conn = manager.connect().unwrap();
+        conn.batch_execute("INSERT INTO init VALUES (1)").unwrap();
+
+        let manager = manager.with_init_statements(["NOT SQL"]);
+        let error = manager.connect().err().unwrap();
+        assert!(error.to_string().contains("init statement 0 (NOT SQL)"));
+    }
+}
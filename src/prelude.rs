@@ -0,0 +1,68 @@
+/*!
+The types needed to adopt the instrumented connections, along with the diesel
+traits used alongside them, so that one glob import covers both.
+
+Only the connections of enabled backends are exported. With the
+`alias-diesel-names` feature the connections are also exported under the
+names of diesel's own, such as `PgConnection`, so that a crate can switch to
+them by changing where it imports those names from.
+
+```
+# #[cfg(feature = "sqlite")]
+# fn main() -> diesel::QueryResult<()> {
+use diesel_tracing::prelude::*;
+
+let mut conn = InstrumentedSqliteConnection::establish(":memory:").unwrap();
+conn.batch_execute("CREATE TABLE users (id INTEGER)")?;
+diesel::sql_query("INSERT INTO users VALUES (1)").execute(&mut conn)?;
+# Ok(())
+# }
+# #[cfg(not(feature = "sqlite"))]
+# fn main() {}
+```
+*/
+pub use diesel::connection::{Connection, SimpleConnection};
+pub use diesel::RunQueryDsl;
+
+pub use crate::config::{
+    DefaultSpanNamer, ErrorLevelPolicy, OperationSpanNamer, SpanNamer, TracingConfig,
+};
+pub use crate::fingerprint::{fingerprint_sql, statement_fingerprint};
+pub use crate::instrumentation::{
+    ComposedInstrumentation, InstrumentationStack, QueryCacheStatsInstrumentation,
+    TransactionSpanInstrumentation,
+};
+pub use crate::statement::{summarize_statement, StatementSummary};
+pub use crate::url::{mask_connection_url, Secret};
+
+#[cfg(feature = "async-mysql")]
+pub use crate::async_mysql::InstrumentedAsyncMysqlConnection;
+#[cfg(feature = "async-postgres")]
+pub use crate::async_pg::InstrumentedAsyncPgConnection;
+#[cfg(feature = "mysql")]
+pub use crate::mysql::InstrumentedMysqlConnection;
+#[cfg(feature = "postgres")]
+pub use crate::pg::{InstrumentedPgConnection, PgConnectConfig};
+#[cfg(feature = "sqlite")]
+pub use crate::sqlite::InstrumentedSqliteConnection;
+
+#[cfg(feature = "r2d2")]
+pub use crate::pool::{InstrumentedConnectionManager, R2d2TracingEventHandler};
+
+/// An r2d2 pool of instrumented mysql connections.
+#[cfg(all(feature = "r2d2", feature = "mysql"))]
+pub type MysqlPool = diesel::r2d2::Pool<InstrumentedConnectionManager<InstrumentedMysqlConnection>>;
+/// An r2d2 pool of instrumented postgresql connections.
+#[cfg(all(feature = "r2d2", feature = "postgres"))]
+pub type PgPool = diesel::r2d2::Pool<InstrumentedConnectionManager<InstrumentedPgConnection>>;
+/// An r2d2 pool of instrumented sqlite connections.
+#[cfg(all(feature = "r2d2", feature = "sqlite"))]
+pub type SqlitePool =
+    diesel::r2d2::Pool<InstrumentedConnectionManager<InstrumentedSqliteConnection>>;
+
+#[cfg(all(feature = "alias-diesel-names", feature = "mysql"))]
+pub use crate::mysql::InstrumentedMysqlConnection as MysqlConnection;
+#[cfg(all(feature = "alias-diesel-names", feature = "postgres"))]
+pub use crate::pg::InstrumentedPgConnection as PgConnection;
+#[cfg(all(feature = "alias-diesel-names", feature = "sqlite"))]
+pub use crate::sqlite::InstrumentedSqliteConnection as SqliteConnection;
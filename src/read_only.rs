@@ -0,0 +1,76 @@
+use diesel::result::Error;
+
+use crate::statement::{summarize_statement, AccessMode, StatementSummary};
+
+/// Whether a connection was set read only with `set_read_only`, and whether
+/// writes are rejected before they are sent rather than only logged.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub(crate) struct ReadOnly {
+    pub(crate) enabled: bool,
+    pub(crate) strict: bool,
+}
+
+impl ReadOnly {
+    /// The value recorded as `db.connection.read_only`, nothing unless the
+    /// connection is read only.
+    #[cfg_attr(feature = "passthrough", allow(dead_code))]
+    pub(crate) fn field(self) -> Option<bool> {
+        self.enabled.then_some(true)
+    }
+
+    /// The summary of a statement about to run if it writes on a read only
+    /// connection. The statement is only rendered if the connection is read
+    /// only.
+    pub(crate) fn violation(
+        self,
+        sql: impl FnOnce() -> Option<String>,
+    ) -> Option<StatementSummary> {
+        if !self.enabled {
+            return None;
+        }
+        let summary = summarize_statement(&sql()?);
+        (summary.access_mode == AccessMode::Write).then_some(summary)
+    }
+}
+
+/// The error of a write rejected by a strict read only connection.
+pub(crate) fn rejected(summary: &StatementSummary) -> Error {
+    let operation = summary.operation.as_deref().unwrap_or("write");
+    Error::QueryBuilderError(
+        format!("{operation} statement rejected on a read only connection").into(),
+    )
+}
+
+/// Check a statement before it is sent on a connection which may be read
+/// only, warning if it writes and, in strict mode, failing with the error of
+/// [`rejected`] instead of sending it.
+///
+/// The target of an event is fixed where it is declared, so it must be a
+/// literal.
+macro_rules! check_read_only {
+    ($target:literal, $read_only:expr, $sql:expr) => {{
+        let read_only: $crate::read_only::ReadOnly = $read_only;
+        match read_only.violation($sql) {
+            Some(summary) => {
+                if !cfg!(feature = "passthrough") {
+                    tracing::warn!(
+                        target: $target,
+                        {
+                            db.operation = summary.operation.as_deref(),
+                            db.sql.table = summary.table.as_deref(),
+                            db.read_only.strict = read_only.strict,
+                        },
+                        "write attempted on a read only connection",
+                    );
+                }
+                if read_only.strict {
+                    Err($crate::read_only::rejected(&summary))
+                } else {
+                    Ok(())
+                }
+            }
+            None => Ok(()),
+        }
+    }};
+}
+pub(crate) use check_read_only;
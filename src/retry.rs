@@ -0,0 +1,726 @@
+/*!
+Retrying operations which fail with transient errors, such as cloud databases
+dropping connections during a failover.
+*/
+use std::any::TypeId;
+use std::fmt::Display;
+use std::time::Duration;
+
+use diesel::connection::{
+    AnsiTransactionManager, Connection, ConnectionSealed, Instrumentation, LoadConnection,
+    SimpleConnection, TransactionManager,
+};
+use diesel::expression::QueryMetadata;
+use diesel::query_builder::{Query, QueryFragment, QueryId};
+use diesel::result::{ConnectionError, ConnectionResult, DatabaseErrorKind, Error, QueryResult};
+use tracing::{field, Span};
+
+use crate::instrumentation::SharedInstrumentationStack;
+
+/// The number of attempts made by a [`RetryingInstrumentedConnection`]
+/// unless changed with [`RetryingInstrumentedConnection::with_max_attempts`].
+pub const DEFAULT_MAX_ATTEMPTS: u32 = 3;
+
+/// The wait between attempts, starting at `initial` and doubling for each
+/// retry after it, up to `max`.
+///
+/// ```
+/// use std::time::Duration;
+///
+/// use diesel_tracing::retry::ExponentialBackoff;
+///
+/// let backoff = ExponentialBackoff {
+///     initial: Duration::from_millis(50),
+///     max: Duration::from_secs(1),
+/// };
+/// assert_eq!(backoff.delay(3), Duration::from_millis(200));
+/// ```
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ExponentialBackoff {
+    /// The wait before the first retry.
+    pub initial: Duration,
+    /// The longest wait between two attempts.
+    pub max: Duration,
+}
+
+impl Default for ExponentialBackoff {
+    fn default() -> Self {
+        ExponentialBackoff {
+            initial: Duration::from_millis(100),
+            max: Duration::from_secs(5),
+        }
+    }
+}
+
+impl ExponentialBackoff {
+    /// The wait before the given retry, counting from 1.
+    #[must_use]
+    pub fn delay(&self, retry: u32) -> Duration {
+        let factor = 2_u32.saturating_pow(retry.saturating_sub(1));
+        self.initial.saturating_mul(factor).min(self.max)
+    }
+}
+
+/// The target of an event of a connection wrapped by a
+/// [`RetryingInstrumentedConnection`], which is the target used by the module
+/// of its backend, or this module's for other backends.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum EventTarget {
+    MysqlQuery,
+    MysqlConnection,
+    PgQuery,
+    PgConnection,
+    SqliteQuery,
+    SqliteConnection,
+    Other,
+}
+
+impl EventTarget {
+    fn query<DB: 'static>() -> Self {
+        match backend_name::<DB>() {
+            Some("mysql") => EventTarget::MysqlQuery,
+            Some("pg") => EventTarget::PgQuery,
+            Some("sqlite") => EventTarget::SqliteQuery,
+            _ => EventTarget::Other,
+        }
+    }
+
+    fn connection<DB: 'static>() -> Self {
+        match backend_name::<DB>() {
+            Some("mysql") => EventTarget::MysqlConnection,
+            Some("pg") => EventTarget::PgConnection,
+            Some("sqlite") => EventTarget::SqliteConnection,
+            _ => EventTarget::Other,
+        }
+    }
+}
+
+/// The name of the module of the instrumented connection for backend `DB`.
+fn backend_name<DB: 'static>() -> Option<&'static str> {
+    let backend = TypeId::of::<DB>();
+    #[cfg(feature = "mysql")]
+    if backend == TypeId::of::<diesel::mysql::Mysql>() {
+        return Some("mysql");
+    }
+    #[cfg(feature = "postgres")]
+    if backend == TypeId::of::<diesel::pg::Pg>() {
+        return Some("pg");
+    }
+    #[cfg(feature = "sqlite")]
+    if backend == TypeId::of::<diesel::sqlite::Sqlite>() {
+        return Some("sqlite");
+    }
+    let _ = backend;
+    None
+}
+
+/// Emit an event or create a span with the given target. The target of an
+/// event is fixed where it is declared, so each target has its own.
+macro_rules! target_event {
+    ($target:expr, $macro:ident!($($args:tt)*)) => {
+        match $target {
+            EventTarget::MysqlQuery => {
+                tracing::$macro!(target: "diesel_tracing::mysql::query", $($args)*)
+            }
+            EventTarget::MysqlConnection => {
+                tracing::$macro!(target: "diesel_tracing::mysql::connection", $($args)*)
+            }
+            EventTarget::PgQuery => {
+                tracing::$macro!(target: "diesel_tracing::pg::query", $($args)*)
+            }
+            EventTarget::PgConnection => {
+                tracing::$macro!(target: "diesel_tracing::pg::connection", $($args)*)
+            }
+            EventTarget::SqliteQuery => {
+                tracing::$macro!(target: "diesel_tracing::sqlite::query", $($args)*)
+            }
+            EventTarget::SqliteConnection => {
+                tracing::$macro!(target: "diesel_tracing::sqlite::connection", $($args)*)
+            }
+            EventTarget::Other => tracing::$macro!($($args)*),
+        }
+    };
+}
+/// Wraps an instrumented connection, retrying statements which fail with a
+/// transient error.
+///
+/// `batch_execute` and `execute_returning_count` are retried when they fail
+/// with a serialization failure. Failures because the connection was closed
+/// or the statement could not be sent are only retried after
+/// [`with_retry_on_disconnect`](Self::with_retry_on_disconnect), as the
+/// server may have run the statement before the connection was lost.
+/// Statements inside a transaction are not retried, as the failure aborts the
+/// transaction, and neither are loads, as their query is consumed by the
+/// first attempt.
+///
+/// Whether or not the statement is retried, a closed connection is replaced
+/// with a new one before the next statement outside a transaction, with the
+/// instrumentation set on the wrapper installed on it. If the new connection
+/// cannot be established, the statement fails with its error.
+///
+/// The retries of an operation are made inside a `db.retry` span recording
+/// `db.retry_attempt`, opened when the first attempt fails. Each retry logs a
+/// warning with the error, and an error is logged if the last attempt fails
+/// too.
+///
+/// ```no_run
+/// # #[cfg(feature = "postgres")]
+/// # fn example() -> diesel::ConnectionResult<()> {
+/// use diesel::connection::Connection;
+/// use diesel_tracing::pg::InstrumentedPgConnection;
+/// use diesel_tracing::retry::RetryingInstrumentedConnection;
+///
+/// let conn: RetryingInstrumentedConnection<InstrumentedPgConnection> =
+///     RetryingInstrumentedConnection::establish("postgresql://localhost/app")?;
+/// let conn = conn.with_max_attempts(5);
+/// # Ok(())
+/// # }
+/// ```
+pub struct RetryingInstrumentedConnection<C> {
+    inner: C,
+    connect: Box<dyn Fn() -> ConnectionResult<C> + Send>,
+    max_attempts: u32,
+    backoff: ExponentialBackoff,
+    retry_on_disconnect: bool,
+    // whether the last statement failed because the connection was closed
+    closed: bool,
+    instrumentation: Option<SharedInstrumentationStack>,
+}
+
+impl<C> RetryingInstrumentedConnection<C>
+where
+    C: Connection<TransactionManager = AnsiTransactionManager> + 'static,
+{
+    /// Wrap an established connection, calling `connect` to replace it if it
+    /// is closed.
+    pub fn new(inner: C, connect: impl Fn() -> ConnectionResult<C> + Send + 'static) -> Self {
+        RetryingInstrumentedConnection {
+            inner,
+            connect: Box::new(connect),
+            max_attempts: DEFAULT_MAX_ATTEMPTS,
+            backoff: ExponentialBackoff::default(),
+            retry_on_disconnect: false,
+            closed: false,
+            instrumentation: None,
+        }
+    }
+
+    /// Make up to `max_attempts` attempts of each operation, `1` disables
+    /// retries.
+    #[must_use]
+    pub fn with_max_attempts(mut self, max_attempts: u32) -> Self {
+        self.max_attempts = max_attempts;
+        self
+    }
+
+    /// Wait between attempts as set by `backoff`.
+    #[must_use]
+    pub fn with_backoff(mut self, backoff: ExponentialBackoff) -> Self {
+        self.backoff = backoff;
+        self
+    }
+
+    /// Also retry statements which failed because the connection was closed
+    /// or the statement could not be sent. The server may already have run
+    /// such a statement, so only enable this when every statement run on the
+    /// connection is safe to repeat.
+    #[must_use]
+    pub fn with_retry_on_disconnect(mut self, retry_on_disconnect: bool) -> Self {
+        self.retry_on_disconnect = retry_on_disconnect;
+        self
+    }
+
+    /// Whether statements are retried after the connection was closed.
+    #[must_use]
+    pub fn retries_on_disconnect(&self) -> bool {
+        self.retry_on_disconnect
+    }
+
+    /// The number of attempts made of each operation.
+    #[must_use]
+    pub fn max_attempts(&self) -> u32 {
+        self.max_attempts
+    }
+
+    /// The wait between attempts.
+    #[must_use]
+    pub fn backoff(&self) -> ExponentialBackoff {
+        self.backoff
+    }
+
+    /// The wrapped connection.
+    #[must_use]
+    pub fn inner(&self) -> &C {
+        &self.inner
+    }
+
+    /// The wrapped connection. Statements run on it directly are not retried.
+    pub fn inner_mut(&mut self) -> &mut C {
+        &mut self.inner
+    }
+
+    /// Unwrap the connection.
+    #[must_use]
+    pub fn into_inner(self) -> C {
+        self.inner
+    }
+
+    fn in_transaction(&mut self) -> bool {
+        !matches!(
+            AnsiTransactionManager::transaction_manager_status_mut(&mut self.inner)
+                .transaction_depth(),
+            Ok(None)
+        )
+    }
+
+    /// Replace the connection if the last statement failed because it was
+    /// closed, unless a transaction is open, which would be silently lost.
+    fn reconnect_if_closed(&mut self) -> QueryResult<()> {
+        if !self.closed || self.in_transaction() {
+            return Ok(());
+        }
+        match (self.connect)() {
+            Ok(mut inner) => {
+                if let Some(stack) = &self.instrumentation {
+                    inner.set_instrumentation(stack.clone());
+                }
+                self.inner = inner;
+                self.closed = false;
+                Ok(())
+            }
+            Err(error) => {
+                if !cfg!(feature = "passthrough") {
+                    target_event!(
+                        EventTarget::connection::<C::Backend>(),
+                        warn!(%error, "failed to reconnect")
+                    );
+                }
+                Err(Error::DatabaseError(
+                    DatabaseErrorKind::ClosedConnection,
+                    Box::new(error.to_string()),
+                ))
+            }
+        }
+    }
+
+    fn retry<T>(&mut self, mut operation: impl FnMut(&mut C) -> QueryResult<T>) -> QueryResult<T> {
+        if self.in_transaction() {
+            return operation(&mut self.inner);
+        }
+
+        let (max_attempts, backoff) = (self.max_attempts, self.backoff);
+        let retry_on_disconnect = self.retry_on_disconnect;
+        retry(
+            EventTarget::query::<C::Backend>(),
+            max_attempts,
+            backoff,
+            |error| is_transient(error, retry_on_disconnect),
+            || {
+                self.reconnect_if_closed()?;
+                let result = operation(&mut self.inner);
+                self.closed = result.as_ref().is_err_and(is_closed_connection);
+                result
+            },
+        )
+    }
+}
+
+fn is_closed_connection(error: &Error) -> bool {
+    matches!(
+        error,
+        Error::DatabaseError(DatabaseErrorKind::ClosedConnection, _)
+    )
+}
+
+/// Whether a statement which failed with `error` may be retried. A statement
+/// which may have been run by the server before the connection was lost is
+/// only retried when `retry_on_disconnect` is set.
+fn is_transient(error: &Error, retry_on_disconnect: bool) -> bool {
+    match error {
+        Error::DatabaseError(DatabaseErrorKind::SerializationFailure, _) => true,
+        Error::DatabaseError(
+            DatabaseErrorKind::ClosedConnection | DatabaseErrorKind::UnableToSendCommand,
+            _,
+        ) => retry_on_disconnect,
+        _ => false,
+    }
+}
+
+/// Make up to `max_attempts` attempts of `operation` while it fails with a
+/// transient error, opening a span recording the attempt number once the
+/// first attempt fails.
+fn retry<T, E: Display>(
+    target: EventTarget,
+    max_attempts: u32,
+    backoff: ExponentialBackoff,
+    is_transient: impl Fn(&E) -> bool,
+    mut operation: impl FnMut() -> Result<T, E>,
+) -> Result<T, E> {
+    let mut error = match operation() {
+        Err(error) if is_transient(&error) => error,
+        result => return result,
+    };
+
+    let span = if cfg!(feature = "passthrough") {
+        Span::none()
+    } else {
+        target_event!(
+            target,
+            debug_span!("db.retry", db.retry_attempt = field::Empty)
+        )
+    };
+    let _entered = span.enter();
+    let mut attempt = 1;
+    loop {
+        if attempt >= max_attempts {
+            if !cfg!(feature = "passthrough") {
+                target_event!(
+                    target,
+                    error!(%error, db.retry_attempt = attempt, "retries exhausted")
+                );
+            }
+            return Err(error);
+        }
+        if !cfg!(feature = "passthrough") {
+            target_event!(
+                target,
+                warn!(%error, db.retry_attempt = attempt, "transient error, retrying")
+            );
+        }
+        std::thread::sleep(backoff.delay(attempt));
+        attempt += 1;
+        span.record("db.retry_attempt", attempt);
+        error = match operation() {
+            Err(error) if is_transient(&error) => error,
+            result => return result,
+        };
+    }
+}
+
+impl<C> SimpleConnection for RetryingInstrumentedConnection<C>
+where
+    C: Connection<TransactionManager = AnsiTransactionManager> + 'static,
+{
+    fn batch_execute(&mut self, query: &str) -> QueryResult<()> {
+        self.retry(|inner| inner.batch_execute(query))
+    }
+}
+
+impl<C> ConnectionSealed for RetryingInstrumentedConnection<C> {}
+
+impl<C> Connection for RetryingInstrumentedConnection<C>
+where
+    C: Connection<TransactionManager = AnsiTransactionManager> + 'static,
+{
+    type Backend = C::Backend;
+    type TransactionManager = AnsiTransactionManager;
+
+    /// Establish a connection, retrying if it fails with
+    /// [`ConnectionError::BadConnection`]. The URL is kept to establish
+    /// replacement connections.
+    fn establish(database_url: &str) -> ConnectionResult<Self> {
+        let database_url = database_url.to_owned();
+        let connect = move || C::establish(&database_url);
+        let inner = retry(
+            EventTarget::connection::<C::Backend>(),
+            DEFAULT_MAX_ATTEMPTS,
+            ExponentialBackoff::default(),
+            |error| matches!(error, ConnectionError::BadConnection(_)),
+            &connect,
+        )?;
+
+        Ok(RetryingInstrumentedConnection::new(inner, connect))
+    }
+
+    fn execute_returning_count<T>(&mut self, source: &T) -> QueryResult<usize>
+    where
+        T: QueryFragment<Self::Backend> + QueryId,
+    {
+        self.retry(|inner| inner.execute_returning_count(source))
+    }
+
+    fn transaction_state(&mut self) -> &mut AnsiTransactionManager {
+        self.inner.transaction_state()
+    }
+
+    fn instrumentation(&mut self) -> &mut dyn Instrumentation {
+        self.inner.instrumentation()
+    }
+
+    /// Set on the wrapped connection, and on the connections replacing it.
+    fn set_instrumentation(&mut self, instrumentation: impl Instrumentation) {
+        if self.instrumentation.is_some() {
+            SharedInstrumentationStack::push(&mut self.instrumentation, instrumentation);
+        } else {
+            let stack = SharedInstrumentationStack::new(instrumentation);
+            self.inner.set_instrumentation(stack.clone());
+            self.instrumentation = Some(stack);
+        }
+    }
+}
+
+impl<C, B> LoadConnection<B> for RetryingInstrumentedConnection<C>
+where
+    C: LoadConnection<B, TransactionManager = AnsiTransactionManager> + 'static,
+{
+    type Cursor<'conn, 'query>
+        = C::Cursor<'conn, 'query>
+    where
+        Self: 'conn;
+    type Row<'conn, 'query>
+        = C::Row<'conn, 'query>
+    where
+        Self: 'conn;
+
+    fn load<'conn, 'query, T>(
+        &'conn mut self,
+        source: T,
+    ) -> QueryResult<Self::Cursor<'conn, 'query>>
+    where
+        T: Query + QueryFragment<Self::Backend> + QueryId + 'query,
+        Self::Backend: QueryMetadata<T::SqlType>,
+    {
+        self.reconnect_if_closed()?;
+        let closed = &mut self.closed;
+        self.inner
+            .load(source)
+            .inspect_err(|error| *closed = is_closed_connection(error))
+    }
+}
+
+#[cfg(all(test, feature = "sqlite", not(feature = "passthrough")))]
+mod tests {
+    use super::*;
+    use crate::test_support::EventRecorder;
+    use diesel::sqlite::Sqlite;
+    use std::sync::atomic::{AtomicU32, Ordering};
+    use std::sync::Arc;
+
+    /// A connection whose statements fail with a closed connection while
+    /// `failures` is above zero, counting down with each failure.
+    struct FlakyConnection {
+        failures: Arc<AtomicU32>,
+        runs: Arc<AtomicU32>,
+        transaction_state: AnsiTransactionManager,
+        instrumentation: Option<Box<dyn Instrumentation>>,
+    }
+
+    impl FlakyConnection {
+        fn new(failures: &Arc<AtomicU32>) -> Self {
+            FlakyConnection {
+                failures: Arc::clone(failures),
+                runs: Arc::default(),
+                transaction_state: AnsiTransactionManager::default(),
+                instrumentation: None,
+            }
+        }
+
+        fn run(&self) -> QueryResult<()> {
+            self.runs.fetch_add(1, Ordering::SeqCst);
+            match self
+                .failures
+                .fetch_update(Ordering::SeqCst, Ordering::SeqCst, |n| n.checked_sub(1))
+            {
+                Ok(_) => Err(Error::DatabaseError(
+                    DatabaseErrorKind::ClosedConnection,
+                    Box::new("connection closed".to_string()),
+                )),
+                Err(_) => Ok(()),
+            }
+        }
+    }
+
+    impl SimpleConnection for FlakyConnection {
+        fn batch_execute(&mut self, _query: &str) -> QueryResult<()> {
+            self.run()
+        }
+    }
+
+    impl ConnectionSealed for FlakyConnection {}
+
+    impl Connection for FlakyConnection {
+        type Backend = Sqlite;
+        type TransactionManager = AnsiTransactionManager;
+
+        fn establish(_database_url: &str) -> ConnectionResult<Self> {
+            Err(ConnectionError::BadConnection("no database".to_string()))
+        }
+
+        fn execute_returning_count<T>(&mut self, _source: &T) -> QueryResult<usize>
+        where
+            T: QueryFragment<Sqlite> + QueryId,
+        {
+            self.run().map(|()| 1)
+        }
+
+        fn transaction_state(&mut self) -> &mut AnsiTransactionManager {
+            &mut self.transaction_state
+        }
+
+        fn instrumentation(&mut self) -> &mut dyn Instrumentation {
+            &mut self.instrumentation
+        }
+
+        fn set_instrumentation(&mut self, instrumentation: impl Instrumentation) {
+            self.instrumentation = Some(Box::new(instrumentation));
+        }
+    }
+
+    fn flaky_connection(
+        failures: u32,
+    ) -> (
+        RetryingInstrumentedConnection<FlakyConnection>,
+        Arc<AtomicU32>,
+        Arc<AtomicU32>,
+    ) {
+        let failures = Arc::new(AtomicU32::new(failures));
+        let connects = Arc::new(AtomicU32::new(0));
+        let conn = RetryingInstrumentedConnection::new(FlakyConnection::new(&failures), {
+            let failures = Arc::clone(&failures);
+            let connects = Arc::clone(&connects);
+            move || {
+                connects.fetch_add(1, Ordering::SeqCst);
+                Ok(FlakyConnection::new(&failures))
+            }
+        })
+        .with_backoff(ExponentialBackoff {
+            initial: Duration::ZERO,
+            max: Duration::ZERO,
+        });
+
+        (conn, failures, connects)
+    }
+
+    fn messages(recorder: &EventRecorder) -> Vec<(String, String)> {
+        recorder
+            .events()
+            .into_iter()
+            .filter_map(|event| Some((event["level"].clone(), event.get("message")?.clone())))
+            .collect()
+    }
+
+    #[test]
+    fn test_retries_until_success() {
+        let recorder = EventRecorder::default();
+        let (conn, _, connects) = flaky_connection(2);
+        let mut conn = conn.with_retry_on_disconnect(true);
+        tracing::subscriber::with_default(recorder.clone(), || {
+            conn.batch_execute("SELECT 1").unwrap();
+        });
+
+        assert_eq!(connects.load(Ordering::SeqCst), 2);
+        let span = recorder.span("db.retry").unwrap();
+        assert_eq!(span.fields["db.retry_attempt"], "3");
+        assert_eq!(span.target, "diesel_tracing::sqlite::query");
+        let retrying = ("WARN".to_string(), "transient error, retrying".to_string());
+        assert_eq!(messages(&recorder), vec![retrying.clone(), retrying]);
+    }
+
+    #[test]
+    fn test_retries_exhausted() {
+        let recorder = EventRecorder::default();
+        let (conn, _, _) = flaky_connection(5);
+        let mut conn = conn.with_max_attempts(2).with_retry_on_disconnect(true);
+        tracing::subscriber::with_default(recorder.clone(), || {
+            let error = conn
+                .execute_returning_count(&diesel::sql_query("SELECT 1"))
+                .unwrap_err();
+            assert!(is_closed_connection(&error));
+        });
+
+        assert_eq!(
+            messages(&recorder),
+            vec![
+                ("WARN".to_string(), "transient error, retrying".to_string()),
+                ("ERROR".to_string(), "retries exhausted".to_string()),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_no_retries_in_transaction() {
+        let (mut conn, failures, connects) = flaky_connection(0);
+        conn.transaction(|conn| {
+            failures.store(1, Ordering::SeqCst);
+            assert!(conn.batch_execute("SELECT 1").is_err());
+            Ok::<_, Error>(())
+        })
+        .unwrap();
+
+        assert_eq!(connects.load(Ordering::SeqCst), 0);
+    }
+
+    #[test]
+    fn test_no_retry_span_on_success() {
+        let recorder = EventRecorder::default();
+        let (mut conn, _, _) = flaky_connection(0);
+        tracing::subscriber::with_default(recorder.clone(), || {
+            conn.batch_execute("SELECT 1").unwrap();
+        });
+
+        assert!(recorder.span("db.retry").is_none());
+    }
+
+    #[test]
+    fn test_closed_connection_not_retried_by_default() {
+        let recorder = EventRecorder::default();
+        let (mut conn, _, connects) = flaky_connection(1);
+        tracing::subscriber::with_default(recorder.clone(), || {
+            let error = conn.batch_execute("INSERT INTO users VALUES (1)");
+            assert!(is_closed_connection(&error.unwrap_err()));
+            assert_eq!(connects.load(Ordering::SeqCst), 0);
+
+            // the closed connection is replaced before the next statement
+            conn.batch_execute("SELECT 1").unwrap();
+        });
+
+        assert_eq!(connects.load(Ordering::SeqCst), 1);
+        assert!(recorder.span("db.retry").is_none());
+        assert!(messages(&recorder).is_empty());
+    }
+
+    #[test]
+    fn test_failed_reconnect_returned() {
+        let recorder = EventRecorder::default();
+        let failures = Arc::new(AtomicU32::new(1));
+        let dead = FlakyConnection::new(&failures);
+        let runs = Arc::clone(&dead.runs);
+        let mut conn = RetryingInstrumentedConnection::new(dead, || {
+            Err(ConnectionError::BadConnection("no database".to_string()))
+        })
+        .with_backoff(ExponentialBackoff {
+            initial: Duration::ZERO,
+            max: Duration::ZERO,
+        })
+        .with_retry_on_disconnect(true);
+        tracing::subscriber::with_default(recorder.clone(), || {
+            let error = conn.batch_execute("SELECT 1").unwrap_err();
+            assert_eq!(error.to_string(), "no database");
+        });
+
+        // the statement is not run again on the closed connection
+        assert_eq!(runs.load(Ordering::SeqCst), 1);
+        let reconnect_failures = recorder
+            .events()
+            .into_iter()
+            .filter(|event| event.get("message").map(String::as_str) == Some("failed to reconnect"))
+            .map(|event| event["target"].clone())
+            .collect::<Vec<_>>();
+        assert_eq!(
+            reconnect_failures,
+            ["diesel_tracing::sqlite::connection"; DEFAULT_MAX_ATTEMPTS as usize - 1]
+        );
+    }
+
+    #[test]
+    fn test_instrumentation_kept_on_reconnect() {
+        let (mut conn, failures, _) = flaky_connection(0);
+        conn.set_instrumentation(|_: diesel::connection::InstrumentationEvent<'_>| {});
+        assert!(conn.inner().instrumentation.is_some());
+
+        failures.store(1, Ordering::SeqCst);
+        conn.batch_execute("SELECT 1").unwrap_err();
+        conn.batch_execute("SELECT 1").unwrap();
+        assert!(conn.inner().instrumentation.is_some());
+    }
+}
@@ -0,0 +1,135 @@
+/// Decides which queries of a connection create spans, so that only a
+/// fraction of them pay the cost of creating a span.
+///
+/// Sampled queries are spread evenly rather than chosen at random: each query
+/// adds the sample rate to a running credit, and is sampled whenever the
+/// credit reaches one. The queries of a transaction are all sampled, or none
+/// of them are, with the decision made once when the transaction begins.
+#[derive(Clone, Copy, Debug)]
+pub(crate) struct QuerySampler {
+    rate: f32,
+    credit: f32,
+    // the decision for the transaction in progress, which its queries follow
+    transaction: Option<bool>,
+}
+
+impl Default for QuerySampler {
+    fn default() -> Self {
+        QuerySampler {
+            rate: 1.0,
+            credit: 0.0,
+            transaction: None,
+        }
+    }
+}
+
+impl QuerySampler {
+    /// Sample the given fraction of queries, clamped between 0 and 1.
+    pub(crate) fn set_rate(&mut self, rate: f32) {
+        self.rate = if rate.is_nan() {
+            1.0
+        } else {
+            rate.clamp(0.0, 1.0)
+        };
+        self.credit = 0.0;
+    }
+
+    /// The fraction of queries which are sampled.
+    pub(crate) fn rate(&self) -> f32 {
+        self.rate
+    }
+
+    /// Whether the next query creates a span.
+    pub(crate) fn sample(&mut self) -> bool {
+        match self.transaction {
+            Some(sampled) => sampled,
+            None => self.decide(),
+        }
+    }
+
+    /// Decide whether a transaction and the queries in it create spans,
+    /// returning the decision along with the one for any transaction it is
+    /// nested in, which [`QuerySampler::end_transaction`] restores.
+    pub(crate) fn begin_transaction(&mut self) -> (bool, Option<bool>) {
+        let outer = self.transaction;
+        let sampled = outer.unwrap_or_else(|| self.decide());
+        self.transaction = Some(sampled);
+        (sampled, outer)
+    }
+
+    /// Restore the decision of the transaction a finished transaction was
+    /// nested in.
+    pub(crate) fn end_transaction(&mut self, outer: Option<bool>) {
+        self.transaction = outer;
+    }
+
+    fn decide(&mut self) -> bool {
+        if self.rate >= 1.0 {
+            return true;
+        }
+        self.credit += self.rate;
+        if self.credit >= 1.0 {
+            self.credit -= 1.0;
+            true
+        } else {
+            false
+        }
+    }
+}
+
+/// Log the failure of a query which was sampled out, as failures are
+/// recorded whatever the sample rate, with the level `levels` assigns to the
+/// error and the target of the connection's query spans, like the error
+/// events of the queries which are traced.
+macro_rules! sampled_out_error_event {
+    ($target:literal, $db_system:literal, $levels:expr, $error:expr) => {{
+        let error = $error;
+        crate::error::error_event!(
+            $target,
+            $levels,
+            error,
+            db.system = $db_system,
+            db.error.kind = crate::error::error_kind(error),
+            "sampled out query failed"
+        );
+    }};
+}
+pub(crate) use sampled_out_error_event;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_sample_rate() {
+        let mut query_sampler = QuerySampler::default();
+        assert!((0..100).all(|_| query_sampler.sample()));
+
+        query_sampler.set_rate(0.25);
+        let sampled = (0..100).filter(|_| query_sampler.sample()).count();
+        assert_eq!(sampled, 25);
+
+        query_sampler.set_rate(0.0);
+        assert!(!(0..100).any(|_| query_sampler.sample()));
+    }
+
+    #[test]
+    fn test_transaction_all_or_nothing() {
+        let mut query_sampler = QuerySampler::default();
+        query_sampler.set_rate(0.5);
+
+        let (sampled, outer) = query_sampler.begin_transaction();
+        assert!(!sampled);
+        assert!((0..10).all(|_| !query_sampler.sample()));
+        let (nested, nested_outer) = query_sampler.begin_transaction();
+        assert!(!nested);
+        query_sampler.end_transaction(nested_outer);
+        assert!(!query_sampler.sample());
+        query_sampler.end_transaction(outer);
+
+        let (sampled, outer) = query_sampler.begin_transaction();
+        assert!(sampled);
+        assert!((0..10).all(|_| query_sampler.sample()));
+        query_sampler.end_transaction(outer);
+    }
+}
@@ -0,0 +1,27 @@
+//! Span fields renamed in version 1.20 of the OpenTelemetry semantic
+//! conventions, which are also recorded under their old names with the
+//! `compat-otel-pre-1.20` feature.
+//!
+//! | Field                  | Deprecated name |
+//! |------------------------|-----------------|
+//! | `db.namespace`         | `db.name`       |
+//! | `server.address`       | `net.peer.name` |
+//! | `server.port`          | `net.peer.port` |
+//! | `network.peer.address` | `net.peer.ip`   |
+use tracing::field::Value;
+use tracing::Span;
+
+/// The value of a field under its deprecated name, which is only recorded
+/// with the `compat-otel-pre-1.20` feature.
+pub(crate) fn deprecated<T>(value: T) -> Option<T> {
+    cfg!(feature = "compat-otel-pre-1.20").then_some(value)
+}
+
+/// Record a field, and with the `compat-otel-pre-1.20` feature the same value
+/// under its deprecated name.
+pub(crate) fn record_renamed<V: Value>(span: &Span, name: &str, deprecated_name: &str, value: V) {
+    span.record(name, &value);
+    if cfg!(feature = "compat-otel-pre-1.20") {
+        span.record(deprecated_name, value);
+    }
+}
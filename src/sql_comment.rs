@@ -0,0 +1,85 @@
+use diesel::backend::Backend;
+use diesel::query_builder::{AstPass, QueryFragment, QueryId};
+use diesel::result::QueryResult;
+use opentelemetry::trace::{SpanContext, TraceContextExt};
+use tracing::Span;
+use tracing_opentelemetry::OpenTelemetrySpanExt;
+
+/// A sqlcommenter style comment carrying the W3C `traceparent` of a span
+/// context, or `None` if the context is not valid.
+fn traceparent_comment(context: &SpanContext) -> Option<String> {
+    if !context.is_valid() {
+        return None;
+    }
+    Some(format!(
+        "/*traceparent='00-{}-{}-{:02x}'*/",
+        context.trace_id(),
+        context.span_id(),
+        context.trace_flags().to_u8()
+    ))
+}
+
+/// The `traceparent` comment of the current span, if it has an OpenTelemetry
+/// context.
+pub(crate) fn current_traceparent() -> Option<String> {
+    traceparent_comment(Span::current().context().span().span_context())
+}
+
+/// A statement followed by an optional comment.
+///
+/// A commented statement is never cached as a prepared statement, as the
+/// comment differs between executions.
+pub(crate) struct Commented<'a, T> {
+    statement: &'a T,
+    comment: Option<&'a str>,
+}
+
+impl<'a, T> Commented<'a, T> {
+    pub(crate) fn new(statement: &'a T, comment: Option<&'a str>) -> Self {
+        Commented { statement, comment }
+    }
+}
+
+impl<DB, T> QueryFragment<DB> for Commented<'_, T>
+where
+    DB: Backend,
+    T: QueryFragment<DB>,
+{
+    fn walk_ast<'b>(&'b self, mut out: AstPass<'_, 'b, DB>) -> QueryResult<()> {
+        self.statement.walk_ast(out.reborrow())?;
+        if let Some(comment) = self.comment {
+            out.unsafe_to_cache_prepared();
+            out.push_sql(" ");
+            out.push_sql(comment);
+        }
+        Ok(())
+    }
+}
+
+impl<T: QueryId> QueryId for Commented<'_, T> {
+    type QueryId = T::QueryId;
+
+    const HAS_STATIC_QUERY_ID: bool = T::HAS_STATIC_QUERY_ID;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use opentelemetry::trace::{SpanId, TraceFlags, TraceId, TraceState};
+
+    #[test]
+    fn test_traceparent_comment() {
+        let context = SpanContext::new(
+            TraceId::from_hex("4bf92f3577b34da6a3ce929d0e0e4736").unwrap(),
+            SpanId::from_hex("00f067aa0ba902b7").unwrap(),
+            TraceFlags::SAMPLED,
+            false,
+            TraceState::default(),
+        );
+        assert_eq!(
+            traceparent_comment(&context).unwrap(),
+            "/*traceparent='00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01'*/"
+        );
+        assert_eq!(traceparent_comment(&SpanContext::empty_context()), None);
+    }
+}
@@ -1,36 +1,437 @@
+use std::io::Write;
+use std::path::Path;
+#[cfg(feature = "audit")]
+use std::sync::Arc;
+use std::time::Duration;
+
 use diesel::associations::HasTable;
 use diesel::connection::{
-    AnsiTransactionManager, Connection, ConnectionSealed, DefaultLoadingMode, Instrumentation,
-    LoadConnection, MultiConnectionHelper, SimpleConnection, TransactionManager,
+    AnsiTransactionManager, BoxableConnection, Connection, ConnectionSealed, DefaultLoadingMode,
+    Instrumentation, LoadConnection, MultiConnectionHelper, SimpleConnection, TransactionManager,
 };
 use diesel::deserialize::{FromSqlRow, StaticallySizedRow};
-use diesel::dsl::{Find, Update};
+use diesel::dsl::{sql, Find, Update};
 use diesel::expression::{is_aggregate, MixedAggregates, QueryMetadata, ValidGrouping};
 use diesel::migration::{MigrationConnection, CREATE_MIGRATIONS_TABLE};
-use diesel::query_builder::{AsChangeset, IntoUpdateTarget, Query, QueryFragment, QueryId};
+use diesel::query_builder::{
+    AsChangeset, InsertStatement, IntoUpdateTarget, Query, QueryFragment, QueryId,
+};
 use diesel::query_dsl::methods::{ExecuteDsl, FindDsl};
 use diesel::query_dsl::{LoadQuery, UpdateAndFetchResults};
-use diesel::result::{ConnectionResult, QueryResult};
+use diesel::result::{ConnectionResult, DatabaseErrorKind, Error, QueryResult};
 use diesel::serialize::ToSql;
-use diesel::sql_types::HasSqlType;
-use diesel::sqlite::{Sqlite, SqliteConnection};
+use diesel::sql_types::{BigInt, HasSqlType, Integer, Text};
+use diesel::sqlite::{SerializedDatabase, Sqlite, SqliteAggregateFunction, SqliteConnection};
 use diesel::RunQueryDsl;
-use diesel::{sql_query, Identifiable, Table};
-use tracing::{debug, instrument};
+use diesel::{sql_query, Identifiable, Insertable, QueryableByName, Table};
+use tracing::{debug, debug_span, error, field, info_span, warn, Span};
+
+#[cfg(feature = "audit")]
+use crate::audit::{AuditSink, PendingAudit};
+use crate::batch::{record_batch, record_batch_error, split_statements};
+use crate::chunks;
+use crate::config::{
+    default_span_namer, format_extra_fields, name_by_statement, SpanNamer, TracingConfig,
+};
+#[cfg(not(feature = "passthrough"))]
+use crate::config::{establish_span_name, span_name};
+use crate::connection_id::{ConnectionId, ConnectionSpan};
+use crate::error::{error_event, record_error, record_status};
+#[cfg(all(feature = "statement-fields", not(feature = "passthrough")))]
+use crate::explain::{is_explainable, is_slow, record_plan, Explain};
+use crate::init::run_init_statements;
+use crate::instrumentation::{
+    SharedInstrumentationStack, StatementCacheProbe, TransactionWarnThresholds,
+};
+use crate::operation::measure;
+use crate::read_only::{check_read_only, ReadOnly};
+use crate::sampling::{sampled_out_error_event, QuerySampler};
+use crate::statement::render_sql;
+#[cfg(all(feature = "statement-fields", not(feature = "passthrough")))]
+use crate::statement::truncate_statement;
+use crate::statement::{record_query_summary, record_statement_summary};
+use crate::transaction::{
+    record_transaction_depth, record_transaction_outcome, run_savepoint, watch_transaction,
+};
 
 #[cfg(feature = "r2d2")]
 use diesel::r2d2::R2D2Connection;
 
+// the default SQLITE_MAX_VARIABLE_NUMBER of sqlite since 3.32.0
+const MAX_BINDS: usize = 32766;
+
+// whether diesel's migrations table exists, for ensure_migrations_table
+const MIGRATIONS_TABLE_EXISTS_QUERY: &str =
+    "SELECT COUNT(*) FROM sqlite_master WHERE type = 'table' AND name = '__diesel_schema_migrations'";
+
+/// Create the span covering the lifetime of a connection, which the spans of
+/// its operations follow from.
+fn connection_span(connection_id: ConnectionId, database_path: Option<&str>) -> ConnectionSpan {
+    if cfg!(feature = "passthrough") {
+        return ConnectionSpan::new(Span::none());
+    }
+
+    let span = debug_span!(
+        target: "diesel_tracing::sqlite::connection",
+        parent: None,
+        "db.connection",
+        db.system="sqlite",
+        db.client.address=database_path,
+        db.connection_id=connection_id.value(),
+        db.connection.id=connection_id.sequence(),
+        db.connection.name=field::Empty,
+        db.extra=field::Empty,
+    );
+    Span::current().follows_from(&span);
+
+    ConnectionSpan::new(span)
+}
+
+/// Instrument a method of the connection `connection` with the spans given,
+/// each under an optional `#[cfg]` predicate, adding the fields describing the
+/// sqlite connection and following from its connection span.
+///
+/// A macro cannot expand to an attribute, so this wraps the whole method.
+macro_rules! instrument_method {
+    (
+        connection = $conn:tt,
+        $(
+            $(#[cfg($cfg:meta)])?
+            instrument(
+                $(name = $name:literal,)?
+                fields($($field:tt)*),
+                $($arg:tt)*
+            )
+        ),+;
+        $item:item
+    ) => {
+        $(
+            #[cfg_attr(
+                all(not(feature = "passthrough") $(, $cfg)?),
+                tracing::instrument(
+                    $(name = $name,)?
+                    fields(
+                        db.system="sqlite",
+                        db.client.address=$conn.database_path.as_deref(),
+                        otel.kind="client",
+                        db.connection_id=$conn.connection_id.value(),
+                        db.connection.id=$conn.connection_id.sequence(),
+                        db.connection.name=$conn.connection_name.as_deref(),
+                        db.extra=$conn.extra_fields.as_deref(),
+                        db.connection.read_only=$conn.read_only.field(),
+                        $($field)*
+                    ),
+                    follows_from = [&$conn.connection_span],
+                    $($arg)*
+                )
+            )]
+        )+
+        $item
+    };
+}
+
+/// How an [`InstrumentedSqliteConnection`] retries statements which fail
+/// because another connection holds a lock on the database.
+///
+/// Each retry logs a warning with the attempt number and the total time
+/// waited so far. The default never retries.
+///
+/// ```
+/// use std::time::Duration;
+///
+/// use diesel_tracing::sqlite::BusyRetryPolicy;
+///
+/// let policy = BusyRetryPolicy {
+///     max_retries: 3,
+///     backoff: Duration::from_millis(10),
+/// };
+/// ```
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct BusyRetryPolicy {
+    /// The number of times a statement is retried, `0` disables retries.
+    pub max_retries: u32,
+    /// The wait before the first retry, doubled for each retry after it.
+    pub backoff: Duration,
+}
+
+/// The mode of a checkpoint run with
+/// [`InstrumentedSqliteConnection::wal_checkpoint`], see the sqlite
+/// documentation of `PRAGMA wal_checkpoint`.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum CheckpointMode {
+    #[default]
+    Passive,
+    Full,
+    Restart,
+    Truncate,
+}
+
+impl CheckpointMode {
+    fn as_str(self) -> &'static str {
+        match self {
+            CheckpointMode::Passive => "PASSIVE",
+            CheckpointMode::Full => "FULL",
+            CheckpointMode::Restart => "RESTART",
+            CheckpointMode::Truncate => "TRUNCATE",
+        }
+    }
+}
+
+#[derive(QueryableByName)]
+struct JournalMode {
+    #[diesel(sql_type = Text)]
+    journal_mode: String,
+}
+
+#[cfg(all(feature = "statement-fields", not(feature = "passthrough")))]
+#[derive(QueryableByName)]
+struct QueryPlanStep {
+    #[diesel(sql_type = Text)]
+    detail: String,
+}
+
+/// The outcome of a checkpoint run with
+/// [`InstrumentedSqliteConnection::wal_checkpoint`], as reported by
+/// `PRAGMA wal_checkpoint`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, QueryableByName)]
+pub struct WalCheckpoint {
+    /// 1 if the checkpoint was blocked from completing by another
+    /// connection, otherwise 0.
+    #[diesel(sql_type = Integer)]
+    pub busy: i32,
+    /// The number of frames in the write-ahead log, or -1 if the database is
+    /// not in WAL mode.
+    #[diesel(sql_type = Integer)]
+    pub log: i32,
+    /// The number of frames in the write-ahead log which were checkpointed,
+    /// or -1 if the database is not in WAL mode.
+    #[diesel(sql_type = Integer)]
+    pub checkpointed: i32,
+}
+
+fn is_identifier(name: &str) -> bool {
+    name.starts_with(|c: char| c.is_ascii_alphabetic() || c == '_')
+        && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_')
+}
+
+/// Format a pragma value, quoting anything other than a keyword or number as
+/// a string literal.
+fn pragma_value(value: &str) -> String {
+    if is_identifier(value) || value.parse::<f64>().is_ok() {
+        value.to_string()
+    } else {
+        format!("'{}'", value.replace('\'', "''"))
+    }
+}
+
+/// Whether sqlite failed to run a statement because the database or one of
+/// its tables is locked.
+fn is_locked(error: &Error) -> bool {
+    matches!(
+        error,
+        Error::DatabaseError(DatabaseErrorKind::Unknown, info) if info.message().contains("is locked")
+    )
+}
+
+/// Run `operation`, retrying it according to `policy` while the database is
+/// locked, and record the number of retries made as `db.sqlite.retries` on
+/// the current span.
+fn retry_locked<T>(
+    policy: BusyRetryPolicy,
+    mut operation: impl FnMut() -> QueryResult<T>,
+) -> QueryResult<T> {
+    let mut retries = 0;
+    let mut waited = Duration::ZERO;
+    let result = loop {
+        match operation() {
+            Err(error) if retries < policy.max_retries && is_locked(&error) => {
+                let wait = policy.backoff.saturating_mul(2_u32.saturating_pow(retries));
+                retries += 1;
+                waited = waited.saturating_add(wait);
+                if !cfg!(feature = "passthrough") {
+                    warn!(
+                        target: "diesel_tracing::sqlite::query",
+                        {
+                            db.sqlite.retry = retries,
+                            db.sqlite.wait_ms = u64::try_from(waited.as_millis()).unwrap_or(u64::MAX),
+                        },
+                        "database is locked, retrying",
+                    );
+                }
+                std::thread::sleep(wait);
+            }
+            result => break result,
+        }
+    };
+    if policy.max_retries > 0 && !cfg!(feature = "passthrough") {
+        Span::current().record("db.sqlite.retries", retries);
+    }
+
+    result
+}
+
+/// Run a batch of statements with [`retry_locked`], retrying it only if it
+/// holds a single statement, as the statements of a longer batch before the
+/// one which found the database locked have already run and would run again.
+fn retry_locked_batch(
+    policy: BusyRetryPolicy,
+    query: &str,
+    operation: impl FnMut() -> QueryResult<()>,
+) -> QueryResult<()> {
+    let policy = if split_statements(query).len() > 1 {
+        BusyRetryPolicy::default()
+    } else {
+        policy
+    };
+    retry_locked(policy, operation)
+}
+
+/// Wrap a custom sql function so that a panic inside it is logged, naming the
+/// function, before being resumed for diesel to turn into a query error.
+#[cfg_attr(feature = "passthrough", allow(dead_code))]
+fn log_function_panics<Args, Ret, F>(
+    fn_name: String,
+    mut f: F,
+) -> impl FnMut(Args) -> Ret + std::panic::UnwindSafe + Send + 'static
+where
+    F: FnMut(Args) -> Ret + std::panic::UnwindSafe + Send + 'static,
+{
+    move |args| {
+        // the panic is resumed, so callers observe the closure no differently
+        // than if it had not been caught
+        match std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| f(args))) {
+            Ok(ret) => ret,
+            Err(payload) => {
+                let message = payload
+                    .downcast_ref::<&str>()
+                    .copied()
+                    .or_else(|| payload.downcast_ref::<String>().map(String::as_str))
+                    .unwrap_or("unknown panic");
+                error!(
+                    target: "diesel_tracing::sqlite::query",
+                    {
+                        db.sqlite.function = fn_name,
+                        panic.message = message,
+                    },
+                    "sql function panicked",
+                );
+                std::panic::resume_unwind(payload)
+            }
+        }
+    }
+}
+
 pub struct InstrumentedSqliteConnection {
     inner: SqliteConnection,
+    #[cfg_attr(feature = "passthrough", allow(dead_code))]
+    connection_id: ConnectionId,
+    connection_name: Option<String>,
+    // db.client.address, the database_url the connection was established with
+    #[cfg_attr(feature = "passthrough", allow(dead_code))]
+    database_path: Option<String>,
+    extra_fields: Option<String>,
+    span_namer: Box<dyn SpanNamer>,
+    connection_span: ConnectionSpan,
+    config: TracingConfig,
+    instrumentation_stack: Option<SharedInstrumentationStack>,
+    sampler: QuerySampler,
+    read_only: ReadOnly,
+    transaction_warn: TransactionWarnThresholds,
+    // set by insert_in_chunks for the binds of its first statement to be
+    // checked against MAX_BINDS before it is run
+    check_binds: bool,
+    busy_retry: BusyRetryPolicy,
+    #[cfg_attr(
+        any(not(feature = "statement-fields"), feature = "passthrough"),
+        allow(dead_code)
+    )]
+    explain_on_slow: bool,
+    #[cfg(feature = "audit")]
+    audit_sink: Option<Arc<dyn AuditSink>>,
+}
+
+/// Prints the settings of the connection, never the URL it was established
+/// with.
+impl std::fmt::Debug for InstrumentedSqliteConnection {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("InstrumentedSqliteConnection")
+            .field("connection_id", &self.connection_id)
+            .field("connection_name", &self.connection_name)
+            .field("config", &self.config)
+            .field("busy_retry", &self.busy_retry)
+            .finish_non_exhaustive()
+    }
+}
+
+/// Wrap an established connection, keeping any in-memory database it holds.
+impl From<SqliteConnection> for InstrumentedSqliteConnection {
+    fn from(inner: SqliteConnection) -> Self {
+        let connection_id = ConnectionId::new();
+        let connection_span = connection_span(connection_id, None);
+
+        InstrumentedSqliteConnection {
+            inner,
+            connection_id,
+            connection_name: None,
+            database_path: None,
+            extra_fields: None,
+            span_namer: default_span_namer(),
+            connection_span,
+            config: TracingConfig::default(),
+            instrumentation_stack: None,
+            sampler: QuerySampler::default(),
+            read_only: ReadOnly::default(),
+            transaction_warn: TransactionWarnThresholds::default(),
+            check_binds: false,
+            busy_retry: BusyRetryPolicy::default(),
+            explain_on_slow: false,
+            #[cfg(feature = "audit")]
+            audit_sink: None,
+        }
+    }
+}
+
+/// Unwrap the diesel connection, see
+/// [`InstrumentedSqliteConnection::into_inner`].
+impl From<InstrumentedSqliteConnection> for SqliteConnection {
+    fn from(conn: InstrumentedSqliteConnection) -> Self {
+        conn.into_inner()
+    }
+}
+
+impl AsRef<SqliteConnection> for InstrumentedSqliteConnection {
+    fn as_ref(&self) -> &SqliteConnection {
+        &self.inner
+    }
+}
+
+/// Queries run through the returned connection are not traced.
+impl AsMut<SqliteConnection> for InstrumentedSqliteConnection {
+    fn as_mut(&mut self) -> &mut SqliteConnection {
+        &mut self.inner
+    }
 }
 
 #[cfg(feature = "r2d2")]
 impl R2D2Connection for InstrumentedSqliteConnection {
-    fn ping(&mut self) -> QueryResult<()> {
-        self.inner.batch_execute("SELECT 1")?;
+    instrument_method! {
+        connection = self,
+        instrument(
+            fields(
+                otel.name=span_name(self.span_namer.as_ref(), "ping"),
+                db.operation="ping",
+            ),
+            target = "diesel_tracing::sqlite::connection",
+            skip(self),
+            err,
+        );
+        fn ping(&mut self) -> QueryResult<()> {
+            self.inner.ping()
+        }
+    }
 
-        Ok(())
+    fn is_broken(&mut self) -> bool {
+        self.inner.is_broken()
     }
 }
 
@@ -49,11 +450,23 @@ impl MultiConnectionHelper for InstrumentedSqliteConnection {
 }
 
 impl SimpleConnection for InstrumentedSqliteConnection {
-    #[instrument(fields(db.system="sqlite", otel.kind="client"), skip(self, query), err)]
     fn batch_execute(&mut self, query: &str) -> QueryResult<()> {
-        self.inner.batch_execute(query)?;
+        check_read_only!("diesel_tracing::sqlite::query", self.read_only, || Some(
+            query.to_owned()
+        ))?;
+        #[cfg(feature = "audit")]
+        if let Some(audit) = self.start_audit(|| Some(query.to_owned())) {
+            let result = self.sampled_batch_execute(query);
+            audit.finish(result.as_ref().map(|&()| None));
+            return result;
+        }
+        self.sampled_batch_execute(query)
+    }
+}
 
-        Ok(())
+impl chunks::BindLimit for InstrumentedSqliteConnection {
+    fn check_binds(&mut self, check: bool) {
+        self.check_binds = check;
     }
 }
 
@@ -63,77 +476,133 @@ impl Connection for InstrumentedSqliteConnection {
     type Backend = Sqlite;
     type TransactionManager = AnsiTransactionManager;
 
-    #[instrument(fields(db.system="sqlite", otel.kind="client"), skip(database_url), err)]
+    #[cfg_attr(
+        not(feature = "passthrough"),
+        tracing::instrument(
+            target = "diesel_tracing::sqlite::connection",
+            fields(
+                db.system="sqlite",
+                db.client.address=database_url,
+                otel.kind="client",
+                otel.name=establish_span_name("establish"),
+                db.connection_id=field::Empty,
+                db.connection.id=field::Empty,
+                otel.status_code=field::Empty,
+                otel.status_message=field::Empty,
+            ),
+            skip(database_url),
+            err,
+        )
+    )]
     fn establish(database_url: &str) -> ConnectionResult<InstrumentedSqliteConnection> {
+        let mut inner = SqliteConnection::establish(database_url)
+            .inspect_err(|error| record_status(&tracing::Span::current(), error))?;
+        let instrumentation_stack = SharedInstrumentationStack::install(&mut inner);
+        let connection_id = ConnectionId::new();
+        connection_id.record(&tracing::Span::current());
+        let connection_span = connection_span(connection_id, Some(database_url));
+
         Ok(InstrumentedSqliteConnection {
-            inner: SqliteConnection::establish(database_url)?,
+            inner,
+            connection_id,
+            connection_name: None,
+            database_path: Some(database_url.to_string()),
+            extra_fields: None,
+            span_namer: default_span_namer(),
+            connection_span,
+            config: TracingConfig::default(),
+            instrumentation_stack,
+            sampler: QuerySampler::default(),
+            read_only: ReadOnly::default(),
+            transaction_warn: TransactionWarnThresholds::default(),
+            check_binds: false,
+            busy_retry: BusyRetryPolicy::default(),
+            explain_on_slow: false,
+            #[cfg(feature = "audit")]
+            audit_sink: None,
         })
     }
 
-    #[instrument(fields(db.system="sqlite", otel.kind="client"), skip(self, f))]
     fn transaction<T, E, F>(&mut self, f: F) -> Result<T, E>
     where
         F: FnOnce(&mut Self) -> Result<T, E>,
         E: From<diesel::result::Error>,
     {
-        Self::TransactionManager::transaction(self, f)
+        if !self.config.trace_transactions {
+            return Self::TransactionManager::transaction(self, f);
+        }
+        let (sampled, outer) = self.sampler.begin_transaction();
+        let result = if sampled {
+            self.traced_transaction(f)
+        } else {
+            Self::TransactionManager::transaction(self, f)
+        };
+        self.sampler.end_transaction(outer);
+        result
     }
 
-    #[instrument(fields(db.system="sqlite", otel.kind="client"), skip(self, source), err)]
     fn execute_returning_count<T>(&mut self, source: &T) -> QueryResult<usize>
     where
         T: QueryFragment<Sqlite> + QueryId,
     {
-        self.inner.execute_returning_count(source)
+        if std::mem::take(&mut self.check_binds) {
+            chunks::check_binds::<Sqlite, _>(source, MAX_BINDS)?;
+        }
+        check_read_only!("diesel_tracing::sqlite::query", self.read_only, || {
+            render_sql::<Sqlite, _>(source)
+        })?;
+        #[cfg(feature = "audit")]
+        if let Some(audit) = self.start_audit(|| render_sql::<Sqlite, _>(source)) {
+            let result = self.sampled_execute_returning_count(source);
+            audit.finish(result.as_ref().map(|count| Some(*count)));
+            return result;
+        }
+        self.sampled_execute_returning_count(source)
     }
 
-    #[instrument(fields(db.system="sqlite", otel.kind="client"), skip(self))]
     fn transaction_state(&mut self) -> &mut Self::TransactionManager {
-        self.inner.transaction_state()
+        if !self.config.trace_transactions {
+            return self.inner.transaction_state();
+        }
+        self.traced_transaction_state()
     }
 
-    #[instrument(fields(db.system="sqlite", otel.kind="client"), skip(self))]
     fn instrumentation(&mut self) -> &mut dyn Instrumentation {
-        self.inner.instrumentation()
+        if !self.config.trace_transactions {
+            return self.inner.instrumentation();
+        }
+        self.traced_instrumentation()
     }
 
-    #[instrument(fields(db.system="sqlite", otel.kind="client"), skip(self, instrumentation))]
-    fn set_instrumentation(&mut self, instrumentation: impl Instrumentation) {
-        self.inner.set_instrumentation(instrumentation)
+    instrument_method! {
+        connection = self,
+        instrument(
+            fields(
+                otel.name=span_name(self.span_namer.as_ref(), "set_instrumentation"),
+            ),
+            target = "diesel_tracing::sqlite::connection",
+            skip(self, instrumentation),
+        );
+        fn set_instrumentation(&mut self, instrumentation: impl Instrumentation) {
+            if let Some(stack) =
+                SharedInstrumentationStack::push(&mut self.instrumentation_stack, instrumentation)
+            {
+                self.inner.set_instrumentation(stack);
+            }
+        }
     }
 }
 
 impl LoadConnection<DefaultLoadingMode> for InstrumentedSqliteConnection {
-    type Cursor<'conn, 'query> = <SqliteConnection as LoadConnection<DefaultLoadingMode>>::Cursor<'conn, 'query>
-        where
-            Self: 'conn;
-    type Row<'conn, 'query> = <SqliteConnection as LoadConnection<DefaultLoadingMode>>::Row<'conn, 'query>
-        where
-            Self: 'conn;
+    type Cursor<'conn, 'query>
+        = <SqliteConnection as LoadConnection<DefaultLoadingMode>>::Cursor<'conn, 'query>
+    where
+        Self: 'conn;
+    type Row<'conn, 'query>
+        = <SqliteConnection as LoadConnection<DefaultLoadingMode>>::Row<'conn, 'query>
+    where
+        Self: 'conn;
 
-    #[cfg_attr(
-        feature = "statement-fields",
-        instrument(
-            fields(
-                db.system="sqlite",
-                otel.kind="client",
-                db.statement=%diesel::debug_query(&source),
-            ),
-            skip(self, source),
-            err,
-        )
-    )]
-    #[cfg_attr(
-        not(feature = "statement-fields"),
-        instrument(
-            fields(
-                db.system="sqlite",
-                otel.kind="client",
-            ),
-            skip(self, source),
-            err,
-        )
-    )]
     fn load<'conn, 'query, T>(
         &'conn mut self,
         source: T,
@@ -142,66 +611,3117 @@ impl LoadConnection<DefaultLoadingMode> for InstrumentedSqliteConnection {
         T: Query + QueryFragment<Self::Backend> + QueryId + 'query,
         Self::Backend: QueryMetadata<T::SqlType>,
     {
-        self.inner.load(source)
+        check_read_only!("diesel_tracing::sqlite::query", self.read_only, || {
+            render_sql::<Sqlite, _>(&source)
+        })?;
+        #[cfg(feature = "audit")]
+        let audit = self.start_audit(|| render_sql::<Sqlite, _>(&source));
+        self.connection_span.count_query();
+        let traced = self.config.trace_load;
+        let levels = self.config.error_levels;
+        let result = if !traced || !self.sampler.sample() {
+            measure("sqlite", move || self.inner.load(source)).inspect_err(|error| {
+                if traced {
+                    sampled_out_error_event!(
+                        "diesel_tracing::sqlite::query",
+                        "sqlite",
+                        levels,
+                        error
+                    );
+                }
+            })
+        } else {
+            self.traced_load(source)
+        };
+        #[cfg(feature = "audit")]
+        if let Some(audit) = audit {
+            audit.finish(result.as_ref().map(|_| None));
+        }
+
+        result
     }
 }
 
 impl MigrationConnection for InstrumentedSqliteConnection {
-    fn setup(&mut self) -> QueryResult<usize> {
-        sql_query(CREATE_MIGRATIONS_TABLE).execute(self)
+    instrument_method! {
+        connection = self,
+        instrument(
+            name = "create_migrations_table",
+            fields(
+                otel.name=span_name(self.span_namer.as_ref(), "create_migrations_table"),
+                db.operation="create_migrations_table",
+            ),
+            target = "diesel_tracing::sqlite::query",
+            skip(self),
+            err,
+        );
+        fn setup(&mut self) -> QueryResult<usize> {
+            sql_query(CREATE_MIGRATIONS_TABLE).execute(self)
+        }
+    }
+}
+
+impl InstrumentedSqliteConnection {
+    instrument_method! {
+        connection = self,
+        instrument(
+            name = "ensure_migrations_table",
+            fields(
+                otel.name=span_name(self.span_namer.as_ref(), "ensure_migrations_table"),
+                db.operation="ensure_migrations_table",
+                db.migrations_table.created=field::Empty,
+            ),
+            target = "diesel_tracing::sqlite::query",
+            skip(self),
+            err,
+        );
+        /// Create diesel's migrations table unless it already exists, returning
+        /// whether it was created. The check is traced like any other query, and
+        /// a debug event says whether the table was created or already existed.
+        ///
+        /// # Errors
+        ///
+        /// Returns an error if the check or creating the table fails.
+        pub fn ensure_migrations_table(&mut self) -> QueryResult<bool> {
+            let exists = sql::<BigInt>(MIGRATIONS_TABLE_EXISTS_QUERY).get_result::<i64>(self)? > 0;
+            if exists {
+                debug!(target: "diesel_tracing::sqlite::query", "migrations table already exists");
+            } else {
+                self.setup()?;
+                debug!(target: "diesel_tracing::sqlite::query", "created migrations table");
+            }
+            Span::current().record("db.migrations_table.created", !exists);
+
+            Ok(!exists)
+        }
     }
 }
 
 impl InstrumentedSqliteConnection {
-    #[instrument(fields(db.system="sqlite", otel.kind="client"), skip(self, f))]
+    /// Run a transaction with `BEGIN IMMEDIATE`. The closure is passed this
+    /// connection, so the queries it runs are traced.
+    ///
+    /// # Errors
+    ///
+    /// Returns any error from beginning or committing the transaction, or
+    /// from the closure itself.
     pub fn immediate_transaction<T, E, F>(&mut self, f: F) -> Result<T, E>
     where
-        F: FnOnce(&mut SqliteConnection) -> Result<T, E>,
+        F: FnOnce(&mut Self) -> Result<T, E>,
         E: From<diesel::result::Error>,
     {
-        self.inner.immediate_transaction(f)
+        if !self.config.trace_transactions {
+            return self.transaction_sql(f, "BEGIN IMMEDIATE");
+        }
+        let (sampled, outer) = self.sampler.begin_transaction();
+        let result = if sampled {
+            self.traced_immediate_transaction(f)
+        } else {
+            self.transaction_sql(f, "BEGIN IMMEDIATE")
+        };
+        self.sampler.end_transaction(outer);
+        result
     }
 
-    #[instrument(fields(db.system="sqlite", otel.kind="client"), skip(self, f))]
+    /// Run a transaction with `BEGIN EXCLUSIVE`. The closure is passed this
+    /// connection, so the queries it runs are traced.
+    ///
+    /// # Errors
+    ///
+    /// Returns any error from beginning or committing the transaction, or
+    /// from the closure itself.
     pub fn exclusive_transaction<T, E, F>(&mut self, f: F) -> Result<T, E>
     where
-        F: FnOnce(&mut SqliteConnection) -> Result<T, E>,
+        F: FnOnce(&mut Self) -> Result<T, E>,
         E: From<diesel::result::Error>,
     {
-        self.inner.exclusive_transaction(f)
+        if !self.config.trace_transactions {
+            return self.transaction_sql(f, "BEGIN EXCLUSIVE");
+        }
+        let (sampled, outer) = self.sampler.begin_transaction();
+        let result = if sampled {
+            self.traced_exclusive_transaction(f)
+        } else {
+            self.transaction_sql(f, "BEGIN EXCLUSIVE")
+        };
+        self.sampler.end_transaction(outer);
+        result
     }
 
-    #[doc(hidden)]
-    #[instrument(fields(db.system="sqlite", otel.kind="client"), skip(self, f))]
-    pub fn register_sql_function<ArgsSqlType, RetSqlType, Args, Ret, F>(
-        &mut self,
-        fn_name: &str,
-        deterministic: bool,
-        f: F,
-    ) -> QueryResult<()>
+    /// Run a transaction begun with `sql` as diesel's
+    /// `SqliteConnection::immediate_transaction` does, but through this
+    /// connection rather than the inner one.
+    fn transaction_sql<T, E, F>(&mut self, f: F, sql: &str) -> Result<T, E>
     where
-        F: FnMut(Args) -> Ret + std::panic::UnwindSafe + Send + 'static,
-        Args: FromSqlRow<ArgsSqlType, Sqlite> + StaticallySizedRow<ArgsSqlType, Sqlite>,
-        Ret: ToSql<RetSqlType, Sqlite>,
-        Sqlite: HasSqlType<RetSqlType>,
+        F: FnOnce(&mut Self) -> Result<T, E>,
+        E: From<diesel::result::Error>,
     {
-        self.inner.register_sql_function(fn_name, deterministic, f)
+        AnsiTransactionManager::begin_transaction_sql(&mut *self, sql)?;
+        match f(&mut *self) {
+            Ok(value) => {
+                AnsiTransactionManager::commit_transaction(&mut *self)?;
+                Ok(value)
+            }
+            Err(error) => {
+                AnsiTransactionManager::rollback_transaction(&mut *self)?;
+                Err(error)
+            }
+        }
     }
-}
 
-impl<'b, Changes, Output> UpdateAndFetchResults<Changes, Output> for InstrumentedSqliteConnection
-where
-    Changes: Copy + Identifiable,
-    Changes: AsChangeset<Target = <Changes as HasTable>::Table> + IntoUpdateTarget,
-    Changes::Table: FindDsl<Changes::Id>,
-    Update<Changes, Changes>: ExecuteDsl<SqliteConnection>,
-    Find<Changes::Table, Changes::Id>: LoadQuery<'b, SqliteConnection, Output>,
-    <Changes::Table as Table>::AllColumns: ValidGrouping<()>,
-    <<Changes::Table as Table>::AllColumns as ValidGrouping<()>>::IsAggregate:
-        MixedAggregates<is_aggregate::No, Output = is_aggregate::No>,
-{
-    fn update_and_fetch(&mut self, changeset: Changes) -> QueryResult<Output> {
-        debug!("updating and fetching changeset");
-        self.inner.update_and_fetch(changeset)
+    /// Replace the configuration controlling which spans are created.
+    pub fn set_tracing_config(&mut self, config: TracingConfig) {
+        self.config = config;
+    }
+
+    /// Make the connection read only, or writable again, by setting
+    /// `PRAGMA query_only` for the session.
+    ///
+    /// While the connection is read only its spans record
+    /// `db.connection.read_only`, and a statement which writes is logged as a
+    /// warning before it is sent, so that the call site is traced even though
+    /// the database rejects it. See [`Self::set_strict_read_only`] to reject
+    /// it without sending it.
+    ///
+    /// # Errors
+    ///
+    /// Returns the error of the statement changing the setting, leaving the
+    /// connection as it was.
+    pub fn set_read_only(&mut self, read_only: bool) -> QueryResult<()> {
+        self.batch_execute(if read_only {
+            "PRAGMA query_only = ON"
+        } else {
+            "PRAGMA query_only = OFF"
+        })?;
+        self.read_only.enabled = read_only;
+        Ok(())
+    }
+
+    /// Whether the connection was made read only with [`Self::set_read_only`].
+    #[must_use]
+    pub fn is_read_only(&self) -> bool {
+        self.read_only.enabled
+    }
+
+    /// Fail statements which write on a read only connection with an error,
+    /// rather than sending them to be rejected by the database.
+    pub fn set_strict_read_only(&mut self, strict: bool) {
+        self.read_only.strict = strict;
+    }
+
+    /// Log a warning while a transaction is still open once it has been open
+    /// for longer than `after`, so that long running transactions can be
+    /// caught before they finish. Its `transaction` span records how long it
+    /// took as `db.transaction.duration_ms` and the number of queries it ran
+    /// as `db.transaction.queries`. Disabled by default.
+    pub fn set_transaction_warn_after(&mut self, after: Duration) {
+        self.transaction_warn.after = Some(after);
+    }
+
+    /// Log a warning while a transaction is still open once it has run more
+    /// than `query_count` queries, like
+    /// [`set_transaction_warn_after`](Self::set_transaction_warn_after).
+    pub fn set_transaction_warn_query_count(&mut self, query_count: usize) {
+        self.transaction_warn.query_count = Some(query_count);
+    }
+
+    /// Replace every instrumentation installed on the connection with
+    /// `instrumentation`. `set_instrumentation` instead pushes onto the
+    /// connection's
+    /// [`InstrumentationStack`](crate::instrumentation::InstrumentationStack),
+    /// so that each instrumentation set receives its events.
+    pub fn replace_instrumentation(&mut self, instrumentation: impl Instrumentation) {
+        let stack = SharedInstrumentationStack::new(instrumentation);
+        self.inner.set_instrumentation(stack.clone());
+        self.instrumentation_stack = Some(stack);
+    }
+
+    /// The configuration controlling which spans are created.
+    #[must_use]
+    pub fn tracing_config(&self) -> TracingConfig {
+        self.config
+    }
+
+    /// Create spans for only the given fraction of queries, between 0 and 1,
+    /// to reduce the cost of tracing busy connections. The default of 1
+    /// traces every query.
+    ///
+    /// Queries which are sampled out still log an event if they fail, at the
+    /// level given to the error by the `error_levels` of the connection's
+    /// [`TracingConfig`]. The queries of a transaction are sampled together
+    /// with the transaction.
+    pub fn set_query_sample_rate(&mut self, rate: f32) {
+        self.sampler.set_rate(rate);
+    }
+
+    /// The fraction of queries which create spans.
+    #[must_use]
+    pub fn query_sample_rate(&self) -> f32 {
+        self.sampler.rate()
+    }
+
+    /// Retry `execute_returning_count` and `batch_execute` according to
+    /// `policy` when the database is locked by another connection.
+    ///
+    /// `load` is not retried, as sqlite only reports that the database is
+    /// locked once the returned rows are iterated, and neither is a
+    /// `batch_execute` of more than one statement, as the statements before
+    /// the one which found the database locked have already run.
+    pub fn set_busy_retry(&mut self, policy: BusyRetryPolicy) {
+        self.busy_retry = policy;
+    }
+
+    /// The policy for retrying statements when the database is locked.
+    #[must_use]
+    pub fn busy_retry(&self) -> BusyRetryPolicy {
+        self.busy_retry
+    }
+
+    /// Log the `EXPLAIN QUERY PLAN` of every `execute_returning_count`
+    /// statement which takes longer than the configured
+    /// [`TracingConfig::slow_query_threshold`], as a warning.
+    ///
+    /// The statement is planned again but not run, so explaining costs the
+    /// time to plan it a second time, and is off by default. Only
+    /// `SELECT`, `INSERT`, `UPDATE` and `DELETE` statements are explained,
+    /// and `load` is not, as the rows it returns borrow the connection.
+    #[cfg(feature = "statement-fields")]
+    pub fn set_explain_on_slow(&mut self, explain_on_slow: bool) {
+        self.explain_on_slow = explain_on_slow;
+    }
+
+    /// Establish a connection labelled with a name, such as `"replica"`, which
+    /// is recorded as `db.connection.name` on its spans.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the connection could not be established.
+    pub fn establish_named(database_url: &str, name: impl Into<String>) -> ConnectionResult<Self> {
+        let mut conn = Self::establish(database_url)?;
+        conn.set_connection_name(name);
+        Ok(conn)
+    }
+
+    /// Establish a connection and run session settings on it, such as
+    /// `SET statement_timeout = '5s'`, before it is used.
+    ///
+    /// Each statement is run in order through the traced `batch_execute`,
+    /// inside an `establish_with_init` span, and a debug event with its index
+    /// as `db.init.index` is emitted for each.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the connection could not be established, or one
+    /// naming the first init statement which failed.
+    #[cfg_attr(
+        not(feature = "passthrough"),
+        tracing::instrument(
+            target = "diesel_tracing::sqlite::connection",
+            fields(
+                db.system="sqlite",
+                otel.kind="client",
+                otel.name=establish_span_name("establish_with_init"),
+                db.init.count=init_statements.len(),
+            ),
+            skip(database_url, init_statements),
+            err,
+        )
+    )]
+    pub fn establish_with_init(
+        database_url: &str,
+        init_statements: &[&str],
+    ) -> ConnectionResult<Self> {
+        let mut conn = Self::establish(database_url)?;
+        run_init_statements(&mut conn, init_statements)?;
+        Ok(conn)
+    }
+
+    /// Label the connection with a name, which is recorded as
+    /// `db.connection.name` on its spans.
+    pub fn set_connection_name(&mut self, name: impl Into<String>) {
+        let name = name.into();
+        self.connection_span
+            .record("db.connection.name", name.as_str());
+        self.connection_name = Some(name);
+    }
+
+    /// The name the connection is labelled with, if any.
+    #[must_use]
+    pub fn connection_name(&self) -> Option<&str> {
+        self.connection_name.as_deref()
+    }
+
+    /// Send an [`AuditEntry`](crate::audit::AuditEntry) to the sink for
+    /// every statement which writes, whether or not it is sampled for
+    /// tracing.
+    #[cfg(feature = "audit")]
+    pub fn set_audit_sink(&mut self, sink: Arc<dyn AuditSink>) {
+        self.audit_sink = Some(sink);
+    }
+
+    /// Start auditing a statement, if the connection has an audit sink and
+    /// the statement writes.
+    #[cfg(feature = "audit")]
+    fn start_audit(&self, sql: impl FnOnce() -> Option<String>) -> Option<PendingAudit> {
+        let sink = self.audit_sink.as_ref()?;
+        PendingAudit::start(sink, "sqlite", self.connection_name.as_deref(), &sql()?)
+    }
+
+    instrument_method! {
+        connection = self,
+        instrument(
+            name = "transaction_state",
+            fields(
+                otel.name=span_name(self.span_namer.as_ref(), "transaction_state"),
+            ),
+            target = "diesel_tracing::sqlite::connection",
+            skip(self),
+        );
+        fn traced_transaction_state(&mut self) -> &mut AnsiTransactionManager {
+            self.inner.transaction_state()
+        }
+    }
+
+    instrument_method! {
+        connection = self,
+        instrument(
+            name = "instrumentation",
+            fields(
+                otel.name=span_name(self.span_namer.as_ref(), "instrumentation"),
+            ),
+            target = "diesel_tracing::sqlite::connection",
+            skip(self),
+        );
+        fn traced_instrumentation(&mut self) -> &mut dyn Instrumentation {
+            self.inner.instrumentation()
+        }
+    }
+
+    fn sampled_batch_execute(&mut self, query: &str) -> QueryResult<()> {
+        self.connection_span.count_query();
+        let traced = self.config.trace_batch;
+        let levels = self.config.error_levels;
+        if !traced || !self.sampler.sample() {
+            let policy = self.busy_retry;
+            return measure("sqlite", || {
+                retry_locked_batch(policy, query, || self.inner.batch_execute(query))
+            })
+            .inspect_err(|error| {
+                if traced {
+                    sampled_out_error_event!(
+                        "diesel_tracing::sqlite::query",
+                        "sqlite",
+                        levels,
+                        error
+                    );
+                }
+            });
+        }
+        self.traced_batch_execute(query)
+    }
+
+    fn sampled_execute_returning_count<T>(&mut self, source: &T) -> QueryResult<usize>
+    where
+        T: QueryFragment<Sqlite> + QueryId,
+    {
+        self.connection_span.count_query();
+        let traced = self.config.trace_execute;
+        let levels = self.config.error_levels;
+        if !traced || !self.sampler.sample() {
+            let policy = self.busy_retry;
+            return measure("sqlite", || {
+                retry_locked(policy, || self.inner.execute_returning_count(source))
+            })
+            .inspect_err(|error| {
+                if traced {
+                    sampled_out_error_event!(
+                        "diesel_tracing::sqlite::query",
+                        "sqlite",
+                        levels,
+                        error
+                    );
+                }
+            });
+        }
+        self.traced_execute_returning_count(source)
+    }
+
+    /// Attach static fields, such as a tenant or shard identifier, to every
+    /// span of the connection, replacing any attached before.
+    ///
+    /// Span field names are fixed when the span is declared, so the fields
+    /// are recorded together as `db.extra`, formatted as comma separated
+    /// `key=value` pairs, when each span is created. Passing no fields
+    /// removes them, recording `db.extra` as empty on the connection span,
+    /// since a field recorded on a span cannot be unset.
+    pub fn with_extra_fields(&mut self, fields: Vec<(String, String)>) {
+        self.extra_fields = format_extra_fields(fields);
+        self.connection_span
+            .record("db.extra", self.extra_fields.as_deref().unwrap_or_default());
+    }
+
+    /// Name the spans of the connection with a [`SpanNamer`], which records
+    /// the names it gives as `otel.name`.
+    #[must_use]
+    pub fn with_span_namer<N: SpanNamer>(mut self, namer: N) -> Self {
+        self.span_namer = Box::new(namer);
+        self
+    }
+
+    /// Run `SELECT 1` on the connection, traced as a `batch_execute`, and
+    /// report whether it succeeded.
+    pub fn is_healthy(&mut self) -> bool {
+        self.batch_execute("SELECT 1").is_ok()
+    }
+
+    /// The wrapped diesel connection.
+    #[must_use]
+    pub fn inner(&self) -> &SqliteConnection {
+        &self.inner
+    }
+
+    /// The wrapped diesel connection, for calling APIs this wrapper does not
+    /// expose. Queries run on it directly are not traced.
+    pub fn inner_mut(&mut self) -> &mut SqliteConnection {
+        &mut self.inner
+    }
+
+    /// Recover the instrumented connection from a type erased
+    /// `dyn BoxableConnection<Sqlite>`, or `None` if it holds another type of
+    /// connection.
+    pub fn downcast_from(conn: &mut dyn BoxableConnection<Sqlite>) -> Option<&mut Self> {
+        conn.as_any_mut().downcast_mut()
+    }
+
+    /// Unwrap the diesel connection, ending the connection's span.
+    #[must_use]
+    pub fn into_inner(self) -> SqliteConnection {
+        self.inner
+    }
+
+    instrument_method! {
+        connection = self,
+        #[cfg(feature = "statement-fields")]
+        instrument(
+            name = "batch_execute",
+            fields(
+                otel.name=span_name(self.span_namer.as_ref(), "batch_execute"),
+                db.operation=field::Empty,
+                db.sql.table=field::Empty,
+                db.access_mode=field::Empty,
+                db.statement_fingerprint=field::Empty,
+                db.statement.hash=field::Empty,
+                db.error.kind=field::Empty,
+                db.error.table=field::Empty,
+                db.error.constraint=field::Empty,
+                otel.status_code=field::Empty,
+                otel.status_message=field::Empty,
+                db.batch.size=field::Empty,
+                db.batch.error_index=field::Empty,
+                db.sqlite.retries=field::Empty,
+                db.statement=truncate_statement(
+                    query.to_owned(),
+                    self.config.max_statement_length(),
+                ),
+            ),
+            target = "diesel_tracing::sqlite::query",
+            skip(self, query),
+        ),
+        #[cfg(not(feature = "statement-fields"))]
+        instrument(
+            name = "batch_execute",
+            fields(
+                otel.name=span_name(self.span_namer.as_ref(), "batch_execute"),
+                db.operation=field::Empty,
+                db.sql.table=field::Empty,
+                db.access_mode=field::Empty,
+                db.statement_fingerprint=field::Empty,
+                db.statement.hash=field::Empty,
+                db.error.kind=field::Empty,
+                db.error.table=field::Empty,
+                db.error.constraint=field::Empty,
+                otel.status_code=field::Empty,
+                otel.status_message=field::Empty,
+                db.batch.size=field::Empty,
+                db.batch.error_index=field::Empty,
+                db.sqlite.retries=field::Empty,
+            ),
+            target = "diesel_tracing::sqlite::query",
+            skip(self, query),
+        );
+        fn traced_batch_execute(&mut self, query: &str) -> QueryResult<()> {
+            record_statement_summary(
+                query,
+                name_by_statement(self.config, self.span_namer.as_ref(), "batch_execute"),
+            );
+            record_batch(query);
+            let policy = self.busy_retry;
+            measure("sqlite", || {
+                retry_locked_batch(policy, query, || self.inner.batch_execute(query))
+            })
+            .inspect_err(|error| {
+                record_error(&tracing::Span::current(), error);
+                error_event!(
+                    "diesel_tracing::sqlite::query",
+                    self.config.error_levels,
+                    error
+                );
+                record_batch_error(query, error);
+            })?;
+
+            Ok(())
+        }
+    }
+
+    /// Run the closure inside a savepoint with the given name, releasing it
+    /// if the closure returns `Ok` and rolling back to it otherwise, without
+    /// ending the enclosing transaction. The `savepoint` span records the
+    /// name as `db.transaction.savepoint`, making nested transactions easier
+    /// to tell apart than diesel's numbered savepoints.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the name is not a plain identifier, any error from
+    /// creating, releasing or rolling back to the savepoint, or the error
+    /// from the closure.
+    pub fn savepoint<T, E, F>(&mut self, name: &str, f: F) -> Result<T, E>
+    where
+        F: FnOnce(&mut Self) -> Result<T, E>,
+        E: From<diesel::result::Error>,
+    {
+        if !self.config.trace_transactions || !self.sampler.sample() {
+            return run_savepoint(self, name, f);
+        }
+        self.traced_savepoint(name, f)
+    }
+
+    instrument_method! {
+        connection = self,
+        instrument(
+            name = "insert_in_chunks",
+            fields(
+                otel.name=span_name(self.span_namer.as_ref(), "insert_in_chunks"),
+                db.operation="INSERT",
+                db.batch.chunks=field::Empty,
+                db.batch.total_rows=values.len(),
+                db.batch.rows_committed=field::Empty,
+                db.rows_affected=field::Empty,
+            ),
+            target = "diesel_tracing::sqlite::query",
+            skip(self, table, values),
+            err,
+        );
+        /// Insert rows in chunks of at most `chunk_size`, keeping each statement
+        /// within the sqlite limit of 32766 bind parameters, and return the number
+        /// of rows inserted.
+        ///
+        /// The insert is traced by an `insert_in_chunks` span recording
+        /// `db.batch.chunks` and `db.batch.total_rows`, with an `insert_chunk`
+        /// span for each chunk recording its `db.batch.index` and `db.batch.size`.
+        /// Chunks are not inserted in a transaction, so when one fails the rows of
+        /// the chunks before it remain, and their number is recorded as
+        /// `db.batch.rows_committed`.
+        ///
+        /// # Errors
+        ///
+        /// Returns an error, before inserting anything, if `chunk_size` is zero or
+        /// a chunk would exceed the bind parameter limit, or else the error of the
+        /// first chunk which fails.
+        pub fn insert_in_chunks<T, V>(
+            &mut self,
+            table: T,
+            values: Vec<V>,
+            chunk_size: usize,
+        ) -> QueryResult<usize>
+        where
+            T: Table + Copy,
+            Vec<V>: Insertable<T>,
+            InsertStatement<T, <Vec<V> as Insertable<T>>::Values>: ExecuteDsl<Self>,
+        {
+            chunks::insert_in_chunks(self, table, values, chunk_size, |index, size| {
+                info_span!(
+                    target: "diesel_tracing::sqlite::query",
+                    "insert_chunk",
+                    db.batch.index = index,
+                    db.batch.size = size,
+                )
+            })
+        }
+    }
+
+    instrument_method! {
+        connection = self,
+        instrument(
+            name = "load_chunked",
+            fields(
+                otel.name=span_name(self.span_namer.as_ref(), "load_chunked"),
+                db.batch.chunks=field::Empty,
+                db.batch.total_rows=field::Empty,
+            ),
+            target = "diesel_tracing::sqlite::query",
+            skip(self, query, f),
+            err,
+        );
+        /// Load the rows of a query in chunks of at most `chunk_size`, passing
+        /// each chunk to `f` as it is read, and return the number of rows loaded.
+        ///
+        /// The load is traced by a `load_chunked` span recording `db.batch.chunks`
+        /// and `db.batch.total_rows`, with a `load_chunk` span around each call of
+        /// `f` recording the chunk's `db.batch.index` and `db.batch.size`.
+        ///
+        /// # Errors
+        ///
+        /// Returns an error if `chunk_size` is zero, or else the first error of
+        /// the query or of `f`, which stops the load.
+        pub fn load_chunked<'conn, 'query, U, Q>(
+            &'conn mut self,
+            query: Q,
+            chunk_size: usize,
+            f: impl FnMut(Vec<U>) -> QueryResult<()>,
+        ) -> QueryResult<usize>
+        where
+            Q: LoadQuery<'query, Self, U, DefaultLoadingMode> + 'conn,
+            U: 'conn,
+        {
+            let chunk_span = |index, size| {
+                info_span!(
+                    target: "diesel_tracing::sqlite::query",
+                    "load_chunk",
+                    db.batch.index = index,
+                    db.batch.size = size,
+                )
+            };
+            chunks::load_chunked::<_, DefaultLoadingMode, _, _, _>(
+                self, query, chunk_size, chunk_span, f,
+            )
+        }
+    }
+
+    instrument_method! {
+        connection = self,
+        instrument(
+            name = "savepoint",
+            fields(
+                otel.name=span_name(self.span_namer.as_ref(), "savepoint"),
+                db.transaction.savepoint=name,
+                db.transaction.rolled_back=field::Empty,
+                otel.status_code=field::Empty,
+            ),
+            target = "diesel_tracing::sqlite::transaction",
+            skip(self, name, f),
+        );
+        fn traced_savepoint<T, E, F>(&mut self, name: &str, f: F) -> Result<T, E>
+        where
+            F: FnOnce(&mut Self) -> Result<T, E>,
+            E: From<diesel::result::Error>,
+        {
+            let result = run_savepoint(self, name, f);
+            record_transaction_outcome(&result);
+
+            result
+        }
+    }
+
+    instrument_method! {
+        connection = self,
+        instrument(
+            name = "transaction",
+            fields(
+                otel.name=span_name(self.span_namer.as_ref(), "transaction"),
+                db.transaction.depth=field::Empty,
+                db.transaction.rolled_back=field::Empty,
+                db.transaction.duration_ms=field::Empty,
+                db.transaction.queries=field::Empty,
+                otel.status_code=field::Empty,
+            ),
+            target = "diesel_tracing::sqlite::transaction",
+            skip(self, f),
+        );
+        fn traced_transaction<T, E, F>(&mut self, f: F) -> Result<T, E>
+        where
+            F: FnOnce(&mut Self) -> Result<T, E>,
+            E: From<diesel::result::Error>,
+        {
+            record_transaction_depth(AnsiTransactionManager::transaction_manager_status_mut(self));
+            let stack = self.instrumentation_stack.clone();
+            let thresholds = self.transaction_warn;
+            let result = AnsiTransactionManager::transaction(self, |conn| {
+                watch_transaction(stack.as_ref(), thresholds, || f(conn))
+            });
+            record_transaction_outcome(&result);
+
+            result
+        }
+    }
+
+    instrument_method! {
+        connection = self,
+        #[cfg(feature = "statement-fields")]
+        instrument(
+            name = "execute_returning_count",
+            fields(
+                otel.name=span_name(self.span_namer.as_ref(), "execute_returning_count"),
+                db.operation=field::Empty,
+                db.sql.table=field::Empty,
+                db.access_mode=field::Empty,
+                db.statement_fingerprint=field::Empty,
+                db.statement.hash=field::Empty,
+                db.bind_count=field::Empty,
+                code.function=field::Empty,
+                code.filepath=field::Empty,
+                code.lineno=field::Empty,
+                db.error.kind=field::Empty,
+                db.error.table=field::Empty,
+                db.error.constraint=field::Empty,
+                otel.status_code=field::Empty,
+                otel.status_message=field::Empty,
+                db.statement.cached=field::Empty,
+                db.rows_affected=field::Empty,
+                db.sqlite.retries=field::Empty,
+                db.statement=truncate_statement(
+                    diesel::debug_query::<Sqlite, _>(source).to_string(),
+                    self.config.max_statement_length(),
+                ),
+            ),
+            target = "diesel_tracing::sqlite::query",
+            skip(self, source),
+        ),
+        #[cfg(not(feature = "statement-fields"))]
+        instrument(
+            name = "execute_returning_count",
+            fields(
+                otel.name=span_name(self.span_namer.as_ref(), "execute_returning_count"),
+                db.operation=field::Empty,
+                db.sql.table=field::Empty,
+                db.access_mode=field::Empty,
+                db.statement_fingerprint=field::Empty,
+                db.statement.hash=field::Empty,
+                db.bind_count=field::Empty,
+                code.function=field::Empty,
+                code.filepath=field::Empty,
+                code.lineno=field::Empty,
+                db.error.kind=field::Empty,
+                db.error.table=field::Empty,
+                db.error.constraint=field::Empty,
+                otel.status_code=field::Empty,
+                otel.status_message=field::Empty,
+                db.statement.cached=field::Empty,
+                db.rows_affected=field::Empty,
+                db.sqlite.retries=field::Empty,
+            ),
+            target = "diesel_tracing::sqlite::query",
+            skip(self, source),
+        );
+        fn traced_execute_returning_count<T>(&mut self, source: &T) -> QueryResult<usize>
+        where
+            T: QueryFragment<Sqlite> + QueryId,
+        {
+            record_query_summary::<Sqlite, _>(
+                source,
+                name_by_statement(
+                    self.config,
+                    self.span_namer.as_ref(),
+                    "execute_returning_count",
+                ),
+            );
+            let policy = self.busy_retry;
+            #[cfg(all(feature = "statement-fields", not(feature = "passthrough")))]
+            let started = std::time::Instant::now();
+            let cache_probe =
+                StatementCacheProbe::start(self.instrumentation_stack.as_ref(), source, &Sqlite);
+            let result = measure("sqlite", || {
+                retry_locked(policy, || self.inner.execute_returning_count(source))
+            });
+            if let Some(probe) = cache_probe {
+                probe.record(&tracing::Span::current());
+            }
+            let count = result.inspect_err(|error| {
+                record_error(&tracing::Span::current(), error);
+                error_event!(
+                    "diesel_tracing::sqlite::query",
+                    self.config.error_levels,
+                    error
+                );
+            })?;
+            tracing::Span::current().record("db.rows_affected", count);
+            #[cfg(all(feature = "statement-fields", not(feature = "passthrough")))]
+            self.explain_if_slow(source, started.elapsed());
+
+            Ok(count)
+        }
+    }
+
+    /// Log the plan of a statement which took `elapsed`, if explaining slow
+    /// queries is enabled and it was slow.
+    #[cfg(all(feature = "statement-fields", not(feature = "passthrough")))]
+    fn explain_if_slow<T>(&mut self, source: &T, elapsed: Duration)
+    where
+        T: QueryFragment<Sqlite> + QueryId,
+    {
+        if !self.explain_on_slow
+            || !is_slow(self.config.slow_query_threshold, elapsed)
+            || !is_explainable(&diesel::debug_query::<Sqlite, _>(source).to_string())
+        {
+            return;
+        }
+        let plan = Explain::new("EXPLAIN QUERY PLAN", source)
+            .load::<QueryPlanStep>(&mut self.inner)
+            .map(|steps| {
+                let details: Vec<_> = steps.into_iter().map(|step| step.detail).collect();
+                details.join("\n")
+            });
+        record_plan(plan, elapsed, self.config.max_statement_length());
+    }
+
+    instrument_method! {
+        connection = self,
+        #[cfg(feature = "statement-fields")]
+        instrument(
+            name = "load",
+            fields(
+                otel.name=span_name(self.span_namer.as_ref(), "load"),
+                db.operation=field::Empty,
+                db.sql.table=field::Empty,
+                db.access_mode=field::Empty,
+                db.statement_fingerprint=field::Empty,
+                db.statement.hash=field::Empty,
+                db.bind_count=field::Empty,
+                code.function=field::Empty,
+                code.filepath=field::Empty,
+                code.lineno=field::Empty,
+                db.error.kind=field::Empty,
+                db.error.table=field::Empty,
+                db.error.constraint=field::Empty,
+                otel.status_code=field::Empty,
+                otel.status_message=field::Empty,
+                db.statement.cached=field::Empty,
+                db.statement=truncate_statement(
+                    diesel::debug_query(&source).to_string(),
+                    self.config.max_statement_length(),
+                ),
+            ),
+            target = "diesel_tracing::sqlite::query",
+            skip(self, source),
+        ),
+        #[cfg(not(feature = "statement-fields"))]
+        instrument(
+            name = "load",
+            fields(
+                otel.name=span_name(self.span_namer.as_ref(), "load"),
+                db.operation=field::Empty,
+                db.sql.table=field::Empty,
+                db.access_mode=field::Empty,
+                db.statement_fingerprint=field::Empty,
+                db.statement.hash=field::Empty,
+                db.bind_count=field::Empty,
+                code.function=field::Empty,
+                code.filepath=field::Empty,
+                code.lineno=field::Empty,
+                db.error.kind=field::Empty,
+                db.error.table=field::Empty,
+                db.error.constraint=field::Empty,
+                otel.status_code=field::Empty,
+                otel.status_message=field::Empty,
+                db.statement.cached=field::Empty,
+            ),
+            target = "diesel_tracing::sqlite::query",
+            skip(self, source),
+        );
+        fn traced_load<'conn, 'query, T>(
+            &'conn mut self,
+            source: T,
+        ) -> QueryResult<<Self as LoadConnection<DefaultLoadingMode>>::Cursor<'conn, 'query>>
+        where
+            T: Query + QueryFragment<Sqlite> + QueryId + 'query,
+            Sqlite: QueryMetadata<T::SqlType>,
+        {
+            record_query_summary::<Sqlite, _>(
+                &source,
+                name_by_statement(self.config, self.span_namer.as_ref(), "load"),
+            );
+            let cache_probe =
+                StatementCacheProbe::start(self.instrumentation_stack.as_ref(), &source, &Sqlite);
+            let levels = self.config.error_levels;
+            let result = measure("sqlite", move || self.inner.load(source));
+            if let Some(probe) = cache_probe {
+                probe.record(&tracing::Span::current());
+            }
+            result.inspect_err(|error| {
+                record_error(&tracing::Span::current(), error);
+                error_event!("diesel_tracing::sqlite::query", levels, error);
+            })
+        }
+    }
+
+    instrument_method! {
+        connection = self,
+        instrument(
+            name = "immediate_transaction",
+            fields(
+                otel.name=span_name(self.span_namer.as_ref(), "immediate_transaction"),
+                db.transaction.depth=field::Empty,
+                db.transaction.rolled_back=field::Empty,
+                db.transaction.duration_ms=field::Empty,
+                db.transaction.queries=field::Empty,
+                otel.status_code=field::Empty,
+            ),
+            target = "diesel_tracing::sqlite::transaction",
+            skip(self, f),
+        );
+        fn traced_immediate_transaction<T, E, F>(&mut self, f: F) -> Result<T, E>
+        where
+            F: FnOnce(&mut Self) -> Result<T, E>,
+            E: From<diesel::result::Error>,
+        {
+            record_transaction_depth(AnsiTransactionManager::transaction_manager_status_mut(
+                &mut self.inner,
+            ));
+            let stack = self.instrumentation_stack.clone();
+            let thresholds = self.transaction_warn;
+            let result = self.transaction_sql(
+                |conn| watch_transaction(stack.as_ref(), thresholds, || f(conn)),
+                "BEGIN IMMEDIATE",
+            );
+            record_transaction_outcome(&result);
+
+            result
+        }
+    }
+
+    instrument_method! {
+        connection = self,
+        instrument(
+            name = "exclusive_transaction",
+            fields(
+                otel.name=span_name(self.span_namer.as_ref(), "exclusive_transaction"),
+                db.transaction.depth=field::Empty,
+                db.transaction.rolled_back=field::Empty,
+                db.transaction.duration_ms=field::Empty,
+                db.transaction.queries=field::Empty,
+                otel.status_code=field::Empty,
+            ),
+            target = "diesel_tracing::sqlite::transaction",
+            skip(self, f),
+        );
+        fn traced_exclusive_transaction<T, E, F>(&mut self, f: F) -> Result<T, E>
+        where
+            F: FnOnce(&mut Self) -> Result<T, E>,
+            E: From<diesel::result::Error>,
+        {
+            record_transaction_depth(AnsiTransactionManager::transaction_manager_status_mut(
+                &mut self.inner,
+            ));
+            let stack = self.instrumentation_stack.clone();
+            let thresholds = self.transaction_warn;
+            let result = self.transaction_sql(
+                |conn| watch_transaction(stack.as_ref(), thresholds, || f(conn)),
+                "BEGIN EXCLUSIVE",
+            );
+            record_transaction_outcome(&result);
+
+            result
+        }
+    }
+
+    instrument_method! {
+        connection = self,
+        instrument(
+            fields(
+                otel.name=span_name(self.span_namer.as_ref(), "register_sql_function"),
+            ),
+            target = "diesel_tracing::sqlite::connection",
+            skip(self, f),
+        );
+        #[doc(hidden)]
+        pub fn register_sql_function<ArgsSqlType, RetSqlType, Args, Ret, F>(
+            &mut self,
+            fn_name: &str,
+            deterministic: bool,
+            f: F,
+        ) -> QueryResult<()>
+        where
+            F: FnMut(Args) -> Ret + std::panic::UnwindSafe + Send + 'static,
+            Args: FromSqlRow<ArgsSqlType, Sqlite> + StaticallySizedRow<ArgsSqlType, Sqlite>,
+            Ret: ToSql<RetSqlType, Sqlite>,
+            Sqlite: HasSqlType<RetSqlType>,
+        {
+            if cfg!(feature = "passthrough") {
+                return self.inner.register_sql_function(fn_name, deterministic, f);
+            }
+
+            self.inner.register_sql_function(
+                fn_name,
+                deterministic,
+                log_function_panics(fn_name.to_owned(), f),
+            )?;
+            debug!(
+                target: "diesel_tracing::sqlite::connection",
+                {
+                    db.sqlite.function = fn_name,
+                    db.sqlite.deterministic = deterministic,
+                },
+                "registered sql function",
+            );
+
+            Ok(())
+        }
+    }
+
+    instrument_method! {
+        connection = self,
+        instrument(
+            fields(
+                otel.name=span_name(self.span_namer.as_ref(), "register_aggregate_function"),
+                db.function_name=fn_name,
+            ),
+            target = "diesel_tracing::sqlite::connection",
+            skip(self),
+            err,
+        );
+        #[doc(hidden)]
+        pub fn register_aggregate_function<ArgsSqlType, RetSqlType, Args, Ret, A>(
+            &mut self,
+            fn_name: &str,
+        ) -> QueryResult<()>
+        where
+            A: SqliteAggregateFunction<Args, Output = Ret> + 'static + Send + std::panic::UnwindSafe,
+            Args: FromSqlRow<ArgsSqlType, Sqlite> + StaticallySizedRow<ArgsSqlType, Sqlite>,
+            Ret: ToSql<RetSqlType, Sqlite>,
+            Sqlite: HasSqlType<RetSqlType>,
+        {
+            self.inner
+                .register_aggregate_function::<ArgsSqlType, RetSqlType, Args, Ret, A>(fn_name)
+        }
+    }
+
+    instrument_method! {
+        connection = self,
+        instrument(
+            fields(
+                otel.name=span_name(self.span_namer.as_ref(), "register_collation"),
+                db.collation_name=collation_name,
+            ),
+            target = "diesel_tracing::sqlite::connection",
+            skip(self, collation),
+            err,
+        );
+        /// Register a collation function, see
+        /// [`SqliteConnection::register_collation`].
+        ///
+        /// # Errors
+        ///
+        /// Returns an error if sqlite fails to register the collation.
+        pub fn register_collation<F>(&mut self, collation_name: &str, collation: F) -> QueryResult<()>
+        where
+            F: Fn(&str, &str) -> std::cmp::Ordering + Send + 'static + std::panic::UnwindSafe,
+        {
+            self.inner.register_collation(collation_name, collation)
+        }
+    }
+
+    instrument_method! {
+        connection = self,
+        instrument(
+            fields(
+                otel.name=span_name(self.span_namer.as_ref(), "set_pragma"),
+                db.operation="PRAGMA",
+                db.sqlite.pragma=name,
+            ),
+            target = "diesel_tracing::sqlite::query",
+            skip(self, value),
+            err,
+        );
+        /// Set a pragma, as `PRAGMA <name> = <value>`.
+        ///
+        /// Values other than keywords and numbers are quoted as string literals.
+        ///
+        /// # Errors
+        ///
+        /// Returns an error if `name` is not a valid identifier, or if sqlite
+        /// fails to set the pragma.
+        pub fn set_pragma(&mut self, name: &str, value: impl std::fmt::Display) -> QueryResult<()> {
+            if !is_identifier(name) {
+                return Err(Error::QueryBuilderError(
+                    format!("invalid pragma name {name:?}").into(),
+                ));
+            }
+            let value = pragma_value(&value.to_string());
+            self.inner
+                .batch_execute(&format!("PRAGMA {name} = {value}"))
+        }
+    }
+
+    instrument_method! {
+        connection = self,
+        instrument(
+            fields(
+                otel.name=span_name(self.span_namer.as_ref(), "journal_mode"),
+                db.operation="PRAGMA",
+                db.sqlite.pragma="journal_mode",
+            ),
+            target = "diesel_tracing::sqlite::query",
+            skip(self),
+            err,
+        );
+        /// The journal mode of the main database, such as `wal` or `delete`.
+        ///
+        /// # Errors
+        ///
+        /// Returns an error if sqlite fails to read the pragma.
+        pub fn journal_mode(&mut self) -> QueryResult<String> {
+            sql_query("PRAGMA journal_mode")
+                .get_result::<JournalMode>(&mut self.inner)
+                .map(|row| row.journal_mode)
+        }
+    }
+
+    instrument_method! {
+        connection = self,
+        instrument(
+            fields(
+                otel.name=span_name(self.span_namer.as_ref(), "wal_checkpoint"),
+                db.operation="PRAGMA",
+                db.sqlite.pragma="wal_checkpoint",
+                db.sqlite.checkpoint.mode=mode.as_str(),
+                db.sqlite.checkpoint.busy=field::Empty,
+                db.sqlite.checkpoint.log=field::Empty,
+                db.sqlite.checkpoint.checkpointed=field::Empty,
+            ),
+            target = "diesel_tracing::sqlite::query",
+            skip(self, mode),
+            err,
+        );
+        /// Checkpoint the write-ahead log, returning whether the checkpoint was
+        /// blocked, the number of frames in the log and the number of frames
+        /// checkpointed. The counts are recorded as `db.sqlite.checkpoint.busy`,
+        /// `db.sqlite.checkpoint.log` and `db.sqlite.checkpoint.checkpointed`.
+        ///
+        /// # Errors
+        ///
+        /// Returns an error if sqlite fails to run the checkpoint.
+        pub fn wal_checkpoint(&mut self, mode: CheckpointMode) -> QueryResult<WalCheckpoint> {
+            let checkpoint = sql_query(format!("PRAGMA wal_checkpoint({})", mode.as_str()))
+                .get_result::<WalCheckpoint>(&mut self.inner)?;
+            if !cfg!(feature = "passthrough") {
+                let span = Span::current();
+                span.record("db.sqlite.checkpoint.busy", checkpoint.busy);
+                span.record("db.sqlite.checkpoint.log", checkpoint.log);
+                span.record("db.sqlite.checkpoint.checkpointed", checkpoint.checkpointed);
+            }
+
+            Ok(checkpoint)
+        }
+    }
+
+    instrument_method! {
+        connection = self,
+        instrument(
+            fields(
+                otel.name=span_name(self.span_namer.as_ref(), "serialize_database_to_buffer"),
+                db.operation="serialize",
+                db.sqlite.buffer_size=field::Empty,
+            ),
+            target = "diesel_tracing::sqlite::connection",
+            skip(self),
+        );
+        /// Serialize the main database into a buffer, see
+        /// [`SqliteConnection::serialize_database_to_buffer`].
+        pub fn serialize_database_to_buffer(&mut self) -> SerializedDatabase {
+            let buffer = self.inner.serialize_database_to_buffer();
+            #[cfg(not(feature = "passthrough"))]
+            Span::current().record("db.sqlite.buffer_size", buffer.as_slice().len());
+
+            buffer
+        }
+    }
+
+    instrument_method! {
+        connection = self,
+        instrument(
+            fields(
+                otel.name=span_name(self.span_namer.as_ref(), "deserialize_readonly_database_from_buffer"),
+                db.operation="deserialize",
+                db.sqlite.buffer_size=data.len(),
+            ),
+            target = "diesel_tracing::sqlite::connection",
+            skip(self, data),
+            err,
+        );
+        /// Replace the database with a read only copy deserialized from a buffer,
+        /// see [`SqliteConnection::deserialize_readonly_database_from_buffer`].
+        ///
+        /// # Errors
+        ///
+        /// Returns an error if sqlite fails to deserialize the buffer.
+        pub fn deserialize_readonly_database_from_buffer(&mut self, data: &[u8]) -> QueryResult<()> {
+            self.inner.deserialize_readonly_database_from_buffer(data)
+        }
+    }
+
+    instrument_method! {
+        connection = self,
+        instrument(
+            fields(
+                otel.name=span_name(self.span_namer.as_ref(), "backup_to_file"),
+                db.operation="backup",
+                db.sqlite.buffer_size=field::Empty,
+            ),
+            target = "diesel_tracing::sqlite::connection",
+            skip(self, path),
+            err,
+        );
+        /// Serialize the main database and write it to `path`, replacing any
+        /// file there. The buffer is written to a temporary file beside `path`
+        /// which is then renamed, so `path` never holds a partial copy.
+        ///
+        /// # Errors
+        ///
+        /// Returns an error if the file could not be written or renamed.
+        pub fn backup_to_file(&mut self, path: impl AsRef<Path>) -> std::io::Result<()> {
+            let path = path.as_ref();
+            let buffer = self.serialize_database_to_buffer();
+            #[cfg(not(feature = "passthrough"))]
+            Span::current().record("db.sqlite.buffer_size", buffer.as_slice().len());
+
+            let mut temp_path = path.as_os_str().to_owned();
+            temp_path.push(".tmp");
+            let written = std::fs::File::create(&temp_path)
+                .and_then(|mut file| {
+                    file.write_all(buffer.as_slice())?;
+                    file.sync_all()
+                })
+                .and_then(|()| std::fs::rename(&temp_path, path));
+            if written.is_err() {
+                let _ = std::fs::remove_file(&temp_path);
+            }
+
+            written
+        }
+    }
+}
+
+impl<'b, Changes, Output> UpdateAndFetchResults<Changes, Output> for InstrumentedSqliteConnection
+where
+    Changes: Copy + Identifiable,
+    Changes: AsChangeset<Target = <Changes as HasTable>::Table> + IntoUpdateTarget,
+    Changes::Table: FindDsl<Changes::Id>,
+    Update<Changes, Changes>: ExecuteDsl<Self>,
+    Find<Changes::Table, Changes::Id>: LoadQuery<'b, Self, Output>,
+    <Changes::Table as Table>::AllColumns: ValidGrouping<()>,
+    <<Changes::Table as Table>::AllColumns as ValidGrouping<()>>::IsAggregate:
+        MixedAggregates<is_aggregate::No, Output = is_aggregate::No>,
+    Update<Changes, Changes>: QueryFragment<Sqlite>,
+{
+    instrument_method! {
+        connection = self,
+        #[cfg(feature = "statement-fields")]
+        instrument(
+            fields(
+                otel.name=span_name(self.span_namer.as_ref(), "update_and_fetch"),
+                db.statement=truncate_statement(
+                    diesel::debug_query::<Sqlite, _>(&diesel::update(changeset).set(changeset))
+                        .to_string(),
+                    self.config.max_statement_length(),
+                ),
+            ),
+            target = "diesel_tracing::sqlite::query",
+            skip(self, changeset),
+        ),
+        #[cfg(not(feature = "statement-fields"))]
+        instrument(
+            fields(
+                otel.name=span_name(self.span_namer.as_ref(), "update_and_fetch"),
+            ),
+            target = "diesel_tracing::sqlite::query",
+            skip(self, changeset),
+        );
+        fn update_and_fetch(&mut self, changeset: Changes) -> QueryResult<Output> {
+            debug!(target: "diesel_tracing::sqlite::query", "updating and fetching changeset");
+            let levels = self.config.error_levels;
+            diesel::update(changeset)
+                .set(changeset)
+                .execute(self)
+                .and_then(|_| FindDsl::find(Changes::table(), changeset.id()).get_result(self))
+                .inspect_err(|error| {
+                    error_event!("diesel_tracing::sqlite::query", levels, error);
+                })
+        }
+    }
+}
+
+#[cfg(all(test, not(feature = "passthrough")))]
+mod tests {
+    use super::*;
+    use crate::config::OperationSpanNamer;
+    use crate::test_support::EventRecorder;
+    use diesel::sql_types::Integer;
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use std::sync::Arc;
+
+    #[test]
+    fn test_rows_affected_recorded() {
+        let recorder = EventRecorder::default();
+        tracing::subscriber::with_default(recorder.clone(), || {
+            let mut conn = InstrumentedSqliteConnection::establish(":memory:").unwrap();
+            conn.batch_execute(
+                "CREATE TABLE users (id INTEGER, name TEXT);
+                INSERT INTO users VALUES (1, 'a'), (2, 'b'), (3, 'c');",
+            )
+            .unwrap();
+
+            let updated = sql_query("UPDATE users SET name = 'x' WHERE id < 3")
+                .execute(&mut conn)
+                .unwrap();
+            let span = recorder.span("execute_returning_count").unwrap();
+            assert_eq!(updated, 2);
+            assert_eq!(span.fields["db.rows_affected"], "2");
+
+            let deleted = sql_query("DELETE FROM users").execute(&mut conn).unwrap();
+            let span = recorder.span("execute_returning_count").unwrap();
+            assert_eq!(deleted, 3);
+            assert_eq!(span.fields["db.rows_affected"], "3");
+        });
+    }
+
+    #[test]
+    fn test_register_collation() {
+        let recorder = EventRecorder::default();
+        tracing::subscriber::with_default(recorder.clone(), || {
+            let used = Arc::new(AtomicBool::new(false));
+            let mut conn = InstrumentedSqliteConnection::establish(":memory:").unwrap();
+            let collation_used = Arc::clone(&used);
+            conn.register_collation("TRACKED", move |a, b| {
+                collation_used.store(true, Ordering::SeqCst);
+                a.cmp(b)
+            })
+            .unwrap();
+
+            let span = recorder.span("register_collation").unwrap();
+            assert_eq!(span.fields["db.collation_name"], "TRACKED");
+
+            conn.batch_execute(
+                "CREATE TABLE names (name TEXT);
+                INSERT INTO names VALUES ('a'), ('b');
+                SELECT name FROM names ORDER BY name COLLATE TRACKED;",
+            )
+            .unwrap();
+            assert!(used.load(Ordering::SeqCst));
+        });
+    }
+
+    struct Product(i32);
+
+    impl Default for Product {
+        fn default() -> Self {
+            Product(1)
+        }
+    }
+
+    impl SqliteAggregateFunction<i32> for Product {
+        type Output = i32;
+
+        fn step(&mut self, value: i32) {
+            self.0 *= value;
+        }
+
+        fn finalize(aggregator: Option<Self>) -> i32 {
+            aggregator.map_or(1, |product| product.0)
+        }
+    }
+
+    #[test]
+    fn test_register_aggregate_function() {
+        let recorder = EventRecorder::default();
+        tracing::subscriber::with_default(recorder.clone(), || {
+            let mut conn = InstrumentedSqliteConnection::establish(":memory:").unwrap();
+            conn.register_aggregate_function::<Integer, Integer, i32, i32, Product>("product")
+                .unwrap();
+
+            let span = recorder.span("register_aggregate_function").unwrap();
+            assert_eq!(span.fields["db.function_name"], "product");
+
+            conn.batch_execute(
+                "CREATE TABLE numbers (n INTEGER);
+                INSERT INTO numbers VALUES (2), (3), (4);",
+            )
+            .unwrap();
+            let product: i32 = diesel::select(diesel::dsl::sql::<Integer>(
+                "(SELECT product(n) FROM numbers)",
+            ))
+            .get_result(&mut conn)
+            .unwrap();
+            assert_eq!(product, 24);
+        });
+    }
+
+    #[test]
+    fn test_register_sql_function_logs_panics() {
+        let recorder = EventRecorder::default();
+        tracing::subscriber::with_default(recorder.clone(), || {
+            let mut conn = InstrumentedSqliteConnection::establish(":memory:").unwrap();
+            conn.register_sql_function::<(Integer,), Integer, _, _, _>(
+                "invert",
+                true,
+                |(n,): (i32,)| {
+                    assert!(n != 0, "cannot invert zero");
+                    100 / n
+                },
+            )
+            .unwrap();
+            sql_query("SELECT invert(4)").execute(&mut conn).unwrap();
+            sql_query("SELECT invert(0)")
+                .execute(&mut conn)
+                .unwrap_err();
+        });
+
+        let events = recorder.events();
+        let registered = events
+            .iter()
+            .find(|event| {
+                event
+                    .get("message")
+                    .is_some_and(|m| m == "registered sql function")
+            })
+            .unwrap();
+        assert_eq!(registered["db.sqlite.function"], "invert");
+        assert_eq!(registered["db.sqlite.deterministic"], "true");
+
+        let panicked: Vec<_> = events
+            .iter()
+            .filter(|event| {
+                event
+                    .get("message")
+                    .is_some_and(|m| m == "sql function panicked")
+            })
+            .collect();
+        assert_eq!(panicked.len(), 1);
+        assert_eq!(panicked[0]["level"], "ERROR");
+        assert_eq!(panicked[0]["db.sqlite.function"], "invert");
+        assert_eq!(panicked[0]["panic.message"], "cannot invert zero");
+    }
+
+    #[test]
+    fn test_set_instrumentation_delegated() {
+        use diesel::connection::InstrumentationEvent;
+        use std::sync::Mutex;
+
+        let queries = Arc::new(Mutex::new(Vec::new()));
+        let mut conn = InstrumentedSqliteConnection::establish(":memory:").unwrap();
+        conn.set_instrumentation({
+            let queries = queries.clone();
+            move |event: InstrumentationEvent<'_>| {
+                if let InstrumentationEvent::StartQuery { query, .. } = event {
+                    queries.lock().unwrap().push(query.to_string());
+                }
+            }
+        });
+        sql_query("SELECT 1").execute(&mut conn).unwrap();
+
+        assert_eq!(*queries.lock().unwrap(), ["SELECT 1 -- binds: []"]);
+    }
+
+    #[cfg(not(feature = "passthrough"))]
+    #[test]
+    fn test_read_only() {
+        use diesel::connection::InstrumentationEvent;
+        use std::sync::atomic::{AtomicUsize, Ordering};
+
+        let started = Arc::new(AtomicUsize::new(0));
+        let recorder = EventRecorder::default();
+        let (error, strict_error) = tracing::subscriber::with_default(recorder.clone(), || {
+            let mut conn = InstrumentedSqliteConnection::establish(":memory:").unwrap();
+            conn.batch_execute("CREATE TABLE users (id INTEGER)")
+                .unwrap();
+            conn.set_read_only(true).unwrap();
+            assert!(conn.is_read_only());
+            let error = sql_query("INSERT INTO users VALUES (1)")
+                .execute(&mut conn)
+                .unwrap_err();
+
+            let counter = started.clone();
+            conn.set_instrumentation(move |event: InstrumentationEvent<'_>| {
+                if let InstrumentationEvent::StartQuery { .. } = event {
+                    counter.fetch_add(1, Ordering::Relaxed);
+                }
+            });
+            conn.set_strict_read_only(true);
+            let strict_error = sql_query("INSERT INTO users VALUES (1)")
+                .execute(&mut conn)
+                .unwrap_err();
+            (error, strict_error)
+        });
+
+        assert!(matches!(error, Error::DatabaseError(..)));
+        assert!(matches!(strict_error, Error::QueryBuilderError(_)));
+        assert_eq!(started.load(Ordering::Relaxed), 0);
+        let warnings: Vec<_> = recorder
+            .events()
+            .into_iter()
+            .filter(|event| {
+                event
+                    .get("message")
+                    .is_some_and(|message| message == "write attempted on a read only connection")
+            })
+            .collect();
+        assert_eq!(warnings.len(), 2);
+        assert_eq!(warnings[0]["level"], "WARN");
+        assert_eq!(warnings[0]["db.sql.table"], "users");
+        let spans = recorder.spans("execute_returning_count");
+        assert_eq!(spans[0].fields["db.connection.read_only"], "true");
+    }
+
+    #[test]
+    fn test_set_instrumentation_stacks() {
+        use diesel::connection::InstrumentationEvent;
+        use std::sync::Mutex;
+
+        let events = Arc::new(Mutex::new(Vec::new()));
+        let recorder = |layer: &'static str| {
+            let events = events.clone();
+            move |event: InstrumentationEvent<'_>| match event {
+                InstrumentationEvent::StartQuery { .. } => {
+                    events.lock().unwrap().push((layer, "start"));
+                }
+                InstrumentationEvent::FinishQuery { .. } => {
+                    events.lock().unwrap().push((layer, "finish"));
+                }
+                _ => {}
+            }
+        };
+        let mut conn = InstrumentedSqliteConnection::establish(":memory:").unwrap();
+        conn.set_instrumentation(recorder("first"));
+        conn.set_instrumentation(recorder("second"));
+        sql_query("SELECT 1").execute(&mut conn).unwrap();
+        assert_eq!(
+            *events.lock().unwrap(),
+            [
+                ("first", "start"),
+                ("second", "start"),
+                ("first", "finish"),
+                ("second", "finish"),
+            ]
+        );
+
+        events.lock().unwrap().clear();
+        conn.replace_instrumentation(recorder("third"));
+        sql_query("SELECT 1").execute(&mut conn).unwrap();
+        assert_eq!(
+            *events.lock().unwrap(),
+            [("third", "start"), ("third", "finish")]
+        );
+    }
+
+    #[test]
+    fn test_convert_from_and_into_inner() {
+        let count = |conn: &mut SqliteConnection| -> i64 {
+            diesel::select(diesel::dsl::sql::<diesel::sql_types::BigInt>(
+                "(SELECT COUNT(*) FROM names)",
+            ))
+            .get_result(conn)
+            .unwrap()
+        };
+
+        let recorder = EventRecorder::default();
+        tracing::subscriber::with_default(recorder.clone(), || {
+            let mut inner = SqliteConnection::establish(":memory:").unwrap();
+            inner
+                .batch_execute(
+                    "CREATE TABLE names (name TEXT);
+                    INSERT INTO names VALUES ('a');",
+                )
+                .unwrap();
+
+            let mut conn = InstrumentedSqliteConnection::from(inner);
+            conn.batch_execute("INSERT INTO names VALUES ('b')")
+                .unwrap();
+            assert_eq!(count(conn.inner_mut()), 2);
+            assert_eq!(count(conn.as_mut()), 2);
+            assert!(std::ptr::eq(conn.as_ref(), conn.inner()));
+
+            let mut inner = SqliteConnection::from(conn);
+            assert_eq!(count(&mut inner), 2);
+        });
+
+        assert!(recorder.span("batch_execute").is_some());
+        assert!(recorder
+            .events()
+            .iter()
+            .any(|event| event["message"] == "connection closed"));
+    }
+
+    /// A database file in the temporary directory, removed along with its
+    /// journal files when dropped, even if the test panics.
+    struct TempDatabase(std::path::PathBuf);
+
+    impl TempDatabase {
+        fn new(name: &str) -> Self {
+            TempDatabase(std::env::temp_dir().join(format!(
+                "diesel-tracing-{name}-{}.sqlite",
+                std::process::id()
+            )))
+        }
+
+        fn path(&self) -> &std::path::Path {
+            &self.0
+        }
+
+        fn url(&self) -> &str {
+            self.0.to_str().unwrap()
+        }
+    }
+
+    impl Drop for TempDatabase {
+        fn drop(&mut self) {
+            for suffix in ["", "-wal", "-shm", "-journal"] {
+                let mut path = self.0.clone().into_os_string();
+                path.push(suffix);
+                let _ = std::fs::remove_file(path);
+            }
+        }
+    }
+
+    #[test]
+    fn test_pragmas() {
+        let database = TempDatabase::new("wal");
+        let url = database.url();
+
+        let recorder = EventRecorder::default();
+        tracing::subscriber::with_default(recorder.clone(), || {
+            let mut conn = InstrumentedSqliteConnection::establish(url).unwrap();
+            conn.set_pragma("journal_mode", "WAL").unwrap();
+            conn.set_pragma("busy_timeout", 5000).unwrap();
+            assert_eq!(conn.journal_mode().unwrap(), "wal");
+            assert!(conn.set_pragma("journal_mode = DELETE; --", 1).is_err());
+
+            conn.batch_execute(
+                "CREATE TABLE names (name TEXT);
+                INSERT INTO names VALUES ('a'), ('b');",
+            )
+            .unwrap();
+            let checkpoint = conn.wal_checkpoint(CheckpointMode::Full).unwrap();
+            assert_eq!(checkpoint.busy, 0);
+            assert!(checkpoint.log > 0);
+            assert_eq!(checkpoint.checkpointed, checkpoint.log);
+            conn.wal_checkpoint(CheckpointMode::Truncate).unwrap();
+        });
+
+        let span = recorder.span("set_pragma").unwrap();
+        assert_eq!(span.fields["db.operation"], "PRAGMA");
+        assert_eq!(span.fields["db.sqlite.pragma"], "journal_mode = DELETE; --");
+        let span = recorder.span("wal_checkpoint").unwrap();
+        assert_eq!(span.fields["db.sqlite.checkpoint.mode"], "TRUNCATE");
+        assert_eq!(span.fields["db.sqlite.checkpoint.busy"], "0");
+    }
+
+    #[cfg(not(feature = "passthrough"))]
+    #[test]
+    fn test_ensure_migrations_table() {
+        let recorder = EventRecorder::default();
+        tracing::subscriber::with_default(recorder.clone(), || {
+            let mut conn = InstrumentedSqliteConnection::establish(":memory:").unwrap();
+            assert!(conn.ensure_migrations_table().unwrap());
+            assert!(!conn.ensure_migrations_table().unwrap());
+        });
+
+        let spans = recorder.spans("ensure_migrations_table");
+        assert_eq!(spans[0].fields["db.migrations_table.created"], "true");
+        assert_eq!(spans[1].fields["db.migrations_table.created"], "false");
+        let messages = |span: &crate::test_support::RecordedSpan| {
+            recorder
+                .events_in_span(&span.id)
+                .into_iter()
+                .map(|event| event["message"].clone())
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(messages(&spans[0]), ["created migrations table"]);
+        assert_eq!(messages(&spans[1]), ["migrations table already exists"]);
+        assert!(recorder.span("create_migrations_table").is_some());
+    }
+
+    #[cfg(not(feature = "passthrough"))]
+    #[test]
+    fn test_establish_with_init() {
+        #[derive(QueryableByName)]
+        struct ForeignKeys {
+            #[diesel(sql_type = Integer)]
+            foreign_keys: i32,
+        }
+
+        let recorder = EventRecorder::default();
+        tracing::subscriber::with_default(recorder.clone(), || {
+            let mut conn = InstrumentedSqliteConnection::establish_with_init(
+                ":memory:",
+                &["PRAGMA foreign_keys = ON"],
+            )
+            .unwrap();
+            let row: ForeignKeys = sql_query("PRAGMA foreign_keys")
+                .get_result(&mut conn)
+                .unwrap();
+            assert_eq!(row.foreign_keys, 1);
+
+            let error = InstrumentedSqliteConnection::establish_with_init(
+                ":memory:",
+                &["PRAGMA foreign_keys = ON", "NOT SQL"],
+            )
+            .err()
+            .unwrap();
+            assert!(error.to_string().contains("init statement 1 (NOT SQL)"));
+        });
+
+        let spans = recorder.spans("establish_with_init");
+        assert_eq!(spans[0].fields["db.init.count"], "1");
+        let events = recorder.events_in_span(&spans[0].id);
+        let event = events
+            .iter()
+            .find(|event| event["message"] == "ran connection init statement")
+            .unwrap();
+        assert_eq!(event["db.init.index"], "0");
+        assert_eq!(event["db.operation"], "PRAGMA");
+        assert!(recorder
+            .events_in_span(&spans[1].id)
+            .iter()
+            .any(
+                |event| event["message"] == "connection init statement failed"
+                    && event["db.init.index"] == "1"
+            ));
+    }
+
+    #[test]
+    fn test_busy_retries_recorded() {
+        let database = TempDatabase::new("busy");
+        let url = database.url();
+
+        let recorder = EventRecorder::default();
+        let result = tracing::subscriber::with_default(recorder.clone(), || {
+            let mut holder = SqliteConnection::establish(url).unwrap();
+            holder
+                .batch_execute("CREATE TABLE IF NOT EXISTS names (name TEXT)")
+                .unwrap();
+            let mut conn = InstrumentedSqliteConnection::establish(url).unwrap();
+            conn.set_busy_retry(BusyRetryPolicy {
+                max_retries: 2,
+                backoff: Duration::from_millis(1),
+            });
+
+            holder.exclusive_transaction(|_| {
+                QueryResult::Ok(conn.batch_execute("INSERT INTO names VALUES ('a')"))
+            })
+        });
+
+        assert!(is_locked(&result.unwrap().unwrap_err()));
+        let retries: Vec<_> = recorder
+            .events()
+            .into_iter()
+            .filter(|event| {
+                event
+                    .get("message")
+                    .is_some_and(|message| message == "database is locked, retrying")
+            })
+            .map(|event| event["db.sqlite.retry"].clone())
+            .collect();
+        assert_eq!(retries, ["1", "2"]);
+        let span = recorder.span("batch_execute").unwrap();
+        assert_eq!(span.fields["db.sqlite.retries"], "2");
+    }
+
+    #[test]
+    fn test_busy_batch_not_retried() {
+        let database = TempDatabase::new("busy-batch");
+        let locked = TempDatabase::new("busy-batch-locked");
+
+        let recorder = EventRecorder::default();
+        let result = tracing::subscriber::with_default(recorder.clone(), || {
+            let mut holder = SqliteConnection::establish(locked.url()).unwrap();
+            holder
+                .batch_execute("CREATE TABLE IF NOT EXISTS names (name TEXT)")
+                .unwrap();
+            let mut conn = InstrumentedSqliteConnection::establish(database.url()).unwrap();
+            conn.batch_execute(&format!(
+                "CREATE TABLE names (name TEXT); ATTACH DATABASE '{}' AS locked",
+                locked.url()
+            ))
+            .unwrap();
+            conn.set_busy_retry(BusyRetryPolicy {
+                max_retries: 2,
+                backoff: Duration::from_millis(1),
+            });
+
+            let result = holder.exclusive_transaction(|_| {
+                QueryResult::Ok(conn.batch_execute(
+                    "INSERT INTO main.names VALUES ('a'); \
+                     INSERT INTO locked.names VALUES ('b')",
+                ))
+            });
+            let count: i64 = diesel::select(diesel::dsl::sql::<diesel::sql_types::BigInt>(
+                "(SELECT COUNT(*) FROM main.names)",
+            ))
+            .get_result(&mut conn)
+            .unwrap();
+            (result, count)
+        });
+
+        let (result, count) = result;
+        assert!(is_locked(&result.unwrap().unwrap_err()));
+        assert_eq!(count, 1);
+        assert!(!recorder.events().iter().any(|event| event
+            .get("message")
+            .is_some_and(|message| message == "database is locked, retrying")));
+    }
+
+    #[test]
+    fn test_serialize_round_trip() {
+        let recorder = EventRecorder::default();
+        tracing::subscriber::with_default(recorder.clone(), || {
+            let mut conn = InstrumentedSqliteConnection::establish(":memory:").unwrap();
+            conn.batch_execute(
+                "CREATE TABLE names (name TEXT);
+                INSERT INTO names VALUES ('a'), ('b');",
+            )
+            .unwrap();
+            let buffer = conn.serialize_database_to_buffer();
+
+            conn.batch_execute("DROP TABLE names").unwrap();
+            conn.deserialize_readonly_database_from_buffer(buffer.as_slice())
+                .unwrap();
+            let count: i64 = diesel::select(diesel::dsl::sql::<diesel::sql_types::BigInt>(
+                "(SELECT COUNT(*) FROM names)",
+            ))
+            .get_result(&mut conn)
+            .unwrap();
+            assert_eq!(count, 2);
+
+            let size = buffer.as_slice().len().to_string();
+            let span = recorder.span("serialize_database_to_buffer").unwrap();
+            assert_eq!(span.fields["db.sqlite.buffer_size"], size);
+            assert_eq!(span.fields["db.operation"], "serialize");
+            let span = recorder
+                .span("deserialize_readonly_database_from_buffer")
+                .unwrap();
+            assert_eq!(span.fields["db.sqlite.buffer_size"], size);
+            assert_eq!(span.fields["db.operation"], "deserialize");
+        });
+    }
+
+    #[test]
+    fn test_backup_to_file() {
+        let database = TempDatabase::new("backup");
+
+        let recorder = EventRecorder::default();
+        tracing::subscriber::with_default(recorder.clone(), || {
+            let mut conn = InstrumentedSqliteConnection::establish(":memory:").unwrap();
+            conn.batch_execute(
+                "CREATE TABLE names (name TEXT);
+                INSERT INTO names VALUES ('a'), ('b');",
+            )
+            .unwrap();
+            conn.backup_to_file(database.path()).unwrap();
+            assert!(conn
+                .backup_to_file(std::env::temp_dir().join("missing").join("backup.sqlite"))
+                .is_err());
+        });
+
+        let mut restored = InstrumentedSqliteConnection::establish(database.url()).unwrap();
+        let count: i64 = diesel::select(diesel::dsl::sql::<diesel::sql_types::BigInt>(
+            "(SELECT COUNT(*) FROM names)",
+        ))
+        .get_result(&mut restored)
+        .unwrap();
+        assert_eq!(count, 2);
+
+        let spans = recorder.spans("backup_to_file");
+        assert_eq!(spans[0].fields["db.operation"], "backup");
+        assert_eq!(
+            spans[0].fields["db.sqlite.buffer_size"],
+            recorder.spans("serialize_database_to_buffer")[0].fields["db.sqlite.buffer_size"]
+        );
+        assert!(recorder
+            .events_in_span(&spans[1].id)
+            .iter()
+            .any(|event| event.contains_key("error")));
+    }
+
+    #[test]
+    fn test_unique_violation_recorded() {
+        let recorder = EventRecorder::default();
+        tracing::subscriber::with_default(recorder.clone(), || {
+            let mut conn = InstrumentedSqliteConnection::establish(":memory:").unwrap();
+            conn.batch_execute("CREATE TABLE users (id INTEGER PRIMARY KEY)")
+                .unwrap();
+            sql_query("INSERT INTO users VALUES (1)")
+                .execute(&mut conn)
+                .unwrap();
+            sql_query("INSERT INTO users VALUES (1)")
+                .execute(&mut conn)
+                .unwrap_err();
+        });
+
+        let span = recorder.span("execute_returning_count").unwrap();
+        assert_eq!(span.fields["db.error.kind"], "unique_violation");
+    }
+
+    #[cfg(not(feature = "passthrough"))]
+    #[test]
+    fn test_error_status_recorded() {
+        let recorder = EventRecorder::default();
+        tracing::subscriber::with_default(recorder.clone(), || {
+            let mut conn = InstrumentedSqliteConnection::establish(":memory:").unwrap();
+            conn.batch_execute("SELECT * FROM missing").unwrap_err();
+            conn.transaction(|conn| conn.batch_execute("SELECT * FROM missing"))
+                .unwrap_err();
+            conn.batch_execute("SELECT 1").unwrap();
+        });
+
+        let spans = recorder.spans("batch_execute");
+        assert_eq!(spans[0].fields["otel.status_code"], "ERROR");
+        assert!(spans[0].fields["otel.status_message"].contains("missing"));
+        assert!(!spans
+            .last()
+            .unwrap()
+            .fields
+            .contains_key("otel.status_code"));
+        let transaction = recorder.span("transaction").unwrap();
+        assert_eq!(transaction.fields["otel.status_code"], "ERROR");
+    }
+
+    #[cfg(not(feature = "passthrough"))]
+    #[test]
+    fn test_statement_hash_recorded() {
+        let recorder = EventRecorder::default();
+        tracing::subscriber::with_default(recorder.clone(), || {
+            let mut conn = InstrumentedSqliteConnection::establish(":memory:").unwrap();
+            conn.batch_execute("CREATE TABLE users (id INTEGER, name TEXT)")
+                .unwrap();
+            for sql in [
+                "SELECT * FROM users WHERE id = 1",
+                "SELECT *  FROM users\n  WHERE id = 2",
+            ] {
+                sql_query(sql).execute(&mut conn).unwrap();
+            }
+        });
+
+        let spans = recorder.spans("execute_returning_count");
+        let hash = crate::statement_fingerprint("SELECT * FROM users WHERE id = ?").to_string();
+        assert_eq!(spans[0].fields["db.statement.hash"], hash);
+        assert_eq!(spans[1].fields["db.statement.hash"], hash);
+    }
+
+    #[cfg(feature = "statement-fingerprint")]
+    #[test]
+    fn test_statement_fingerprint_recorded() {
+        let recorder = EventRecorder::default();
+        tracing::subscriber::with_default(recorder.clone(), || {
+            let mut conn = InstrumentedSqliteConnection::establish(":memory:").unwrap();
+            conn.batch_execute("CREATE TABLE users (id INTEGER, name TEXT)")
+                .unwrap();
+            sql_query("INSERT INTO users VALUES (1, 'alice'), (2, 'bob')")
+                .execute(&mut conn)
+                .unwrap();
+        });
+
+        let span = recorder.span("execute_returning_count").unwrap();
+        assert_eq!(
+            span.fields["db.statement_fingerprint"],
+            "INSERT INTO users VALUES (?,+), (?,+)"
+        );
+    }
+
+    diesel::table! {
+        users (id) {
+            id -> Integer,
+        }
+    }
+
+    #[cfg(feature = "statement-fields")]
+    #[test]
+    fn test_long_statement_truncated() {
+        #[derive(diesel::QueryableByName)]
+        #[diesel(table_name = users)]
+        struct User {
+            #[allow(dead_code)]
+            id: i32,
+        }
+
+        let recorder = EventRecorder::default();
+        tracing::subscriber::with_default(recorder.clone(), || {
+            let mut conn = InstrumentedSqliteConnection::establish(":memory:").unwrap();
+            conn.set_tracing_config(TracingConfig {
+                max_statement_length: Some(100),
+                ..TracingConfig::default()
+            });
+            let padding = "x".repeat(1 << 20);
+            sql_query(format!("SELECT 1 AS id /* {padding} */"))
+                .load::<User>(&mut conn)
+                .unwrap();
+        });
+
+        let statement = &recorder.span("load").unwrap().fields["db.statement"];
+        assert!(statement.starts_with("SELECT 1 AS id /* xxx"));
+        assert!(statement.ends_with(" bytes total]"));
+        assert!(statement.chars().count() < 150);
+    }
+
+    #[test]
+    fn test_insert_in_chunks() {
+        use diesel::{ExpressionMethods, QueryDsl};
+
+        let recorder = EventRecorder::default();
+        tracing::subscriber::with_default(recorder.clone(), || {
+            let mut conn = InstrumentedSqliteConnection::establish(":memory:").unwrap();
+            conn.batch_execute("CREATE TABLE users (id INTEGER PRIMARY KEY)")
+                .unwrap();
+            let rows: Vec<_> = (0..2500).map(|id| users::id.eq(id)).collect();
+            assert_eq!(
+                conn.insert_in_chunks(users::table, rows, 1000).unwrap(),
+                2500
+            );
+
+            let rows: Vec<_> = (2500..40000).map(|id| users::id.eq(id)).collect();
+            conn.insert_in_chunks(users::table, rows, 40000)
+                .unwrap_err();
+            let count = users::table.count().get_result::<i64>(&mut conn).unwrap();
+            assert_eq!(count, 2500);
+        });
+
+        let spans = recorder.spans("insert_in_chunks");
+        assert_eq!(spans[0].fields["db.batch.chunks"], "3");
+        assert_eq!(spans[0].fields["db.batch.total_rows"], "2500");
+        assert_eq!(spans[0].fields["db.rows_affected"], "2500");
+        assert!(!spans[1].fields.contains_key("db.batch.chunks"));
+        assert_eq!(spans[1].fields["db.batch.rows_committed"], "0");
+        let sizes: Vec<_> = recorder
+            .spans("insert_chunk")
+            .iter()
+            .map(|span| span.fields["db.batch.size"].clone())
+            .collect();
+        // the chunk of the second insert is rejected before it is run
+        assert_eq!(sizes, ["1000", "1000", "500", "37500"]);
+        assert!(recorder
+            .spans("insert_chunk")
+            .iter()
+            .all(|span| span.target == "diesel_tracing::sqlite::query"));
+    }
+
+    #[test]
+    fn test_insert_in_chunks_counts_inserted_binds() {
+        use diesel::{ExpressionMethods, QueryDsl};
+
+        diesel::table! {
+            orders (id) {
+                id -> Integer,
+                #[sql_name = "shipped, at"]
+                shipped_at -> Nullable<Integer>,
+                note -> Nullable<Text>,
+            }
+        }
+
+        let mut conn = InstrumentedSqliteConnection::establish(":memory:").unwrap();
+        conn.batch_execute(
+            r#"CREATE TABLE orders (id INTEGER PRIMARY KEY, "shipped, at" INTEGER, note TEXT)"#,
+        )
+        .unwrap();
+        // one bind for each row, where counting the columns of the table
+        // would exceed the limit
+        let rows: Vec<_> = (0..20000).map(|id| orders::id.eq(id)).collect();
+        assert_eq!(
+            conn.insert_in_chunks(orders::table, rows, 20000).unwrap(),
+            20000
+        );
+        let count = orders::table.count().get_result::<i64>(&mut conn).unwrap();
+        assert_eq!(count, 20000);
+    }
+
+    #[test]
+    fn test_load_chunked() {
+        use diesel::{ExpressionMethods, QueryDsl};
+
+        let recorder = EventRecorder::default();
+        tracing::subscriber::with_default(recorder.clone(), || {
+            let mut conn = InstrumentedSqliteConnection::establish(":memory:").unwrap();
+            conn.batch_execute("CREATE TABLE users (id INTEGER PRIMARY KEY)")
+                .unwrap();
+            let rows: Vec<_> = (0..10).map(|id| users::id.eq(id)).collect();
+            diesel::insert_into(users::table)
+                .values(rows)
+                .execute(&mut conn)
+                .unwrap();
+
+            let mut ids = Vec::new();
+            let loaded = conn
+                .load_chunked(
+                    users::table.select(users::id).order(users::id),
+                    4,
+                    |chunk: Vec<i32>| {
+                        ids.extend(chunk);
+                        Ok(())
+                    },
+                )
+                .unwrap();
+            assert_eq!(loaded, 10);
+            assert_eq!(ids, (0..10).collect::<Vec<_>>());
+
+            let error = conn
+                .load_chunked(users::table.select(users::id), 4, |_: Vec<i32>| {
+                    Err(Error::NotFound)
+                })
+                .unwrap_err();
+            assert!(matches!(error, Error::NotFound));
+        });
+
+        let spans = recorder.spans("load_chunked");
+        assert_eq!(spans[0].fields["db.batch.chunks"], "3");
+        assert_eq!(spans[0].fields["db.batch.total_rows"], "10");
+        assert!(!spans[1].fields.contains_key("db.batch.chunks"));
+        let sizes: Vec<_> = recorder
+            .spans("load_chunk")
+            .iter()
+            .map(|span| span.fields["db.batch.size"].clone())
+            .collect();
+        assert_eq!(sizes, ["4", "4", "2", "4"]);
+        assert!(recorder
+            .spans("load_chunk")
+            .iter()
+            .all(|span| span.target == "diesel_tracing::sqlite::query"));
+    }
+
+    #[cfg(feature = "statement-fields")]
+    #[test]
+    fn test_execute_statement_recorded() {
+        let recorder = EventRecorder::default();
+        tracing::subscriber::with_default(recorder.clone(), || {
+            let mut conn = InstrumentedSqliteConnection::establish(":memory:").unwrap();
+            conn.batch_execute("CREATE TABLE users (id INTEGER)")
+                .unwrap();
+            sql_query("INSERT INTO users VALUES (?)")
+                .bind::<Integer, _>(1)
+                .execute(&mut conn)
+                .unwrap();
+        });
+
+        let span = recorder.span("execute_returning_count").unwrap();
+        assert_eq!(
+            span.fields["db.statement"],
+            "INSERT INTO users VALUES (?) -- binds: [1]"
+        );
+    }
+
+    #[cfg(feature = "statement-fields")]
+    #[test]
+    fn test_batch_statement_recorded() {
+        let recorder = EventRecorder::default();
+        tracing::subscriber::with_default(recorder.clone(), || {
+            let mut conn = InstrumentedSqliteConnection::establish(":memory:").unwrap();
+            conn.batch_execute("CREATE TABLE users (id INTEGER); INSERT INTO users VALUES (1)")
+                .unwrap();
+        });
+
+        let span = recorder.span("batch_execute").unwrap();
+        assert_eq!(
+            span.fields["db.statement"],
+            "CREATE TABLE users (id INTEGER); INSERT INTO users VALUES (1)"
+        );
+    }
+
+    #[cfg(feature = "audit")]
+    #[test]
+    fn test_writes_audited() {
+        use crate::audit::JsonLinesSink;
+
+        #[derive(QueryableByName)]
+        struct Row {
+            #[diesel(sql_type = Integer)]
+            id: i32,
+        }
+
+        let sink = Arc::new(JsonLinesSink::new(Vec::new()));
+        let mut conn = InstrumentedSqliteConnection::establish(":memory:").unwrap();
+        conn.batch_execute("CREATE TABLE users (id INTEGER)")
+            .unwrap();
+        conn.set_connection_name("primary");
+        conn.set_audit_sink(sink.clone());
+        sql_query("INSERT INTO users VALUES (1)")
+            .execute(&mut conn)
+            .unwrap();
+        let rows = sql_query("SELECT id FROM users")
+            .load::<Row>(&mut conn)
+            .unwrap();
+        assert_eq!(rows[0].id, 1);
+        drop(conn);
+
+        let output = Arc::try_unwrap(sink).ok().unwrap().into_inner();
+        let lines = String::from_utf8(output).unwrap();
+        let lines: Vec<&str> = lines.lines().collect();
+        assert_eq!(lines.len(), 1);
+        let entry: serde_json::Value = serde_json::from_str(lines[0]).unwrap();
+        assert_eq!(entry["db.system"], "sqlite");
+        assert_eq!(entry["db.operation"], "INSERT");
+        assert_eq!(entry["db.sql.table"], "users");
+        assert_eq!(entry["db.rows_affected"], 1);
+        assert_eq!(entry["db.connection.name"], "primary");
+        assert_eq!(entry["success"], true);
+    }
+
+    #[cfg(feature = "audit")]
+    #[test]
+    fn test_returning_writes_audited() {
+        use crate::audit::JsonLinesSink;
+
+        #[derive(QueryableByName)]
+        struct Row {
+            #[diesel(sql_type = Integer)]
+            id: i32,
+        }
+
+        let sink = Arc::new(JsonLinesSink::new(Vec::new()));
+        let mut conn = InstrumentedSqliteConnection::establish(":memory:").unwrap();
+        conn.batch_execute("CREATE TABLE users (id INTEGER)")
+            .unwrap();
+        conn.set_audit_sink(sink.clone());
+        let rows = sql_query("INSERT INTO users VALUES (1) RETURNING id")
+            .load::<Row>(&mut conn)
+            .unwrap();
+        assert_eq!(rows[0].id, 1);
+        drop(conn);
+
+        let output = Arc::try_unwrap(sink).ok().unwrap().into_inner();
+        let lines = String::from_utf8(output).unwrap();
+        let lines: Vec<&str> = lines.lines().collect();
+        assert_eq!(lines.len(), 1);
+        let entry: serde_json::Value = serde_json::from_str(lines[0]).unwrap();
+        assert_eq!(entry["db.operation"], "INSERT");
+        assert_eq!(entry["db.sql.table"], "users");
+        assert_eq!(entry["db.rows_affected"], serde_json::Value::Null);
+        assert_eq!(entry["success"], true);
+    }
+
+    #[cfg(feature = "statement-fields")]
+    #[test]
+    fn test_slow_query_plan_recorded() {
+        let recorder = EventRecorder::default();
+        tracing::subscriber::with_default(recorder.clone(), || {
+            let mut conn = InstrumentedSqliteConnection::establish(":memory:").unwrap();
+            conn.register_sql_function::<(Integer,), Integer, _, _, _>(
+                "slow",
+                true,
+                |(n,): (i32,)| {
+                    std::thread::sleep(Duration::from_millis(5));
+                    n
+                },
+            )
+            .unwrap();
+            conn.set_tracing_config(TracingConfig {
+                slow_query_threshold: Some(Duration::from_millis(1)),
+                ..TracingConfig::default()
+            });
+            conn.set_explain_on_slow(true);
+            conn.batch_execute("CREATE TABLE t (x INTEGER); INSERT INTO t VALUES (1)")
+                .unwrap();
+            sql_query("UPDATE t SET x = slow(x)")
+                .execute(&mut conn)
+                .unwrap();
+        });
+
+        let plans: Vec<_> = recorder
+            .events()
+            .into_iter()
+            .filter(|event| event.get("message").is_some_and(|m| m == "slow query plan"))
+            .collect();
+        assert_eq!(plans.len(), 1);
+        assert_eq!(plans[0]["level"], "WARN");
+        assert!(plans[0]["db.query_plan"].contains("SCAN"));
+    }
+
+    #[test]
+    fn test_bind_count_recorded() {
+        use diesel::{ExpressionMethods, QueryDsl};
+
+        let recorder = EventRecorder::default();
+        tracing::subscriber::with_default(recorder.clone(), || {
+            let mut conn = InstrumentedSqliteConnection::establish(":memory:").unwrap();
+            conn.batch_execute("CREATE TABLE users (id INTEGER PRIMARY KEY)")
+                .unwrap();
+
+            users::table
+                .filter(users::id.eq_any(vec![1, 2, 3]))
+                .select(users::id)
+                .load::<i32>(&mut conn)
+                .unwrap();
+            let span = recorder.span("load").unwrap();
+            assert_eq!(span.fields["db.bind_count"], "3");
+
+            users::table
+                .select(users::id)
+                .load::<i32>(&mut conn)
+                .unwrap();
+            let span = recorder.span("load").unwrap();
+            assert_eq!(span.fields["db.bind_count"], "0");
+        });
+    }
+
+    #[test]
+    fn test_nested_transaction_depth() {
+        let recorder = EventRecorder::default();
+        tracing::subscriber::with_default(recorder.clone(), || {
+            let mut conn = InstrumentedSqliteConnection::establish(":memory:").unwrap();
+            conn.transaction(|conn| {
+                conn.transaction(|_| Err::<(), _>(diesel::result::Error::RollbackTransaction))
+                    .unwrap_err();
+                Ok::<_, diesel::result::Error>(())
+            })
+            .unwrap();
+        });
+
+        let spans = recorder.spans("transaction");
+        assert_eq!(spans.len(), 2);
+        assert_eq!(spans[0].fields["db.transaction.depth"], "1");
+        assert_eq!(spans[0].fields["db.transaction.rolled_back"], "false");
+        assert_eq!(spans[1].fields["db.transaction.depth"], "2");
+        assert_eq!(spans[1].fields["db.transaction.rolled_back"], "true");
+    }
+
+    #[test]
+    fn test_savepoint_rolled_back() {
+        let recorder = EventRecorder::default();
+        let remaining = tracing::subscriber::with_default(recorder.clone(), || {
+            let mut conn = InstrumentedSqliteConnection::establish(":memory:").unwrap();
+            conn.batch_execute("CREATE TABLE users (id INTEGER)")
+                .unwrap();
+            conn.transaction(|conn| {
+                sql_query("INSERT INTO users VALUES (1)").execute(conn)?;
+                conn.savepoint("before_second_user", |conn| {
+                    sql_query("INSERT INTO users VALUES (2)").execute(conn)?;
+                    Err::<(), _>(diesel::result::Error::RollbackTransaction)
+                })
+                .unwrap_err();
+                sql_query("DELETE FROM users").execute(conn)
+            })
+            .unwrap()
+        });
+
+        assert_eq!(remaining, 1);
+        let span = recorder.span("savepoint").unwrap();
+        assert_eq!(
+            span.fields["db.transaction.savepoint"],
+            "before_second_user"
+        );
+        assert_eq!(span.fields["db.transaction.rolled_back"], "true");
+        assert_eq!(
+            recorder.span("transaction").unwrap().fields["db.transaction.rolled_back"],
+            "false"
+        );
+    }
+
+    #[test]
+    fn test_savepoint_name_validated() {
+        let mut conn = InstrumentedSqliteConnection::establish(":memory:").unwrap();
+        let result = conn.savepoint("sp; DROP TABLE users", |_| {
+            Ok::<_, diesel::result::Error>(())
+        });
+        assert!(matches!(
+            result,
+            Err(diesel::result::Error::QueryBuilderError(_))
+        ));
+    }
+
+    #[test]
+    fn test_immediate_transaction_queries_traced() {
+        let recorder = EventRecorder::default();
+        tracing::subscriber::with_default(recorder.clone(), || {
+            let mut conn = InstrumentedSqliteConnection::establish(":memory:").unwrap();
+            conn.batch_execute("CREATE TABLE users (id INTEGER)")
+                .unwrap();
+            conn.immediate_transaction(|conn| {
+                sql_query("INSERT INTO users VALUES (1)").execute(conn)
+            })
+            .unwrap();
+        });
+
+        let span = recorder.span("execute_returning_count").unwrap();
+        assert_eq!(span.fields["db.operation"], "INSERT");
+        assert_eq!(
+            recorder.span("immediate_transaction").unwrap().fields["db.transaction.rolled_back"],
+            "false"
+        );
+    }
+
+    #[test]
+    fn test_exclusive_transaction_queries_traced() {
+        let recorder = EventRecorder::default();
+        tracing::subscriber::with_default(recorder.clone(), || {
+            let mut conn = InstrumentedSqliteConnection::establish(":memory:").unwrap();
+            conn.batch_execute("CREATE TABLE users (id INTEGER)")
+                .unwrap();
+            let result: QueryResult<usize> = conn.exclusive_transaction(|conn| {
+                sql_query("INSERT INTO users VALUES (1)").execute(conn)?;
+                Err(Error::RollbackTransaction)
+            });
+            assert!(result.is_err());
+        });
+
+        let span = recorder.span("execute_returning_count").unwrap();
+        assert_eq!(span.fields["db.operation"], "INSERT");
+        assert_eq!(
+            recorder.span("exclusive_transaction").unwrap().fields["db.transaction.rolled_back"],
+            "true"
+        );
+    }
+
+    #[test]
+    fn test_disabled_tracing_config_creates_no_spans() {
+        let mut conn = InstrumentedSqliteConnection::establish(":memory:").unwrap();
+        conn.set_tracing_config(TracingConfig {
+            trace_load: false,
+            trace_execute: false,
+            trace_batch: false,
+            trace_transactions: false,
+            ..TracingConfig::default()
+        });
+
+        let recorder = EventRecorder::default();
+        tracing::subscriber::with_default(recorder.clone(), || {
+            conn.batch_execute("CREATE TABLE users (id INTEGER)")
+                .unwrap();
+            conn.transaction(|conn| {
+                sql_query("INSERT INTO users VALUES (1)").execute(conn)?;
+                sql_query("SELECT id FROM users").execute(conn)?;
+                diesel::dsl::select(diesel::dsl::sql::<diesel::sql_types::Integer>("1"))
+                    .get_result::<i32>(conn)
+            })
+            .unwrap();
+            conn.immediate_transaction(|_| Ok::<_, diesel::result::Error>(()))
+                .unwrap();
+        });
+
+        let names: Vec<_> = recorder
+            .all_spans()
+            .into_iter()
+            .map(|span| span.name)
+            .collect();
+        assert!(names.is_empty(), "spans recorded: {names:?}");
+    }
+
+    #[cfg(feature = "postgres")]
+    #[derive(diesel::MultiConnection)]
+    #[allow(clippy::large_enum_variant)]
+    enum AnyConnection {
+        Postgresql(crate::pg::InstrumentedPgConnection),
+        Sqlite(InstrumentedSqliteConnection),
+    }
+
+    #[cfg(feature = "postgres")]
+    #[test]
+    fn test_multi_connection_sqlite_arm() {
+        let mut conn = AnyConnection::establish("sqlite://:memory:").unwrap();
+        assert!(matches!(conn, AnyConnection::Sqlite(_)));
+        let one = diesel::dsl::select(diesel::dsl::sql::<diesel::sql_types::Integer>("1"))
+            .get_result::<i32>(&mut conn)
+            .unwrap();
+        assert_eq!(one, 1);
+    }
+
+    #[cfg(feature = "connection-id")]
+    #[test]
+    fn test_connection_id_recorded() {
+        let recorder = EventRecorder::default();
+        tracing::subscriber::with_default(recorder.clone(), || {
+            let mut conn = InstrumentedSqliteConnection::establish(":memory:").unwrap();
+            conn.batch_execute("SELECT 1").unwrap();
+        });
+
+        let establish = recorder.span("establish").unwrap();
+        let batch_execute = recorder.span("batch_execute").unwrap();
+        assert_eq!(establish.fields["db.connection_id"].len(), 36);
+        assert_eq!(
+            establish.fields["db.connection_id"],
+            batch_execute.fields["db.connection_id"]
+        );
+    }
+
+    diesel::table! {
+        accounts (id) {
+            id -> Integer,
+            name -> Text,
+        }
+    }
+
+    #[derive(Debug, PartialEq, diesel::Queryable, diesel::Identifiable, diesel::AsChangeset)]
+    #[diesel(table_name = accounts)]
+    struct Account {
+        id: i32,
+        name: String,
+    }
+
+    #[test]
+    fn test_save_changes_traced() {
+        use diesel::SaveChangesDsl;
+
+        let recorder = EventRecorder::default();
+        tracing::subscriber::with_default(recorder.clone(), || {
+            let mut conn = InstrumentedSqliteConnection::establish(":memory:").unwrap();
+            conn.batch_execute(
+                "CREATE TABLE accounts (id INTEGER PRIMARY KEY, name TEXT NOT NULL);
+                INSERT INTO accounts VALUES (1, 'alice');",
+            )
+            .unwrap();
+
+            let account = Account {
+                id: 1,
+                name: "bob".to_string(),
+            }
+            .save_changes::<Account>(&mut conn)
+            .unwrap();
+            assert_eq!(account.name, "bob");
+        });
+
+        let update = recorder.span("update_and_fetch").unwrap();
+        assert_eq!(update.fields["db.system"], "sqlite");
+        assert_eq!(update.fields["otel.kind"], "client");
+        assert_eq!(update.fields["db.client.address"], ":memory:");
+        let execute = recorder.span("execute_returning_count").unwrap();
+        assert_eq!(execute.fields["db.operation"], "UPDATE");
+        assert_eq!(execute.fields["db.sql.table"], "accounts");
+        assert_eq!(execute.fields["db.access_mode"], "write");
+        let load = recorder.span("load").unwrap();
+        assert_eq!(load.fields["db.operation"], "SELECT");
+        assert_eq!(load.fields["db.sql.table"], "accounts");
+        assert_eq!(load.fields["db.access_mode"], "read");
+        let batch = recorder.span("batch_execute").unwrap();
+        assert_eq!(batch.fields["db.access_mode"], "write");
+    }
+
+    #[cfg(feature = "statement-fields")]
+    #[test]
+    fn test_update_and_fetch_statement_recorded() {
+        use diesel::SaveChangesDsl;
+
+        let recorder = EventRecorder::default();
+        tracing::subscriber::with_default(recorder.clone(), || {
+            let mut conn = InstrumentedSqliteConnection::establish(":memory:").unwrap();
+            conn.batch_execute(
+                "CREATE TABLE accounts (id INTEGER PRIMARY KEY, name TEXT NOT NULL);
+                INSERT INTO accounts VALUES (1, 'alice');",
+            )
+            .unwrap();
+
+            Account {
+                id: 1,
+                name: "bob".to_string(),
+            }
+            .save_changes::<Account>(&mut conn)
+            .unwrap();
+        });
+
+        let update = recorder.span("update_and_fetch").unwrap();
+        assert_eq!(
+            update.fields["db.statement"],
+            "UPDATE `accounts` SET `name` = ? WHERE (`accounts`.`id` = ?) -- binds: [\"bob\", 1]"
+        );
+    }
+
+    #[cfg(feature = "statement-fields")]
+    #[test]
+    fn test_statement_not_rendered_when_disabled() {
+        // counts the walks of its AST, each of which is either part of running
+        // the query or of rendering it for a span
+        struct CountingQuery<'a, T> {
+            inner: T,
+            walks: &'a std::sync::atomic::AtomicUsize,
+        }
+
+        impl<T: QueryFragment<Sqlite>> QueryFragment<Sqlite> for CountingQuery<'_, T> {
+            fn walk_ast<'b>(
+                &'b self,
+                pass: diesel::query_builder::AstPass<'_, 'b, Sqlite>,
+            ) -> QueryResult<()> {
+                self.walks.fetch_add(1, Ordering::SeqCst);
+                self.inner.walk_ast(pass)
+            }
+        }
+
+        impl<T> QueryId for CountingQuery<'_, T> {
+            type QueryId = ();
+            const HAS_STATIC_QUERY_ID: bool = false;
+        }
+
+        let walks = |run: &dyn Fn(&CountingQuery<'_, diesel::query_builder::SqlQuery>)| {
+            let walks = std::sync::atomic::AtomicUsize::new(0);
+            run(&CountingQuery {
+                inner: sql_query("SELECT 1"),
+                walks: &walks,
+            });
+            walks.into_inner()
+        };
+        let untraced = walks(&|query| {
+            let mut conn = SqliteConnection::establish(":memory:").unwrap();
+            conn.execute_returning_count(query).unwrap();
+        });
+        let traced = |dispatch: tracing::Dispatch| {
+            walks(&|query| {
+                tracing::dispatcher::with_default(&dispatch, || {
+                    let mut conn = InstrumentedSqliteConnection::establish(":memory:").unwrap();
+                    conn.execute_returning_count(query).unwrap();
+                });
+            })
+        };
+
+        assert!(traced(EventRecorder::default().into()) > untraced);
+        assert_eq!(
+            traced(tracing::subscriber::NoSubscriber::default().into()),
+            untraced
+        );
+    }
+
+    #[test]
+    fn test_not_found_error_level() {
+        use diesel::SaveChangesDsl;
+
+        let save_missing = |config: TracingConfig, sample_rate: f32| {
+            let recorder = EventRecorder::default();
+            tracing::subscriber::with_default(recorder.clone(), || {
+                let mut conn = InstrumentedSqliteConnection::establish(":memory:").unwrap();
+                conn.set_tracing_config(config);
+                conn.batch_execute(
+                    "CREATE TABLE accounts (id INTEGER PRIMARY KEY, name TEXT NOT NULL);",
+                )
+                .unwrap();
+                conn.set_query_sample_rate(sample_rate);
+
+                let result = Account {
+                    id: 1,
+                    name: "bob".to_string(),
+                }
+                .save_changes::<Account>(&mut conn);
+                assert_eq!(result, Err(diesel::result::Error::NotFound));
+            });
+            recorder
+                .events()
+                .into_iter()
+                .filter(|event| event.contains_key("error"))
+                .map(|event| (event["level"].clone(), event["target"].clone()))
+                .collect::<Vec<_>>()
+        };
+        let all_errors = TracingConfig {
+            error_levels: crate::ErrorLevelPolicy::all_errors(),
+            ..Default::default()
+        };
+        let level = |level: &str| {
+            (
+                level.to_string(),
+                "diesel_tracing::sqlite::query".to_string(),
+            )
+        };
+
+        assert_eq!(
+            save_missing(TracingConfig::default(), 1.0),
+            [level("DEBUG")]
+        );
+        assert_eq!(save_missing(all_errors, 1.0), [level("ERROR")]);
+        // sampled out queries log their failures at the same levels
+        assert_eq!(
+            save_missing(TracingConfig::default(), 0.0),
+            [level("DEBUG")]
+        );
+        assert_eq!(save_missing(all_errors, 0.0), [level("ERROR")]);
+    }
+
+    #[test]
+    fn test_is_healthy() {
+        let recorder = EventRecorder::default();
+        tracing::subscriber::with_default(recorder.clone(), || {
+            let mut conn = InstrumentedSqliteConnection::establish(":memory:").unwrap();
+            conn.set_connection_name("primary");
+            assert!(conn.is_healthy());
+            assert!(format!("{conn:?}").contains("primary"));
+        });
+
+        assert!(recorder.span("batch_execute").is_some());
+    }
+
+    #[test]
+    fn test_connection_span_covers_lifetime() {
+        let recorder = EventRecorder::default();
+        tracing::subscriber::with_default(recorder.clone(), || {
+            let mut conn = InstrumentedSqliteConnection::establish(":memory:").unwrap();
+            conn.set_connection_name("primary");
+            conn.batch_execute("SELECT 1").unwrap();
+            drop(conn);
+        });
+
+        let connection = recorder.span("db.connection").unwrap();
+        let batch_execute = recorder.span("batch_execute").unwrap();
+        assert_eq!(connection.fields["db.system"], "sqlite");
+        assert_eq!(connection.fields["db.connection.name"], "primary");
+        assert_eq!(
+            connection.fields["db.connection.id"],
+            batch_execute.fields["db.connection.id"]
+        );
+        assert!(recorder
+            .events()
+            .iter()
+            .any(|event| event["message"] == "connection closed"));
+    }
+
+    #[test]
+    fn test_connection_close_reports_lifetime() {
+        let recorder = EventRecorder::default();
+        tracing::subscriber::with_default(recorder.clone(), || {
+            let mut conn = InstrumentedSqliteConnection::establish(":memory:").unwrap();
+            sql_query("SELECT 1").execute(&mut conn).unwrap();
+            conn.batch_execute("SELECT 1").unwrap();
+            std::thread::sleep(std::time::Duration::from_millis(2));
+        });
+
+        let events = recorder.events();
+        let closed = events
+            .iter()
+            .find(|event| event["message"] == "connection closed")
+            .unwrap();
+        assert_eq!(closed["db.connection.queries"], "2");
+        assert!(closed["db.connection.duration_ms"].parse::<u64>().unwrap() > 0);
+    }
+
+    #[test]
+    fn test_targets_recorded() {
+        let recorder = EventRecorder::default();
+        tracing::subscriber::with_default(recorder.clone(), || {
+            let mut conn = InstrumentedSqliteConnection::establish(":memory:").unwrap();
+            conn.transaction(|conn| conn.batch_execute("CREATE TABLE users (id INTEGER)"))
+                .unwrap();
+            conn.register_sql_function::<(Integer,), Integer, _, _, _>(
+                "double",
+                true,
+                |(x,): (i32,)| x * 2,
+            )
+            .unwrap();
+        });
+
+        let target = |name| recorder.span(name).unwrap().target;
+        assert_eq!(
+            target("db.connection"),
+            "diesel_tracing::sqlite::connection"
+        );
+        assert_eq!(target("establish"), "diesel_tracing::sqlite::connection");
+        assert_eq!(target("batch_execute"), "diesel_tracing::sqlite::query");
+        assert_eq!(target("transaction"), "diesel_tracing::sqlite::transaction");
+        let registered = recorder
+            .events()
+            .into_iter()
+            .find(|event| {
+                event
+                    .get("message")
+                    .is_some_and(|m| m == "registered sql function")
+            })
+            .unwrap();
+        assert_eq!(registered["target"], "diesel_tracing::sqlite::connection");
+    }
+
+    #[test]
+    fn test_connection_names_recorded() {
+        let recorder = EventRecorder::default();
+        tracing::subscriber::with_default(recorder.clone(), || {
+            let mut primary =
+                InstrumentedSqliteConnection::establish_named(":memory:", "primary").unwrap();
+            let mut replica = InstrumentedSqliteConnection::establish(":memory:").unwrap();
+            replica.set_connection_name("replica");
+            assert_eq!(replica.connection_name(), Some("replica"));
+
+            primary.batch_execute("SELECT 1").unwrap();
+            replica.batch_execute("SELECT 1").unwrap();
+        });
+
+        let spans = recorder.spans("batch_execute");
+        assert_eq!(spans[0].fields["db.connection.name"], "primary");
+        assert_eq!(spans[1].fields["db.connection.name"], "replica");
+        let primary_id: u64 = spans[0].fields["db.connection.id"].parse().unwrap();
+        let replica_id: u64 = spans[1].fields["db.connection.id"].parse().unwrap();
+        assert!(replica_id > primary_id);
+    }
+
+    #[test]
+    fn test_extra_fields_recorded() {
+        let recorder = EventRecorder::default();
+        tracing::subscriber::with_default(recorder.clone(), || {
+            let mut conn = InstrumentedSqliteConnection::establish(":memory:").unwrap();
+            conn.with_extra_fields(vec![
+                ("tenant.id".to_string(), "acme".to_string()),
+                ("shard".to_string(), "7".to_string()),
+            ]);
+            sql_query("SELECT 1").execute(&mut conn).unwrap();
+            assert_eq!(
+                recorder.span("db.connection").unwrap().fields["db.extra"],
+                "tenant.id=acme,shard=7"
+            );
+            conn.with_extra_fields(vec![]);
+            sql_query("SELECT 2").execute(&mut conn).unwrap();
+        });
+
+        let spans = recorder.spans("execute_returning_count");
+        assert_eq!(spans[0].fields["db.extra"], "tenant.id=acme,shard=7");
+        assert!(!spans[1].fields.contains_key("db.extra"));
+        let connection = recorder.span("db.connection").unwrap();
+        assert_eq!(connection.fields["db.extra"], "");
+    }
+
+    #[test]
+    fn test_query_sampling() {
+        let recorder = EventRecorder::default();
+        tracing::subscriber::with_default(recorder.clone(), || {
+            let mut conn = InstrumentedSqliteConnection::establish(":memory:").unwrap();
+            conn.set_query_sample_rate(0.1);
+            for i in 0..1000 {
+                if i % 100 == 0 {
+                    sql_query("SELECT * FROM missing")
+                        .execute(&mut conn)
+                        .unwrap_err();
+                } else {
+                    sql_query("SELECT 1").execute(&mut conn).unwrap();
+                }
+            }
+            conn.transaction(|conn| {
+                sql_query("SELECT 2").execute(conn)?;
+                sql_query("SELECT 3").execute(conn)
+            })
+            .unwrap();
+        });
+
+        let spans = recorder.query_spans();
+        assert!(spans.len() < 200, "{} query spans", spans.len());
+        let errors = recorder
+            .events()
+            .into_iter()
+            .filter(|event| event["level"] == "ERROR")
+            .count();
+        assert_eq!(errors, 10);
+        // the transaction and each of its statements are sampled together
+        let in_transaction = spans
+            .iter()
+            .filter(|span| {
+                span.fields
+                    .get("db.operation")
+                    .is_some_and(|operation| ["BEGIN", "COMMIT"].contains(&operation.as_str()))
+            })
+            .count();
+        let transactions = recorder.spans("transaction").len();
+        assert_eq!(in_transaction, transactions * 2);
+    }
+
+    #[test]
+    fn test_client_address_recorded() {
+        let recorder = EventRecorder::default();
+        tracing::subscriber::with_default(recorder.clone(), || {
+            let mut conn = InstrumentedSqliteConnection::establish(":memory:").unwrap();
+            sql_query("SELECT 1").execute(&mut conn).unwrap();
+            let mut conn = InstrumentedSqliteConnection::from(conn.into_inner());
+            sql_query("SELECT 2").execute(&mut conn).unwrap();
+        });
+
+        let spans = recorder.spans("execute_returning_count");
+        assert_eq!(spans[0].fields["db.client.address"], ":memory:");
+        assert!(!spans[1].fields.contains_key("db.client.address"));
+        let span = recorder.span("establish").unwrap();
+        assert_eq!(span.fields["db.client.address"], ":memory:");
+    }
+
+    #[test]
+    fn test_span_namer_recorded() {
+        struct CheckoutNamer;
+
+        impl SpanNamer for CheckoutNamer {
+            fn name_span(&self, db_method: &'static str) -> &'static str {
+                match db_method {
+                    "execute_returning_count" => "checkout.reserve_stock",
+                    _ => db_method,
+                }
+            }
+        }
+
+        let recorder = EventRecorder::default();
+        tracing::subscriber::with_default(recorder.clone(), || {
+            let mut conn = InstrumentedSqliteConnection::establish(":memory:")
+                .unwrap()
+                .with_span_namer(CheckoutNamer);
+            sql_query("SELECT 1").execute(&mut conn).unwrap();
+            conn.batch_execute("SELECT 1").unwrap();
+        });
+
+        let execute = recorder.span("execute_returning_count").unwrap();
+        assert_eq!(execute.fields["otel.name"], "checkout.reserve_stock");
+        let batch_execute = recorder.span("batch_execute").unwrap();
+        assert!(!batch_execute.fields.contains_key("otel.name"));
+    }
+
+    #[cfg(not(feature = "passthrough"))]
+    #[test]
+    fn test_statement_span_names_recorded() {
+        struct CheckoutNamer;
+
+        impl SpanNamer for CheckoutNamer {
+            fn name_span(&self, db_method: &'static str) -> &'static str {
+                match db_method {
+                    "execute_returning_count" => "checkout.reserve_stock",
+                    _ => db_method,
+                }
+            }
+        }
+
+        let recorder = EventRecorder::default();
+        tracing::subscriber::with_default(recorder.clone(), || {
+            let mut conn = InstrumentedSqliteConnection::establish(":memory:")
+                .unwrap()
+                .with_span_namer(CheckoutNamer);
+            conn.set_tracing_config(TracingConfig {
+                statement_span_names: true,
+                ..TracingConfig::default()
+            });
+            conn.batch_execute("CREATE TABLE users (id INTEGER)")
+                .unwrap();
+            sql_query("INSERT INTO users VALUES (1)")
+                .execute(&mut conn)
+                .unwrap();
+            users::table.load::<(i32,)>(&mut conn).unwrap();
+        });
+
+        let batch_execute = recorder.span("batch_execute").unwrap();
+        assert_eq!(batch_execute.fields["otel.name"], "CREATE");
+        let execute = recorder.span("execute_returning_count").unwrap();
+        assert_eq!(execute.fields["otel.name"], "checkout.reserve_stock");
+        let load = recorder.span("load").unwrap();
+        assert_eq!(load.fields["otel.name"], "SELECT users");
+    }
+
+    #[test]
+    fn test_operation_span_names_recorded() {
+        let recorder = EventRecorder::default();
+        tracing::subscriber::with_default(recorder.clone(), || {
+            let mut conn = InstrumentedSqliteConnection::establish(":memory:")
+                .unwrap()
+                .with_span_namer(OperationSpanNamer);
+            conn.batch_execute("CREATE TABLE users (id INTEGER)")
+                .unwrap();
+            conn.transaction(|conn| {
+                sql_query("INSERT INTO users VALUES (1)").execute(conn)?;
+                users::table.load::<(i32,)>(conn)
+            })
+            .unwrap();
+        });
+
+        for (span, name) in [
+            ("batch_execute", "db.batch_execute"),
+            ("transaction", "db.transaction"),
+            ("execute_returning_count", "db.execute"),
+            ("load", "db.query"),
+        ] {
+            assert_eq!(recorder.span(span).unwrap().fields["otel.name"], name);
+        }
+    }
+
+    #[cfg(not(feature = "passthrough"))]
+    #[test]
+    fn test_default_span_namer_names_establish() {
+        // only renames the establish spans, as the default namer is shared
+        // with the connections of concurrent tests
+        struct ConnectNamer;
+
+        impl SpanNamer for ConnectNamer {
+            fn name_span(&self, db_method: &'static str) -> &'static str {
+                match db_method {
+                    "establish" | "establish_with_init" => OperationSpanNamer.name_span(db_method),
+                    _ => db_method,
+                }
+            }
+        }
+
+        crate::config::set_default_span_namer(ConnectNamer);
+        let recorder = EventRecorder::default();
+        tracing::subscriber::with_default(recorder.clone(), || {
+            InstrumentedSqliteConnection::establish_with_init(":memory:", &["SELECT 1"]).unwrap();
+        });
+
+        let establish = recorder.span("establish").unwrap();
+        assert_eq!(establish.fields["otel.name"], "db.connect");
+        let establish_with_init = recorder.span("establish_with_init").unwrap();
+        assert_eq!(establish_with_init.fields["otel.name"], "db.connect");
+        let batch_execute = recorder.span("batch_execute").unwrap();
+        assert!(!batch_execute.fields.contains_key("otel.name"));
+    }
+
+    #[test]
+    fn test_long_transaction_warned_while_open() {
+        let is_warning = |event: &std::collections::HashMap<&'static str, String>| {
+            event
+                .get("message")
+                .is_some_and(|message| message.contains("warning threshold"))
+        };
+        let recorder = EventRecorder::default();
+        tracing::subscriber::with_default(recorder.clone(), || {
+            let mut conn = InstrumentedSqliteConnection::establish(":memory:").unwrap();
+            conn.register_sql_function::<(Integer,), Integer, _, _, _>(
+                "sleep_ms",
+                false,
+                |(ms,): (i32,)| {
+                    std::thread::sleep(Duration::from_millis(ms.unsigned_abs().into()));
+                    ms
+                },
+            )
+            .unwrap();
+            conn.set_transaction_warn_after(Duration::from_millis(10));
+            conn.transaction(|conn| {
+                sql_query("SELECT sleep_ms(20)").execute(conn)?;
+                assert_eq!(
+                    recorder.events().iter().filter(|e| is_warning(e)).count(),
+                    1
+                );
+                sql_query("SELECT 1").execute(conn)
+            })
+            .unwrap();
+        });
+
+        assert_eq!(
+            recorder.events().iter().filter(|e| is_warning(e)).count(),
+            1
+        );
+        let transaction = recorder.span("transaction").unwrap();
+        assert!(
+            transaction.fields["db.transaction.duration_ms"]
+                .parse::<u64>()
+                .unwrap()
+                >= 20
+        );
+        assert_eq!(transaction.fields["db.transaction.queries"], "2");
+    }
+
+    #[test]
+    fn test_statement_cached_recorded() {
+        let recorder = EventRecorder::default();
+        tracing::subscriber::with_default(recorder.clone(), || {
+            let mut conn = InstrumentedSqliteConnection::establish(":memory:").unwrap();
+            sql_query("CREATE TABLE users (id INTEGER)")
+                .execute(&mut conn)
+                .unwrap();
+            users::table.load::<(i32,)>(&mut conn).unwrap();
+            users::table.load::<(i32,)>(&mut conn).unwrap();
+        });
+
+        let execute = recorder.span("execute_returning_count").unwrap();
+        assert!(!execute.fields.contains_key("db.statement.cached"));
+        let loads = recorder.spans("load");
+        assert_eq!(loads[0].fields["db.statement.cached"], "false");
+        assert_eq!(loads[1].fields["db.statement.cached"], "true");
+    }
+
+    #[cfg(feature = "r2d2")]
+    #[test]
+    fn test_is_broken_matches_inner() {
+        let mut plain = SqliteConnection::establish(":memory:").unwrap();
+        let mut instrumented = InstrumentedSqliteConnection::establish(":memory:").unwrap();
+        assert!(!plain.is_broken());
+        assert!(!instrumented.is_broken());
+
+        // leave a transaction open, as if its guard was dropped without committing
+        AnsiTransactionManager::begin_transaction(&mut plain).unwrap();
+        AnsiTransactionManager::begin_transaction(&mut instrumented).unwrap();
+
+        assert!(plain.is_broken());
+        assert!(instrumented.is_broken());
+        instrumented.ping().unwrap();
     }
 }
@@ -0,0 +1,589 @@
+use std::borrow::Cow;
+use std::iter::Peekable;
+use std::str::CharIndices;
+
+use diesel::backend::Backend;
+use diesel::query_builder::{QueryBuilder, QueryFragment};
+use tracing::Span;
+
+#[cfg(feature = "statement-fingerprint")]
+use crate::fingerprint::fingerprint_sql;
+use crate::fingerprint::statement_fingerprint;
+
+/// Whether a statement reads or writes, recorded as `db.access_mode`.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum AccessMode {
+    /// A `SELECT` which takes no row locks.
+    Read,
+    /// Data modification, including within a `WITH` clause, a locking read
+    /// such as `SELECT ... FOR UPDATE`, or a schema change.
+    Write,
+    /// Anything else, such as `SHOW`, `SET` or a statement which could not be
+    /// understood.
+    #[default]
+    Unknown,
+}
+
+impl AccessMode {
+    /// The value recorded as `db.access_mode`.
+    #[must_use]
+    pub fn as_str(self) -> &'static str {
+        match self {
+            AccessMode::Read => "read",
+            AccessMode::Write => "write",
+            AccessMode::Unknown => "unknown",
+        }
+    }
+}
+
+/// Low-cardinality description of a SQL statement.
+///
+/// Produced by [`summarize_statement`], this holds the values recorded as the
+/// `db.operation`, `db.sql.table` and `db.access_mode` span fields. The
+/// operation and table may be missing if the statement could not be
+/// understood.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct StatementSummary {
+    /// The leading keyword of the statement, upper-cased, e.g. `SELECT`.
+    pub operation: Option<String>,
+    /// The primary table the statement operates on, without quoting.
+    pub table: Option<String>,
+    /// Whether the statement reads or writes.
+    pub access_mode: AccessMode,
+}
+
+impl StatementSummary {
+    pub(crate) fn record(&self, span: &Span) {
+        if let Some(operation) = &self.operation {
+            span.record("db.operation", operation.as_str());
+        }
+        if let Some(table) = &self.table {
+            span.record("db.sql.table", table.as_str());
+        }
+        span.record("db.access_mode", self.access_mode.as_str());
+    }
+
+    /// A low-cardinality name for the span of the statement, such as
+    /// `SELECT users`.
+    fn span_name(&self) -> Option<String> {
+        let operation = self.operation.as_deref()?;
+        Some(match &self.table {
+            Some(table) => format!("{operation} {table}"),
+            None => operation.to_owned(),
+        })
+    }
+
+    fn record_span_name(&self, span: &Span) {
+        if let Some(name) = self.span_name() {
+            span.record("otel.name", name);
+        }
+    }
+}
+
+/// Extract the operation, primary table and access mode of a SQL statement.
+///
+/// This is a token scan rather than a full parser: the operation is the first
+/// keyword of the statement (looking through `WITH` clauses) and the table is
+/// the identifier following `FROM`, `INTO`, `UPDATE` or `JOIN`. A `SELECT` is
+/// a read unless a `WITH` clause modifies data or it locks rows with `FOR
+/// UPDATE`, `FOR SHARE` or `LOCK IN SHARE MODE`. Comments and quoted
+/// identifiers are handled, and only the first statement of a batch is
+/// considered.
+///
+/// ```
+/// use diesel_tracing::AccessMode;
+///
+/// let summary = diesel_tracing::summarize_statement(r#"SELECT "users"."id" FROM "users""#);
+///
+/// assert_eq!(summary.operation.as_deref(), Some("SELECT"));
+/// assert_eq!(summary.table.as_deref(), Some("users"));
+/// assert_eq!(summary.access_mode, AccessMode::Read);
+/// ```
+#[must_use]
+pub fn summarize_statement(sql: &str) -> StatementSummary {
+    let mut tokens = Tokens::new(sql).peekable();
+    let mut summary = StatementSummary::default();
+    let mut depth = 0usize;
+    let mut first = true;
+    let mut after_open = false;
+    let mut previous_word = None;
+    let mut writes = false;
+
+    while let Some(token) = tokens.next() {
+        if let Token::Word(word) = token {
+            // a data modifying statement nested in a `WITH` clause, or a
+            // locking clause such as `FOR UPDATE` or `FOR NO KEY UPDATE`
+            let nested_write = after_open
+                && ["INSERT", "UPDATE", "DELETE", "MERGE"]
+                    .iter()
+                    .any(|keyword| word.eq_ignore_ascii_case(keyword));
+            let locking = (word.eq_ignore_ascii_case("UPDATE")
+                || word.eq_ignore_ascii_case("SHARE"))
+                && previous_word.is_some_and(|previous: &str| {
+                    ["FOR", "KEY", "IN"]
+                        .iter()
+                        .any(|keyword| previous.eq_ignore_ascii_case(keyword))
+                });
+            writes |= nested_write || locking;
+        }
+        after_open = token == Token::Open;
+        previous_word = match &token {
+            Token::Word(word) => Some(*word),
+            _ => None,
+        };
+        match token {
+            Token::Open => depth += 1,
+            Token::Close => depth = depth.saturating_sub(1),
+            Token::Terminator if depth == 0 => break,
+            Token::Word(word) if depth == 0 => {
+                let keyword = word.to_ascii_uppercase();
+                match summary.operation.as_deref() {
+                    None if keyword == "WITH" => {}
+                    None if first || is_statement_keyword(&keyword) => {
+                        if keyword == "UPDATE" {
+                            summary.table = read_identifier(&mut tokens);
+                        }
+                        summary.operation = Some(keyword);
+                    }
+                    None => {}
+                    Some(operation) => {
+                        let precedes_table = match operation {
+                            "SELECT" => keyword == "FROM" || keyword == "JOIN",
+                            "INSERT" | "REPLACE" => keyword == "INTO",
+                            "DELETE" => keyword == "FROM",
+                            _ => false,
+                        };
+                        if precedes_table && summary.table.is_none() {
+                            summary.table = read_identifier(&mut tokens);
+                        }
+                    }
+                }
+            }
+            _ => {}
+        }
+        first = false;
+    }
+
+    summary.access_mode = match summary.operation.as_deref() {
+        Some(operation) if writes || is_write_keyword(operation) || is_ddl_keyword(operation) => {
+            AccessMode::Write
+        }
+        Some("SELECT" | "VALUES") => AccessMode::Read,
+        _ if writes => AccessMode::Write,
+        _ => AccessMode::Unknown,
+    };
+
+    summary
+}
+
+/// Render a query without its binds.
+pub(crate) fn render_sql<DB, T>(source: &T) -> Option<String>
+where
+    DB: Backend + Default,
+    DB::QueryBuilder: Default,
+    T: QueryFragment<DB> + ?Sized,
+{
+    let mut query_builder = DB::QueryBuilder::default();
+    source.to_sql(&mut query_builder, &DB::default()).ok()?;
+    Some(query_builder.finish())
+}
+
+/// Render a query without its binds and record its summary on the current
+/// span, returning the summary, and name the span after it if `name_span` is
+/// set. Nothing is rendered if the span is disabled, or with the
+/// `passthrough` feature.
+pub(crate) fn record_query_summary<DB, T>(source: &T, name_span: bool) -> Option<StatementSummary>
+where
+    DB: Backend + Default,
+    DB::QueryBuilder: Default,
+    T: QueryFragment<DB> + ?Sized,
+{
+    let span = Span::current();
+    if cfg!(feature = "passthrough") || span.is_disabled() {
+        return None;
+    }
+
+    let sql = render_sql::<DB, _>(source)?;
+    let summary = summarize_statement(&sql);
+    summary.record(&span);
+    if name_span {
+        summary.record_span_name(&span);
+    }
+    span.record("db.bind_count", count_bind_params(&sql));
+    span.record("db.statement.hash", statement_fingerprint(&sql));
+    #[cfg(feature = "caller-location")]
+    crate::caller::record_caller(&span);
+    #[cfg(feature = "statement-fingerprint")]
+    span.record("db.statement_fingerprint", fingerprint_sql(&sql));
+
+    Some(summary)
+}
+
+/// Count the bind parameters of a rendered statement. Numbered `$n`
+/// placeholders take precedence, so postgres operators such as `?|` are not
+/// counted when the statement has any.
+pub(crate) fn count_bind_params(sql: &str) -> usize {
+    let mut positional = 0;
+    let mut numbered = 0;
+    for token in Tokens::new(sql) {
+        match token {
+            Token::Bind(None) => positional += 1,
+            Token::Bind(Some(n)) => numbered = numbered.max(n),
+            _ => {}
+        }
+    }
+
+    if numbered > 0 {
+        numbered
+    } else {
+        positional
+    }
+}
+
+/// Truncate a statement to at most `max_len` characters, marking it with the
+/// length of the full statement in bytes if it was truncated.
+#[cfg(all(feature = "statement-fields", not(feature = "passthrough")))]
+pub(crate) fn truncate_statement(statement: String, max_len: usize) -> String {
+    match statement.char_indices().nth(max_len) {
+        Some((end, _)) => format!(
+            "{}\u{2026} [truncated, {} bytes total]",
+            &statement[..end],
+            statement.len()
+        ),
+        None => statement,
+    }
+}
+
+/// Record the summary of a raw SQL string on the current span, naming the
+/// span after it if `name_span` is set.
+pub(crate) fn record_statement_summary(sql: &str, name_span: bool) {
+    let span = Span::current();
+    if !cfg!(feature = "passthrough") && !span.is_disabled() {
+        let summary = summarize_statement(sql);
+        summary.record(&span);
+        if name_span {
+            summary.record_span_name(&span);
+        }
+        span.record("db.statement.hash", statement_fingerprint(sql));
+        #[cfg(feature = "statement-fingerprint")]
+        span.record("db.statement_fingerprint", fingerprint_sql(sql));
+    }
+}
+
+fn is_statement_keyword(keyword: &str) -> bool {
+    matches!(
+        keyword,
+        "SELECT" | "INSERT" | "UPDATE" | "DELETE" | "REPLACE" | "MERGE" | "VALUES"
+    )
+}
+
+fn is_write_keyword(keyword: &str) -> bool {
+    matches!(
+        keyword,
+        "INSERT" | "UPDATE" | "DELETE" | "REPLACE" | "MERGE"
+    )
+}
+
+fn is_ddl_keyword(keyword: &str) -> bool {
+    matches!(
+        keyword,
+        "CREATE" | "ALTER" | "DROP" | "TRUNCATE" | "RENAME" | "GRANT" | "REVOKE"
+    )
+}
+
+/// Read a possibly schema-qualified identifier, skipping modifiers such as
+/// `ONLY` and sqlite's `OR <conflict>` clause.
+fn read_identifier(tokens: &mut Peekable<Tokens<'_>>) -> Option<String> {
+    let mut parts = Vec::new();
+    loop {
+        match tokens.next()? {
+            Token::Word(word) if parts.is_empty() && word.eq_ignore_ascii_case("ONLY") => {
+                continue;
+            }
+            Token::Word(word) if parts.is_empty() && word.eq_ignore_ascii_case("OR") => {
+                tokens.next();
+                continue;
+            }
+            Token::Word(word) => parts.push(Cow::Borrowed(word)),
+            Token::Quoted(word) => parts.push(word),
+            _ => return None,
+        }
+        if tokens.peek() == Some(&Token::Dot) {
+            tokens.next();
+        } else {
+            return Some(parts.join("."));
+        }
+    }
+}
+
+#[derive(Debug, PartialEq)]
+enum Token<'a> {
+    Word(&'a str),
+    Quoted(Cow<'a, str>),
+    Open,
+    Close,
+    Dot,
+    Terminator,
+    /// A `?` placeholder, or a numbered `$n` placeholder with its number.
+    Bind(Option<usize>),
+    Other,
+}
+
+/// Lazy SQL tokenizer which skips whitespace, comments and string literals.
+struct Tokens<'a> {
+    sql: &'a str,
+    chars: Peekable<CharIndices<'a>>,
+}
+
+impl<'a> Tokens<'a> {
+    fn new(sql: &'a str) -> Self {
+        Tokens {
+            sql,
+            chars: sql.char_indices().peekable(),
+        }
+    }
+
+    fn next_is(&mut self, c: char) -> bool {
+        self.chars.next_if(|&(_, next)| next == c).is_some()
+    }
+
+    /// Consume up to an unescaped `close`, treating a doubled `close` as an
+    /// escaped literal character.
+    fn quoted(&mut self, start: usize, close: char) -> Cow<'a, str> {
+        let mut escaped = None::<String>;
+        let mut end = self.sql.len();
+        while let Some((i, c)) = self.chars.next() {
+            if c == close {
+                if self.next_is(close) {
+                    escaped
+                        .get_or_insert_with(|| self.sql[start..i].to_string())
+                        .push(close);
+                    continue;
+                }
+                end = i;
+                break;
+            }
+            if let Some(escaped) = &mut escaped {
+                escaped.push(c);
+            }
+        }
+        escaped.map_or(Cow::Borrowed(&self.sql[start..end]), Cow::Owned)
+    }
+}
+
+impl<'a> Iterator for Tokens<'a> {
+    type Item = Token<'a>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            let (start, c) = self.chars.next()?;
+            let token = match c {
+                c if c.is_whitespace() => continue,
+                '-' if self.next_is('-') => {
+                    while self.chars.next_if(|&(_, c)| c != '\n').is_some() {}
+                    continue;
+                }
+                '/' if self.next_is('*') => {
+                    let mut previous = None;
+                    for (_, c) in self.chars.by_ref() {
+                        if previous == Some('*') && c == '/' {
+                            break;
+                        }
+                        previous = Some(c);
+                    }
+                    continue;
+                }
+                '"' | '`' => Token::Quoted(self.quoted(start + 1, c)),
+                '[' => Token::Quoted(self.quoted(start + 1, ']')),
+                '\'' => {
+                    self.quoted(start + 1, '\'');
+                    Token::Other
+                }
+                '(' => Token::Open,
+                ')' => Token::Close,
+                '.' => Token::Dot,
+                ';' => Token::Terminator,
+                '?' => Token::Bind(None),
+                '$' if self.chars.peek().is_some_and(|(_, c)| c.is_ascii_digit()) => {
+                    let mut end = start + 1;
+                    while let Some((i, _)) = self.chars.next_if(|(_, c)| c.is_ascii_digit()) {
+                        end = i + 1;
+                    }
+                    Token::Bind(self.sql[start + 1..end].parse().ok())
+                }
+                c if c.is_alphanumeric() || c == '_' => {
+                    let mut end = start + c.len_utf8();
+                    while let Some((i, c)) = self
+                        .chars
+                        .next_if(|&(_, c)| c.is_alphanumeric() || c == '_' || c == '$')
+                    {
+                        end = i + c.len_utf8();
+                    }
+                    Token::Word(&self.sql[start..end])
+                }
+                _ => Token::Other,
+            };
+            return Some(token);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_summarize_statement() {
+        let cases: &[(&str, Option<&str>, Option<&str>)] = &[
+            (
+                r#"SELECT "users"."id", "users"."name" FROM "users" WHERE "users"."id" = $1"#,
+                Some("SELECT"),
+                Some("users"),
+            ),
+            (
+                "SELECT `posts`.`id` FROM `blog`.`posts` INNER JOIN `users` ON 1",
+                Some("SELECT"),
+                Some("blog.posts"),
+            ),
+            (
+                r#"INSERT INTO "users" ("name") VALUES (?) RETURNING "users"."id""#,
+                Some("INSERT"),
+                Some("users"),
+            ),
+            (
+                r#"UPDATE "users" SET "name" = $1 WHERE "users"."id" = $2"#,
+                Some("UPDATE"),
+                Some("users"),
+            ),
+            (
+                "UPDATE OR REPLACE users SET name = 'x'",
+                Some("UPDATE"),
+                Some("users"),
+            ),
+            (
+                "update only public.users set name = 'x'",
+                Some("UPDATE"),
+                Some("public.users"),
+            ),
+            (
+                r#"DELETE FROM "users" WHERE "id" = $1"#,
+                Some("DELETE"),
+                Some("users"),
+            ),
+            (
+                "-- fetch the names\n/* leading */ select name from users",
+                Some("SELECT"),
+                Some("users"),
+            ),
+            (
+                "WITH recent (id) AS (SELECT id FROM posts) SELECT * FROM users JOIN recent ON 1",
+                Some("SELECT"),
+                Some("users"),
+            ),
+            (
+                "WITH moved AS (DELETE FROM a RETURNING *) INSERT INTO b SELECT * FROM moved",
+                Some("INSERT"),
+                Some("b"),
+            ),
+            (
+                "SELECT (SELECT count(*) FROM inner_table) FROM outer_table",
+                Some("SELECT"),
+                Some("outer_table"),
+            ),
+            ("SELECT 'FROM users' AS x", Some("SELECT"), None),
+            ("SELECT 1", Some("SELECT"), None),
+            ("SELECT * FROM (SELECT 1) AS sub", Some("SELECT"), None),
+            (
+                r#"SELECT * FROM "weird ""name""""#,
+                Some("SELECT"),
+                Some(r#"weird "name""#),
+            ),
+            ("CREATE TABLE users (id INTEGER)", Some("CREATE"), None),
+            ("SELECT 1; DELETE FROM users", Some("SELECT"), None),
+            ("", None, None),
+            ("-- only a comment", None, None),
+            ("(((", None, None),
+        ];
+
+        for (sql, operation, table) in cases {
+            let summary = summarize_statement(sql);
+            assert_eq!(
+                summary.operation.as_deref(),
+                *operation,
+                "operation of {sql}"
+            );
+            assert_eq!(summary.table.as_deref(), *table, "table of {sql}");
+        }
+    }
+
+    #[test]
+    fn test_access_mode() {
+        let cases = [
+            (r#"SELECT "users"."id" FROM "users""#, AccessMode::Read),
+            (
+                "select replace(name, 'a', 'b') from users",
+                AccessMode::Read,
+            ),
+            ("VALUES (1), (2)", AccessMode::Read),
+            (
+                "WITH recent AS (SELECT id FROM posts) SELECT * FROM recent",
+                AccessMode::Read,
+            ),
+            ("SELECT * FROM users FOR UPDATE", AccessMode::Write),
+            ("SELECT * FROM users FOR NO KEY UPDATE", AccessMode::Write),
+            (
+                "select * from users for share skip locked",
+                AccessMode::Write,
+            ),
+            ("SELECT * FROM users LOCK IN SHARE MODE", AccessMode::Write),
+            ("SELECT 'FOR UPDATE' FROM users", AccessMode::Read),
+            (
+                "WITH x AS (INSERT INTO logs VALUES (1) RETURNING id) SELECT * FROM x",
+                AccessMode::Write,
+            ),
+            (
+                "WITH moved AS (DELETE FROM a RETURNING *) INSERT INTO b SELECT * FROM moved",
+                AccessMode::Write,
+            ),
+            ("INSERT INTO users VALUES (1)", AccessMode::Write),
+            ("UPDATE users SET name = 'x'", AccessMode::Write),
+            ("DELETE FROM users", AccessMode::Write),
+            ("REPLACE INTO users VALUES (1)", AccessMode::Write),
+            ("CREATE TABLE users (id INTEGER)", AccessMode::Write),
+            ("drop table users", AccessMode::Write),
+            (
+                "-- update the names
+/* FOR UPDATE */ SELECT 1",
+                AccessMode::Read,
+            ),
+            ("/* read */ DELETE FROM users", AccessMode::Write),
+            ("SHOW TABLES", AccessMode::Unknown),
+            ("SET search_path TO app", AccessMode::Unknown),
+            ("", AccessMode::Unknown),
+        ];
+
+        for (sql, expected) in cases {
+            assert_eq!(
+                summarize_statement(sql).access_mode,
+                expected,
+                "access mode of {sql}"
+            );
+        }
+    }
+
+    #[test]
+    fn test_count_bind_params() {
+        let cases = [
+            (r#"SELECT * FROM "users" WHERE "id" IN ($1, $2, $3)"#, 3),
+            ("SELECT * FROM `users` WHERE `id` = ? AND `name` = ?", 2),
+            ("SELECT $2, $1, $2", 2),
+            ("SELECT '?', \"$1\" -- ?\n FROM t WHERE a = ?", 1),
+            ("SELECT data ?| $1 FROM t", 1),
+            ("SELECT 1", 0),
+        ];
+
+        for (sql, expected) in cases {
+            assert_eq!(count_bind_params(sql), expected, "binds of {sql}");
+        }
+    }
+}
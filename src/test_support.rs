@@ -0,0 +1,301 @@
+//! A subscriber recording the spans and events of the instrumented
+//! connections, for asserting on them in tests.
+//!
+//! ```
+//! use diesel_tracing::test_support::EventRecorder;
+//!
+//! let recorder = EventRecorder::default();
+//! tracing::subscriber::with_default(recorder.clone(), || {
+//!     tracing::debug_span!("load", db.statement = "SELECT 1").in_scope(|| {});
+//! });
+//!
+//! assert_eq!(recorder.query_spans().len(), 1);
+//! ```
+use std::collections::HashMap;
+use std::fmt::Debug;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+
+use tracing::field::{Field, Visit};
+use tracing::span::{Attributes, Id, Record};
+use tracing::{Event, Metadata, Subscriber};
+use tracing_core::span::Current;
+
+/// The names of the spans created for each query by the instrumented
+/// connections.
+const QUERY_SPAN_NAMES: [&str; 3] = ["load", "execute_returning_count", "batch_execute"];
+
+type Fields = HashMap<&'static str, String>;
+
+/// An event seen by an [`EventRecorder`], with the span it was emitted in.
+struct RecordedEvent {
+    parent: Option<Id>,
+    fields: Fields,
+}
+
+/// A span seen by an [`EventRecorder`], with its fields formatted as
+/// strings.
+#[derive(Clone, Debug)]
+pub struct RecordedSpan {
+    /// The id the span was given by the recorder.
+    pub id: Id,
+    /// The name of the span.
+    pub name: &'static str,
+    /// The target of the span, such as `diesel_tracing::pg::query`.
+    pub target: &'static str,
+    /// The fields recorded on the span, when it was created or since.
+    pub fields: HashMap<&'static str, String>,
+}
+
+/// Minimal subscriber which records every span and the values recorded on
+/// it, along with every event and the span it was emitted in.
+///
+/// The recorder is cheap to clone and its clones share what was recorded, so
+/// one clone can be installed as the subscriber and the other inspected.
+#[derive(Clone, Default)]
+pub struct EventRecorder {
+    next_id: Arc<AtomicU64>,
+    spans: Arc<Mutex<HashMap<u64, (&'static Metadata<'static>, RecordedSpan)>>>,
+    events: Arc<Mutex<Vec<RecordedEvent>>>,
+    stack: Arc<Mutex<Vec<Id>>>,
+}
+
+impl EventRecorder {
+    /// The most recently created span with the given name.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the recorder's lock was poisoned.
+    #[must_use]
+    pub fn span(&self, name: &str) -> Option<RecordedSpan> {
+        let spans = self.spans.lock().unwrap();
+        spans
+            .iter()
+            .filter(|(_, (_, span))| span.name == name)
+            .max_by_key(|(id, _)| **id)
+            .map(|(_, (_, span))| span.clone())
+    }
+
+    /// Every span with the given name, in the order they were created.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the recorder's lock was poisoned.
+    #[must_use]
+    pub fn spans(&self, name: &str) -> Vec<RecordedSpan> {
+        self.spans_matching(|span_name| span_name == name)
+    }
+
+    /// Every span, in the order they were created.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the recorder's lock was poisoned.
+    #[must_use]
+    pub fn all_spans(&self) -> Vec<RecordedSpan> {
+        self.spans_matching(|_| true)
+    }
+
+    /// Every `load`, `execute_returning_count` and `batch_execute` span, one
+    /// of which is created for each query run through an instrumented
+    /// connection, in the order they were created.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the recorder's lock was poisoned.
+    #[must_use]
+    pub fn query_spans(&self) -> Vec<RecordedSpan> {
+        self.spans_matching(|name| QUERY_SPAN_NAMES.contains(&name))
+    }
+
+    /// The fields of every event, along with its `level` and `target`, in the
+    /// order they were emitted.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the recorder's lock was poisoned.
+    #[must_use]
+    pub fn events(&self) -> Vec<HashMap<&'static str, String>> {
+        let events = self.events.lock().unwrap();
+        events.iter().map(|event| event.fields.clone()).collect()
+    }
+
+    /// The fields of the events emitted directly in the span with the given
+    /// id, in the order they were emitted.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the recorder's lock was poisoned.
+    #[must_use]
+    pub fn events_in_span(&self, id: &Id) -> Vec<HashMap<&'static str, String>> {
+        let events = self.events.lock().unwrap();
+        events
+            .iter()
+            .filter(|event| event.parent.as_ref() == Some(id))
+            .map(|event| event.fields.clone())
+            .collect()
+    }
+
+    pub(crate) fn spans_matching(&self, matches: impl Fn(&str) -> bool) -> Vec<RecordedSpan> {
+        let spans = self.spans.lock().unwrap();
+        let mut matching: Vec<_> = spans
+            .iter()
+            .filter(|(_, (_, span))| matches(span.name))
+            .map(|(id, (_, span))| (*id, span.clone()))
+            .collect();
+        matching.sort_by_key(|(id, _)| *id);
+        matching.into_iter().map(|(_, span)| span).collect()
+    }
+}
+
+/// Assert that a query span was recorded with a `db.statement` containing
+/// the given text, returning the most recent such span.
+///
+/// The statement is only recorded with the `statement-fields` feature.
+///
+/// # Panics
+///
+/// Panics if no query span has a matching statement.
+#[allow(clippy::must_use_candidate)]
+pub fn assert_query_span(recorder: &EventRecorder, contains: &str) -> RecordedSpan {
+    let spans = recorder.query_spans();
+    spans
+        .iter()
+        .rev()
+        .find(|span| {
+            span.fields
+                .get("db.statement")
+                .is_some_and(|statement| statement.contains(contains))
+        })
+        .cloned()
+        .unwrap_or_else(|| {
+            let statements: Vec<_> = spans
+                .iter()
+                .map(|span| span.fields.get("db.statement"))
+                .collect();
+            panic!("no query span with a statement containing {contains:?}, found {statements:?}")
+        })
+}
+
+struct FieldVisitor<'a>(&'a mut Fields);
+
+impl Visit for FieldVisitor<'_> {
+    fn record_str(&mut self, field: &Field, value: &str) {
+        self.0.insert(field.name(), value.to_string());
+    }
+
+    fn record_debug(&mut self, field: &Field, value: &dyn Debug) {
+        self.0.insert(field.name(), format!("{value:?}"));
+    }
+}
+
+impl Subscriber for EventRecorder {
+    fn enabled(&self, _metadata: &Metadata<'_>) -> bool {
+        true
+    }
+
+    fn new_span(&self, attributes: &Attributes<'_>) -> Id {
+        let id = Id::from_u64(self.next_id.fetch_add(1, Ordering::SeqCst) + 1);
+        let mut span = RecordedSpan {
+            id: id.clone(),
+            name: attributes.metadata().name(),
+            target: attributes.metadata().target(),
+            fields: HashMap::new(),
+        };
+        attributes.record(&mut FieldVisitor(&mut span.fields));
+        self.spans
+            .lock()
+            .unwrap()
+            .insert(id.into_u64(), (attributes.metadata(), span));
+
+        id
+    }
+
+    fn record(&self, id: &Id, values: &Record<'_>) {
+        if let Some((_, span)) = self.spans.lock().unwrap().get_mut(&id.into_u64()) {
+            values.record(&mut FieldVisitor(&mut span.fields));
+        }
+    }
+
+    fn record_follows_from(&self, _span: &Id, _follows: &Id) {}
+
+    fn event(&self, event: &Event<'_>) {
+        let parent = if event.is_contextual() {
+            self.stack.lock().unwrap().last().cloned()
+        } else {
+            event.parent().cloned()
+        };
+        let mut fields = HashMap::new();
+        fields.insert("level", event.metadata().level().to_string());
+        fields.insert("target", event.metadata().target().to_string());
+        event.record(&mut FieldVisitor(&mut fields));
+        self.events
+            .lock()
+            .unwrap()
+            .push(RecordedEvent { parent, fields });
+    }
+
+    fn enter(&self, id: &Id) {
+        self.stack.lock().unwrap().push(id.clone());
+    }
+
+    fn exit(&self, _id: &Id) {
+        self.stack.lock().unwrap().pop();
+    }
+
+    fn current_span(&self) -> Current {
+        let stack = self.stack.lock().unwrap();
+        match stack.last() {
+            Some(id) => {
+                let spans = self.spans.lock().unwrap();
+                let (metadata, _) = spans[&id.into_u64()];
+                Current::new(id.clone(), metadata)
+            }
+            None => Current::none(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_query_spans_and_events_recorded() {
+        let recorder = EventRecorder::default();
+        tracing::subscriber::with_default(recorder.clone(), || {
+            let load = tracing::debug_span!("load", db.statement = tracing::field::Empty);
+            load.record("db.statement", "SELECT id FROM users");
+            load.in_scope(|| tracing::debug!("in load"));
+            tracing::debug_span!("transaction").in_scope(|| {
+                tracing::debug_span!("batch_execute", db.statement = "DELETE FROM users")
+                    .in_scope(|| {});
+            });
+            tracing::debug!("outside");
+        });
+
+        let spans = recorder.query_spans();
+        assert_eq!(spans.len(), 2);
+        assert_eq!(spans[0].name, "load");
+        assert_eq!(spans[0].target, "diesel_tracing::test_support::tests");
+        assert_eq!(spans[1].name, "batch_execute");
+        let span = assert_query_span(&recorder, "FROM users");
+        assert_eq!(span.name, "batch_execute");
+        let span = assert_query_span(&recorder, "SELECT");
+        let events = recorder.events_in_span(&span.id);
+        assert_eq!(events.len(), 1);
+        assert_eq!(events[0]["message"], "in load");
+        assert_eq!(recorder.events().len(), 2);
+    }
+
+    #[test]
+    #[should_panic(expected = "no query span with a statement containing")]
+    fn test_assert_query_span_panics_without_match() {
+        let recorder = EventRecorder::default();
+        tracing::subscriber::with_default(recorder.clone(), || {
+            tracing::debug_span!("load", db.statement = "SELECT 1").in_scope(|| {});
+        });
+
+        assert_query_span(&recorder, "UPDATE");
+    }
+}
@@ -0,0 +1,168 @@
+/*!
+Totals of the time spent in the database, such as for each request handled
+by a web server.
+
+While the guard returned by [`scope`] is alive, every `load`,
+`execute_returning_count` and `batch_execute` of a synchronous instrumented
+connection on the same thread adds its duration to the guard's totals. Scopes
+can be nested, and the queries run inside an inner scope count towards every
+scope which encloses it. When the guard is dropped the totals are recorded
+on its `db_time_scope` span, which is entered for the lifetime of the guard
+so that the spans of its queries are nested beneath it.
+
+Scopes are tracked per thread, so queries of the asynchronous connections,
+whose futures may be polled on other threads, are not counted.
+
+```
+# #[cfg(feature = "sqlite")]
+# fn main() {
+use diesel::connection::{Connection, SimpleConnection};
+use diesel_tracing::sqlite::InstrumentedSqliteConnection;
+use diesel_tracing::time_tracking;
+
+let mut conn = InstrumentedSqliteConnection::establish(":memory:").unwrap();
+let scope = time_tracking::scope();
+conn.batch_execute("SELECT 1").unwrap();
+assert_eq!(scope.query_count(), 1);
+println!("spent {:?} in the database", scope.total());
+# }
+# #[cfg(not(feature = "sqlite"))]
+# fn main() {}
+```
+*/
+use std::cell::{Cell, RefCell};
+use std::rc::Rc;
+use std::time::Duration;
+
+use tracing::span::EnteredSpan;
+use tracing::{field, info_span, Span};
+
+thread_local! {
+    static SCOPES: RefCell<Vec<Rc<Totals>>> = const { RefCell::new(Vec::new()) };
+}
+
+#[derive(Debug, Default)]
+struct Totals {
+    total: Cell<Duration>,
+    query_count: Cell<u64>,
+}
+
+/// Start accumulating the time spent in the database on this thread until
+/// the returned guard is dropped.
+#[must_use = "queries are only counted while the scope is alive"]
+pub fn scope() -> TimeTrackingScope {
+    let totals = Rc::new(Totals::default());
+    SCOPES.with(|scopes| scopes.borrow_mut().push(Rc::clone(&totals)));
+    let span = if cfg!(feature = "passthrough") {
+        Span::none()
+    } else {
+        info_span!(
+            "db_time_scope",
+            db.total_duration_ms = field::Empty,
+            db.query_count = field::Empty,
+        )
+    };
+
+    TimeTrackingScope {
+        totals,
+        span: span.entered(),
+    }
+}
+
+/// The guard of a scope started with [`scope`], recording
+/// `db.total_duration_ms` and `db.query_count` on its span when dropped.
+#[derive(Debug)]
+pub struct TimeTrackingScope {
+    totals: Rc<Totals>,
+    span: EnteredSpan,
+}
+
+impl TimeTrackingScope {
+    /// The time spent in the queries run so far within the scope.
+    #[must_use]
+    pub fn total(&self) -> Duration {
+        self.totals.total.get()
+    }
+
+    /// The number of queries run so far within the scope.
+    #[must_use]
+    pub fn query_count(&self) -> u64 {
+        self.totals.query_count.get()
+    }
+}
+
+impl Drop for TimeTrackingScope {
+    fn drop(&mut self) {
+        SCOPES.with(|scopes| {
+            scopes
+                .borrow_mut()
+                .retain(|totals| !Rc::ptr_eq(totals, &self.totals));
+        });
+        self.span
+            .record("db.total_duration_ms", self.total().as_secs_f64() * 1000.0);
+        self.span.record("db.query_count", self.query_count());
+    }
+}
+
+/// Whether any scope is active on this thread, so that queries need timing.
+#[cfg_attr(feature = "metrics", allow(dead_code))]
+pub(crate) fn is_active() -> bool {
+    SCOPES.with(|scopes| !scopes.borrow().is_empty())
+}
+
+/// Add a query which took `elapsed` to every scope active on this thread.
+pub(crate) fn record_query(elapsed: Duration) {
+    SCOPES.with(|scopes| {
+        for totals in scopes.borrow().iter() {
+            totals.total.set(totals.total.get() + elapsed);
+            totals.query_count.set(totals.query_count.get() + 1);
+        }
+    });
+}
+
+#[cfg(all(test, feature = "sqlite"))]
+mod tests {
+    use super::*;
+    use crate::sqlite::InstrumentedSqliteConnection;
+    use crate::test_support::EventRecorder;
+    use diesel::connection::{Connection, SimpleConnection};
+    use diesel::sql_query;
+    use diesel::RunQueryDsl;
+
+    #[test]
+    fn test_scope_totals() {
+        let recorder = EventRecorder::default();
+        tracing::subscriber::with_default(recorder.clone(), || {
+            let mut conn = InstrumentedSqliteConnection::establish(":memory:").unwrap();
+            let scope = scope();
+            conn.batch_execute("CREATE TABLE users (id INTEGER)")
+                .unwrap();
+            sql_query("INSERT INTO users VALUES (1)")
+                .execute(&mut conn)
+                .unwrap();
+            sql_query("SELECT * FROM users").execute(&mut conn).unwrap();
+            assert_eq!(scope.query_count(), 3);
+            assert!(scope.total() > Duration::ZERO);
+        });
+        assert!(!is_active());
+
+        let span = recorder.span("db_time_scope").unwrap();
+        assert_eq!(span.fields["db.query_count"], "3");
+        assert!(span.fields.contains_key("db.total_duration_ms"));
+    }
+
+    #[test]
+    fn test_nested_scopes() {
+        let mut conn = InstrumentedSqliteConnection::establish(":memory:").unwrap();
+        let outer = scope();
+        conn.batch_execute("SELECT 1").unwrap();
+        {
+            let inner = scope();
+            conn.batch_execute("SELECT 2").unwrap();
+            assert_eq!(inner.query_count(), 1);
+            assert!(inner.total() <= outer.total());
+        }
+        conn.batch_execute("SELECT 3").unwrap();
+        assert_eq!(outer.query_count(), 3);
+    }
+}
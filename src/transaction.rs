@@ -0,0 +1,118 @@
+use diesel::connection::{SimpleConnection, TransactionManagerStatus};
+use diesel::result::{Error, QueryResult};
+use tracing::Span;
+
+use crate::instrumentation::{SharedInstrumentationStack, TransactionWarnThresholds};
+
+/// Record the depth of the transaction about to begin on the current span,
+/// where 1 is the outermost transaction and greater depths are savepoints.
+pub(crate) fn record_transaction_depth(status: &mut TransactionManagerStatus) {
+    if cfg!(feature = "passthrough") {
+        return;
+    }
+    if let Ok(depth) = status.transaction_depth() {
+        let depth = depth.map_or(1, |depth| depth.get() + 1);
+        Span::current().record("db.transaction.depth", depth);
+    }
+}
+
+/// Record on the current span whether the transaction was rolled back, which
+/// happens whenever its closure returns an error.
+pub(crate) fn record_transaction_outcome<T, E>(result: &Result<T, E>) {
+    if cfg!(feature = "passthrough") {
+        return;
+    }
+    let span = Span::current();
+    span.record("db.transaction.rolled_back", result.is_err());
+    // the error of the closure is not required to implement Display, so only
+    // the status code is recorded
+    if result.is_err() {
+        span.record("otel.status_code", "ERROR");
+    }
+}
+
+/// Run the body of a transaction, warning while it is still open if it runs
+/// past `thresholds`, and record how long it took and the number of queries
+/// it ran on the current span.
+///
+/// Queries are counted by the instrumentation stack installed on the inner
+/// connection, so nothing is watched without one.
+pub(crate) fn watch_transaction<T>(
+    stack: Option<&SharedInstrumentationStack>,
+    thresholds: TransactionWarnThresholds,
+    f: impl FnOnce() -> T,
+) -> T {
+    let Some(stack) = stack.filter(|_| !cfg!(feature = "passthrough")) else {
+        return f();
+    };
+    if !stack.watch_transaction(thresholds) {
+        return f();
+    }
+    let result = f();
+    if let Some((elapsed, queries)) = stack.finish_transaction() {
+        let span = Span::current();
+        span.record(
+            "db.transaction.duration_ms",
+            u64::try_from(elapsed.as_millis()).unwrap_or(u64::MAX),
+        );
+        span.record("db.transaction.queries", queries);
+    }
+
+    result
+}
+
+/// Check that a savepoint name is a plain identifier, which every backend
+/// accepts without quoting.
+fn check_savepoint_name(name: &str) -> QueryResult<()> {
+    let mut chars = name.chars();
+    let valid = chars
+        .next()
+        .is_some_and(|first| first.is_ascii_alphabetic() || first == '_')
+        && chars.all(|c| c.is_ascii_alphanumeric() || c == '_');
+    if valid {
+        Ok(())
+    } else {
+        Err(Error::QueryBuilderError(
+            format!("invalid savepoint name {name:?}").into(),
+        ))
+    }
+}
+
+/// Run a closure inside a savepoint with the given name, releasing the
+/// savepoint if the closure returns `Ok` and rolling back to it otherwise.
+pub(crate) fn run_savepoint<C, T, E, F>(conn: &mut C, name: &str, f: F) -> Result<T, E>
+where
+    C: SimpleConnection,
+    F: FnOnce(&mut C) -> Result<T, E>,
+    E: From<Error>,
+{
+    check_savepoint_name(name)?;
+    conn.batch_execute(&format!("SAVEPOINT {name}"))?;
+    match f(conn) {
+        Ok(value) => {
+            conn.batch_execute(&format!("RELEASE SAVEPOINT {name}"))?;
+            Ok(value)
+        }
+        Err(error) => {
+            conn.batch_execute(&format!(
+                "ROLLBACK TO SAVEPOINT {name}; RELEASE SAVEPOINT {name}"
+            ))?;
+            Err(error)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_check_savepoint_name() {
+        assert!(check_savepoint_name("before_import").is_ok());
+        assert!(check_savepoint_name("_sp2").is_ok());
+        assert!(check_savepoint_name("").is_err());
+        assert!(check_savepoint_name("2fast").is_err());
+        assert!(check_savepoint_name("a; DROP TABLE users").is_err());
+        assert!(check_savepoint_name("\"quoted\"").is_err());
+    }
+}
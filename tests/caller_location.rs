@@ -0,0 +1,49 @@
+//! The caller recorded with the `caller-location` feature is the first frame
+//! outside this crate, so it is tested from outside the crate.
+#![cfg(all(
+    feature = "caller-location",
+    feature = "sqlite",
+    feature = "test-support",
+    not(feature = "passthrough")
+))]
+
+use diesel::connection::{Connection, SimpleConnection};
+use diesel::{sql_query, RunQueryDsl};
+use diesel_tracing::sqlite::InstrumentedSqliteConnection;
+use diesel_tracing::test_support::EventRecorder;
+
+fn insert_row(conn: &mut InstrumentedSqliteConnection) {
+    sql_query("INSERT INTO users VALUES (1)")
+        .execute(conn)
+        .unwrap();
+}
+
+#[test]
+fn test_caller_location_recorded() {
+    let recorder = EventRecorder::default();
+    tracing::subscriber::with_default(recorder.clone(), || {
+        let mut conn = InstrumentedSqliteConnection::establish(":memory:").unwrap();
+        conn.batch_execute("CREATE TABLE users (id INTEGER)")
+            .unwrap();
+        insert_row(&mut conn);
+    });
+
+    let span = recorder.span("execute_returning_count").unwrap();
+    assert!(span.fields["code.filepath"].ends_with("caller_location.rs"));
+    assert_eq!(span.fields["code.function"], "caller_location::insert_row");
+    assert!(span.fields["code.lineno"].parse::<u32>().unwrap() > 0);
+
+    diesel_tracing::set_caller_skip_prefixes(["caller_location::insert_row"]);
+    tracing::subscriber::with_default(recorder.clone(), || {
+        let mut conn = InstrumentedSqliteConnection::establish(":memory:").unwrap();
+        conn.batch_execute("CREATE TABLE users (id INTEGER)")
+            .unwrap();
+        insert_row(&mut conn);
+    });
+
+    let span = recorder.span("execute_returning_count").unwrap();
+    assert_eq!(
+        span.fields["code.function"],
+        "caller_location::test_caller_location_recorded::{{closure}}"
+    );
+}